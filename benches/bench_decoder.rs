@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use itchy::MessageStream;
+use orderbook_rust::itch::{self, decode::Frames};
+use orderbook_rust::orderbook::*;
+
+fn bench_decoders(c: &mut Criterion) {
+    let path = PathBuf::from("aapl_orders.itch"); // adjust path
+    let data = std::fs::read(&path).expect("failed to read ITCH file");
+
+    println!("Loaded {} bytes", data.len());
+
+    let mut group = c.benchmark_group("decode");
+    group.measurement_time(std::time::Duration::from_secs(30));
+
+    group.bench_function("itchy decode", |b| {
+        b.iter(|| {
+            let stream = MessageStream::from_reader(data.as_slice());
+            stream.into_iter().flatten().count()
+        });
+    });
+    group.bench_function("native decode", |b| {
+        b.iter(|| Frames::new(&data).flatten().count());
+    });
+
+    group.bench_function("itchy decode + apply", |b| {
+        b.iter(|| {
+            let mut book = OrderBook::with_capacity(1_024, 65_536);
+            for msg in MessageStream::from_reader(data.as_slice()).flatten() {
+                itch::apply(&mut book, &msg);
+            }
+            book.best_bid_raw()
+        });
+    });
+    group.bench_function("native decode + apply", |b| {
+        b.iter(|| {
+            let mut book = OrderBook::with_capacity(1_024, 65_536);
+            for view in Frames::new(&data).flatten() {
+                itch::apply_view(&mut book, &view);
+            }
+            book.best_bid_raw()
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_decoders);
+criterion_main!(benches);
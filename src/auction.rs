@@ -0,0 +1,216 @@
+use std::io::{self, Write};
+
+use itchy::{ArrayString8, Body, CrossType, ImbalanceDirection, Message};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::itch::{ApplyOutcome, ItchHandler};
+use crate::orderbook::OrderBook;
+
+// Latest Net Order Imbalance Indicator (I) for one cross
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AuctionState {
+    pub timestamp: u64,
+    pub stock_locate: u16,
+    pub symbol: ArrayString8,
+    pub cross_type: CrossType,
+    pub paired_shares: u64,
+    pub imbalance_shares: u64,
+    pub imbalance_direction: ImbalanceDirection,
+    // Raw ITCH prices, 4 implied decimals
+    pub far_price: u32,
+    pub near_price: u32,
+    pub current_ref_price: u32,
+    pub price_variation_indicator: char,
+}
+
+impl AuctionState {
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let Body::Imbalance(noii) = &msg.body else {
+            return None;
+        };
+
+        Some(AuctionState {
+            timestamp: msg.timestamp,
+            stock_locate: msg.stock_locate,
+            symbol: ArrayString8::from(noii.stock.trim_end()).unwrap_or_default(),
+            cross_type: noii.cross_type,
+            paired_shares: noii.paired_shares,
+            imbalance_shares: noii.imbalance_shares,
+            imbalance_direction: noii.imbalance_direction,
+            far_price: noii.far_price.raw(),
+            near_price: noii.near_price.raw(),
+            current_ref_price: noii.current_ref_price.raw(),
+            price_variation_indicator: noii.price_variation_indicator,
+        })
+    }
+
+    // Lower bound, in percent, of the deviation of the near price from the
+    // reference price. None if Nasdaq couldn't calculate it.
+    pub fn price_variation_pct(&self) -> Option<u32> {
+        match self.price_variation_indicator {
+            'L' => Some(0),
+            c @ '1'..='9' => c.to_digit(10),
+            'A' => Some(10),
+            'B' => Some(20),
+            'C' => Some(30),
+            _ => None,
+        }
+    }
+}
+
+// Every NOII message seen, in order, for export as a time series
+#[derive(Debug, Default)]
+pub struct AuctionHistory {
+    states: Vec<AuctionState>,
+}
+
+impl AuctionHistory {
+    pub fn new() -> Self {
+        AuctionHistory::default()
+    }
+
+    pub fn states(&self) -> &[AuctionState] {
+        &self.states
+    }
+
+    pub fn series(&self, stock_locate: u16) -> impl Iterator<Item = &AuctionState> {
+        self.states
+            .iter()
+            .filter(move |s| s.stock_locate == stock_locate)
+    }
+
+    pub fn write_csv<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        writeln!(
+            w,
+            "timestamp,symbol,cross_type,paired_shares,imbalance_shares,imbalance_direction,far_price,near_price,current_ref_price,price_variation_indicator"
+        )?;
+
+        let price = |p: u32| Decimal::from(p) / Decimal::from(10_000);
+        for s in &self.states {
+            writeln!(
+                w,
+                "{},{},{:?},{},{},{:?},{},{},{},{}",
+                s.timestamp,
+                s.symbol,
+                s.cross_type,
+                s.paired_shares,
+                s.imbalance_shares,
+                s.imbalance_direction,
+                price(s.far_price),
+                price(s.near_price),
+                price(s.current_ref_price),
+                s.price_variation_indicator,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ItchHandler for AuctionHistory {
+    fn after_apply(&mut self, book: &OrderBook, _msg: &Message, outcome: ApplyOutcome) {
+        if outcome != ApplyOutcome::AuctionUpdated {
+            return;
+        }
+        if let Some(state) = book.latest_auction() {
+            self.states.push(state.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::ImbalanceIndicator;
+
+    use super::*;
+    use crate::itch::{self, NOII};
+
+    fn noii(timestamp: u64, cross_type: CrossType, imbalance_shares: u64, pvi: char) -> Message {
+        Message {
+            tag: NOII,
+            stock_locate: 1,
+            tracking_number: 0,
+            timestamp,
+            body: Body::Imbalance(ImbalanceIndicator {
+                paired_shares: 10_000,
+                imbalance_shares,
+                imbalance_direction: ImbalanceDirection::Buy,
+                stock: ArrayString8::from("TEST    ").unwrap(),
+                far_price: 1_010_000.into(),
+                near_price: 1_005_000.into(),
+                current_ref_price: 1_002_500.into(),
+                cross_type,
+                price_variation_indicator: pvi,
+            }),
+        }
+    }
+
+    #[test]
+    fn imbalances_update_the_book_per_cross() {
+        let mut book = OrderBook::with_capacity(4, 4);
+        let mut history = AuctionHistory::new();
+        for msg in [
+            noii(10, CrossType::Opening, 2_000, '1'),
+            noii(20, CrossType::Closing, 500, 'L'),
+            noii(30, CrossType::Opening, 1_500, ' '),
+        ] {
+            assert_eq!(
+                itch::apply_with(&mut book, &msg, &mut history),
+                ApplyOutcome::AuctionUpdated
+            );
+        }
+
+        // The latest message for each cross replaces the one before it
+        let opening = book.auction(CrossType::Opening).unwrap();
+        assert_eq!(opening.timestamp, 30);
+        assert_eq!(opening.imbalance_shares, 1_500);
+        assert_eq!(opening.symbol.as_str(), "TEST");
+        assert_eq!(opening.price_variation_pct(), None);
+        assert_eq!(book.latest_auction(), Some(opening));
+        let closing = book.auction(CrossType::Closing).unwrap();
+        assert_eq!(closing.price_variation_pct(), Some(0));
+
+        assert_eq!(history.states().len(), 3);
+        let series: Vec<_> = history.series(1).map(|s| s.timestamp).collect();
+        assert_eq!(series, [10, 20, 30]);
+        assert_eq!(history.series(2).count(), 0);
+    }
+
+    #[test]
+    fn price_variation_indicators() {
+        let state = |pvi| AuctionState::from_message(&noii(0, CrossType::Opening, 0, pvi)).unwrap();
+        assert_eq!(state('L').price_variation_pct(), Some(0));
+        assert_eq!(state('7').price_variation_pct(), Some(7));
+        assert_eq!(state('C').price_variation_pct(), Some(30));
+        assert_eq!(state(' ').price_variation_pct(), None);
+        assert!(
+            AuctionState::from_message(&Message {
+                body: Body::DeleteOrder { reference: 1 },
+                ..noii(0, CrossType::Opening, 0, ' ')
+            })
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn csv_prices_are_decimals() {
+        let mut history = AuctionHistory::new();
+        let mut book = OrderBook::with_capacity(4, 4);
+        itch::apply_with(
+            &mut book,
+            &noii(10, CrossType::Closing, 500, 'A'),
+            &mut history,
+        );
+
+        let mut csv = Vec::new();
+        history.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("timestamp,symbol,"));
+        assert_eq!(
+            lines.next(),
+            Some("10,TEST,Closing,10000,500,Buy,101,100.50,100.25,A")
+        );
+        assert_eq!(lines.next(), None);
+    }
+}
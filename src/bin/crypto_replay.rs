@@ -0,0 +1,79 @@
+use std::io::BufRead;
+
+use clap::{Parser, ValueEnum};
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::crypto::Scale;
+use orderbook_rust::crypto::binance::BinanceRouter;
+use orderbook_rust::crypto::coinbase::CoinbaseRouter;
+use orderbook_rust::input;
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum Venue {
+    // Full channel (L3) with level 3 snapshots
+    Coinbase,
+    // Diff depth streams with REST depth snapshots
+    Binance,
+}
+
+// Builds books from a newline-delimited JSON recording of a crypto venue's
+// feed, which may be gzip or zstd compressed.
+#[derive(Parser, Debug)]
+struct Args {
+    file: String,
+    #[arg(long, value_enum)]
+    venue: Venue,
+    #[arg(long, default_value_t = 4)]
+    price_decimals: u32,
+    #[arg(long, default_value_t = 4)]
+    size_decimals: u32,
+    #[arg(long)]
+    symbol: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    let scale = Scale {
+        price_decimals: args.price_decimals,
+        size_decimals: args.size_decimals,
+    };
+
+    let mut books = BookSet::new();
+    let mut coinbase = CoinbaseRouter::new(scale);
+    let mut binance = BinanceRouter::new(scale);
+
+    let mut bad_lines = 0;
+    for line in input::open(&args.file)?.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let outcome = match args.venue {
+            Venue::Coinbase => coinbase.apply_line(&mut books, &line),
+            Venue::Binance => binance.apply_line(&mut books, &line),
+        };
+        if let Err(e) = outcome {
+            if bad_lines == 0 {
+                eprintln!("first bad line: {e}");
+            }
+            bad_lines += 1;
+        }
+    }
+
+    match args.venue {
+        Venue::Coinbase => eprintln!("{:#?}", coinbase.stats()),
+        Venue::Binance => eprintln!("{:#?}", binance.stats()),
+    }
+    eprintln!(
+        "{} books, {} stale, {bad_lines} bad lines",
+        books.len(),
+        books.stale_books().count()
+    );
+
+    if let Some(symbol) = &args.symbol {
+        match books.get_by_symbol(symbol) {
+            Some(book) => println!("{}", book.summary()),
+            None => eprintln!("symbol {symbol} not found"),
+        }
+    }
+    Ok(())
+}
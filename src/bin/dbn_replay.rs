@@ -0,0 +1,66 @@
+use clap::Parser;
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::dbn::{DbnReader, MboRouter};
+
+// Builds books from a Databento DBN file of MBO records, which may be zstd
+// compressed.
+#[derive(Parser, Debug)]
+struct Args {
+    file: String,
+    #[arg(long)]
+    symbol: Option<String>,
+    // Print the exchange, gateway and receive timestamps of the first N
+    // records
+    #[arg(long, default_value_t = 0)]
+    show: usize,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut reader = match DbnReader::open(&args.file) {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+    eprintln!(
+        "{} v{}, {} symbols mapped",
+        reader.metadata().dataset,
+        reader.metadata().version,
+        reader.metadata().symbols.len()
+    );
+    let mut router = MboRouter::new(reader.metadata());
+    let mut books = BookSet::new();
+
+    let mut shown = 0;
+    let result = router.run(&mut reader, &mut books, |msg, outcome| {
+        if shown < args.show {
+            shown += 1;
+            println!(
+                "event={} gateway={} receive={} {msg:?} {outcome:?}",
+                msg.ts_event,
+                msg.ts_gateway(),
+                msg.ts_recv
+            );
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("{e}");
+    }
+
+    eprintln!("{:#?}", router.stats());
+    eprintln!(
+        "{} books, {} stale",
+        books.len(),
+        books.stale_books().count()
+    );
+
+    if let Some(symbol) = &args.symbol {
+        match books.get_by_symbol(symbol) {
+            Some(book) => println!("{}", book.summary()),
+            None => eprintln!("symbol {symbol} not found"),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::net::IpAddr;
+
+use clap::Parser;
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::iex::DeepRouter;
+use orderbook_rust::pcap::{PcapReader, UdpFilter};
+
+// Builds price level books from a pcap or pcapng capture of IEX DEEP, e.g.
+// one of IEX's HIST files, which may be gzipped.
+#[derive(Parser, Debug)]
+struct Args {
+    file: String,
+    // Multicast group of the feed
+    #[arg(long)]
+    group: Option<IpAddr>,
+    #[arg(long)]
+    port: Option<u16>,
+    #[arg(long)]
+    symbol: Option<String>,
+    // Print the receive and exchange timestamps of the first N messages
+    #[arg(long, default_value_t = 0)]
+    show: usize,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut reader = match PcapReader::open(&args.file) {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+    let filter = UdpFilter {
+        group: args.group,
+        port: args.port,
+    };
+    let mut router = DeepRouter::new();
+    let mut books = BookSet::new();
+
+    let mut shown = 0;
+    loop {
+        let datagram = match reader.next_datagram(&filter) {
+            Ok(Some(datagram)) => datagram,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("{e}");
+                break;
+            }
+        };
+        let receive_time = datagram.timestamp;
+        // Bad segments are counted in the router's stats
+        let _ = router.apply_segment(&mut books, datagram.payload, |msg, outcome| {
+            if shown < args.show {
+                shown += 1;
+                println!(
+                    "receive={receive_time} exchange={} {msg:?} {outcome:?}",
+                    msg.timestamp()
+                );
+            }
+        });
+    }
+
+    eprintln!("{:#?}", reader.stats());
+    eprintln!("{:#?}", router.stats());
+    eprintln!(
+        "{} books, {} stale",
+        books.len(),
+        books.stale_books().count()
+    );
+
+    if let Some(symbol) = &args.symbol {
+        match books.get_by_symbol(symbol) {
+            Some(book) => println!("{}", book.summary()),
+            None => eprintln!("symbol {symbol} not found"),
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::io::{self, Read, Write};
 use std::path::PathBuf;
 
 use clap::Parser;
 use itchy::MessageStream;
-
-const STOCK_DIRECTORY: u8 = b'R';
+use orderbook_rust::input::{self, Compression, Output};
+use orderbook_rust::itch::decode::DecodeError;
+use orderbook_rust::itch::{STOCK_DIRECTORY, is_order_tag};
+use orderbook_rust::mmap::MappedFile;
 
 #[derive(Parser, Debug)]
 struct Args {
@@ -17,19 +18,9 @@ struct Args {
     symbol: String,
     #[arg(long)]
     max_messages: Option<usize>,
-}
-
-fn is_order_tag(tag: u8) -> bool {
-    matches!(
-        tag,
-        b'A' | // Add Order
-        b'F' | // Add Order (Attributed)
-        b'E' | // Order Executed
-        b'C' | // Order Executed With Price
-        b'X' | // Order Cancel
-        b'D' | // Order Delete
-        b'U' // Order Replace
-    )
+    // none, gzip or zstd. Defaults to the output file's extension
+    #[arg(long)]
+    compress: Option<Compression>,
 }
 
 fn read_exact_or_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
@@ -49,58 +40,54 @@ fn read_exact_or_eof<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<bool> {
     Ok(true)
 }
 
-fn main() -> io::Result<()> {
-    let args = Args::parse();
-    let symbol = args.symbol.to_lowercase();
-
-    let mut locate: Option<u16> = None;
-    let stream = MessageStream::from_file(&args.input).map_err(std::io::Error::other)?;
-
-    let mut directory_started = false;
+fn extract_mapped<W: Write>(
+    file: &MappedFile,
+    locate: u16,
+    max: usize,
+    w: &mut W,
+) -> io::Result<(usize, usize)> {
+    let mut kept = 0usize;
+    let mut total = 0usize;
 
-    for msg in stream {
-        let msg = match msg {
-            Ok(m) => m,
-            Err(e) => {
-                eprintln!("error decoding message: {e}");
-                continue;
-            }
-        };
-        if msg.tag != STOCK_DIRECTORY && directory_started {
+    for view in file.frames() {
+        if kept >= max {
             break;
         }
-        let itchy::Body::StockDirectory(dir) = &msg.body else {
-            continue;
+        let view = match view {
+            Ok(view) => view,
+            Err(e @ DecodeError::Truncated { .. }) => {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, e.to_string()));
+            }
+            // The streaming path doesn't validate frames either, skip them
+            Err(_) => {
+                total += 1;
+                continue;
+            }
         };
+        total += 1;
 
-        directory_started = true;
-
-        if dir.stock.trim_end().to_lowercase() == symbol {
-            locate = Some(msg.stock_locate);
-            break;
+        if view.stock_locate() == locate && is_order_tag(view.tag()) {
+            let payload = view.as_bytes();
+            w.write_all(&(payload.len() as u16).to_be_bytes())?;
+            w.write_all(payload)?;
+            kept += 1;
         }
     }
+    Ok((total, kept))
+}
 
-    let Some(locate) = locate else {
-        eprintln!("symbol {symbol} not found in stock directory");
-        return Ok(());
-    };
-    eprintln!("found symbol {symbol} with stock_locate={locate}");
-
-    let infile = File::open(&args.input)?;
-    let mut r = BufReader::with_capacity(1 << 20, infile);
-
-    let outfile = File::create(&args.output)?;
-    let mut w = BufWriter::with_capacity(1 << 20, outfile);
-
+fn extract_stream<R: Read, W: Write>(
+    mut r: R,
+    locate: u16,
+    max: usize,
+    w: &mut W,
+) -> io::Result<(usize, usize)> {
     let mut kept = 0usize;
     let mut total = 0usize;
 
     loop {
-        if let Some(max) = args.max_messages {
-            if kept >= max {
-                break;
-            }
+        if kept >= max {
+            break;
         }
 
         let mut len_buf = [0u8; 2];
@@ -127,8 +114,63 @@ fn main() -> io::Result<()> {
             kept += 1;
         }
     }
+    Ok((total, kept))
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+    let symbol = args.symbol.to_lowercase();
+
+    let mut locate: Option<u16> = None;
+    let stream = MessageStream::from_reader(input::open(&args.input)?);
+
+    let mut directory_started = false;
+
+    for msg in stream {
+        let msg = match msg {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("error decoding message: {e}");
+                continue;
+            }
+        };
+        if msg.tag != STOCK_DIRECTORY && directory_started {
+            break;
+        }
+        let itchy::Body::StockDirectory(dir) = &msg.body else {
+            continue;
+        };
+
+        directory_started = true;
+
+        if dir.stock.trim_end().to_lowercase() == symbol {
+            locate = Some(msg.stock_locate);
+            break;
+        }
+    }
+
+    let Some(locate) = locate else {
+        eprintln!("symbol {symbol} not found in stock directory");
+        return Ok(());
+    };
+    eprintln!("found symbol {symbol} with stock_locate={locate}");
+
+    let compression = args
+        .compress
+        .unwrap_or_else(|| Compression::from_path(&args.output));
+    let mut w = Output::create(&args.output, compression)?;
+
+    // Raw input is scanned in place, compressed input is streamed
+    let max = args.max_messages.unwrap_or(usize::MAX);
+    let (total, kept) = match MappedFile::open(&args.input) {
+        Ok(file) => extract_mapped(&file, locate, max, &mut w)?,
+        Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+            extract_stream(input::open(&args.input)?, locate, max, &mut w)?
+        }
+        Err(e) => return Err(e),
+    };
 
-    w.flush()?;
+    w.finish()?;
     eprintln!(
         "Frames scanned: {total}, kept: {kept}{}",
         if let Some(max) = args.max_messages {
@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::input;
+use orderbook_rust::itch::protocol::{self, Decoded, Protocol};
+use orderbook_rust::lobster::{self, LobsterReader, LobsterWriter};
+use orderbook_rust::orderbook::{BookConfig, OrderBook};
+use orderbook_rust::seek::parse_time;
+
+// Converts between ITCH and LOBSTER message/orderbook files
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    // Writes SYMBOL_message_LEVELS.csv and SYMBOL_orderbook_LEVELS.csv
+    Export {
+        file: PathBuf,
+        symbol: String,
+        #[arg(long, default_value_t = 10)]
+        levels: usize,
+        // Detected from the file when not given
+        #[arg(long)]
+        protocol: Option<Protocol>,
+        // Only messages from this time of day on are written, the book is
+        // still built from the start of the feed
+        #[arg(long, value_parser = parse_time)]
+        from: Option<u64>,
+        #[arg(long, value_parser = parse_time)]
+        to: Option<u64>,
+        #[arg(short, long, default_value = ".")]
+        out_dir: PathBuf,
+    },
+    // Builds a book from a message file, checking it against an orderbook
+    // file if one is given
+    Import {
+        messages: PathBuf,
+        #[arg(long)]
+        orderbook: Option<PathBuf>,
+    },
+}
+
+fn export(
+    file: PathBuf,
+    symbol: String,
+    levels: usize,
+    protocol: Option<Protocol>,
+    window: (u64, u64),
+    out_dir: PathBuf,
+) -> io::Result<()> {
+    let mut reader = input::open(&file)?;
+    let protocol = match protocol {
+        Some(protocol) => protocol,
+        None => Protocol::detect(reader.fill_buf()?).unwrap_or_default(),
+    };
+
+    let create = |kind: &str| -> io::Result<BufWriter<File>> {
+        let path = out_dir.join(format!("{symbol}_{kind}_{levels}.csv"));
+        Ok(BufWriter::new(File::create(path)?))
+    };
+    let mut writer = LobsterWriter::new(&symbol, levels, create("message")?, create("orderbook")?);
+    let mut books = BookSet::new();
+
+    let result = protocol::decode_stream(reader, protocol, |decoded| {
+        let msg = match decoded {
+            Ok(Decoded::View(view)) => match view.to_message() {
+                Some(msg) => msg,
+                None => return,
+            },
+            Ok(Decoded::Message(msg)) => msg,
+            Ok(Decoded::Listing(info)) => {
+                books.add_listing(info);
+                return;
+            }
+            Err(e) => {
+                eprintln!("skipping frame: {e}");
+                return;
+            }
+        };
+        if (window.0..=window.1).contains(&msg.timestamp) {
+            books.apply_with(&msg, &mut writer);
+        } else {
+            books.apply(&msg);
+        }
+    });
+    match result {
+        // A truncated frame is the last
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => eprintln!("stopped at a bad frame: {e}"),
+        result => result?,
+    }
+
+    let rows = writer.rows();
+    writer.finish()?;
+    eprintln!("{rows} rows written for {symbol}");
+    Ok(())
+}
+
+fn import(messages: PathBuf, orderbook: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
+    let events = LobsterReader::new(BufReader::new(File::open(&messages)?));
+    let mut expected = match orderbook {
+        Some(path) => Some(BufReader::new(File::open(path)?).lines()),
+        None => None,
+    };
+    let mut book = OrderBook::with_capacity(1_024, 65_536);
+    // Message files are named SYMBOL_message_LEVELS.csv
+    if let Some((symbol, _)) = messages
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split_once("_message_"))
+    {
+        book.set_config(BookConfig {
+            symbol: symbol.to_string(),
+            ..BookConfig::default()
+        });
+    }
+
+    let mut applied = 0;
+    let mut mismatches = 0;
+    for event in events {
+        let event = event?;
+        lobster::apply(&mut book, &event);
+        applied += 1;
+
+        let Some(lines) = expected.as_mut() else {
+            continue;
+        };
+        let Some(line) = lines.next().transpose()? else {
+            return Err(format!("orderbook file ends before message {applied}").into());
+        };
+        let row = lobster::parse_book_row(&line)
+            .map_err(|reason| format!("orderbook line {applied}: {reason}"))?;
+        if lobster::book_row(&book, row.len() / 4) != row {
+            if mismatches == 0 {
+                eprintln!("first mismatch at line {applied}: {event}");
+            }
+            mismatches += 1;
+        }
+    }
+
+    eprintln!("{applied} events applied");
+    if expected.is_some() {
+        eprintln!("{mismatches} orderbook rows differ");
+    }
+    println!("{}", book.summary());
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    match Args::parse().command {
+        Command::Export {
+            file,
+            symbol,
+            levels,
+            protocol,
+            from,
+            to,
+            out_dir,
+        } => export(
+            file,
+            symbol,
+            levels,
+            protocol,
+            (from.unwrap_or(0), to.unwrap_or(u64::MAX)),
+            out_dir,
+        )?,
+        Command::Import {
+            messages,
+            orderbook,
+        } => import(messages, orderbook)?,
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+use std::net::IpAddr;
+
+use clap::Parser;
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::cme::{MdpRouter, PriceScale};
+use orderbook_rust::crypto::parse_decimal;
+use orderbook_rust::pcap::{PcapReader, UdpFilter};
+
+// Builds books from a pcap or pcapng capture of CME MDP 3.0 incremental
+// traffic. Capturing both the A and B feeds is fine, duplicates are dropped.
+// Books are named by security id, implied books with an .implied suffix.
+// Book prices have four decimals unless an instrument's tick is given.
+#[derive(Parser, Debug)]
+struct Args {
+    file: String,
+    // Multicast group of the feed
+    #[arg(long)]
+    group: Option<IpAddr>,
+    #[arg(long)]
+    port: Option<u16>,
+    #[arg(long)]
+    symbol: Option<String>,
+    // SECURITY_ID=TICK, e.g. 42=0.0000005, for instruments that tick finer
+    // than four decimals. Their book prices are in ticks. May be repeated
+    #[arg(long, value_parser = parse_tick)]
+    tick: Vec<(i32, i64)>,
+    // Print the receive and transact times of the first N entries
+    #[arg(long, default_value_t = 0)]
+    show: usize,
+}
+
+fn parse_tick(s: &str) -> Result<(i32, i64), String> {
+    let (security_id, tick) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected SECURITY_ID=TICK, got {s}"))?;
+    let security_id = security_id
+        .trim()
+        .parse::<i32>()
+        .map_err(|e| format!("{security_id}: {e}"))?;
+    let tick = parse_decimal(tick.trim(), 9)
+        .and_then(|tick| i64::try_from(tick).ok())
+        .filter(|tick| *tick > 0)
+        .ok_or_else(|| format!("{tick} isn't a tick of at least 1e-9"))?;
+    Ok((security_id, tick))
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut reader = match PcapReader::open(&args.file) {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+    let filter = UdpFilter {
+        group: args.group,
+        port: args.port,
+    };
+    let mut router = MdpRouter::new();
+    for (security_id, tick) in &args.tick {
+        router.set_price_scale(*security_id, PriceScale::ticks(*tick));
+    }
+    let mut books = BookSet::new();
+
+    let mut shown = 0;
+    loop {
+        let datagram = match reader.next_datagram(&filter) {
+            Ok(Some(datagram)) => datagram,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("{e}");
+                break;
+            }
+        };
+        let receive_time = datagram.timestamp;
+        // Bad packets are counted in the router's stats
+        let _ = router.apply_packet(&mut books, datagram.payload, |timestamp, entry, outcome| {
+            if shown < args.show {
+                shown += 1;
+                println!("receive={receive_time} transact={timestamp} {entry:?} {outcome:?}");
+            }
+        });
+    }
+
+    eprintln!("{:#?}", reader.stats());
+    eprintln!("{:#?}", router.stats());
+    eprintln!(
+        "{} books, {} stale",
+        books.len(),
+        books.stale_books().count()
+    );
+
+    if let Some(symbol) = &args.symbol {
+        match books.get_by_symbol(symbol) {
+            Some(book) => println!("{}", book.summary()),
+            None => eprintln!("symbol {symbol} not found"),
+        }
+    }
+}
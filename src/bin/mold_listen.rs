@@ -0,0 +1,71 @@
+use std::net::UdpSocket;
+
+use clap::Parser;
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::moldudp::{self, MoldReceiver};
+
+// Builds books from MoldUDP64 packets until the end of session packet
+#[derive(Parser, Debug)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1:26400")]
+    bind: String,
+    #[arg(long)]
+    symbol: Option<String>,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let socket = UdpSocket::bind(&args.bind)?;
+    eprintln!("listening on {}", socket.local_addr()?);
+
+    let mut receiver = MoldReceiver::new();
+    let mut books = BookSet::new();
+    let mut buf = [0u8; 65_536];
+
+    loop {
+        let len = socket.recv(&mut buf)?;
+        let result = match moldudp::apply_packet(&mut receiver, &mut books, &buf[..len]) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("{e}");
+                continue;
+            }
+        };
+
+        if let Some(gap) = result.gap {
+            eprintln!("gap: missing {}..{}", gap.start, gap.end);
+        }
+        if let Some(lost) = result.lost {
+            eprintln!("gave up on {}..{}", lost.start, lost.end);
+        }
+        if result.recovered {
+            eprintln!("recovered");
+        }
+        if result.end_of_session {
+            break;
+        }
+    }
+
+    // Nothing will fill the gaps still open at the end of the session
+    while receiver.in_gap() {
+        receiver.skip(|_, view| {
+            books.apply_view(&view);
+        });
+        books.mark_stale();
+    }
+
+    eprintln!("{:#?}", receiver.stats());
+    eprintln!(
+        "{} books, {} stale",
+        books.len(),
+        books.stale_books().count()
+    );
+
+    if let Some(symbol) = &args.symbol {
+        match books.get_by_symbol(symbol) {
+            Some(book) => println!("{}", book.summary()),
+            None => eprintln!("symbol {symbol} not found"),
+        }
+    }
+    Ok(())
+}
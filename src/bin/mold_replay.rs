@@ -0,0 +1,98 @@
+use std::io::ErrorKind;
+use std::net::UdpSocket;
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+use orderbook_rust::input;
+use orderbook_rust::itch::decode::{FrameReader, MessageView};
+use orderbook_rust::moldudp::{DEFAULT_MAX_PACKET, PacketWriter, Session};
+
+// Replays an ITCH file as MoldUDP64 packets over UDP, e.g. to 127.0.0.1 for
+// testing mold_listen
+#[derive(Parser, Debug)]
+struct Args {
+    file: String,
+    #[arg(long, default_value = "127.0.0.1:26400")]
+    target: String,
+    #[arg(long, default_value = "TEST")]
+    session: String,
+    #[arg(long, default_value_t = DEFAULT_MAX_PACKET)]
+    max_packet: usize,
+    // Sleep between packets, so a receiver on loopback can keep up
+    #[arg(long, default_value_t = 20)]
+    delay_us: u64,
+    // Drop every Nth packet to simulate gaps
+    #[arg(long)]
+    drop_every: Option<usize>,
+    // Send every Nth packet twice to simulate duplicates
+    #[arg(long)]
+    duplicate_every: Option<usize>,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let mut frames = FrameReader::new(input::open(&args.file)?);
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(&args.target)?;
+
+    let mut writer = PacketWriter::new(Session::new(&args.session), 1, args.max_packet);
+    let mut packets = 0usize;
+    let mut dropped = 0usize;
+
+    let mut send = |packet: &[u8]| -> std::io::Result<()> {
+        packets += 1;
+        if args.drop_every.is_some_and(|n| packets.is_multiple_of(n)) {
+            dropped += 1;
+            return Ok(());
+        }
+        socket.send(packet)?;
+        if args
+            .duplicate_every
+            .is_some_and(|n| packets.is_multiple_of(n))
+        {
+            socket.send(packet)?;
+        }
+        if args.delay_us > 0 {
+            thread::sleep(Duration::from_micros(args.delay_us));
+        }
+        Ok(())
+    };
+
+    loop {
+        // A truncated frame is the last
+        let payload = match frames.next_frame() {
+            Ok(Some(payload)) => payload,
+            Ok(None) => break,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                eprintln!("stopped at a bad frame: {e}");
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        let view = match MessageView::new(payload) {
+            Ok(view) => view,
+            Err(e) => {
+                eprintln!("skipping frame: {e}");
+                continue;
+            }
+        };
+        if !writer.push(view.as_bytes()) {
+            send(&writer.take())?;
+            writer.push(view.as_bytes());
+        }
+    }
+    if !writer.is_empty() {
+        send(&writer.take())?;
+    }
+
+    // Not subject to drop_every, so the receiver always sees the end
+    socket.send(&writer.end_of_session())?;
+
+    eprintln!(
+        "Sent {} messages in {packets} packets ({dropped} dropped)",
+        writer.next_sequence() - 1
+    );
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use std::net::IpAddr;
+
+use clap::Parser;
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::moldudp::MoldReceiver;
+use orderbook_rust::orphans::{self, OrphanPolicy};
+use orderbook_rust::pcap::{self, PcapReader, UdpFilter};
+
+// Builds books from a pcap or pcapng capture of MoldUDP64 ITCH traffic
+#[derive(Parser, Debug)]
+struct Args {
+    file: String,
+    // Multicast group of the feed
+    #[arg(long)]
+    group: Option<IpAddr>,
+    #[arg(long)]
+    port: Option<u16>,
+    #[arg(long)]
+    symbol: Option<String>,
+    // Print the receive and exchange timestamps of the first N messages
+    #[arg(long, default_value_t = 0)]
+    show: usize,
+    // What to do with messages for orders added before the capture started:
+    // ignore, count or placeholder
+    #[arg(long, default_value_t = OrphanPolicy::Count)]
+    orphans: OrphanPolicy,
+    // Warn when more messages than this are orphaned
+    #[arg(long, default_value_t = orphans::DEFAULT_WARN_THRESHOLD)]
+    orphan_warn: u64,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut reader = match PcapReader::open(&args.file) {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+    let filter = UdpFilter {
+        group: args.group,
+        port: args.port,
+    };
+    let mut receiver = MoldReceiver::new();
+    let mut books = BookSet::new();
+    books.set_orphan_policy(args.orphans);
+
+    let mut shown = 0;
+    let result = pcap::replay(
+        &mut reader,
+        &filter,
+        &mut receiver,
+        &mut books,
+        |receive_time, view, outcome| {
+            if shown < args.show {
+                shown += 1;
+                println!(
+                    "{} receive={receive_time} exchange={} locate={} {outcome:?}",
+                    view.tag() as char,
+                    view.timestamp(),
+                    view.stock_locate(),
+                );
+            }
+        },
+    );
+    if let Err(e) = result {
+        eprintln!("{e}");
+    }
+
+    eprintln!("{:#?}", reader.stats());
+    eprintln!("{:#?}", receiver.stats());
+    eprintln!(
+        "{} books, {} stale",
+        books.len(),
+        books.stale_books().count()
+    );
+    eprintln!("{:#?}", books.orphan_stats());
+    if let Some(warning) = books.orphan_stats().warning(args.orphan_warn) {
+        eprintln!("{warning}");
+    }
+
+    if let Some(symbol) = &args.symbol {
+        match books.get_by_symbol(symbol) {
+            Some(book) => println!("{}", book.summary()),
+            None => eprintln!("symbol {symbol} not found"),
+        }
+    }
+}
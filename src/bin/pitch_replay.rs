@@ -0,0 +1,76 @@
+use std::net::IpAddr;
+
+use clap::Parser;
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::cboe::PitchRouter;
+use orderbook_rust::pcap::{PcapReader, UdpFilter};
+
+// Builds books from a pcap or pcapng capture of Cboe Multicast PITCH traffic.
+// Capturing both the A and B feeds is fine, duplicates are dropped.
+#[derive(Parser, Debug)]
+struct Args {
+    file: String,
+    // Multicast group of the feed
+    #[arg(long)]
+    group: Option<IpAddr>,
+    #[arg(long)]
+    port: Option<u16>,
+    #[arg(long)]
+    symbol: Option<String>,
+    // Print the receive and exchange timestamps of the first N messages
+    #[arg(long, default_value_t = 0)]
+    show: usize,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut reader = match PcapReader::open(&args.file) {
+        Ok(reader) => reader,
+        Err(e) => {
+            eprintln!("{e}");
+            return;
+        }
+    };
+    let filter = UdpFilter {
+        group: args.group,
+        port: args.port,
+    };
+    let mut router = PitchRouter::new();
+    let mut books = BookSet::new();
+
+    let mut shown = 0;
+    loop {
+        let datagram = match reader.next_datagram(&filter) {
+            Ok(Some(datagram)) => datagram,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("{e}");
+                break;
+            }
+        };
+        let receive_time = datagram.timestamp;
+        // Bad packets are counted in the router's stats
+        let _ = router.apply_packet(&mut books, datagram.payload, |timestamp, msg, outcome| {
+            if shown < args.show {
+                shown += 1;
+                println!("receive={receive_time} exchange={timestamp} {msg:?} {outcome:?}");
+            }
+        });
+    }
+
+    eprintln!("{:#?}", reader.stats());
+    eprintln!("{:#?}", router.stats());
+    eprintln!(
+        "{} books, {} stale",
+        books.len(),
+        books.stale_books().count()
+    );
+
+    if let Some(symbol) = &args.symbol {
+        match books.get_by_symbol(symbol) {
+            Some(book) => println!("{}", book.summary()),
+            None => eprintln!("symbol {symbol} not found"),
+        }
+    }
+}
@@ -1,16 +1,14 @@
 use clap::Parser;
+use std::io::ErrorKind;
 use std::time::Instant;
 
 use itchy::Message;
-use orderbook_rust::orderbook::{OrderBook, OrderSide};
-
-const ORDER_ADD: u8 = b'A';
-const ORDER_ADD_ATTRIBUTED: u8 = b'F';
-const ORDER_EXECUTED: u8 = b'E';
-const ORDER_EXECUTED_PRICE: u8 = b'C';
-const ORDER_CANCEL: u8 = b'X';
-const ORDER_DELETE: u8 = b'D';
-const ORDER_REPLACE: u8 = b'U';
+use orderbook_rust::input;
+use orderbook_rust::itch::decode::{DecodeError, FrameReader, MessageView};
+use orderbook_rust::itch::{self, ApplyCounts};
+use orderbook_rust::mmap::MappedFile;
+use orderbook_rust::orderbook::OrderBook;
+use orderbook_rust::orphans::{self, OrphanPolicy};
 
 #[derive(Parser)]
 struct Args {
@@ -18,124 +16,105 @@ struct Args {
 
     #[arg(long)]
     max_messages: Option<usize>,
+
+    // Decode with itchy instead of the native decoder, for comparison
+    #[arg(long)]
+    itchy: bool,
+
+    // What to do with messages for orders added before the start of the
+    // file: ignore, count or placeholder
+    #[arg(long, default_value_t = OrphanPolicy::Count)]
+    orphans: OrphanPolicy,
+    // Warn when more messages than this are orphaned
+    #[arg(long, default_value_t = orphans::DEFAULT_WARN_THRESHOLD)]
+    orphan_warn: u64,
 }
 
 fn main() {
     let args = Args::parse();
 
-    let stream = itchy::MessageStream::from_file(args.file).unwrap();
-    let mut messages: Vec<Message> = Vec::with_capacity(2_000_000);
-
-    for msg in stream {
-        let m = msg.unwrap();
-        messages.push(m);
-    }
-
+    let limit = args.max_messages.map_or(usize::MAX, |max| max + 1);
     let mut book = OrderBook::new();
+    book.set_orphan_policy(args.orphans);
+    let mut counts = ApplyCounts::default();
+    let mut bad_frames = 0;
 
-    let mut count_add = 0;
-    let mut count_executed = 0;
-    let mut count_executed_price = 0;
-    let mut count_cancel = 0;
-    let mut count_delete = 0;
-    let mut count_replace = 0;
-
-    let start = Instant::now();
-
-    for (processed, m) in messages.iter().enumerate() {
-        if let Some(max) = args.max_messages {
-            if processed > max {
-                break;
-            }
+    let (total_messages, duration) = if args.itchy {
+        let stream = itchy::MessageStream::from_reader(input::open(&args.file).unwrap());
+        let mut messages: Vec<Message> = Vec::with_capacity(2_000_000);
+        for msg in stream {
+            messages.push(msg.unwrap());
         }
 
-        match m.tag {
-            ORDER_ADD | ORDER_ADD_ATTRIBUTED => {
-                let itchy::Body::AddOrder(order) = &m.body else {
-                    continue;
-                };
-                let side = if order.side == itchy::Side::Buy {
-                    OrderSide::Buy
-                } else {
-                    OrderSide::Sell
-                };
-                book.add_order(order.reference, order.price.raw(), order.shares, side);
-                count_add += 1;
-            }
-            ORDER_EXECUTED => {
-                let itchy::Body::OrderExecuted {
-                    reference,
-                    executed,
-                    ..
-                } = m.body
-                else {
-                    continue;
-                };
-                book.execute_order(reference, executed);
-                count_executed += 1;
-            }
-            ORDER_EXECUTED_PRICE => {
-                let itchy::Body::OrderExecutedWithPrice {
-                    reference,
-                    executed,
-                    printable,
-                    ..
-                } = m.body
-                else {
-                    continue;
-                };
-                if printable {
-                    book.execute_order(reference, executed);
-                    count_executed_price += 1;
+        let start = Instant::now();
+        for m in messages.iter().take(limit) {
+            itch::apply_with(&mut book, m, &mut counts);
+        }
+        (messages.len().min(limit), start.elapsed())
+    } else {
+        // Raw files are mapped and decoded in place, compressed ones are
+        // streamed, so their timings include decompression
+        let start = Instant::now();
+        let mut processed = 0;
+        let mut apply = |view: Result<MessageView, DecodeError>| {
+            // Malformed messages are skipped
+            match view {
+                Ok(view) => {
+                    counts.record(itch::apply_view(&mut book, &view));
+                    processed += 1;
+                }
+                Err(e) => {
+                    if bad_frames == 0 {
+                        eprintln!("first bad frame: {e}");
+                    }
+                    bad_frames += 1;
                 }
             }
-            ORDER_CANCEL => {
-                let itchy::Body::OrderCancelled {
-                    reference,
-                    cancelled,
-                } = m.body
-                else {
-                    continue;
-                };
-                book.cancel_order(reference, cancelled);
-                count_cancel += 1;
-            }
-            ORDER_DELETE => {
-                let itchy::Body::DeleteOrder { reference } = m.body else {
-                    continue;
-                };
-                book.delete_order(reference);
-                count_delete += 1;
-            }
-            ORDER_REPLACE => {
-                let itchy::Body::ReplaceOrder(order) = &m.body else {
-                    continue;
-                };
-                book.replace_order(
-                    order.old_reference,
-                    order.new_reference,
-                    order.price.raw(),
-                    order.shares,
-                );
-                count_replace += 1;
+        };
+        match MappedFile::open(&args.file) {
+            Ok(file) => file.frames().take(limit).for_each(apply),
+            Err(e) if e.kind() == ErrorKind::InvalidData => {
+                let mut frames = FrameReader::new(input::open(&args.file).unwrap());
+                let mut read = 0;
+                while read < limit {
+                    // A truncated frame is the last
+                    match frames.next_frame() {
+                        Ok(Some(payload)) => apply(MessageView::new(payload)),
+                        Ok(None) => break,
+                        Err(e) => {
+                            eprintln!("stopped at a bad frame: {e}");
+                            break;
+                        }
+                    }
+                    read += 1;
+                }
             }
-            _ => continue,
+            Err(e) => panic!("can't open {}: {e}", args.file),
         }
-    }
+        (processed, start.elapsed())
+    };
 
-    let duration = start.elapsed();
-    let total_messages = messages
-        .len()
-        .min(args.max_messages.unwrap_or(messages.len()));
     let ns_per_message = (duration.as_nanos() as f64) / (total_messages as f64);
 
     println!("Processed {total_messages} messages in {duration:?}");
     println!("~{ns_per_message:.2} ns/message");
+    if bad_frames > 0 {
+        println!("Skipped {bad_frames} bad frames");
+    }
     println!("Order counts:");
-    println!("  ADD: {count_add}");
-    println!("  EXECUTED: {count_executed}");
-    println!("  EXECUTED_PRICE: {count_executed_price}");
-    println!("  CANCEL: {count_cancel}");
-    println!("  DELETE: {count_delete}");
-    println!("  REPLACE: {count_replace}");
+    println!("  ADD: {}", counts.add);
+    println!("  EXECUTED: {}", counts.executed);
+    println!("  EXECUTED_PRICE: {}", counts.executed_price);
+    println!(
+        "  EXECUTED_PRICE (non-printable): {}",
+        counts.executed_price_non_printable
+    );
+    println!("  CANCEL: {}", counts.cancel);
+    println!("  DELETE: {}", counts.delete);
+    println!("  REPLACE: {}", counts.replace);
+    println!("  ORPHANED: {}", counts.orphaned);
+    println!("{:#?}", book.orphan_stats());
+    if let Some(warning) = book.orphan_stats().warning(args.orphan_warn) {
+        eprintln!("{warning}");
+    }
 }
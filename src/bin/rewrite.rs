@@ -0,0 +1,106 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::Parser;
+use rustc_hash::FxHashMap;
+
+use orderbook_rust::input::{self, Compression, Output};
+use orderbook_rust::itch::decode::{FrameReader, MessageView};
+use orderbook_rust::itch::encode::{Header, ItchWriter, patch_header};
+use orderbook_rust::seek::parse_duration;
+
+// Copies an ITCH file, shifting timestamps and remapping stock locates
+#[derive(Parser, Debug)]
+struct Args {
+    #[arg(short, long)]
+    input: PathBuf,
+    #[arg(short, long)]
+    output: PathBuf,
+    // Added to every timestamp, e.g. 1h or -30m
+    #[arg(long, allow_hyphen_values = true, value_parser = parse_shift)]
+    shift: Option<i64>,
+    // OLD=NEW, may be repeated
+    #[arg(long, value_parser = parse_remap)]
+    remap: Vec<(u16, u16)>,
+    // none, gzip or zstd. Defaults to the output file's extension
+    #[arg(long)]
+    compress: Option<Compression>,
+}
+
+fn parse_shift(s: &str) -> Result<i64, String> {
+    let (sign, value) = match s.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let ns = parse_duration(value).map_err(|e| e.to_string())?;
+    i64::try_from(ns)
+        .map(|ns| sign * ns)
+        .map_err(|_| format!("shift {s} is too large"))
+}
+
+fn parse_remap(s: &str) -> Result<(u16, u16), String> {
+    let (old, new) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected OLD=NEW, got {s}"))?;
+    let locate = |v: &str| v.trim().parse::<u16>().map_err(|e| format!("{v}: {e}"));
+    Ok((locate(old)?, locate(new)?))
+}
+
+fn main() -> io::Result<()> {
+    let args = Args::parse();
+    let mut frames = FrameReader::new(input::open(&args.input)?);
+    let remap: FxHashMap<u16, u16> = args.remap.iter().copied().collect();
+    let shift = args.shift.unwrap_or(0);
+
+    let compression = args
+        .compress
+        .unwrap_or_else(|| Compression::from_path(&args.output));
+    let mut w = ItchWriter::new(Output::create(&args.output, compression)?);
+
+    let mut patched = Vec::with_capacity(64);
+    let mut skipped = 0usize;
+    loop {
+        // A truncated frame is the last
+        let payload = match frames.next_frame() {
+            Ok(Some(payload)) => payload,
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                eprintln!("stopped at a bad frame: {e}");
+                skipped += 1;
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        let view = match MessageView::new(payload) {
+            Ok(view) => view,
+            Err(e) => {
+                eprintln!("skipping frame: {e}");
+                skipped += 1;
+                continue;
+            }
+        };
+
+        let locate = view.stock_locate();
+        let header = Header {
+            stock_locate: remap.get(&locate).copied().unwrap_or(locate),
+            tracking_number: view.tracking_number(),
+            timestamp: view.timestamp().checked_add_signed(shift).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("shifted timestamp of {} is negative", view.timestamp()),
+                )
+            })?,
+        };
+
+        patched.clear();
+        patched.extend_from_slice(view.as_bytes());
+        patch_header(&mut patched, &header)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        w.write_payload(&patched)?;
+    }
+
+    let messages = w.messages();
+    w.into_inner().finish()?;
+    eprintln!("Frames written: {messages}, skipped: {skipped}");
+    Ok(())
+}
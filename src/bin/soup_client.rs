@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use clap::Parser;
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::moldudp::Session;
+use orderbook_rust::soupbin::{ClientConfig, SoupClient};
+
+// Builds books from a SoupBinTCP session until the server ends it
+#[derive(Parser, Debug)]
+struct Args {
+    #[arg(long, default_value = "127.0.0.1:26401")]
+    addr: String,
+    #[arg(long)]
+    session: Option<String>,
+    #[arg(long, default_value = "")]
+    username: String,
+    #[arg(long, default_value = "")]
+    password: String,
+    #[arg(long, default_value_t = 1)]
+    sequence: u64,
+    #[arg(long, default_value_t = 5)]
+    max_reconnects: usize,
+    #[arg(long)]
+    symbol: Option<String>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut config = ClientConfig::new(&args.addr);
+    config.username = args.username;
+    config.password = args.password;
+    config.session = args.session.as_deref().map(Session::new);
+    config.first_sequence = args.sequence;
+    config.max_reconnects = args.max_reconnects;
+    config.reconnect_delay = Duration::from_millis(100);
+
+    let mut client = SoupClient::new(config);
+    let mut books = BookSet::new();
+
+    let result = client.run(|_, view| {
+        books.apply_view(&view);
+    });
+    if let Err(e) = result {
+        eprintln!("{e}");
+    }
+    let _ = client.logout();
+
+    eprintln!(
+        "session {:?}, next sequence {}",
+        client.session().map(|s| s.to_string()),
+        client.next_sequence()
+    );
+    eprintln!("{:#?}", client.stats());
+    eprintln!("{} books", books.len());
+
+    if let Some(symbol) = &args.symbol {
+        match books.get_by_symbol(symbol) {
+            Some(book) => println!("{}", book.summary()),
+            None => eprintln!("symbol {symbol} not found"),
+        }
+    }
+}
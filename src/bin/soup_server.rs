@@ -0,0 +1,61 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use clap::Parser;
+use orderbook_rust::input;
+use orderbook_rust::moldudp::Session;
+use orderbook_rust::soupbin::{self, ServerConfig};
+
+// Serves an ITCH file over SoupBinTCP, one replay per connection
+#[derive(Parser, Debug)]
+struct Args {
+    file: String,
+    #[arg(long, default_value = "127.0.0.1:26401")]
+    bind: String,
+    #[arg(long, default_value = "TEST")]
+    session: String,
+    #[arg(long, default_value = "")]
+    username: String,
+    #[arg(long, default_value = "")]
+    password: String,
+    // Drop each connection after this many messages to test reconnects
+    #[arg(long)]
+    disconnect_after: Option<u64>,
+}
+
+fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    // Opened up front so a bad path fails before listening
+    input::open(&args.file)?;
+    let config = Arc::new(ServerConfig {
+        session: Session::new(&args.session),
+        username: args.username,
+        password: args.password,
+        heartbeat_interval: Duration::from_secs(1),
+        disconnect_after: args.disconnect_after,
+    });
+
+    let listener = TcpListener::bind(&args.bind)?;
+    eprintln!("serving {} on {}", args.file, listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr()?;
+        // Each connection streams its own replay of the file
+        let itch = match input::open(&args.file) {
+            Ok(itch) => itch,
+            Err(e) => {
+                eprintln!("{peer}: can't open {}: {e}", args.file);
+                continue;
+            }
+        };
+        let config = Arc::clone(&config);
+        thread::spawn(move || match soupbin::serve(stream, itch, &config) {
+            Ok(()) => eprintln!("{peer}: done"),
+            Err(e) => eprintln!("{peer}: {e}"),
+        });
+    }
+    Ok(())
+}
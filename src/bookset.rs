@@ -0,0 +1,282 @@
+use itchy::Message;
+use rustc_hash::FxHashMap;
+
+use crate::directory::{StockDirectory, StockInfo, normalize_symbol};
+use crate::itch::decode::MessageView;
+use crate::itch::{self, ApplyOutcome, ItchHandler};
+use crate::limits::CircuitBreakers;
+use crate::orderbook::{BookConfig, OrderBook};
+use crate::orphans::{OrphanPolicy, OrphanStats};
+use crate::trading::TradingStatus;
+
+// Capacity used for symbols without a hint. Most of the ~8,000 names in a
+// TotalView feed only ever have a few hundred live orders.
+const DEFAULT_PRICE_LEVELS: usize = 64;
+const DEFAULT_ORDERS: usize = 1_024;
+
+#[derive(Debug, Clone, Copy)]
+pub struct CapacityHint {
+    pub price_levels: usize,
+    pub orders: usize,
+}
+
+impl Default for CapacityHint {
+    fn default() -> Self {
+        CapacityHint {
+            price_levels: DEFAULT_PRICE_LEVELS,
+            orders: DEFAULT_ORDERS,
+        }
+    }
+}
+
+// One OrderBook per stock_locate, created when the Stock Directory message
+// for the locate is seen
+#[derive(Debug, Default)]
+pub struct BookSet {
+    // Indexed by stock_locate
+    books: Vec<Option<OrderBook>>,
+    directory: StockDirectory,
+    capacity_hints: FxHashMap<String, CapacityHint>,
+    default_hint: CapacityHint,
+    orphan_policy: OrphanPolicy,
+
+    // Market-wide status that new books start from
+    market_status: TradingStatus,
+    circuit_breakers: CircuitBreakers,
+}
+
+impl BookSet {
+    pub fn new() -> Self {
+        BookSet {
+            books: Vec::new(),
+            directory: StockDirectory::new(),
+            capacity_hints: FxHashMap::default(),
+            default_hint: CapacityHint::default(),
+            orphan_policy: OrphanPolicy::default(),
+            market_status: TradingStatus::default(),
+            circuit_breakers: CircuitBreakers::default(),
+        }
+    }
+
+    // Only affects books created after the hint is set
+    pub fn set_capacity_hint(&mut self, symbol: &str, hint: CapacityHint) {
+        self.capacity_hints.insert(normalize_symbol(symbol), hint);
+    }
+
+    pub fn set_default_capacity_hint(&mut self, hint: CapacityHint) {
+        self.default_hint = hint;
+    }
+
+    // Sets the policy of every book, including ones created later
+    pub fn set_orphan_policy(&mut self, policy: OrphanPolicy) {
+        self.orphan_policy = policy;
+        for book in self.books.iter_mut().flatten() {
+            book.set_orphan_policy(policy);
+        }
+    }
+
+    // Orphaned messages across every book
+    pub fn orphan_stats(&self) -> OrphanStats {
+        let mut stats = OrphanStats::default();
+        for (_, book) in self.iter() {
+            stats.merge(book.orphan_stats());
+        }
+        stats
+    }
+
+    // Books in the set, not counting removed ones
+    pub fn len(&self) -> usize {
+        self.books.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.iter().flatten().next().is_none()
+    }
+
+    pub fn directory(&self) -> &StockDirectory {
+        &self.directory
+    }
+
+    pub fn circuit_breakers(&self) -> &CircuitBreakers {
+        &self.circuit_breakers
+    }
+
+    pub fn locate(&self, symbol: &str) -> Option<u16> {
+        self.directory.locate(symbol)
+    }
+
+    pub fn get(&self, stock_locate: u16) -> Option<&OrderBook> {
+        self.books.get(stock_locate as usize)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, stock_locate: u16) -> Option<&mut OrderBook> {
+        self.books.get_mut(stock_locate as usize)?.as_mut()
+    }
+
+    pub fn get_by_symbol(&self, symbol: &str) -> Option<&OrderBook> {
+        self.get(self.locate(symbol)?)
+    }
+
+    pub fn get_by_symbol_mut(&mut self, symbol: &str) -> Option<&mut OrderBook> {
+        self.get_mut(self.locate(symbol)?)
+    }
+
+    // Takes a book out of the set, later messages for the locate are ignored
+    pub fn remove(&mut self, stock_locate: u16) -> Option<OrderBook> {
+        self.books.get_mut(stock_locate as usize)?.take()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &OrderBook)> {
+        self.books
+            .iter()
+            .enumerate()
+            .filter_map(|(locate, book)| Some((locate as u16, book.as_ref()?)))
+    }
+
+    // Marks every book as possibly missing messages. Feed gaps don't say
+    // which stock_locates they covered.
+    pub fn mark_stale(&mut self) {
+        for book in self.books.iter_mut().flatten() {
+            book.set_stale(true);
+        }
+    }
+
+    pub fn clear_stale(&mut self) {
+        for book in self.books.iter_mut().flatten() {
+            book.set_stale(false);
+        }
+    }
+
+    pub fn stale_books(&self) -> impl Iterator<Item = (u16, &OrderBook)> {
+        self.iter().filter(|(_, book)| book.is_stale())
+    }
+
+    // Lists a symbol without a Stock Directory message, for feeds that
+    // describe instruments differently
+    pub fn add_listing(&mut self, info: StockInfo) {
+        let info = self.directory.insert(info).clone();
+        self.add_book(&info);
+    }
+
+    pub fn apply(&mut self, msg: &Message) -> ApplyOutcome {
+        self.apply_with(msg, &mut ())
+    }
+
+    // Routes a message to the book for its stock_locate. Order messages for
+    // locates without a Stock Directory entry are ignored.
+    pub fn apply_with<H: ItchHandler>(&mut self, msg: &Message, handler: &mut H) -> ApplyOutcome {
+        if let Some(info) = self.directory.update(msg) {
+            let info = info.clone();
+            self.add_book(&info);
+            return ApplyOutcome::Ignored;
+        }
+
+        if itch::is_market_wide_tag(msg.tag) {
+            self.circuit_breakers.on_message(msg);
+            self.market_status.on_message(&msg.body);
+            for book in self.books.iter_mut().flatten() {
+                itch::apply_with(book, msg, handler);
+            }
+            return ApplyOutcome::StatusUpdated;
+        }
+
+        let Some(book) = self.get_mut(msg.stock_locate) else {
+            return ApplyOutcome::Ignored;
+        };
+        itch::apply_with(book, msg, handler)
+    }
+
+    // Same routing for a decoded message. Order messages go straight to their
+    // book, everything else is rare enough to take the Message path.
+    pub fn apply_view(&mut self, view: &MessageView) -> ApplyOutcome {
+        if itch::is_order_tag(view.tag()) {
+            return match self.get_mut(view.stock_locate()) {
+                Some(book) => itch::apply_view(book, view),
+                None => ApplyOutcome::Ignored,
+            };
+        }
+
+        match view.to_message() {
+            Some(msg) => self.apply(&msg),
+            None => ApplyOutcome::Ignored,
+        }
+    }
+
+    // Repeated directory messages for a locate update its book's config,
+    // keeping its orders
+    fn add_book(&mut self, info: &StockInfo) {
+        if let Some(book) = self.get_mut(info.stock_locate) {
+            book.set_config(BookConfig::from(info));
+            return;
+        }
+
+        let hint = self
+            .capacity_hints
+            .get(&normalize_symbol(&info.symbol))
+            .copied()
+            .unwrap_or(self.default_hint);
+
+        let mut book = OrderBook::with_capacity(hint.price_levels, hint.orders);
+        book.set_config(BookConfig::from(info));
+        book.set_orphan_policy(self.orphan_policy);
+        *book.trading_status_mut() = self.market_status.clone();
+
+        let idx = info.stock_locate as usize;
+        if self.books.len() <= idx {
+            self.books.resize_with(idx + 1, || None);
+        }
+        self.books[idx] = Some(book);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderSide;
+
+    #[test]
+    fn relisting_keeps_the_book() {
+        let mut books = BookSet::new();
+        books.add_listing(StockInfo::new(1, "AAPL", 0));
+        books
+            .get_mut(1)
+            .unwrap()
+            .add_order(7, 1_000_000, 100, OrderSide::Buy);
+
+        let mut info = StockInfo::new(1, "AAPL", 1);
+        info.round_lot_size = 10;
+        books.add_listing(info);
+
+        let book = books.get(1).unwrap();
+        assert_eq!(book.order(7), Some((1_000_000, 100, OrderSide::Buy)));
+        assert_eq!(book.config().round_lot_size, 10);
+        assert_eq!(books.len(), 1);
+    }
+
+    #[test]
+    fn relisting_under_a_new_symbol() {
+        let mut books = BookSet::new();
+        books.add_listing(StockInfo::new(1, "FB", 0));
+        books.add_listing(StockInfo::new(1, "META", 1));
+
+        assert_eq!(books.locate("FB"), None);
+        assert_eq!(books.locate("META"), Some(1));
+        assert_eq!(books.get_by_symbol("META").unwrap().symbol(), "META");
+        assert_eq!(books.directory().len(), 1);
+    }
+
+    #[test]
+    fn len_counts_books() {
+        let mut books = BookSet::new();
+        books.add_listing(StockInfo::new(1, "AAPL", 0));
+        books.add_listing(StockInfo::new(3, "MSFT", 0));
+        assert_eq!(books.len(), 2);
+
+        books.remove(1);
+        assert_eq!(books.len(), 1);
+        assert!(!books.is_empty());
+        books.remove(3);
+        assert!(books.is_empty());
+        assert_eq!(books.directory().len(), 2);
+    }
+}
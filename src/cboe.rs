@@ -0,0 +1,1148 @@
+// Cboe US equities Multicast PITCH (BZX, BYX, EDGX, EDGA). Each UDP packet is
+// a Sequenced Unit Header followed by messages that start with their length
+// and type. Fields are little-endian. Timestamps are split between Time
+// messages, which give the seconds since midnight for a unit, and a
+// nanosecond offset on every other message.
+//
+// Symbols are given stock locates as they're first seen, and orders are
+// applied as OrderEvents so the same books and analytics run on Cboe data.
+// Modifies keep the order's queue position only when Cboe's priority rules
+// allow it, see PitchRouter::modify.
+
+use std::fmt;
+
+use itchy::{ArrayString4, ArrayString8, EventCode, RegShoAction};
+use rustc_hash::FxHashMap;
+
+use crate::bookset::BookSet;
+use crate::directory::StockInfo;
+use crate::itch::{self, ApplyOutcome, OrderEvent};
+use crate::orderbook::{OrderBook, OrderSide};
+use crate::seek::NANOS_PER_SECOND;
+use crate::trading::StateChange;
+
+// Length (u16), message count (u8), unit (u8) and sequence number of the
+// first message (u32)
+pub const HEADER_LEN: usize = 8;
+
+pub const TIME: u8 = 0x20;
+pub const ADD_ORDER_LONG: u8 = 0x21;
+pub const ADD_ORDER_SHORT: u8 = 0x22;
+pub const ORDER_EXECUTED: u8 = 0x23;
+pub const ORDER_EXECUTED_AT_PRICE: u8 = 0x24;
+pub const REDUCE_SIZE_LONG: u8 = 0x25;
+pub const REDUCE_SIZE_SHORT: u8 = 0x26;
+pub const MODIFY_ORDER_LONG: u8 = 0x27;
+pub const MODIFY_ORDER_SHORT: u8 = 0x28;
+pub const DELETE_ORDER: u8 = 0x29;
+pub const TRADE_LONG: u8 = 0x2A;
+pub const TRADE_SHORT: u8 = 0x2B;
+pub const END_OF_SESSION: u8 = 0x2D;
+pub const ADD_ORDER_EXPANDED: u8 = 0x2F;
+pub const TRADE_EXPANDED: u8 = 0x30;
+pub const TRADING_STATUS: u8 = 0x31;
+pub const UNIT_CLEAR: u8 = 0x97;
+
+// Bit 1 of the Modify Order flags
+const MAINTAIN_PRIORITY: u8 = 0x02;
+
+// Shortest valid length of each message type. Cboe may append fields, so
+// longer messages are accepted and the extra bytes ignored
+pub fn min_message_len(kind: u8) -> Option<usize> {
+    Some(match kind {
+        TIME => 6,
+        ADD_ORDER_LONG => 34,
+        ADD_ORDER_SHORT => 26,
+        ADD_ORDER_EXPANDED => 40,
+        ORDER_EXECUTED => 26,
+        ORDER_EXECUTED_AT_PRICE => 38,
+        REDUCE_SIZE_LONG => 18,
+        REDUCE_SIZE_SHORT => 16,
+        MODIFY_ORDER_LONG => 27,
+        MODIFY_ORDER_SHORT => 19,
+        DELETE_ORDER => 14,
+        TRADE_LONG => 41,
+        TRADE_SHORT => 33,
+        TRADE_EXPANDED => 43,
+        TRADING_STATUS => 18,
+        END_OF_SESSION => 6,
+        UNIT_CLEAR => 6,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum PitchError {
+    // Shorter than the header, or the messages don't add up to the header's
+    // length and count
+    Truncated,
+    // A known message type shorter than its fields
+    BadLength { kind: u8, len: usize },
+    // Side other than B or S
+    BadSide(u8),
+}
+
+impl fmt::Display for PitchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PitchError::Truncated => write!(f, "truncated PITCH packet"),
+            PitchError::BadLength { kind, len } => {
+                write!(f, "PITCH message {kind:#04x} is too short ({len} bytes)")
+            }
+            PitchError::BadSide(side) => write!(f, "invalid PITCH side {side:#04x}"),
+        }
+    }
+}
+
+impl std::error::Error for PitchError {}
+
+fn le_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+// Space padded alphanumeric field, trimmed
+fn alpha(b: &[u8], at: usize, len: usize) -> &str {
+    std::str::from_utf8(&b[at..at + len])
+        .unwrap_or("")
+        .trim_end()
+}
+
+fn side(b: u8) -> Result<OrderSide, PitchError> {
+    match b {
+        b'B' => Ok(OrderSide::Buy),
+        b'S' => Ok(OrderSide::Sell),
+        _ => Err(PitchError::BadSide(b)),
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Packet<'a> {
+    pub unit: u8,
+    // Zero for unsequenced packets, e.g. heartbeats
+    pub sequence: u32,
+    pub count: u8,
+    messages: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Self, PitchError> {
+        if buf.len() < HEADER_LEN {
+            return Err(PitchError::Truncated);
+        }
+        let len = le_u16(buf, 0) as usize;
+        if len < HEADER_LEN || len > buf.len() {
+            return Err(PitchError::Truncated);
+        }
+
+        let packet = Packet {
+            count: buf[2],
+            unit: buf[3],
+            sequence: le_u32(buf, 4),
+            messages: &buf[HEADER_LEN..len],
+        };
+
+        // Check the framing up front so a short packet can't be half applied
+        let mut messages = 0;
+        let mut pos = 0;
+        while pos < packet.messages.len() {
+            let len = packet.messages[pos] as usize;
+            if len < 2 {
+                return Err(PitchError::Truncated);
+            }
+            pos += len;
+            messages += 1;
+        }
+        if pos != packet.messages.len() || messages != packet.count as usize {
+            return Err(PitchError::Truncated);
+        }
+        Ok(packet)
+    }
+
+    pub fn is_sequenced(&self) -> bool {
+        self.sequence != 0
+    }
+
+    // Sequence number after the last message in the packet
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence.wrapping_add(self.count as u32)
+    }
+
+    pub fn messages(&self) -> Messages<'a> {
+        Messages {
+            buf: self.messages,
+            pos: 0,
+        }
+    }
+}
+
+// The messages of a packet, each decoded as it's reached
+#[derive(Debug, Clone)]
+pub struct Messages<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<PitchMessage, PitchError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let len = *self.buf.get(self.pos)? as usize;
+        let end = (self.pos + len.max(2)).min(self.buf.len());
+        let msg = &self.buf[self.pos..end];
+        self.pos = end;
+        Some(PitchMessage::parse(msg))
+    }
+}
+
+// A decoded message. Prices have 4 implied decimals, as in ITCH, and short
+// form quantities and prices are widened to the long form.
+#[derive(Debug, PartialEq, Clone)]
+pub enum PitchMessage {
+    Time {
+        seconds: u32,
+    },
+    AddOrder {
+        offset: u32,
+        order_id: u64,
+        side: OrderSide,
+        quantity: u32,
+        symbol: ArrayString8,
+        price: u64,
+        // Only on Add Order Expanded messages, for attributed orders
+        participant: Option<ArrayString4>,
+    },
+    OrderExecuted {
+        offset: u32,
+        order_id: u64,
+        executed: u32,
+        execution_id: u64,
+    },
+    // Executed at a price other than the order's, with the order's
+    // remaining size after the execution
+    OrderExecutedAtPrice {
+        offset: u32,
+        order_id: u64,
+        executed: u32,
+        remaining: u32,
+        execution_id: u64,
+        price: u64,
+    },
+    ReduceSize {
+        offset: u32,
+        order_id: u64,
+        cancelled: u32,
+    },
+    // The order's new size and price. maintain_priority is Cboe's flag for
+    // whether the order kept its place in the queue
+    ModifyOrder {
+        offset: u32,
+        order_id: u64,
+        quantity: u32,
+        price: u64,
+        maintain_priority: bool,
+    },
+    DeleteOrder {
+        offset: u32,
+        order_id: u64,
+    },
+    // An execution against a non-displayed order
+    Trade {
+        offset: u32,
+        order_id: u64,
+        side: OrderSide,
+        quantity: u32,
+        symbol: ArrayString8,
+        price: u64,
+        execution_id: u64,
+    },
+    TradingStatus {
+        offset: u32,
+        symbol: ArrayString8,
+        status: u8,
+        reg_sho_action: u8,
+    },
+    EndOfSession {
+        offset: u32,
+    },
+    // Every order on the unit is gone, e.g. after a unit restart
+    UnitClear {
+        offset: u32,
+    },
+    // Types the books don't use, e.g. auction updates
+    Other {
+        kind: u8,
+    },
+}
+
+impl PitchMessage {
+    // b starts with the length byte and is exactly one message long
+    pub fn parse(b: &[u8]) -> Result<Self, PitchError> {
+        let (Some(&len), Some(&kind)) = (b.first(), b.get(1)) else {
+            return Err(PitchError::Truncated);
+        };
+        if len as usize != b.len() {
+            return Err(PitchError::Truncated);
+        }
+        let Some(min_len) = min_message_len(kind) else {
+            return Ok(PitchMessage::Other { kind });
+        };
+        if b.len() < min_len {
+            return Err(PitchError::BadLength { kind, len: b.len() });
+        }
+
+        let symbol =
+            |at: usize, len: usize| ArrayString8::from(alpha(b, at, len)).unwrap_or_default();
+        let offset = le_u32(b, 2);
+        match kind {
+            TIME => return Ok(PitchMessage::Time { seconds: offset }),
+            END_OF_SESSION => return Ok(PitchMessage::EndOfSession { offset }),
+            UNIT_CLEAR => return Ok(PitchMessage::UnitClear { offset }),
+            _ => {}
+        }
+        let order_id = le_u64(b, 6);
+        // Short prices have 2 implied decimals
+        let short_price = |at: usize| le_u16(b, at) as u64 * 100;
+
+        Ok(match kind {
+            ADD_ORDER_LONG => PitchMessage::AddOrder {
+                offset,
+                order_id,
+                side: side(b[14])?,
+                quantity: le_u32(b, 15),
+                symbol: symbol(19, 6),
+                price: le_u64(b, 25),
+                participant: None,
+            },
+            ADD_ORDER_SHORT => PitchMessage::AddOrder {
+                offset,
+                order_id,
+                side: side(b[14])?,
+                quantity: le_u16(b, 15) as u32,
+                symbol: symbol(17, 6),
+                price: short_price(23),
+                participant: None,
+            },
+            ADD_ORDER_EXPANDED => PitchMessage::AddOrder {
+                offset,
+                order_id,
+                side: side(b[14])?,
+                quantity: le_u32(b, 15),
+                symbol: symbol(19, 8),
+                price: le_u64(b, 27),
+                participant: Some(alpha(b, 36, 4))
+                    .filter(|p| !p.is_empty())
+                    .and_then(|p| ArrayString4::from(p).ok()),
+            },
+            ORDER_EXECUTED => PitchMessage::OrderExecuted {
+                offset,
+                order_id,
+                executed: le_u32(b, 14),
+                execution_id: le_u64(b, 18),
+            },
+            ORDER_EXECUTED_AT_PRICE => PitchMessage::OrderExecutedAtPrice {
+                offset,
+                order_id,
+                executed: le_u32(b, 14),
+                remaining: le_u32(b, 18),
+                execution_id: le_u64(b, 22),
+                price: le_u64(b, 30),
+            },
+            REDUCE_SIZE_LONG => PitchMessage::ReduceSize {
+                offset,
+                order_id,
+                cancelled: le_u32(b, 14),
+            },
+            REDUCE_SIZE_SHORT => PitchMessage::ReduceSize {
+                offset,
+                order_id,
+                cancelled: le_u16(b, 14) as u32,
+            },
+            MODIFY_ORDER_LONG => PitchMessage::ModifyOrder {
+                offset,
+                order_id,
+                quantity: le_u32(b, 14),
+                price: le_u64(b, 18),
+                maintain_priority: b[26] & MAINTAIN_PRIORITY != 0,
+            },
+            MODIFY_ORDER_SHORT => PitchMessage::ModifyOrder {
+                offset,
+                order_id,
+                quantity: le_u16(b, 14) as u32,
+                price: short_price(16),
+                maintain_priority: b[18] & MAINTAIN_PRIORITY != 0,
+            },
+            DELETE_ORDER => PitchMessage::DeleteOrder { offset, order_id },
+            TRADE_LONG => PitchMessage::Trade {
+                offset,
+                order_id,
+                side: side(b[14])?,
+                quantity: le_u32(b, 15),
+                symbol: symbol(19, 6),
+                price: le_u64(b, 25),
+                execution_id: le_u64(b, 33),
+            },
+            TRADE_SHORT => PitchMessage::Trade {
+                offset,
+                order_id,
+                side: side(b[14])?,
+                quantity: le_u16(b, 15) as u32,
+                symbol: symbol(17, 6),
+                price: short_price(23),
+                execution_id: le_u64(b, 25),
+            },
+            TRADE_EXPANDED => PitchMessage::Trade {
+                offset,
+                order_id,
+                side: side(b[14])?,
+                quantity: le_u32(b, 15),
+                symbol: symbol(19, 8),
+                price: le_u64(b, 27),
+                execution_id: le_u64(b, 35),
+            },
+            TRADING_STATUS => PitchMessage::TradingStatus {
+                offset,
+                symbol: symbol(6, 8),
+                status: b[14],
+                reg_sho_action: b[15],
+            },
+            _ => unreachable!(),
+        })
+    }
+
+    // Nanoseconds past the unit's last Time message. None for Time messages
+    // and types the books don't use
+    pub fn offset(&self) -> Option<u32> {
+        match *self {
+            PitchMessage::Time { .. } | PitchMessage::Other { .. } => None,
+            PitchMessage::AddOrder { offset, .. }
+            | PitchMessage::OrderExecuted { offset, .. }
+            | PitchMessage::OrderExecutedAtPrice { offset, .. }
+            | PitchMessage::ReduceSize { offset, .. }
+            | PitchMessage::ModifyOrder { offset, .. }
+            | PitchMessage::DeleteOrder { offset, .. }
+            | PitchMessage::Trade { offset, .. }
+            | PitchMessage::TradingStatus { offset, .. }
+            | PitchMessage::EndOfSession { offset }
+            | PitchMessage::UnitClear { offset } => Some(offset),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct PitchStats {
+    pub packets: u64,
+    pub messages: u64,
+    // Messages already applied from the other feed of an A/B pair
+    pub duplicates: u64,
+    pub gaps: u64,
+    pub lost: u64,
+    // Packets with bad framing and messages too short for their type
+    pub malformed: u64,
+    // Executions, reduces, modifies and deletes for orders not on any book
+    pub unknown_orders: u64,
+    // Modifies applied in place, keeping the order's queue position
+    pub modifies_in_place: u64,
+    // Modifies applied as a replace, sending the order to the back of the queue
+    pub modifies_requeued: u64,
+    // Modifies where Cboe's Maintain Priority flag disagreed with its
+    // priority rules. The rules win
+    pub priority_mismatches: u64,
+    // Prices too large for the book's 32 bit prices
+    pub clamped: u64,
+    pub unit_clears: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct PitchRouter {
+    // Seconds from the last Time message, per unit
+    seconds: FxHashMap<u8, u64>,
+    next_sequence: FxHashMap<u8, u32>,
+    locates: FxHashMap<ArrayString8, u16>,
+    // Unit of each listed locate. Cboe splits symbols between units
+    units: FxHashMap<u16, u8>,
+    // Live orders: order id -> stock_locate
+    orders: FxHashMap<u64, u16>,
+    stats: PitchStats,
+}
+
+impl PitchRouter {
+    pub fn new() -> Self {
+        PitchRouter::default()
+    }
+
+    pub fn stats(&self) -> &PitchStats {
+        &self.stats
+    }
+
+    // Locate assigned to a symbol, 0 if it hasn't been seen
+    pub fn locate(&self, symbol: &str) -> u16 {
+        ArrayString8::from(symbol)
+            .ok()
+            .and_then(|symbol| self.locates.get(&symbol).copied())
+            .unwrap_or(0)
+    }
+
+    // Applies the messages of a packet that haven't been applied yet, in
+    // order. A gap in a unit's sequence marks its books stale until the
+    // unit is cleared. on_applied sees each message with its timestamp.
+    pub fn apply_packet<F>(
+        &mut self,
+        books: &mut BookSet,
+        buf: &[u8],
+        mut on_applied: F,
+    ) -> Result<(), PitchError>
+    where
+        F: FnMut(u64, &PitchMessage, ApplyOutcome),
+    {
+        let packet = Packet::parse(buf).inspect_err(|_| self.stats.malformed += 1)?;
+        self.stats.packets += 1;
+
+        let mut skip = 0;
+        if packet.is_sequenced() && packet.count > 0 {
+            let next = *self
+                .next_sequence
+                .entry(packet.unit)
+                .or_insert(packet.sequence);
+            if packet.next_sequence() <= next {
+                self.stats.duplicates += packet.count as u64;
+                return Ok(());
+            }
+            if packet.sequence > next {
+                self.stats.gaps += 1;
+                self.stats.lost += (packet.sequence - next) as u64;
+                self.mark_stale(books, packet.unit);
+            } else {
+                skip = (next - packet.sequence) as usize;
+                self.stats.duplicates += skip as u64;
+            }
+            self.next_sequence
+                .insert(packet.unit, packet.next_sequence());
+        }
+
+        for msg in packet.messages().skip(skip) {
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(_) => {
+                    self.stats.malformed += 1;
+                    continue;
+                }
+            };
+            let outcome = self.apply(books, packet.unit, &msg);
+            on_applied(self.timestamp(packet.unit, &msg), &msg, outcome);
+        }
+        Ok(())
+    }
+
+    // Nanoseconds since midnight
+    pub fn timestamp(&self, unit: u8, msg: &PitchMessage) -> u64 {
+        let seconds = match msg {
+            PitchMessage::Time { seconds } => return *seconds as u64 * NANOS_PER_SECOND,
+            _ => self.seconds.get(&unit).copied().unwrap_or(0),
+        };
+        seconds * NANOS_PER_SECOND + msg.offset().unwrap_or(0) as u64
+    }
+
+    // Applies one message from a unit, without sequence checks
+    pub fn apply(&mut self, books: &mut BookSet, unit: u8, msg: &PitchMessage) -> ApplyOutcome {
+        self.stats.messages += 1;
+        let timestamp = self.timestamp(unit, msg);
+
+        match *msg {
+            PitchMessage::Time { seconds } => {
+                self.seconds.insert(unit, seconds as u64);
+                ApplyOutcome::Ignored
+            }
+            PitchMessage::AddOrder {
+                order_id,
+                side,
+                quantity,
+                symbol,
+                price,
+                participant,
+                ..
+            } => {
+                let Some(locate) = self.listing(books, unit, symbol, timestamp) else {
+                    return ApplyOutcome::Ignored;
+                };
+                let price = self.price(price);
+                let Some(book) = books.get_mut(locate) else {
+                    return ApplyOutcome::Ignored;
+                };
+                self.orders.insert(order_id, locate);
+                itch::apply_event(
+                    book,
+                    OrderEvent::Add {
+                        reference: order_id,
+                        side,
+                        shares: quantity,
+                        price,
+                        mpid: participant,
+                    },
+                )
+            }
+            PitchMessage::OrderExecuted {
+                order_id, executed, ..
+            } => self.on_order(books, order_id, |book, shares| {
+                itch::apply_event(
+                    book,
+                    OrderEvent::Executed {
+                        reference: order_id,
+                        executed: executed.min(shares),
+                    },
+                )
+            }),
+            PitchMessage::OrderExecutedAtPrice {
+                order_id,
+                executed,
+                remaining,
+                ..
+            } => self.on_order(books, order_id, |book, shares| {
+                let outcome = itch::apply_event(
+                    book,
+                    OrderEvent::ExecutedWithPrice {
+                        reference: order_id,
+                        executed: executed.min(shares),
+                        printable: true,
+                    },
+                );
+                // The rest of the order may have been cancelled along with
+                // the execution
+                let left = shares.saturating_sub(executed);
+                if remaining < left {
+                    itch::apply_event(
+                        book,
+                        OrderEvent::Cancelled {
+                            reference: order_id,
+                            cancelled: left - remaining,
+                        },
+                    );
+                }
+                outcome
+            }),
+            PitchMessage::ReduceSize {
+                order_id,
+                cancelled,
+                ..
+            } => self.on_order(books, order_id, |book, shares| {
+                itch::apply_event(
+                    book,
+                    OrderEvent::Cancelled {
+                        reference: order_id,
+                        cancelled: cancelled.min(shares),
+                    },
+                )
+            }),
+            PitchMessage::ModifyOrder {
+                order_id,
+                quantity,
+                price,
+                maintain_priority,
+                ..
+            } => {
+                let price = self.price(price);
+                self.modify(books, order_id, quantity, price, maintain_priority)
+            }
+            PitchMessage::DeleteOrder { order_id, .. } => {
+                self.on_order(books, order_id, |book, _| {
+                    itch::apply_event(
+                        book,
+                        OrderEvent::Deleted {
+                            reference: order_id,
+                        },
+                    )
+                })
+            }
+            PitchMessage::Trade { .. } => ApplyOutcome::Ignored,
+            PitchMessage::TradingStatus {
+                symbol,
+                status,
+                reg_sho_action,
+                ..
+            } => {
+                let Some(book) = self
+                    .listing(books, unit, symbol, timestamp)
+                    .and_then(|locate| books.get_mut(locate))
+                else {
+                    return ApplyOutcome::Ignored;
+                };
+                trading_status(book, timestamp, status, reg_sho_action)
+            }
+            PitchMessage::EndOfSession { .. } => {
+                for locate in self.unit_locates(unit) {
+                    if let Some(book) = books.get_mut(locate) {
+                        book.trading_status_mut()
+                            .on_system_event(EventCode::EndOfMessages);
+                    }
+                }
+                ApplyOutcome::StatusUpdated
+            }
+            PitchMessage::UnitClear { .. } => {
+                self.stats.unit_clears += 1;
+                for locate in self.unit_locates(unit) {
+                    if let Some(book) = books.get_mut(locate) {
+                        book.clear();
+                        book.set_stale(false);
+                    }
+                }
+                let units = &self.units;
+                self.orders
+                    .retain(|_, locate| units.get(locate) != Some(&unit));
+                ApplyOutcome::Cleared
+            }
+            PitchMessage::Other { .. } => ApplyOutcome::Ignored,
+        }
+    }
+
+    // Cboe keeps an order's priority when its size is reduced at the same
+    // price. A price change or a size increase sends it to the back of the
+    // queue, which is applied as a replace that keeps the order id. The
+    // Maintain Priority flag should agree, mismatches are counted.
+    fn modify(
+        &mut self,
+        books: &mut BookSet,
+        order_id: u64,
+        quantity: u32,
+        price: u32,
+        maintain_priority: bool,
+    ) -> ApplyOutcome {
+        let Some((book, (old_price, shares, _))) = self.order_book(books, order_id) else {
+            return ApplyOutcome::Ignored;
+        };
+
+        let in_place = price == old_price && quantity <= shares;
+        if in_place != maintain_priority {
+            self.stats.priority_mismatches += 1;
+        }
+
+        let outcome = if quantity == 0 {
+            itch::apply_event(
+                book,
+                OrderEvent::Deleted {
+                    reference: order_id,
+                },
+            )
+        } else if in_place {
+            self.stats.modifies_in_place += 1;
+            if quantity == shares {
+                // Only the flags changed
+                return ApplyOutcome::Ignored;
+            }
+            itch::apply_event(
+                book,
+                OrderEvent::Cancelled {
+                    reference: order_id,
+                    cancelled: shares - quantity,
+                },
+            )
+        } else {
+            self.stats.modifies_requeued += 1;
+            itch::apply_event(
+                book,
+                OrderEvent::Replaced {
+                    old_reference: order_id,
+                    new_reference: order_id,
+                    shares: quantity,
+                    price,
+                },
+            )
+        };
+
+        if quantity == 0 {
+            self.orders.remove(&order_id);
+        }
+        outcome
+    }
+
+    // Runs f on the book holding a live order, with the order's size.
+    // Forgets the order once it's gone from the book
+    fn on_order<F>(&mut self, books: &mut BookSet, order_id: u64, f: F) -> ApplyOutcome
+    where
+        F: FnOnce(&mut OrderBook, u32) -> ApplyOutcome,
+    {
+        let Some((book, (_, shares, _))) = self.order_book(books, order_id) else {
+            return ApplyOutcome::Ignored;
+        };
+        let outcome = f(book, shares);
+        if book.order(order_id).is_none() {
+            self.orders.remove(&order_id);
+        }
+        outcome
+    }
+
+    fn order_book<'b>(
+        &mut self,
+        books: &'b mut BookSet,
+        order_id: u64,
+    ) -> Option<(&'b mut OrderBook, (u32, u32, OrderSide))> {
+        let found = self
+            .orders
+            .get(&order_id)
+            .and_then(|locate| books.get_mut(*locate))
+            .and_then(|book| book.order(order_id).map(|order| (book, order)));
+        if found.is_none() {
+            self.orders.remove(&order_id);
+            self.stats.unknown_orders += 1;
+        }
+        found
+    }
+
+    // The symbol's locate, listing it on first sight. None once the
+    // locates run out
+    fn listing(
+        &mut self,
+        books: &mut BookSet,
+        unit: u8,
+        symbol: ArrayString8,
+        timestamp: u64,
+    ) -> Option<u16> {
+        if let Some(locate) = self.locates.get(&symbol) {
+            return Some(*locate);
+        }
+        let locate = u16::try_from(self.locates.len() + 1).ok()?;
+        self.locates.insert(symbol, locate);
+        self.units.insert(locate, unit);
+        books.add_listing(StockInfo::new(locate, &symbol, timestamp));
+        Some(locate)
+    }
+
+    fn unit_locates(&self, unit: u8) -> Vec<u16> {
+        self.units
+            .iter()
+            .filter(|(_, u)| **u == unit)
+            .map(|(locate, _)| *locate)
+            .collect()
+    }
+
+    fn mark_stale(&self, books: &mut BookSet, unit: u8) {
+        for locate in self.unit_locates(unit) {
+            if let Some(book) = books.get_mut(locate) {
+                book.set_stale(true);
+            }
+        }
+    }
+
+    fn price(&mut self, price: u64) -> u32 {
+        u32::try_from(price).unwrap_or_else(|_| {
+            self.stats.clamped += 1;
+            u32::MAX
+        })
+    }
+}
+
+// Halt status is H (halted), Q (quote only), S (suspended by the exchange),
+// A (accepting orders for queuing) or T (trading). PITCH has no market-wide
+// session events, so T opens the session for the symbol.
+fn trading_status(book: &mut OrderBook, timestamp: u64, status: u8, reg_sho: u8) -> ApplyOutcome {
+    let from = book.trading_state();
+    let trading = book.trading_status_mut();
+    trading.on_reg_sho(if reg_sho == b'1' {
+        RegShoAction::Intraday
+    } else {
+        RegShoAction::None
+    });
+
+    let action = match status {
+        b'H' | b'S' => itchy::TradingState::Halted,
+        b'Q' | b'A' => itchy::TradingState::QuotationOnly,
+        b'T' => {
+            trading.on_system_event(EventCode::StartOfMarketHours);
+            itchy::TradingState::Trading
+        }
+        _ => return ApplyOutcome::Ignored,
+    };
+    trading.on_trading_action(action, ArrayString4::new());
+
+    let to = book.trading_state();
+    if from == to {
+        return ApplyOutcome::StatusUpdated;
+    }
+    ApplyOutcome::StateChanged(StateChange {
+        stock_locate: book.config().stock_locate,
+        timestamp,
+        from,
+        to,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A zeroed message of a type at its shortest length, filled in at spec
+    // offsets
+    struct Msg(Vec<u8>);
+
+    impl Msg {
+        fn new(kind: u8) -> Self {
+            Msg::with_len(kind, min_message_len(kind).unwrap())
+        }
+
+        fn with_len(kind: u8, len: usize) -> Self {
+            let mut b = vec![0; len];
+            b[0] = len as u8;
+            b[1] = kind;
+            b[2..6].copy_from_slice(&500u32.to_le_bytes());
+            Msg(b)
+        }
+
+        fn put(mut self, at: usize, bytes: &[u8]) -> Self {
+            self.0[at..at + bytes.len()].copy_from_slice(bytes);
+            self
+        }
+
+        fn parse(&self) -> PitchMessage {
+            PitchMessage::parse(&self.0).unwrap()
+        }
+    }
+
+    fn symbol(s: &str) -> ArrayString8 {
+        ArrayString8::from(s).unwrap()
+    }
+
+    #[test]
+    fn add_orders_long_and_short() {
+        let long = Msg::new(ADD_ORDER_LONG)
+            .put(6, &7u64.to_le_bytes())
+            .put(14, b"B")
+            .put(15, &70_000u32.to_le_bytes())
+            .put(19, b"AAPL  ")
+            .put(25, &1_502_500u64.to_le_bytes());
+        let short = Msg::new(ADD_ORDER_SHORT)
+            .put(6, &7u64.to_le_bytes())
+            .put(14, b"B")
+            .put(15, &700u16.to_le_bytes())
+            .put(17, b"AAPL  ")
+            .put(23, &15_025u16.to_le_bytes());
+        let expected = |quantity| PitchMessage::AddOrder {
+            offset: 500,
+            order_id: 7,
+            side: OrderSide::Buy,
+            quantity,
+            symbol: symbol("AAPL"),
+            price: 1_502_500,
+            participant: None,
+        };
+        assert_eq!(long.parse(), expected(70_000));
+        // Short prices have 2 decimals
+        assert_eq!(short.parse(), expected(700));
+
+        let expanded = Msg::new(ADD_ORDER_EXPANDED)
+            .put(6, &8u64.to_le_bytes())
+            .put(14, b"S")
+            .put(15, &100u32.to_le_bytes())
+            .put(19, b"BRK.B   ")
+            .put(27, &3_500_000u64.to_le_bytes())
+            .put(36, b"CBOE");
+        let PitchMessage::AddOrder {
+            side,
+            symbol: parsed,
+            participant,
+            ..
+        } = expanded.parse()
+        else {
+            panic!("expected an add");
+        };
+        assert_eq!(side, OrderSide::Sell);
+        assert_eq!(parsed, symbol("BRK.B"));
+        assert_eq!(participant, Some(ArrayString4::from("CBOE").unwrap()));
+    }
+
+    #[test]
+    fn modifies_and_reduces_long_and_short() {
+        let long = Msg::new(MODIFY_ORDER_LONG)
+            .put(6, &7u64.to_le_bytes())
+            .put(14, &300u32.to_le_bytes())
+            .put(18, &1_500_000u64.to_le_bytes())
+            .put(26, &[MAINTAIN_PRIORITY]);
+        let short = Msg::new(MODIFY_ORDER_SHORT)
+            .put(6, &7u64.to_le_bytes())
+            .put(14, &300u16.to_le_bytes())
+            .put(16, &15_000u16.to_le_bytes());
+        let expected = |maintain_priority| PitchMessage::ModifyOrder {
+            offset: 500,
+            order_id: 7,
+            quantity: 300,
+            price: 1_500_000,
+            maintain_priority,
+        };
+        assert_eq!(long.parse(), expected(true));
+        assert_eq!(short.parse(), expected(false));
+
+        let long = Msg::new(REDUCE_SIZE_LONG)
+            .put(6, &7u64.to_le_bytes())
+            .put(14, &70_000u32.to_le_bytes());
+        let short = Msg::new(REDUCE_SIZE_SHORT)
+            .put(6, &7u64.to_le_bytes())
+            .put(14, &700u16.to_le_bytes());
+        assert!(matches!(
+            long.parse(),
+            PitchMessage::ReduceSize {
+                cancelled: 70_000,
+                ..
+            }
+        ));
+        assert!(matches!(
+            short.parse(),
+            PitchMessage::ReduceSize { cancelled: 700, .. }
+        ));
+    }
+
+    #[test]
+    fn trades_long_and_short() {
+        let long = Msg::new(TRADE_LONG)
+            .put(6, &9u64.to_le_bytes())
+            .put(14, b"S")
+            .put(15, &200u32.to_le_bytes())
+            .put(19, b"MSFT  ")
+            .put(25, &4_000_000u64.to_le_bytes())
+            .put(33, &11u64.to_le_bytes());
+        let short = Msg::new(TRADE_SHORT)
+            .put(6, &9u64.to_le_bytes())
+            .put(14, b"S")
+            .put(15, &200u16.to_le_bytes())
+            .put(17, b"MSFT  ")
+            .put(23, &40_000u16.to_le_bytes())
+            .put(25, &11u64.to_le_bytes());
+        assert_eq!(long.parse(), short.parse());
+        assert_eq!(long.parse().offset(), Some(500));
+    }
+
+    #[test]
+    fn parse_checks_lengths_and_sides() {
+        // Fields Cboe appends are ignored
+        let longer = Msg::with_len(DELETE_ORDER, 20).put(6, &7u64.to_le_bytes());
+        assert_eq!(
+            longer.parse(),
+            PitchMessage::DeleteOrder {
+                offset: 500,
+                order_id: 7,
+            }
+        );
+
+        let short = Msg::with_len(ADD_ORDER_LONG, 30);
+        assert_eq!(
+            PitchMessage::parse(&short.0),
+            Err(PitchError::BadLength {
+                kind: ADD_ORDER_LONG,
+                len: 30,
+            })
+        );
+        // The length byte must match the message
+        assert_eq!(
+            PitchMessage::parse(&Msg::new(DELETE_ORDER).0[..10]),
+            Err(PitchError::Truncated)
+        );
+        let bad_side = Msg::new(ADD_ORDER_SHORT).put(14, b"X");
+        assert_eq!(
+            PitchMessage::parse(&bad_side.0),
+            Err(PitchError::BadSide(b'X'))
+        );
+        assert_eq!(
+            Msg::with_len(0x95, 10).parse(),
+            PitchMessage::Other { kind: 0x95 }
+        );
+    }
+
+    fn add(order_id: u64, quantity: u32, price: u64) -> PitchMessage {
+        PitchMessage::AddOrder {
+            offset: 0,
+            order_id,
+            side: OrderSide::Buy,
+            quantity,
+            symbol: symbol("AAPL"),
+            price,
+            participant: None,
+        }
+    }
+
+    fn modify(order_id: u64, quantity: u32, price: u64, maintain_priority: bool) -> PitchMessage {
+        PitchMessage::ModifyOrder {
+            offset: 0,
+            order_id,
+            quantity,
+            price,
+            maintain_priority,
+        }
+    }
+
+    // Two orders of 100 at 150.00
+    fn router() -> (PitchRouter, BookSet) {
+        let mut router = PitchRouter::new();
+        let mut books = BookSet::new();
+        router.apply(&mut books, 1, &add(1, 100, 1_500_000));
+        router.apply(&mut books, 1, &add(2, 100, 1_500_000));
+        (router, books)
+    }
+
+    fn order(books: &BookSet, order_id: u64) -> Option<(u32, u32, OrderSide)> {
+        books.get_by_symbol("AAPL").unwrap().order(order_id)
+    }
+
+    #[test]
+    fn size_reductions_keep_priority() {
+        let (mut router, mut books) = router();
+        let outcome = router.apply(&mut books, 1, &modify(1, 60, 1_500_000, true));
+        assert_eq!(outcome, ApplyOutcome::Cancelled);
+        assert_eq!(order(&books, 1), Some((1_500_000, 60, OrderSide::Buy)));
+        assert_eq!(router.stats().modifies_in_place, 1);
+        assert_eq!(router.stats().modifies_requeued, 0);
+        assert_eq!(router.stats().priority_mismatches, 0);
+
+        // Only the flags changed
+        let outcome = router.apply(&mut books, 1, &modify(1, 60, 1_500_000, true));
+        assert_eq!(outcome, ApplyOutcome::Ignored);
+        assert_eq!(router.stats().modifies_in_place, 2);
+    }
+
+    #[test]
+    fn price_changes_and_increases_requeue() {
+        let (mut router, mut books) = router();
+        let outcome = router.apply(&mut books, 1, &modify(1, 100, 1_499_900, false));
+        assert_eq!(outcome, ApplyOutcome::Replaced);
+        assert_eq!(order(&books, 1), Some((1_499_900, 100, OrderSide::Buy)));
+
+        let outcome = router.apply(&mut books, 1, &modify(2, 150, 1_500_000, false));
+        assert_eq!(outcome, ApplyOutcome::Replaced);
+        assert_eq!(order(&books, 2), Some((1_500_000, 150, OrderSide::Buy)));
+
+        assert_eq!(router.stats().modifies_in_place, 0);
+        assert_eq!(router.stats().modifies_requeued, 2);
+        assert_eq!(router.stats().priority_mismatches, 0);
+    }
+
+    #[test]
+    fn priority_rules_override_the_flag() {
+        let (mut router, mut books) = router();
+        // Flagged as keeping priority, but the size went up
+        let outcome = router.apply(&mut books, 1, &modify(1, 200, 1_500_000, true));
+        assert_eq!(outcome, ApplyOutcome::Replaced);
+        // Flagged as losing priority, but only the size went down
+        let outcome = router.apply(&mut books, 1, &modify(2, 50, 1_500_000, false));
+        assert_eq!(outcome, ApplyOutcome::Cancelled);
+
+        assert_eq!(router.stats().modifies_in_place, 1);
+        assert_eq!(router.stats().modifies_requeued, 1);
+        assert_eq!(router.stats().priority_mismatches, 2);
+    }
+
+    #[test]
+    fn modify_to_zero_deletes() {
+        let (mut router, mut books) = router();
+        let outcome = router.apply(&mut books, 1, &modify(1, 0, 1_500_000, true));
+        assert_eq!(outcome, ApplyOutcome::Deleted);
+        assert_eq!(order(&books, 1), None);
+
+        // The order is forgotten
+        let outcome = router.apply(&mut books, 1, &modify(1, 10, 1_500_000, true));
+        assert_eq!(outcome, ApplyOutcome::Ignored);
+        assert_eq!(router.stats().unknown_orders, 1);
+    }
+}
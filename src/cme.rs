@@ -0,0 +1,1310 @@
+// CME MDP 3.0 market by order data. Each UDP packet is a sequence number and
+// sending time followed by SBE messages, each starting with its size and an
+// SBE header. Fields are little-endian. Two templates carry orders:
+// MDIncrementalRefreshOrderBook (47), which is only orders, and
+// MDIncrementalRefreshBook (46), whose price level entries may be followed by
+// order entries that point back at one of them for their price and side.
+//
+// Outright orders drive a book per instrument. Implied liquidity is only
+// published as price levels, so it goes to a second book per instrument
+// built with set_level, keeping the two apart. Prices are signed 64 bit with
+// nine decimals and are mapped into the books' 32 bit prices by a
+// PriceScale per instrument. Entries with prices the scale can't map are
+// rejected.
+
+use std::fmt;
+
+use rustc_hash::FxHashMap;
+
+use crate::bookset::BookSet;
+use crate::directory::StockInfo;
+use crate::itch::{self, ApplyOutcome, OrderEvent};
+use crate::orderbook::{OrderBook, OrderSide};
+
+// Sequence number (u32) and sending time (u64)
+pub const PACKET_HEADER_LEN: usize = 12;
+// Message size (u16), then the SBE header: block length, template, schema
+// and version (u16 each)
+pub const MESSAGE_HEADER_LEN: usize = 10;
+
+pub const INCREMENTAL_REFRESH_BOOK: u16 = 46;
+pub const INCREMENTAL_REFRESH_ORDER_BOOK: u16 = 47;
+
+// MatchEventIndicator bit set on the last message of an event
+pub const END_OF_EVENT: u8 = 0x80;
+
+// MDUpdateAction
+pub const NEW: u8 = 0;
+pub const CHANGE: u8 = 1;
+pub const DELETE: u8 = 2;
+pub const DELETE_THRU: u8 = 3;
+pub const DELETE_FROM: u8 = 4;
+pub const OVERLAY: u8 = 5;
+
+const PRICE_NULL: i64 = i64::MAX;
+const INT32_NULL: i32 = i32::MAX;
+const UINT64_NULL: u64 = u64::MAX;
+const UINT8_NULL: u8 = u8::MAX;
+
+// CME publishes two levels of implied depth
+const IMPLIED_DEPTH: usize = 2;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MdpError {
+    // Shorter than the packet header, or the messages don't add up to the
+    // packet's length
+    Truncated,
+    // A message or repeating group shorter than its fields
+    BadLength { template: u16 },
+    // An order entry in template 46 that doesn't point at a bid or offer
+    // entry of the same message
+    BadReference(u8),
+}
+
+impl fmt::Display for MdpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MdpError::Truncated => write!(f, "truncated MDP packet"),
+            MdpError::BadLength { template } => {
+                write!(f, "MDP template {template} is too short for its fields")
+            }
+            MdpError::BadReference(reference) => {
+                write!(f, "MDP order entry refers to invalid entry {reference}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MdpError {}
+
+fn le_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn le_i32(b: &[u8], at: usize) -> i32 {
+    le_u32(b, at) as i32
+}
+
+fn le_u64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+fn le_i64(b: &[u8], at: usize) -> i64 {
+    le_u64(b, at) as i64
+}
+
+fn price(b: &[u8], at: usize) -> Option<i64> {
+    Some(le_i64(b, at)).filter(|price| *price != PRICE_NULL)
+}
+
+fn quantity(b: &[u8], at: usize) -> Option<i32> {
+    Some(le_i32(b, at)).filter(|quantity| *quantity != INT32_NULL)
+}
+
+// Entries of a repeating group. groupSize headers are a block length and
+// an u8 count, groupSize8Byte pads the count out to eight bytes
+struct Group<'a> {
+    entries: &'a [u8],
+    block_len: usize,
+    count: usize,
+}
+
+impl<'a> Group<'a> {
+    fn parse(
+        body: &'a [u8],
+        pos: usize,
+        header_len: usize,
+        min_block_len: usize,
+        template: u16,
+    ) -> Result<(Self, usize), MdpError> {
+        let bad = MdpError::BadLength { template };
+        let header = body.get(pos..pos + header_len).ok_or(bad)?;
+        let block_len = le_u16(header, 0) as usize;
+        let count = header[header_len - 1] as usize;
+        let end = pos + header_len + block_len * count;
+        if block_len < min_block_len || end > body.len() {
+            return Err(bad);
+        }
+        let group = Group {
+            entries: &body[pos + header_len..end],
+            block_len,
+            count,
+        };
+        Ok((group, end))
+    }
+
+    fn entry(&self, idx: usize) -> &'a [u8] {
+        &self.entries[idx * self.block_len..(idx + 1) * self.block_len]
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &'a [u8]> + '_ {
+        (0..self.count).map(|idx| self.entry(idx))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum EntryType {
+    Bid,
+    Offer,
+    ImpliedBid,
+    ImpliedOffer,
+    // Every book for the instrument should be emptied
+    BookReset,
+    Other(u8),
+}
+
+impl EntryType {
+    fn from_byte(b: u8) -> Self {
+        match b {
+            b'0' => EntryType::Bid,
+            b'1' => EntryType::Offer,
+            b'E' => EntryType::ImpliedBid,
+            b'F' => EntryType::ImpliedOffer,
+            b'J' => EntryType::BookReset,
+            _ => EntryType::Other(b),
+        }
+    }
+
+    pub fn side(self) -> Option<OrderSide> {
+        match self {
+            EntryType::Bid | EntryType::ImpliedBid => Some(OrderSide::Buy),
+            EntryType::Offer | EntryType::ImpliedOffer => Some(OrderSide::Sell),
+            _ => None,
+        }
+    }
+
+    pub fn is_implied(self) -> bool {
+        matches!(self, EntryType::ImpliedBid | EntryType::ImpliedOffer)
+    }
+}
+
+// A price level entry of template 46
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct LevelEntry {
+    pub security_id: i32,
+    pub price: Option<i64>,
+    pub size: Option<i32>,
+    // Per instrument sequence number
+    pub rpt_seq: u32,
+    pub orders: Option<i32>,
+    // 1 is the top of the book
+    pub level: u8,
+    pub action: u8,
+    pub entry_type: EntryType,
+}
+
+impl LevelEntry {
+    fn parse(b: &[u8]) -> Self {
+        LevelEntry {
+            security_id: le_i32(b, 12),
+            price: price(b, 0),
+            size: quantity(b, 8),
+            rpt_seq: le_u32(b, 16),
+            orders: quantity(b, 20),
+            level: b[24],
+            action: b[25],
+            entry_type: EntryType::from_byte(b[26]),
+        }
+    }
+}
+
+// An order, from either template. Deletes may not carry a price
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct OrderEntry {
+    pub security_id: i32,
+    pub order_id: u64,
+    // Orders at a price fill lowest priority first. A change that loses the
+    // order its place in the queue gives it a new priority
+    pub priority: Option<u64>,
+    pub price: Option<i64>,
+    pub quantity: Option<i32>,
+    // NEW, CHANGE or DELETE
+    pub action: u8,
+    pub side: OrderSide,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MdpMessage {
+    // MDIncrementalRefreshBook (46)
+    Book {
+        transact_time: u64,
+        match_event: u8,
+        levels: Vec<LevelEntry>,
+        orders: Vec<OrderEntry>,
+    },
+    // MDIncrementalRefreshOrderBook (47)
+    OrderBook {
+        transact_time: u64,
+        match_event: u8,
+        orders: Vec<OrderEntry>,
+    },
+    Other {
+        template: u16,
+    },
+}
+
+impl MdpMessage {
+    // msg starts at the message size
+    pub fn parse(msg: &[u8]) -> Result<Self, MdpError> {
+        if msg.len() < MESSAGE_HEADER_LEN {
+            return Err(MdpError::Truncated);
+        }
+        let block_len = le_u16(msg, 2) as usize;
+        let template = le_u16(msg, 4);
+        if template != INCREMENTAL_REFRESH_BOOK && template != INCREMENTAL_REFRESH_ORDER_BOOK {
+            return Ok(MdpMessage::Other { template });
+        }
+
+        let body = &msg[MESSAGE_HEADER_LEN..];
+        if block_len < 9 || body.len() < block_len {
+            return Err(MdpError::BadLength { template });
+        }
+        let transact_time = le_u64(body, 0);
+        let match_event = body[8];
+
+        if template == INCREMENTAL_REFRESH_ORDER_BOOK {
+            let (entries, _) = Group::parse(body, block_len, 8, 34, template)?;
+            let mut orders = Vec::with_capacity(entries.count);
+            for b in entries.iter() {
+                let Some(side) = EntryType::from_byte(b[33]).side() else {
+                    continue;
+                };
+                orders.push(OrderEntry {
+                    security_id: le_i32(b, 28),
+                    order_id: le_u64(b, 0),
+                    priority: Some(le_u64(b, 8)).filter(|p| *p != UINT64_NULL),
+                    price: price(b, 16),
+                    quantity: quantity(b, 24),
+                    action: b[32],
+                    side,
+                });
+            }
+            return Ok(MdpMessage::OrderBook {
+                transact_time,
+                match_event,
+                orders,
+            });
+        }
+
+        let (entries, pos) = Group::parse(body, block_len, 3, 27, template)?;
+        let levels: Vec<LevelEntry> = entries.iter().map(LevelEntry::parse).collect();
+
+        // Order entries are optional
+        let mut orders = Vec::new();
+        if pos < body.len() {
+            let (entries, _) = Group::parse(body, pos, 8, 22, template)?;
+            orders.reserve(entries.count);
+            for b in entries.iter() {
+                let reference = b[20];
+                let level = match reference {
+                    UINT8_NULL | 0 => None,
+                    _ => levels.get(reference as usize - 1),
+                };
+                let Some((level, side)) = level
+                    .filter(|level| !level.entry_type.is_implied())
+                    .and_then(|level| level.entry_type.side().map(|side| (level, side)))
+                else {
+                    return Err(MdpError::BadReference(reference));
+                };
+                orders.push(OrderEntry {
+                    security_id: level.security_id,
+                    order_id: le_u64(b, 0),
+                    priority: Some(le_u64(b, 8)).filter(|p| *p != UINT64_NULL),
+                    price: level.price,
+                    quantity: quantity(b, 16),
+                    action: b[21],
+                    side,
+                });
+            }
+        }
+        Ok(MdpMessage::Book {
+            transact_time,
+            match_event,
+            levels,
+            orders,
+        })
+    }
+
+    pub fn transact_time(&self) -> Option<u64> {
+        match self {
+            MdpMessage::Book { transact_time, .. }
+            | MdpMessage::OrderBook { transact_time, .. } => Some(*transact_time),
+            MdpMessage::Other { .. } => None,
+        }
+    }
+
+    pub fn is_end_of_event(&self) -> bool {
+        match self {
+            MdpMessage::Book { match_event, .. } | MdpMessage::OrderBook { match_event, .. } => {
+                match_event & END_OF_EVENT != 0
+            }
+            MdpMessage::Other { .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Packet<'a> {
+    pub sequence: u32,
+    // Nanoseconds since the Unix epoch
+    pub sending_time: u64,
+    messages: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Self, MdpError> {
+        if buf.len() < PACKET_HEADER_LEN {
+            return Err(MdpError::Truncated);
+        }
+        let packet = Packet {
+            sequence: le_u32(buf, 0),
+            sending_time: le_u64(buf, 4),
+            messages: &buf[PACKET_HEADER_LEN..],
+        };
+
+        // Check the framing up front so a short packet can't be half applied
+        let mut pos = 0;
+        while pos < packet.messages.len() {
+            if pos + 2 > packet.messages.len() {
+                return Err(MdpError::Truncated);
+            }
+            let len = le_u16(packet.messages, pos) as usize;
+            if len < MESSAGE_HEADER_LEN {
+                return Err(MdpError::Truncated);
+            }
+            pos += len;
+        }
+        if pos != packet.messages.len() {
+            return Err(MdpError::Truncated);
+        }
+        Ok(packet)
+    }
+
+    pub fn messages(&self) -> Messages<'a> {
+        Messages {
+            buf: self.messages,
+            pos: 0,
+        }
+    }
+}
+
+// The messages of a packet, each decoded as it's reached
+#[derive(Debug, Clone)]
+pub struct Messages<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Iterator for Messages<'_> {
+    type Item = Result<MdpMessage, MdpError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos + 2 > self.buf.len() {
+            return None;
+        }
+        let len = (le_u16(self.buf, self.pos) as usize).max(2);
+        let end = (self.pos + len).min(self.buf.len());
+        let msg = &self.buf[self.pos..end];
+        self.pos = end;
+        Some(MdpMessage::parse(msg))
+    }
+}
+
+// Maps an instrument's prices into a book's: (price - offset) / increment.
+// The default gives four decimals like ITCH, for instruments priced above
+// zero with ticks of 1e-4 or coarser. Instruments with finer ticks, e.g. 6J,
+// need their tick as the increment, and ones that trade at negative prices,
+// e.g. calendar spreads, an offset below their lowest price. See
+// PriceScale::ticks.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct PriceScale {
+    pub offset: i64,
+    pub increment: i64,
+}
+
+impl Default for PriceScale {
+    fn default() -> Self {
+        PriceScale {
+            offset: 0,
+            increment: 100_000,
+        }
+    }
+}
+
+impl PriceScale {
+    // None when the price is outside the book's range or between increments
+    pub fn to_book(&self, price: i64) -> Option<u32> {
+        let from_offset = price.checked_sub(self.offset)?;
+        if from_offset % self.increment != 0 {
+            return None;
+        }
+        u32::try_from(from_offset / self.increment).ok()
+    }
+
+    pub fn from_book(&self, price: u32) -> i64 {
+        self.offset + price as i64 * self.increment
+    }
+
+    // Book prices in ticks, with zero in the middle of the book's range so
+    // prices can go about 2^31 ticks either side of it
+    pub fn ticks(tick: i64) -> Self {
+        PriceScale {
+            offset: -(1 << 31) * tick,
+            increment: tick,
+        }
+    }
+}
+
+// An entry as it's applied, for on_applied callbacks
+#[derive(Debug, Copy, Clone)]
+pub enum Entry<'a> {
+    Level(&'a LevelEntry),
+    Order(&'a OrderEntry),
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct MdpStats {
+    pub packets: u64,
+    pub messages: u64,
+    // Packets already applied from the other feed of an A/B pair
+    pub duplicates: u64,
+    pub gaps: u64,
+    pub lost: u64,
+    // Packets with bad framing and messages too short for their template
+    pub malformed: u64,
+    pub order_entries: u64,
+    pub implied_entries: u64,
+    // Outright price levels, which the order entries already cover
+    pub outright_levels: u64,
+    // Changes and deletes for orders not on any book. Changes of unknown
+    // orders are applied as adds
+    pub unknown_orders: u64,
+    // Changes that kept the order's priority
+    pub modifies_in_place: u64,
+    // Changes that gave the order a new priority or price, sending it to the
+    // back of the queue
+    pub modifies_requeued: u64,
+    pub book_resets: u64,
+    // Entries with prices the instrument's PriceScale can't map, outside the
+    // book's range or between its increments. Changes of live orders to such
+    // prices delete them
+    pub rejected_prices: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Instrument {
+    locate: u16,
+    implied_locate: u16,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Order {
+    priority: Option<u64>,
+    price: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct MdpRouter {
+    next_sequence: Option<u32>,
+    symbols: FxHashMap<i32, String>,
+    scales: FxHashMap<i32, PriceScale>,
+    instruments: FxHashMap<i32, Instrument>,
+    // Live orders by security id and order id
+    orders: FxHashMap<(i32, u64), Order>,
+    locates: u16,
+    stats: MdpStats,
+}
+
+impl MdpRouter {
+    pub fn new() -> Self {
+        MdpRouter::default()
+    }
+
+    pub fn stats(&self) -> &MdpStats {
+        &self.stats
+    }
+
+    // Names the books of an instrument, which otherwise use its security id.
+    // Must be called before the instrument is first seen
+    pub fn set_symbol(&mut self, security_id: i32, symbol: &str) {
+        self.symbols.insert(security_id, symbol.to_string());
+    }
+
+    // Must be called before the instrument is first seen. Instruments without
+    // one get the default scale unless their first price is negative, then
+    // PriceScale::ticks at four decimals
+    pub fn set_price_scale(&mut self, security_id: i32, scale: PriceScale) {
+        self.scales.insert(security_id, scale);
+    }
+
+    pub fn price_scale(&self, security_id: i32) -> PriceScale {
+        self.scales.get(&security_id).copied().unwrap_or_default()
+    }
+
+    // Locate of an instrument's outright book, 0 if it hasn't been seen
+    pub fn locate(&self, security_id: i32) -> u16 {
+        self.instruments
+            .get(&security_id)
+            .map_or(0, |instrument| instrument.locate)
+    }
+
+    // Locate of an instrument's implied book, 0 if it hasn't been seen
+    pub fn implied_locate(&self, security_id: i32) -> u16 {
+        self.instruments
+            .get(&security_id)
+            .map_or(0, |instrument| instrument.implied_locate)
+    }
+
+    // CME's priority for a live order
+    pub fn priority(&self, security_id: i32, order_id: u64) -> Option<u64> {
+        self.orders.get(&(security_id, order_id))?.priority
+    }
+
+    // Order ids at a price in the order they'll fill, by their priority
+    pub fn queue(&self, security_id: i32, price: i64) -> Vec<u64> {
+        let mut queue: Vec<(u64, u64)> = self
+            .orders
+            .iter()
+            .filter(|((id, _), order)| *id == security_id && order.price == price)
+            .map(|((_, order_id), order)| (order.priority.unwrap_or(u64::MAX), *order_id))
+            .collect();
+        queue.sort_unstable();
+        queue.into_iter().map(|(_, order_id)| order_id).collect()
+    }
+
+    // Applies a packet unless it was already applied. A gap in the sequence
+    // marks every book stale until CME resets it. on_applied sees each entry
+    // with its message's transact time.
+    pub fn apply_packet<F>(
+        &mut self,
+        books: &mut BookSet,
+        buf: &[u8],
+        mut on_applied: F,
+    ) -> Result<(), MdpError>
+    where
+        F: FnMut(u64, Entry, ApplyOutcome),
+    {
+        let packet = Packet::parse(buf).inspect_err(|_| self.stats.malformed += 1)?;
+        self.stats.packets += 1;
+
+        let next = *self.next_sequence.get_or_insert(packet.sequence);
+        if packet.sequence < next {
+            self.stats.duplicates += 1;
+            return Ok(());
+        }
+        if packet.sequence > next {
+            self.stats.gaps += 1;
+            self.stats.lost += (packet.sequence - next) as u64;
+            books.mark_stale();
+        }
+        self.next_sequence = Some(packet.sequence.wrapping_add(1));
+
+        for msg in packet.messages() {
+            match msg {
+                Ok(msg) => self.apply(books, &msg, &mut on_applied),
+                Err(_) => self.stats.malformed += 1,
+            }
+        }
+        Ok(())
+    }
+
+    // Applies a message's entries in order, without sequence checks
+    pub fn apply<F>(&mut self, books: &mut BookSet, msg: &MdpMessage, mut on_applied: F)
+    where
+        F: FnMut(u64, Entry, ApplyOutcome),
+    {
+        self.stats.messages += 1;
+        let (transact_time, levels, orders) = match msg {
+            MdpMessage::Book {
+                transact_time,
+                levels,
+                orders,
+                ..
+            } => (*transact_time, levels.as_slice(), orders.as_slice()),
+            MdpMessage::OrderBook {
+                transact_time,
+                orders,
+                ..
+            } => (*transact_time, &[][..], orders.as_slice()),
+            MdpMessage::Other { .. } => return,
+        };
+
+        for level in levels {
+            let outcome = self.apply_level(books, level, transact_time);
+            on_applied(transact_time, Entry::Level(level), outcome);
+        }
+        for order in orders {
+            let outcome = self.apply_order(books, order, transact_time);
+            on_applied(transact_time, Entry::Order(order), outcome);
+        }
+    }
+
+    // Implied levels go to the instrument's implied book and a book reset
+    // empties both. Outright levels are left to the order entries
+    pub fn apply_level(
+        &mut self,
+        books: &mut BookSet,
+        entry: &LevelEntry,
+        transact_time: u64,
+    ) -> ApplyOutcome {
+        if entry.entry_type == EntryType::BookReset {
+            let Some(instrument) = self.listing(books, entry.security_id, transact_time) else {
+                return ApplyOutcome::Ignored;
+            };
+            self.stats.book_resets += 1;
+            for locate in [instrument.locate, instrument.implied_locate] {
+                if let Some(book) = books.get_mut(locate) {
+                    book.clear();
+                    book.set_stale(false);
+                }
+            }
+            self.orders.retain(|(id, _), _| *id != entry.security_id);
+            return ApplyOutcome::Cleared;
+        }
+
+        let Some(side) = entry.entry_type.side() else {
+            return ApplyOutcome::Ignored;
+        };
+        if !entry.entry_type.is_implied() {
+            self.stats.outright_levels += 1;
+            return ApplyOutcome::Ignored;
+        }
+        self.stats.implied_entries += 1;
+
+        let Some(instrument) = self.listing(books, entry.security_id, transact_time) else {
+            return ApplyOutcome::Ignored;
+        };
+        let price = match entry.price {
+            Some(price) => match self.price(entry.security_id, price) {
+                Some(price) => Some(price),
+                None => return ApplyOutcome::Ignored,
+            },
+            None => None,
+        };
+        let size = entry.size.unwrap_or(0).max(0) as u32;
+        let Some(book) = books.get_mut(instrument.implied_locate) else {
+            return ApplyOutcome::Ignored;
+        };
+
+        match (entry.action, price) {
+            (NEW | CHANGE | OVERLAY, Some(price)) => {
+                // A level at this depth with another price has been replaced
+                let replaced = (entry.action != NEW)
+                    .then(|| (entry.level as usize).checked_sub(1))
+                    .flatten()
+                    .and_then(|depth| book.levels(side).nth(depth))
+                    .map(|(old_price, _, _)| old_price)
+                    .filter(|old_price| *old_price != price);
+                if let Some(old_price) = replaced {
+                    book.set_level(side, old_price, 0);
+                }
+                book.set_level(side, price, size);
+                // Levels pushed past the published depth are dropped
+                let beyond: Vec<u32> = book
+                    .levels(side)
+                    .skip(IMPLIED_DEPTH)
+                    .map(|(price, _, _)| price)
+                    .collect();
+                for price in beyond {
+                    book.set_level(side, price, 0);
+                }
+            }
+            (DELETE, Some(price)) => book.set_level(side, price, 0),
+            // Every level on the side
+            (DELETE_THRU, _) => delete_levels(book, side, usize::MAX),
+            // Levels from the top down to this one
+            (DELETE_FROM, _) => delete_levels(book, side, entry.level as usize),
+            _ => return ApplyOutcome::Ignored,
+        }
+        ApplyOutcome::LevelsUpdated
+    }
+
+    pub fn apply_order(
+        &mut self,
+        books: &mut BookSet,
+        entry: &OrderEntry,
+        transact_time: u64,
+    ) -> ApplyOutcome {
+        self.stats.order_entries += 1;
+        let Some(instrument) = self.listing(books, entry.security_id, transact_time) else {
+            return ApplyOutcome::Ignored;
+        };
+        let key = (entry.security_id, entry.order_id);
+        let Some(book) = books.get_mut(instrument.locate) else {
+            return ApplyOutcome::Ignored;
+        };
+
+        match entry.action {
+            NEW | CHANGE => {
+                let (Some(price), Some(quantity)) = (entry.price, entry.quantity) else {
+                    return ApplyOutcome::Ignored;
+                };
+                let quantity = quantity.max(0) as u32;
+                let Some(book_price) = self.price(entry.security_id, price) else {
+                    // The order can't be shown at its new price
+                    if self.orders.remove(&key).is_some() && book.order(entry.order_id).is_some() {
+                        return delete(book, entry.order_id);
+                    }
+                    return ApplyOutcome::Ignored;
+                };
+                let order = Order {
+                    priority: entry.priority,
+                    price,
+                };
+
+                // A repeated add is a change and a change of an order we
+                // haven't seen is an add
+                let Some(old) = self.orders.get(&key).copied() else {
+                    if entry.action == CHANGE {
+                        self.stats.unknown_orders += 1;
+                    }
+                    if quantity == 0 {
+                        return ApplyOutcome::Ignored;
+                    }
+                    self.orders.insert(key, order);
+                    return add(book, entry.order_id, entry.side, quantity, book_price);
+                };
+                let Some((_, shares, side)) = book.order(entry.order_id) else {
+                    self.orders.remove(&key);
+                    return ApplyOutcome::Ignored;
+                };
+
+                if quantity == 0 {
+                    self.orders.remove(&key);
+                    return delete(book, entry.order_id);
+                }
+                self.orders.insert(key, order);
+                self.modify(book, entry, &old, (shares, side), quantity, book_price)
+            }
+            DELETE => {
+                if self.orders.remove(&key).is_none() || book.order(entry.order_id).is_none() {
+                    self.stats.unknown_orders += 1;
+                    return ApplyOutcome::Ignored;
+                }
+                delete(book, entry.order_id)
+            }
+            _ => ApplyOutcome::Ignored,
+        }
+    }
+
+    // An order keeps its place in the queue while its priority and price are
+    // unchanged, e.g. after a size cut. The book only holds volume per
+    // level, so a size increase that keeps priority is still applied as a
+    // replace, but counted in place. Anything else is a replace that keeps
+    // the order id.
+    fn modify(
+        &mut self,
+        book: &mut OrderBook,
+        entry: &OrderEntry,
+        old: &Order,
+        (shares, side): (u32, OrderSide),
+        quantity: u32,
+        price: u32,
+    ) -> ApplyOutcome {
+        let reference = entry.order_id;
+        if side != entry.side {
+            self.stats.modifies_requeued += 1;
+            delete(book, reference);
+            add(book, reference, entry.side, quantity, price);
+            return ApplyOutcome::Replaced;
+        }
+
+        let same_priority = entry.priority.is_none() || entry.priority == old.priority;
+        let in_place = same_priority && entry.price == Some(old.price);
+        if in_place {
+            self.stats.modifies_in_place += 1;
+            if quantity == shares {
+                return ApplyOutcome::Ignored;
+            }
+            if quantity < shares {
+                return itch::apply_event(
+                    book,
+                    OrderEvent::Cancelled {
+                        reference,
+                        cancelled: shares - quantity,
+                    },
+                );
+            }
+        } else {
+            self.stats.modifies_requeued += 1;
+        }
+        itch::apply_event(
+            book,
+            OrderEvent::Replaced {
+                old_reference: reference,
+                new_reference: reference,
+                shares: quantity,
+                price,
+            },
+        )
+    }
+
+    // The instrument's books, listing them on first sight. None once the
+    // locates run out
+    fn listing(
+        &mut self,
+        books: &mut BookSet,
+        security_id: i32,
+        transact_time: u64,
+    ) -> Option<Instrument> {
+        if let Some(instrument) = self.instruments.get(&security_id) {
+            return Some(*instrument);
+        }
+        let locate = self.locates.checked_add(1)?;
+        let implied_locate = locate.checked_add(1)?;
+        self.locates = implied_locate;
+
+        let symbol = self
+            .symbols
+            .get(&security_id)
+            .cloned()
+            .unwrap_or_else(|| security_id.to_string());
+        books.add_listing(StockInfo::new(locate, &symbol, transact_time));
+        books.add_listing(StockInfo::new(
+            implied_locate,
+            &implied_symbol(&symbol),
+            transact_time,
+        ));
+
+        let instrument = Instrument {
+            locate,
+            implied_locate,
+        };
+        self.instruments.insert(security_id, instrument);
+        Some(instrument)
+    }
+
+    // The book price for an instrument's price, picking the instrument's
+    // scale on its first price. None if the scale can't map it
+    fn price(&mut self, security_id: i32, price: i64) -> Option<u32> {
+        let scale = *self.scales.entry(security_id).or_insert_with(|| {
+            if price < 0 {
+                PriceScale::ticks(PriceScale::default().increment)
+            } else {
+                PriceScale::default()
+            }
+        });
+        let book_price = scale.to_book(price);
+        if book_price.is_none() {
+            self.stats.rejected_prices += 1;
+        }
+        book_price
+    }
+}
+
+// Symbol of an instrument's implied book
+pub fn implied_symbol(symbol: &str) -> String {
+    format!("{symbol}.implied")
+}
+
+// Removes up to count levels from the top of a side
+fn delete_levels(book: &mut OrderBook, side: OrderSide, count: usize) {
+    let prices: Vec<u32> = book
+        .levels(side)
+        .take(count)
+        .map(|(price, _, _)| price)
+        .collect();
+    for price in prices {
+        book.set_level(side, price, 0);
+    }
+}
+
+fn add(
+    book: &mut OrderBook,
+    reference: u64,
+    side: OrderSide,
+    shares: u32,
+    price: u32,
+) -> ApplyOutcome {
+    itch::apply_event(
+        book,
+        OrderEvent::Add {
+            reference,
+            side,
+            shares,
+            price,
+            mpid: None,
+        },
+    )
+}
+
+fn delete(book: &mut OrderBook, reference: u64) -> ApplyOutcome {
+    itch::apply_event(book, OrderEvent::Deleted { reference })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ES: i32 = 1_001;
+    const SPREAD: i32 = 2_002;
+    // Ticks in 5e-7
+    const JPY: i32 = 3_003;
+
+    // Prices have nine decimals
+    const fn px(dollars: i64, hundredths: i64) -> i64 {
+        dollars * 1_000_000_000 + hundredths * 10_000_000
+    }
+
+    // Builds packets with the block lengths CME sends: 11 byte roots,
+    // 32 byte level entries and 24 or 40 byte order entries
+    struct Sbe(Vec<u8>);
+
+    impl Sbe {
+        fn message(template: u16, match_event: u8) -> Self {
+            let mut b = Vec::new();
+            for field in [0, 11, template, 1, 13] {
+                b.extend_from_slice(&field.to_le_bytes());
+            }
+            b.extend_from_slice(&1_000u64.to_le_bytes());
+            b.push(match_event);
+            b.extend_from_slice(&[0; 2]);
+            Sbe(b)
+        }
+
+        fn group(mut self, block_len: u16, header_len: usize, entries: &[Vec<u8>]) -> Self {
+            self.0.extend_from_slice(&block_len.to_le_bytes());
+            self.0.resize(self.0.len() + header_len - 3, 0);
+            self.0.push(entries.len() as u8);
+            for entry in entries {
+                let mut entry = entry.clone();
+                entry.resize(block_len as usize, 0);
+                self.0.extend_from_slice(&entry);
+            }
+            self
+        }
+
+        fn finish(mut self) -> Vec<u8> {
+            let len = self.0.len() as u16;
+            self.0[..2].copy_from_slice(&len.to_le_bytes());
+            self.0
+        }
+    }
+
+    fn level(security_id: i32, price: i64, size: i32, level: u8, action: u8, kind: u8) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&price.to_le_bytes());
+        b.extend_from_slice(&size.to_le_bytes());
+        b.extend_from_slice(&security_id.to_le_bytes());
+        b.extend_from_slice(&1u32.to_le_bytes());
+        b.extend_from_slice(&1i32.to_le_bytes());
+        b.extend_from_slice(&[level, action, kind]);
+        b
+    }
+
+    // An order entry of template 46, pointing at the reference'th level
+    fn book_order(
+        order_id: u64,
+        priority: u64,
+        quantity: i32,
+        reference: u8,
+        action: u8,
+    ) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&order_id.to_le_bytes());
+        b.extend_from_slice(&priority.to_le_bytes());
+        b.extend_from_slice(&quantity.to_le_bytes());
+        b.extend_from_slice(&[reference, action]);
+        b
+    }
+
+    fn order(
+        security_id: i32,
+        order_id: u64,
+        priority: u64,
+        price: i64,
+        quantity: i32,
+        action: u8,
+        kind: u8,
+    ) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&order_id.to_le_bytes());
+        b.extend_from_slice(&priority.to_le_bytes());
+        b.extend_from_slice(&price.to_le_bytes());
+        b.extend_from_slice(&quantity.to_le_bytes());
+        b.extend_from_slice(&security_id.to_le_bytes());
+        b.extend_from_slice(&[action, kind]);
+        b
+    }
+
+    fn order_book(orders: &[Vec<u8>]) -> Vec<u8> {
+        Sbe::message(INCREMENTAL_REFRESH_ORDER_BOOK, END_OF_EVENT)
+            .group(40, 8, orders)
+            .finish()
+    }
+
+    fn book(levels: &[Vec<u8>], orders: &[Vec<u8>]) -> Vec<u8> {
+        Sbe::message(INCREMENTAL_REFRESH_BOOK, END_OF_EVENT)
+            .group(32, 3, levels)
+            .group(24, 8, orders)
+            .finish()
+    }
+
+    fn packet(sequence: u32, messages: &[Vec<u8>]) -> Vec<u8> {
+        let mut b = Vec::new();
+        b.extend_from_slice(&sequence.to_le_bytes());
+        b.extend_from_slice(&0u64.to_le_bytes());
+        for msg in messages {
+            b.extend_from_slice(msg);
+        }
+        b
+    }
+
+    fn apply(router: &mut MdpRouter, books: &mut BookSet, packet: &[u8]) {
+        router.apply_packet(books, packet, |_, _, _| {}).unwrap();
+    }
+
+    // Implied levels have no order counts
+    fn levels(books: &BookSet, locate: u16, side: OrderSide) -> Vec<(u32, u32, usize)> {
+        books.get(locate).unwrap().levels(side).collect()
+    }
+
+    #[test]
+    fn parses_order_book_messages() {
+        let msg = order_book(&[order(ES, 7, 100, px(4_500, 25), 3, NEW, b'0')]);
+        assert_eq!(
+            MdpMessage::parse(&msg).unwrap(),
+            MdpMessage::OrderBook {
+                transact_time: 1_000,
+                match_event: END_OF_EVENT,
+                orders: vec![OrderEntry {
+                    security_id: ES,
+                    order_id: 7,
+                    priority: Some(100),
+                    price: Some(px(4_500, 25)),
+                    quantity: Some(3),
+                    action: NEW,
+                    side: OrderSide::Buy,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn book_orders_take_their_level_price_and_side() {
+        let msg = book(
+            &[
+                level(ES, px(4_500, 0), 5, 1, NEW, b'E'),
+                level(ES, px(4_500, 25), 2, 1, NEW, b'1'),
+            ],
+            &[book_order(9, 200, 2, 2, NEW)],
+        );
+        let MdpMessage::Book { levels, orders, .. } = MdpMessage::parse(&msg).unwrap() else {
+            panic!("46 should parse as a book message");
+        };
+        assert_eq!(levels[0].entry_type, EntryType::ImpliedBid);
+        assert_eq!(orders[0].price, Some(px(4_500, 25)));
+        assert_eq!(orders[0].side, OrderSide::Sell);
+
+        // Order entries can't point at implied levels
+        let msg = book(
+            &[level(ES, px(4_500, 0), 5, 1, NEW, b'E')],
+            &[book_order(9, 200, 2, 1, NEW)],
+        );
+        assert_eq!(MdpMessage::parse(&msg), Err(MdpError::BadReference(1)));
+    }
+
+    #[test]
+    fn outright_and_implied_books() {
+        let mut router = MdpRouter::new();
+        let mut books = BookSet::new();
+        router.set_symbol(ES, "ESZ6");
+
+        apply(
+            &mut router,
+            &mut books,
+            &packet(
+                1,
+                &[
+                    order_book(&[
+                        order(ES, 1, 10, px(4_500, 0), 5, NEW, b'0'),
+                        order(ES, 2, 11, px(4_500, 0), 3, NEW, b'0'),
+                        order(ES, 3, 12, px(4_500, 25), 4, NEW, b'1'),
+                    ]),
+                    book(
+                        &[
+                            level(ES, px(4_499, 75), 20, 1, NEW, b'E'),
+                            level(ES, px(4_500, 50), 30, 1, NEW, b'F'),
+                            level(ES, px(4_500, 25), 6, 1, CHANGE, b'1'),
+                        ],
+                        &[book_order(4, 13, 2, 3, NEW)],
+                    ),
+                ],
+            ),
+        );
+
+        let outright = router.locate(ES);
+        let implied = router.implied_locate(ES);
+        assert_eq!(books.get(outright).unwrap().symbol(), "ESZ6");
+        assert_eq!(books.get(implied).unwrap().symbol(), "ESZ6.implied");
+        assert_eq!(
+            levels(&books, outright, OrderSide::Buy),
+            [(45_000_000, 8, 2)]
+        );
+        assert_eq!(
+            levels(&books, outright, OrderSide::Sell),
+            [(45_002_500, 6, 2)]
+        );
+        assert_eq!(
+            levels(&books, implied, OrderSide::Buy),
+            [(44_997_500, 20, 0)]
+        );
+        assert_eq!(
+            levels(&books, implied, OrderSide::Sell),
+            [(45_005_000, 30, 0)]
+        );
+        assert_eq!(router.queue(ES, px(4_500, 0)), [1, 2]);
+        assert_eq!(router.stats().outright_levels, 1);
+        assert_eq!(router.stats().implied_entries, 2);
+
+        // A size cut keeps priority, a new priority sends the order back
+        apply(
+            &mut router,
+            &mut books,
+            &packet(
+                2,
+                &[order_book(&[
+                    order(ES, 1, 10, px(4_500, 0), 4, CHANGE, b'0'),
+                    order(ES, 2, 14, px(4_500, 0), 3, CHANGE, b'0'),
+                    order(ES, 1, 0, 0, 0, DELETE, b'0'),
+                ])],
+            ),
+        );
+        assert_eq!(
+            levels(&books, outright, OrderSide::Buy),
+            [(45_000_000, 3, 1)]
+        );
+        assert_eq!(router.stats().modifies_in_place, 1);
+        assert_eq!(router.stats().modifies_requeued, 1);
+
+        // Implied levels deeper than CME publishes are dropped
+        apply(
+            &mut router,
+            &mut books,
+            &packet(
+                3,
+                &[book(
+                    &[
+                        level(ES, px(4_499, 50), 10, 2, NEW, b'E'),
+                        level(ES, px(4_499, 25), 10, 3, NEW, b'E'),
+                    ],
+                    &[],
+                )],
+            ),
+        );
+        assert_eq!(
+            levels(&books, implied, OrderSide::Buy),
+            [(44_997_500, 20, 0), (44_995_000, 10, 0)]
+        );
+    }
+
+    #[test]
+    fn negative_prices_center_the_scale_on_zero() {
+        let mut router = MdpRouter::new();
+        let mut books = BookSet::new();
+        apply(
+            &mut router,
+            &mut books,
+            &packet(
+                1,
+                &[order_book(&[
+                    order(SPREAD, 1, 1, px(-1, -25), 10, NEW, b'0'),
+                    order(SPREAD, 2, 2, px(0, 50), 10, NEW, b'1'),
+                ])],
+            ),
+        );
+
+        let scale = router.price_scale(SPREAD);
+        let book = books.get(router.locate(SPREAD)).unwrap();
+        let bid = book.best_bid_raw().unwrap();
+        let ask = book.best_ask_raw().unwrap();
+        assert_eq!(scale, PriceScale::ticks(100_000));
+        assert_eq!(scale.from_book(bid), px(-1, -25));
+        assert_eq!(scale.from_book(ask), px(0, 50));
+        assert_eq!(ask - bid, 17_500);
+        assert_eq!(router.stats().rejected_prices, 0);
+    }
+
+    #[test]
+    fn out_of_range_prices_are_rejected() {
+        let mut router = MdpRouter::new();
+        let mut books = BookSet::new();
+        apply(
+            &mut router,
+            &mut books,
+            &packet(
+                1,
+                &[order_book(&[
+                    order(ES, 1, 1, px(4_500, 0), 10, NEW, b'0'),
+                    // Below the default scale's zero
+                    order(ES, 2, 2, px(-1, 0), 10, NEW, b'0'),
+                    // Beyond u32::MAX at four decimals
+                    order(ES, 3, 3, px(500_000, 0), 10, NEW, b'1'),
+                ])],
+            ),
+        );
+        let outright = router.locate(ES);
+        assert_eq!(
+            levels(&books, outright, OrderSide::Buy),
+            [(45_000_000, 10, 1)]
+        );
+        assert!(levels(&books, outright, OrderSide::Sell).is_empty());
+        assert_eq!(router.stats().rejected_prices, 2);
+
+        // A live order moved out of range leaves the book
+        apply(
+            &mut router,
+            &mut books,
+            &packet(
+                2,
+                &[order_book(&[order(ES, 1, 1, px(-5, 0), 10, CHANGE, b'0')])],
+            ),
+        );
+        assert!(levels(&books, outright, OrderSide::Buy).is_empty());
+        assert_eq!(router.priority(ES, 1), None);
+        assert_eq!(router.stats().rejected_prices, 3);
+    }
+
+    #[test]
+    fn prices_between_increments_are_rejected() {
+        let (tick, near) = (500, 6_500_000);
+        let entries = [
+            order(JPY, 1, 1, near, 10, NEW, b'0'),
+            order(JPY, 2, 2, near - tick, 10, NEW, b'0'),
+            order(JPY, 3, 3, near + tick, 10, NEW, b'1'),
+            order(JPY, 4, 4, near + tick + 1, 10, NEW, b'1'),
+        ];
+
+        // Four decimals can't tell the ticks apart
+        let mut router = MdpRouter::new();
+        let mut books = BookSet::new();
+        apply(&mut router, &mut books, &packet(1, &[order_book(&entries)]));
+        let outright = router.locate(JPY);
+        assert_eq!(levels(&books, outright, OrderSide::Buy), [(65, 10, 1)]);
+        assert!(levels(&books, outright, OrderSide::Sell).is_empty());
+        assert_eq!(router.stats().rejected_prices, 3);
+
+        let mut router = MdpRouter::new();
+        let mut books = BookSet::new();
+        let scale = PriceScale::ticks(tick);
+        router.set_price_scale(JPY, scale);
+        apply(&mut router, &mut books, &packet(1, &[order_book(&entries)]));
+        let outright = router.locate(JPY);
+        let book_price = |price| scale.to_book(price).unwrap();
+        assert_eq!(
+            levels(&books, outright, OrderSide::Buy),
+            [(book_price(near), 10, 1), (book_price(near - tick), 10, 1)]
+        );
+        assert_eq!(
+            levels(&books, outright, OrderSide::Sell),
+            [(book_price(near + tick), 10, 1)]
+        );
+        assert_eq!(book_price(near) - book_price(near - tick), 1);
+        assert_eq!(scale.from_book(book_price(near)), near);
+        assert_eq!(router.stats().rejected_prices, 1);
+    }
+}
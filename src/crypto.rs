@@ -0,0 +1,175 @@
+// Recordings of crypto venue feeds, stored as one JSON message per line.
+// Prices and sizes arrive as decimal strings and are read into u64 fixed
+// point at the decimals of a Scale. Values with more decimals than the Scale
+// keeps, or too large for the book's 32 bit prices and volumes, are
+// rejected rather than rounded or clamped. Both venues need a snapshot before their updates can
+// be applied: updates seen before it are held, and a break in their
+// sequence marks the book stale until the next snapshot.
+
+pub mod binance;
+pub mod coinbase;
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum FeedError {
+    Json(serde_json::Error),
+    // A price or size that isn't a plain decimal
+    BadNumber(String),
+    // A decimal with more precision than the Scale keeps, or too large for
+    // the book
+    OutOfRange(String),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::Json(e) => write!(f, "{e}"),
+            FeedError::BadNumber(s) => write!(f, "invalid decimal {s:?}"),
+            FeedError::OutOfRange(s) => write!(f, "decimal {s:?} doesn't fit the book's scale"),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+impl From<serde_json::Error> for FeedError {
+    fn from(e: serde_json::Error) -> Self {
+        FeedError::Json(e)
+    }
+}
+
+// Decimals kept when reading prices and sizes, nonzero digits past them are
+// rejected. Book prices are shown with four decimals, like ITCH's, so other
+// price scales are only right in raw prices. Sizes need few enough decimals
+// that a level's volume fits in 32 bits.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Scale {
+    pub price_decimals: u32,
+    pub size_decimals: u32,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Scale {
+            price_decimals: 4,
+            size_decimals: 4,
+        }
+    }
+}
+
+impl Scale {
+    pub fn price(&self, s: &str) -> Result<u32, FeedError> {
+        to_book(s, self.price_decimals)
+    }
+
+    pub fn size(&self, s: &str) -> Result<u32, FeedError> {
+        to_book(s, self.size_decimals)
+    }
+}
+
+fn to_book(s: &str, decimals: u32) -> Result<u32, FeedError> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if (int.is_empty() && frac.is_empty()) || !digits(int) || !digits(frac) {
+        return Err(FeedError::BadNumber(s.to_string()));
+    }
+    parse_decimal(s, decimals)
+        .and_then(|value| u32::try_from(value).ok())
+        .ok_or_else(|| FeedError::OutOfRange(s.to_string()))
+}
+
+// "12.340" at 2 decimals is 1234. None if it isn't a plain decimal, has
+// nonzero digits past the decimals or is too large for u64
+pub fn parse_decimal(s: &str, decimals: u32) -> Option<u64> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if int.is_empty() && frac.is_empty() {
+        return None;
+    }
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if !digits(int) || !digits(frac) {
+        return None;
+    }
+    if frac.bytes().skip(decimals as usize).any(|b| b != b'0') {
+        return None;
+    }
+
+    let mut value: u64 = 0;
+    for b in int.bytes() {
+        value = value.checked_mul(10)?.checked_add((b - b'0') as u64)?;
+    }
+    let mut frac = frac.bytes();
+    for _ in 0..decimals {
+        let digit = frac.next().map_or(0, |b| b - b'0');
+        value = value.checked_mul(10)?.checked_add(digit as u64)?;
+    }
+    Some(value)
+}
+
+// "2014-11-07T08:19:27.028459Z" as nanoseconds since the Unix epoch. Only
+// UTC times are accepted
+pub fn parse_utc_time(s: &str) -> Option<u64> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, frac) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || frac.len() > 9 {
+        return None;
+    }
+    let nanos = if frac.is_empty() {
+        0
+    } else {
+        frac.parse::<u64>().ok()? * 10u64.pow(9 - frac.len() as u32)
+    };
+
+    // Days from the epoch to the civil date, from Howard Hinnant's
+    // days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+
+    let seconds = days * 86_400 + hour * 3_600 + minute * 60 + second;
+    Some(seconds * 1_000_000_000 + nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimals_at_a_scale() {
+        assert_eq!(parse_decimal("12.34", 2), Some(1234));
+        assert_eq!(parse_decimal("12.340000", 2), Some(1234));
+        assert_eq!(parse_decimal("12", 2), Some(1200));
+        assert_eq!(parse_decimal(".5", 1), Some(5));
+        assert_eq!(parse_decimal("12.", 0), Some(12));
+        assert_eq!(parse_decimal("12.345", 2), None);
+        assert_eq!(parse_decimal("", 2), None);
+        assert_eq!(parse_decimal(".", 2), None);
+        assert_eq!(parse_decimal("-1", 2), None);
+        assert_eq!(parse_decimal("1e5", 2), None);
+        assert_eq!(parse_decimal("18446744073709551616", 0), None);
+    }
+
+    #[test]
+    fn values_the_book_cant_hold_are_rejected() {
+        let scale = Scale::default();
+        assert_eq!(scale.price("429496.7295").unwrap(), u32::MAX);
+        assert!(matches!(
+            scale.price("429496.7296"),
+            Err(FeedError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            scale.size("0.00001"),
+            Err(FeedError::OutOfRange(_))
+        ));
+        assert!(matches!(scale.size("1,5"), Err(FeedError::BadNumber(_))));
+        assert_eq!(scale.size("0.00010000").unwrap(), 1);
+    }
+}
@@ -0,0 +1,307 @@
+// Binance style diff depth streams (L2). Each depthUpdate event carries the
+// first (U) and last (u) update ids it covers, futures streams also the last
+// id of the previous event (pu), and sets the quantity of each level it
+// lists, zero removing the level.
+//
+// The book starts from a REST depth snapshot, recorded with the symbol it's
+// for: {"lastUpdateId":..,"symbol":..,"bids":[[price, qty]],"asks":[..]}.
+// Events that end at or before lastUpdateId are already in it, and the first
+// event applied must span lastUpdateId + 1. Combined stream messages,
+// {"stream":..,"data":{..}}, are unwrapped.
+
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use super::{FeedError, Scale};
+use crate::bookset::BookSet;
+use crate::directory::StockInfo;
+use crate::itch::ApplyOutcome;
+use crate::orderbook::{OrderBook, OrderSide};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DepthUpdate {
+    // Event time, milliseconds since the Unix epoch
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    #[serde(rename = "u")]
+    pub final_update_id: u64,
+    // Futures only
+    #[serde(rename = "pu")]
+    pub previous_final_update_id: Option<u64>,
+    #[serde(rename = "b")]
+    pub bids: Vec<(String, String)>,
+    #[serde(rename = "a")]
+    pub asks: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    pub last_update_id: u64,
+    #[serde(alias = "s")]
+    pub symbol: Option<String>,
+    pub bids: Vec<(String, String)>,
+    pub asks: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum BinanceMessage {
+    Stream {
+        stream: String,
+        data: Box<BinanceMessage>,
+    },
+    Snapshot(DepthSnapshot),
+    Update(DepthUpdate),
+    Other(serde_json::Value),
+}
+
+impl BinanceMessage {
+    // Unwraps combined stream messages, giving snapshots without a symbol
+    // the stream's
+    pub fn parse(line: &str) -> Result<Self, FeedError> {
+        let mut msg: BinanceMessage = serde_json::from_str(line)?;
+        while let BinanceMessage::Stream { stream, data } = msg {
+            msg = *data;
+            if let BinanceMessage::Snapshot(snapshot) = &mut msg
+                && snapshot.symbol.is_none()
+            {
+                let symbol = stream.split('@').next().unwrap_or_default();
+                snapshot.symbol = Some(symbol.to_uppercase());
+            }
+        }
+        Ok(msg)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct BinanceStats {
+    pub updates: u64,
+    pub snapshots: u64,
+    // Events held until their symbol's snapshot
+    pub buffered: u64,
+    // Events the snapshot already covered
+    pub superseded: u64,
+    // Events that don't follow on from the last one applied
+    pub gaps: u64,
+    // Levels with prices or quantities that aren't decimals, or don't fit
+    // the book at the router's Scale
+    pub malformed: u64,
+}
+
+#[derive(Debug, Default)]
+struct Depth {
+    locate: u16,
+    // Snapshot's lastUpdateId until the first event is applied
+    snapshot_id: Option<u64>,
+    // Final update id of the last event applied
+    last_update_id: Option<u64>,
+    pending: Vec<DepthUpdate>,
+}
+
+// Applies depth snapshots and diffs to a BookSet, one price level book per
+// symbol.
+#[derive(Debug, Default)]
+pub struct BinanceRouter {
+    scale: Scale,
+    symbols: FxHashMap<String, Depth>,
+    stats: BinanceStats,
+}
+
+impl BinanceRouter {
+    pub fn new(scale: Scale) -> Self {
+        BinanceRouter {
+            scale,
+            ..BinanceRouter::default()
+        }
+    }
+
+    pub fn stats(&self) -> &BinanceStats {
+        &self.stats
+    }
+
+    // Locate of a symbol's book, 0 if it hasn't been seen
+    pub fn locate(&self, symbol: &str) -> u16 {
+        self.symbols.get(symbol).map_or(0, |depth| depth.locate)
+    }
+
+    pub fn apply_line(
+        &mut self,
+        books: &mut BookSet,
+        line: &str,
+    ) -> Result<ApplyOutcome, FeedError> {
+        let msg = BinanceMessage::parse(line)?;
+        Ok(self.apply(books, msg))
+    }
+
+    pub fn apply(&mut self, books: &mut BookSet, msg: BinanceMessage) -> ApplyOutcome {
+        match msg {
+            BinanceMessage::Snapshot(snapshot) => self.snapshot(books, snapshot),
+            BinanceMessage::Update(update) => {
+                self.stats.updates += 1;
+                self.update(books, update)
+            }
+            _ => ApplyOutcome::Ignored,
+        }
+    }
+
+    // Replaces the symbol's book with the snapshot, then applies the held
+    // events that come after it. Snapshots without a symbol are ignored
+    fn snapshot(&mut self, books: &mut BookSet, snapshot: DepthSnapshot) -> ApplyOutcome {
+        let Some(symbol) = &snapshot.symbol else {
+            return ApplyOutcome::Ignored;
+        };
+        let Some(locate) = self.listing(books, symbol, 0) else {
+            return ApplyOutcome::Ignored;
+        };
+        self.stats.snapshots += 1;
+        let Some(book) = books.get_mut(locate) else {
+            return ApplyOutcome::Ignored;
+        };
+        book.clear();
+        book.set_stale(false);
+        self.set_levels(book, &snapshot.bids, &snapshot.asks);
+
+        let depth = self.symbols.get_mut(symbol).unwrap();
+        depth.snapshot_id = Some(snapshot.last_update_id);
+        depth.last_update_id = None;
+        for update in std::mem::take(&mut depth.pending) {
+            self.update(books, update);
+        }
+        ApplyOutcome::LevelsUpdated
+    }
+
+    // Applies an event if it follows on from the snapshot or the last event
+    // applied. Otherwise the book is stale and events are held for the next
+    // snapshot, reporting Deferred
+    fn update(&mut self, books: &mut BookSet, update: DepthUpdate) -> ApplyOutcome {
+        let timestamp = update.event_time * 1_000_000;
+        let Some(locate) = self.listing(books, &update.symbol, timestamp) else {
+            return ApplyOutcome::Ignored;
+        };
+        let depth = self.symbols.get_mut(&update.symbol).unwrap();
+
+        let follows = match (depth.last_update_id, depth.snapshot_id) {
+            (Some(last), _) => {
+                if update.final_update_id <= last {
+                    self.stats.superseded += 1;
+                    return ApplyOutcome::Ignored;
+                }
+                match update.previous_final_update_id {
+                    Some(previous) => previous == last,
+                    None => update.first_update_id == last + 1,
+                }
+            }
+            (None, Some(snapshot_id)) => {
+                if update.final_update_id <= snapshot_id {
+                    self.stats.superseded += 1;
+                    return ApplyOutcome::Ignored;
+                }
+                update.first_update_id <= snapshot_id + 1
+            }
+            (None, None) => {
+                self.stats.buffered += 1;
+                depth.pending.push(update);
+                return ApplyOutcome::Deferred;
+            }
+        };
+
+        if !follows {
+            self.stats.gaps += 1;
+            depth.snapshot_id = None;
+            depth.last_update_id = None;
+            self.stats.buffered += 1;
+            depth.pending.push(update);
+            if let Some(book) = books.get_mut(locate) {
+                book.set_stale(true);
+            }
+            return ApplyOutcome::Deferred;
+        }
+        depth.last_update_id = Some(update.final_update_id);
+
+        let Some(book) = books.get_mut(locate) else {
+            return ApplyOutcome::Ignored;
+        };
+        self.set_levels(book, &update.bids, &update.asks);
+        ApplyOutcome::LevelsUpdated
+    }
+
+    fn set_levels(
+        &mut self,
+        book: &mut OrderBook,
+        bids: &[(String, String)],
+        asks: &[(String, String)],
+    ) {
+        let levels = bids
+            .iter()
+            .map(|level| (OrderSide::Buy, level))
+            .chain(asks.iter().map(|level| (OrderSide::Sell, level)));
+        for (side, (price, quantity)) in levels {
+            let (Ok(price), Ok(volume)) = (self.scale.price(price), self.scale.size(quantity))
+            else {
+                self.stats.malformed += 1;
+                continue;
+            };
+            book.set_level(side, price, volume);
+        }
+    }
+
+    // The symbol's locate, listing it on first sight. None once the locates
+    // run out
+    fn listing(&mut self, books: &mut BookSet, symbol: &str, timestamp: u64) -> Option<u16> {
+        if let Some(depth) = self.symbols.get(symbol) {
+            return Some(depth.locate);
+        }
+        let locate = u16::try_from(self.symbols.len() + 1).ok()?;
+        self.symbols.insert(
+            symbol.to_string(),
+            Depth {
+                locate,
+                ..Depth::default()
+            },
+        );
+        books.add_listing(StockInfo::new(locate, symbol, timestamp));
+        Some(locate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(books: &BookSet, side: OrderSide) -> Vec<(u32, u32)> {
+        let book = books.get_by_symbol("BTCUSDT").unwrap();
+        book.levels(side)
+            .map(|(price, volume, _)| (price, volume))
+            .collect()
+    }
+
+    #[test]
+    fn levels_that_dont_fit_the_scale_are_malformed() {
+        let mut router = BinanceRouter::new(Scale::default());
+        let mut books = BookSet::new();
+        router
+            .apply_line(
+                &mut books,
+                r#"{"lastUpdateId":10,"symbol":"BTCUSDT",
+                    "bids":[["100.00000000","1.50000000"],["99.99","0.00001"]],
+                    "asks":[["100.01","99999999"],["100.02","abc"]]}"#,
+            )
+            .unwrap();
+        router
+            .apply_line(
+                &mut books,
+                r#"{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":11,"u":11,
+                    "b":[["100.00001","1"]],"a":[["100.01","2"]]}"#,
+            )
+            .unwrap();
+
+        assert_eq!(levels(&books, OrderSide::Buy), [(1_000_000, 15_000)]);
+        assert_eq!(levels(&books, OrderSide::Sell), [(1_000_100, 20_000)]);
+        assert_eq!(router.stats().malformed, 4);
+    }
+}
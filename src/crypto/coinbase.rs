@@ -0,0 +1,441 @@
+// Coinbase Exchange full channel (L3): received, open, match, change and done
+// messages, each with a sequence number per product. Only open orders rest
+// on the book, received orders may fill before they get there.
+//
+// The book starts from a snapshot of the REST level 3 book, recorded as
+// {"type":"snapshot","product_id":..,"sequence":..,"bids":[[price, size,
+// order_id]],"asks":[..]}. Messages at or before the snapshot's sequence are
+// already in it.
+
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Deserializer};
+
+use super::{FeedError, Scale, parse_utc_time};
+use crate::bookset::BookSet;
+use crate::directory::StockInfo;
+use crate::itch::{self, ApplyOutcome, OrderEvent};
+use crate::orderbook::{OrderBook, OrderSide};
+
+fn side<'de, D: Deserializer<'de>>(d: D) -> Result<OrderSide, D::Error> {
+    match <&str>::deserialize(d)? {
+        "buy" => Ok(OrderSide::Buy),
+        "sell" => Ok(OrderSide::Sell),
+        other => Err(serde::de::Error::unknown_variant(other, &["buy", "sell"])),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CoinbaseMessage {
+    Received {
+        product_id: String,
+        sequence: u64,
+        time: Option<String>,
+        order_id: String,
+        #[serde(deserialize_with = "side")]
+        side: OrderSide,
+    },
+    Open {
+        product_id: String,
+        sequence: u64,
+        time: Option<String>,
+        order_id: String,
+        #[serde(deserialize_with = "side")]
+        side: OrderSide,
+        price: String,
+        remaining_size: String,
+    },
+    Match {
+        product_id: String,
+        sequence: u64,
+        time: Option<String>,
+        maker_order_id: String,
+        taker_order_id: String,
+        // Side of the maker order
+        #[serde(deserialize_with = "side")]
+        side: OrderSide,
+        price: String,
+        size: String,
+    },
+    // Only ever a size cut, which keeps the order's priority
+    Change {
+        product_id: String,
+        sequence: u64,
+        time: Option<String>,
+        order_id: String,
+        #[serde(deserialize_with = "side")]
+        side: OrderSide,
+        price: Option<String>,
+        new_size: Option<String>,
+    },
+    // The order is filled or cancelled and off the book, if it was on it
+    Done {
+        product_id: String,
+        sequence: u64,
+        time: Option<String>,
+        order_id: String,
+        reason: String,
+    },
+    Snapshot {
+        product_id: String,
+        sequence: u64,
+        bids: Vec<(String, String, String)>,
+        asks: Vec<(String, String, String)>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl CoinbaseMessage {
+    pub fn parse(line: &str) -> Result<Self, FeedError> {
+        Ok(serde_json::from_str(line)?)
+    }
+
+    pub fn product_id(&self) -> Option<&str> {
+        match self {
+            CoinbaseMessage::Received { product_id, .. }
+            | CoinbaseMessage::Open { product_id, .. }
+            | CoinbaseMessage::Match { product_id, .. }
+            | CoinbaseMessage::Change { product_id, .. }
+            | CoinbaseMessage::Done { product_id, .. }
+            | CoinbaseMessage::Snapshot { product_id, .. } => Some(product_id),
+            CoinbaseMessage::Other => None,
+        }
+    }
+
+    pub fn sequence(&self) -> Option<u64> {
+        match self {
+            CoinbaseMessage::Received { sequence, .. }
+            | CoinbaseMessage::Open { sequence, .. }
+            | CoinbaseMessage::Match { sequence, .. }
+            | CoinbaseMessage::Change { sequence, .. }
+            | CoinbaseMessage::Done { sequence, .. }
+            | CoinbaseMessage::Snapshot { sequence, .. } => Some(*sequence),
+            CoinbaseMessage::Other => None,
+        }
+    }
+
+    // Nanoseconds since the Unix epoch
+    pub fn timestamp(&self) -> Option<u64> {
+        match self {
+            CoinbaseMessage::Received { time, .. }
+            | CoinbaseMessage::Open { time, .. }
+            | CoinbaseMessage::Match { time, .. }
+            | CoinbaseMessage::Change { time, .. }
+            | CoinbaseMessage::Done { time, .. } => parse_utc_time(time.as_deref()?),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct CoinbaseStats {
+    pub messages: u64,
+    pub snapshots: u64,
+    // Messages held until their product's snapshot
+    pub buffered: u64,
+    // Held messages the snapshot already covered
+    pub superseded: u64,
+    pub duplicates: u64,
+    pub gaps: u64,
+    pub lost: u64,
+    // Messages with prices or sizes that aren't decimals, or don't fit the
+    // book at the router's Scale
+    pub malformed: u64,
+    // Matches and changes for orders not on the book
+    pub unknown_orders: u64,
+}
+
+#[derive(Debug, Default)]
+struct Product {
+    locate: u16,
+    // Last applied sequence number, None until a snapshot arrives
+    sequence: Option<u64>,
+    pending: Vec<CoinbaseMessage>,
+}
+
+// Applies full channel messages to a BookSet, one book per product. Order
+// ids are given book references as they're opened.
+#[derive(Debug, Default)]
+pub struct CoinbaseRouter {
+    scale: Scale,
+    products: FxHashMap<String, Product>,
+    // Orders on a book: order id -> (stock_locate, reference)
+    orders: FxHashMap<String, (u16, u64)>,
+    next_reference: u64,
+    stats: CoinbaseStats,
+}
+
+impl CoinbaseRouter {
+    pub fn new(scale: Scale) -> Self {
+        CoinbaseRouter {
+            scale,
+            ..CoinbaseRouter::default()
+        }
+    }
+
+    pub fn stats(&self) -> &CoinbaseStats {
+        &self.stats
+    }
+
+    // Locate of a product's book, 0 if it hasn't been seen
+    pub fn locate(&self, product_id: &str) -> u16 {
+        self.products
+            .get(product_id)
+            .map_or(0, |product| product.locate)
+    }
+
+    // Book reference of an order on the book
+    pub fn reference(&self, order_id: &str) -> Option<u64> {
+        self.orders.get(order_id).map(|(_, reference)| *reference)
+    }
+
+    pub fn apply_line(
+        &mut self,
+        books: &mut BookSet,
+        line: &str,
+    ) -> Result<ApplyOutcome, FeedError> {
+        let msg = CoinbaseMessage::parse(line)?;
+        Ok(self.apply(books, msg))
+    }
+
+    // Applies a message in sequence. Messages before a product's snapshot,
+    // or after a gap until the next one, are held and report Deferred
+    pub fn apply(&mut self, books: &mut BookSet, msg: CoinbaseMessage) -> ApplyOutcome {
+        let Some(product_id) = msg.product_id() else {
+            return ApplyOutcome::Ignored;
+        };
+        self.stats.messages += 1;
+        let Some(locate) = self.listing(books, product_id, msg.timestamp().unwrap_or(0)) else {
+            return ApplyOutcome::Ignored;
+        };
+
+        if let CoinbaseMessage::Snapshot {
+            product_id,
+            sequence,
+            bids,
+            asks,
+        } = msg
+        {
+            return self.snapshot(books, &product_id, locate, sequence, &bids, &asks);
+        }
+        self.sequenced(books, locate, msg)
+    }
+
+    // Applies a message if it's next for its product, holding it otherwise
+    fn sequenced(
+        &mut self,
+        books: &mut BookSet,
+        locate: u16,
+        msg: CoinbaseMessage,
+    ) -> ApplyOutcome {
+        let (Some(product_id), Some(sequence)) = (msg.product_id(), msg.sequence()) else {
+            return ApplyOutcome::Ignored;
+        };
+        let product = self.products.get_mut(product_id).unwrap();
+        let Some(last) = product.sequence else {
+            self.stats.buffered += 1;
+            product.pending.push(msg);
+            return ApplyOutcome::Deferred;
+        };
+        if sequence <= last {
+            self.stats.duplicates += 1;
+            return ApplyOutcome::Ignored;
+        }
+        if sequence > last + 1 {
+            self.stats.gaps += 1;
+            self.stats.lost += sequence - last - 1;
+            product.sequence = None;
+            self.stats.buffered += 1;
+            product.pending.push(msg);
+            if let Some(book) = books.get_mut(locate) {
+                book.set_stale(true);
+            }
+            return ApplyOutcome::Deferred;
+        }
+        product.sequence = Some(sequence);
+
+        let Some(book) = books.get_mut(locate) else {
+            return ApplyOutcome::Ignored;
+        };
+        self.apply_to_book(book, locate, &msg).unwrap_or_else(|_| {
+            self.stats.malformed += 1;
+            ApplyOutcome::Ignored
+        })
+    }
+
+    // Replaces the product's book with the snapshot, then applies the held
+    // messages that came after it
+    fn snapshot(
+        &mut self,
+        books: &mut BookSet,
+        product_id: &str,
+        locate: u16,
+        sequence: u64,
+        bids: &[(String, String, String)],
+        asks: &[(String, String, String)],
+    ) -> ApplyOutcome {
+        self.stats.snapshots += 1;
+        let Some(book) = books.get_mut(locate) else {
+            return ApplyOutcome::Ignored;
+        };
+        book.clear();
+        book.set_stale(false);
+        self.orders.retain(|_, (l, _)| *l != locate);
+
+        let orders = bids
+            .iter()
+            .map(|order| (OrderSide::Buy, order))
+            .chain(asks.iter().map(|order| (OrderSide::Sell, order)));
+        for (side, (price, size, order_id)) in orders {
+            let (Ok(price), Ok(size)) = (self.scale.price(price), self.scale.size(size)) else {
+                self.stats.malformed += 1;
+                continue;
+            };
+            self.add(book, locate, order_id, side, price, size);
+        }
+
+        let product = self.products.get_mut(product_id).unwrap();
+        product.sequence = Some(sequence);
+        let pending = std::mem::take(&mut product.pending);
+        for msg in pending {
+            if msg.sequence().is_some_and(|s| s <= sequence) {
+                self.stats.superseded += 1;
+                continue;
+            }
+            self.sequenced(books, locate, msg);
+        }
+        ApplyOutcome::Added
+    }
+
+    fn apply_to_book(
+        &mut self,
+        book: &mut OrderBook,
+        locate: u16,
+        msg: &CoinbaseMessage,
+    ) -> Result<ApplyOutcome, FeedError> {
+        Ok(match msg {
+            CoinbaseMessage::Open {
+                order_id,
+                side,
+                price,
+                remaining_size,
+                ..
+            } => {
+                let price = self.scale.price(price)?;
+                let size = self.scale.size(remaining_size)?;
+                self.add(book, locate, order_id, *side, price, size)
+            }
+            CoinbaseMessage::Match {
+                maker_order_id,
+                size,
+                ..
+            } => {
+                let size = self.scale.size(size)?;
+                self.on_order(book, maker_order_id, |book, reference, shares| {
+                    itch::apply_event(
+                        book,
+                        OrderEvent::Executed {
+                            reference,
+                            executed: size.min(shares),
+                        },
+                    )
+                })
+            }
+            CoinbaseMessage::Change {
+                order_id, new_size, ..
+            } => {
+                let Some(new_size) = new_size else {
+                    // Market orders change their funds, they don't rest
+                    return Ok(ApplyOutcome::Ignored);
+                };
+                let new_size = self.scale.size(new_size)?;
+                self.on_order(book, order_id, |book, reference, shares| {
+                    if new_size >= shares {
+                        return ApplyOutcome::Ignored;
+                    }
+                    itch::apply_event(
+                        book,
+                        OrderEvent::Cancelled {
+                            reference,
+                            cancelled: shares - new_size,
+                        },
+                    )
+                })
+            }
+            CoinbaseMessage::Done { order_id, .. } => {
+                // Orders that never opened were never on the book
+                let Some((_, reference)) = self.orders.remove(order_id) else {
+                    return Ok(ApplyOutcome::Ignored);
+                };
+                if book.order(reference).is_none() {
+                    return Ok(ApplyOutcome::Ignored);
+                }
+                itch::apply_event(book, OrderEvent::Deleted { reference })
+            }
+            _ => ApplyOutcome::Ignored,
+        })
+    }
+
+    fn add(
+        &mut self,
+        book: &mut OrderBook,
+        locate: u16,
+        order_id: &str,
+        side: OrderSide,
+        price: u32,
+        shares: u32,
+    ) -> ApplyOutcome {
+        if shares == 0 {
+            return ApplyOutcome::Ignored;
+        }
+        self.next_reference += 1;
+        let reference = self.next_reference;
+        self.orders
+            .insert(order_id.to_string(), (locate, reference));
+        itch::apply_event(
+            book,
+            OrderEvent::Add {
+                reference,
+                side,
+                shares,
+                price,
+                mpid: None,
+            },
+        )
+    }
+
+    // Runs f on an order on the book with its reference and size
+    fn on_order<F>(&mut self, book: &mut OrderBook, order_id: &str, f: F) -> ApplyOutcome
+    where
+        F: FnOnce(&mut OrderBook, u64, u32) -> ApplyOutcome,
+    {
+        let Some(&(_, reference)) = self.orders.get(order_id) else {
+            self.stats.unknown_orders += 1;
+            return ApplyOutcome::Ignored;
+        };
+        let Some((_, shares, _)) = book.order(reference) else {
+            self.stats.unknown_orders += 1;
+            return ApplyOutcome::Ignored;
+        };
+        f(book, reference, shares)
+    }
+
+    // The product's locate, listing it on first sight. None once the
+    // locates run out
+    fn listing(&mut self, books: &mut BookSet, product_id: &str, timestamp: u64) -> Option<u16> {
+        if let Some(product) = self.products.get(product_id) {
+            return Some(product.locate);
+        }
+        let locate = u16::try_from(self.products.len() + 1).ok()?;
+        self.products.insert(
+            product_id.to_string(),
+            Product {
+                locate,
+                ..Product::default()
+            },
+        );
+        books.add_listing(StockInfo::new(locate, product_id, timestamp));
+        Some(locate)
+    }
+}
@@ -0,0 +1,800 @@
+// Databento Binary Encoding (DBN) files with MBO (market by order) records,
+// versions 1 to 3, raw or zstd compressed. A file is the DBN magic, a
+// version byte and length-prefixed metadata, then records that each start
+// with their length in 4 byte words. Fields are little-endian.
+//
+// Records carry two timestamps, both nanoseconds since the Unix epoch:
+// ts_event from the exchange's matching engine and ts_recv from Databento's
+// capture. ts_in_delta is how long before ts_recv the venue's gateway sent
+// the record.
+
+use std::fmt;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+use rustc_hash::FxHashMap;
+
+use crate::bookset::BookSet;
+use crate::directory::StockInfo;
+use crate::input;
+use crate::itch::{self, ApplyOutcome, OrderEvent};
+use crate::orderbook::{OrderBook, OrderSide};
+
+pub const MAGIC: &[u8; 3] = b"DBN";
+pub const MAX_VERSION: u8 = 3;
+
+pub const RTYPE_MBO: u8 = 0xA0;
+pub const RTYPE_SYMBOL_MAPPING: u8 = 0x16;
+const RECORD_HEADER_LEN: usize = 16;
+const MBO_LEN: usize = 56;
+
+// Fixed part of the metadata, before the variable length symbol lists
+const METADATA_FIXED_LEN: usize = 100;
+// Symbol fields in version 1 files, later versions give their own length
+const V1_SYMBOL_CSTR_LEN: usize = 22;
+
+// Record flags
+pub const F_LAST: u8 = 1 << 7;
+// Top of book rather than an order
+pub const F_TOB: u8 = 1 << 6;
+pub const F_SNAPSHOT: u8 = 1 << 5;
+pub const F_MBP: u8 = 1 << 4;
+pub const F_BAD_TS_RECV: u8 = 1 << 3;
+// An unrecoverable gap upstream, the book may be wrong
+pub const F_MAYBE_BAD_BOOK: u8 = 1 << 2;
+
+// Sent for prices that don't apply, e.g. on Clear records
+pub const UNDEF_PRICE: i64 = i64::MAX;
+// DBN prices are in units of 1e-9, book prices in 1e-4
+const PRICE_SCALE: i64 = 100_000;
+
+#[derive(Debug)]
+pub enum DbnError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion(u8),
+    // The file ends inside the metadata or a record, or a record is shorter
+    // than its type
+    Truncated,
+}
+
+impl fmt::Display for DbnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbnError::Io(e) => write!(f, "{e}"),
+            DbnError::BadMagic => write!(f, "not a DBN file"),
+            DbnError::UnsupportedVersion(v) => write!(f, "unsupported DBN version {v}"),
+            DbnError::Truncated => write!(f, "truncated DBN file"),
+        }
+    }
+}
+
+impl std::error::Error for DbnError {}
+
+impl From<io::Error> for DbnError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            DbnError::Truncated
+        } else {
+            DbnError::Io(e)
+        }
+    }
+}
+
+fn le_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+// Null padded string field
+fn cstr(b: &[u8]) -> &str {
+    let end = b.iter().position(|&c| c == 0).unwrap_or(b.len());
+    std::str::from_utf8(&b[..end]).unwrap_or("")
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    pub version: u8,
+    pub dataset: String,
+    // None for files mixing schemas
+    pub schema: Option<u16>,
+    // Nanoseconds since the Unix epoch
+    pub start: u64,
+    pub end: u64,
+    // Instrument ID -> raw symbol, from the metadata's symbol mappings
+    pub symbols: FxHashMap<u32, String>,
+}
+
+impl Metadata {
+    fn parse(version: u8, b: &[u8]) -> Result<Self, DbnError> {
+        if b.len() < METADATA_FIXED_LEN {
+            return Err(DbnError::Truncated);
+        }
+        let schema = le_u16(b, 16);
+        let mut metadata = Metadata {
+            version,
+            dataset: cstr(&b[..16]).to_string(),
+            schema: (schema != u16::MAX).then_some(schema),
+            start: le_u64(b, 18),
+            end: le_u64(b, 26),
+            symbols: FxHashMap::default(),
+        };
+
+        let symbol_len = if version == 1 {
+            V1_SYMBOL_CSTR_LEN
+        } else {
+            le_u16(b, 45) as usize
+        };
+        // Symbol mappings are a convenience, a file without them is still
+        // usable with instrument IDs as symbols
+        let _ = metadata.parse_mappings(&b[METADATA_FIXED_LEN..], symbol_len);
+        Ok(metadata)
+    }
+
+    fn parse_mappings(&mut self, b: &[u8], symbol_len: usize) -> Option<()> {
+        let mut pos = 0;
+        let mut take = |len: usize| -> Option<&[u8]> {
+            let field = b.get(pos..pos + len)?;
+            pos += len;
+            Some(field)
+        };
+        let schema_definition_len = le_u32(take(4)?, 0) as usize;
+        take(schema_definition_len)?;
+
+        // Requested symbols, partially resolved and not found ones
+        for _ in 0..3 {
+            let count = le_u32(take(4)?, 0) as usize;
+            take(count.checked_mul(symbol_len)?)?;
+        }
+
+        let mappings = le_u32(take(4)?, 0);
+        for _ in 0..mappings {
+            let raw_symbol = cstr(take(symbol_len)?).to_string();
+            let intervals = le_u32(take(4)?, 0);
+            for _ in 0..intervals {
+                // Start and end dates
+                take(8)?;
+                if let Ok(instrument_id) = cstr(take(symbol_len)?).parse::<u32>() {
+                    self.symbols.insert(instrument_id, raw_symbol.clone());
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+// An MBO record. Prices are in units of 1e-9
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct MboMsg {
+    pub publisher_id: u16,
+    pub instrument_id: u32,
+    pub ts_event: u64,
+    pub order_id: u64,
+    pub price: i64,
+    pub size: u32,
+    pub flags: u8,
+    pub channel_id: u8,
+    // A(dd), M(odify), C(ancel), R (clear book), T(rade), F(ill) or N(one)
+    pub action: u8,
+    // A(sk), B(id) or N(one)
+    pub side: u8,
+    pub ts_recv: u64,
+    pub ts_in_delta: i32,
+    pub sequence: u32,
+}
+
+impl MboMsg {
+    pub fn parse(b: &[u8]) -> Option<Self> {
+        if b.len() < MBO_LEN || b[1] != RTYPE_MBO {
+            return None;
+        }
+        Some(MboMsg {
+            publisher_id: le_u16(b, 2),
+            instrument_id: le_u32(b, 4),
+            ts_event: le_u64(b, 8),
+            order_id: le_u64(b, 16),
+            price: le_u64(b, 24) as i64,
+            size: le_u32(b, 32),
+            flags: b[36],
+            channel_id: b[37],
+            action: b[38],
+            side: b[39],
+            ts_recv: le_u64(b, 40),
+            ts_in_delta: le_u32(b, 48) as i32,
+            sequence: le_u32(b, 52),
+        })
+    }
+
+    // When the venue's gateway sent the record, nanoseconds since the Unix
+    // epoch
+    pub fn ts_gateway(&self) -> u64 {
+        self.ts_recv.wrapping_sub(self.ts_in_delta as i64 as u64)
+    }
+
+    // Last record of an event, the book is consistent after it
+    pub fn is_last(&self) -> bool {
+        self.flags & F_LAST != 0
+    }
+
+    pub fn order_side(&self) -> Option<OrderSide> {
+        match self.side {
+            b'B' => Some(OrderSide::Buy),
+            b'A' => Some(OrderSide::Sell),
+            _ => None,
+        }
+    }
+}
+
+// A record of any type, for callers that want more than MBO
+#[derive(Debug, Copy, Clone)]
+pub struct Record<'a> {
+    pub rtype: u8,
+    pub instrument_id: u32,
+    pub bytes: &'a [u8],
+}
+
+pub struct DbnReader<R> {
+    reader: R,
+    metadata: Metadata,
+    buf: Vec<u8>,
+}
+
+impl DbnReader<Box<dyn BufRead + Send>> {
+    // zstd compressed files are decompressed as they're read
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DbnError> {
+        DbnReader::new(input::open(path)?)
+    }
+}
+
+impl<R: Read> DbnReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, DbnError> {
+        let mut prelude = [0u8; 8];
+        reader.read_exact(&mut prelude)?;
+        if &prelude[..3] != MAGIC {
+            return Err(DbnError::BadMagic);
+        }
+        let version = prelude[3];
+        if version == 0 || version > MAX_VERSION {
+            return Err(DbnError::UnsupportedVersion(version));
+        }
+
+        let mut metadata = vec![0u8; le_u32(&prelude, 4) as usize];
+        reader.read_exact(&mut metadata)?;
+        Ok(DbnReader {
+            reader,
+            metadata: Metadata::parse(version, &metadata)?,
+            buf: Vec::with_capacity(256),
+        })
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    // The next record, or None at the end of the file. Symbol mapping
+    // records from live data are added to the metadata's symbols
+    pub fn next_record(&mut self) -> Result<Option<Record<'_>>, DbnError> {
+        let mut len = [0u8; 1];
+        if self.reader.read(&mut len)? == 0 {
+            return Ok(None);
+        }
+        let len = len[0] as usize * 4;
+        if len < RECORD_HEADER_LEN {
+            return Err(DbnError::Truncated);
+        }
+        self.buf.resize(len, 0);
+        self.buf[0] = len as u8;
+        self.reader.read_exact(&mut self.buf[1..])?;
+
+        let rtype = self.buf[1];
+        let instrument_id = le_u32(&self.buf, 4);
+        if rtype == RTYPE_SYMBOL_MAPPING {
+            self.map_symbol(instrument_id);
+        }
+        Ok(Some(Record {
+            rtype,
+            instrument_id,
+            bytes: &self.buf,
+        }))
+    }
+
+    // The next MBO record, skipping records of other types
+    pub fn next_mbo(&mut self) -> Result<Option<MboMsg>, DbnError> {
+        loop {
+            let Some(record) = self.next_record()? else {
+                return Ok(None);
+            };
+            if record.rtype != RTYPE_MBO {
+                continue;
+            }
+            return MboMsg::parse(record.bytes)
+                .map(Some)
+                .ok_or(DbnError::Truncated);
+        }
+    }
+
+    fn map_symbol(&mut self, instrument_id: u32) {
+        // stype_in_symbol follows the header, after a stype byte from
+        // version 2 on
+        let (at, len) = if self.metadata.version == 1 {
+            (16, V1_SYMBOL_CSTR_LEN)
+        } else {
+            (17, 71)
+        };
+        if let Some(symbol) = self.buf.get(at..at + len) {
+            self.metadata
+                .symbols
+                .insert(instrument_id, cstr(symbol).to_string());
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct MboStats {
+    pub records: u64,
+    // Events whose records were applied together
+    pub events: u64,
+    pub clears: u64,
+    // Modifies applied in place, keeping the order's queue position
+    pub modifies_in_place: u64,
+    // Modifies applied as a delete and add, losing queue position
+    pub modifies_requeued: u64,
+    // Cancels and modifies for orders not on the book. Modifies of unknown
+    // orders are applied as adds
+    pub unknown_orders: u64,
+    // Top of book records, which aren't orders
+    pub top_of_book: u64,
+    // Records flagged as possibly leaving the book wrong
+    pub maybe_bad_book: u64,
+    // Adds and modifies with prices finer than the book's 1e-4 or outside
+    // its 32 bits. Orders modified to such a price are removed
+    pub rejected: u64,
+}
+
+// Applies MBO records to a BookSet, one book per instrument ID. Books are
+// listed with the metadata's symbol for the instrument, or the instrument ID
+// if it has none. Records are held until the record that ends their event
+// (F_LAST), then applied together, so a book is never seen half way through
+// an event. Held records report Deferred.
+#[derive(Debug, Default)]
+pub struct MboRouter {
+    symbols: FxHashMap<u32, String>,
+    locates: FxHashMap<u32, u16>,
+    pending: Vec<MboMsg>,
+    stats: MboStats,
+}
+
+impl MboRouter {
+    pub fn new(metadata: &Metadata) -> Self {
+        MboRouter {
+            symbols: metadata.symbols.clone(),
+            ..MboRouter::default()
+        }
+    }
+
+    pub fn stats(&self) -> &MboStats {
+        &self.stats
+    }
+
+    // Locate of an instrument's book, 0 if it hasn't been seen
+    pub fn locate(&self, instrument_id: u32) -> u16 {
+        self.locates.get(&instrument_id).copied().unwrap_or(0)
+    }
+
+    // Applies every MBO record in a file. on_applied sees each record as
+    // it's applied, at the end of its event
+    pub fn run<R, F>(
+        &mut self,
+        reader: &mut DbnReader<R>,
+        books: &mut BookSet,
+        mut on_applied: F,
+    ) -> Result<(), DbnError>
+    where
+        R: Read,
+        F: FnMut(&MboMsg, ApplyOutcome),
+    {
+        while let Some(msg) = reader.next_mbo()? {
+            // Symbols may have been mapped by records since the last one
+            if !self.locates.contains_key(&msg.instrument_id)
+                && let Some(symbol) = reader.metadata().symbols.get(&msg.instrument_id)
+            {
+                self.symbols.insert(msg.instrument_id, symbol.clone());
+            }
+
+            self.stats.records += 1;
+            self.pending.push(msg);
+            if msg.is_last() {
+                self.stats.events += 1;
+                for msg in std::mem::take(&mut self.pending) {
+                    let outcome = self.apply_now(books, &msg);
+                    on_applied(&msg, outcome);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Applies one record, or holds it until its event ends
+    pub fn apply(&mut self, books: &mut BookSet, msg: &MboMsg) -> ApplyOutcome {
+        self.stats.records += 1;
+        self.pending.push(*msg);
+        if !msg.is_last() {
+            return ApplyOutcome::Deferred;
+        }
+
+        self.stats.events += 1;
+        let pending = std::mem::take(&mut self.pending);
+        let (last, held) = pending.split_last().unwrap();
+        for msg in held {
+            self.apply_now(books, msg);
+        }
+        self.apply_now(books, last)
+    }
+
+    fn apply_now(&mut self, books: &mut BookSet, msg: &MboMsg) -> ApplyOutcome {
+        let Some(locate) = self.listing(books, msg) else {
+            return ApplyOutcome::Ignored;
+        };
+        let book = books.get_mut(locate).unwrap();
+        if msg.flags & F_MAYBE_BAD_BOOK != 0 {
+            self.stats.maybe_bad_book += 1;
+            book.set_stale(true);
+        }
+        if msg.flags & F_TOB != 0 {
+            self.stats.top_of_book += 1;
+            return ApplyOutcome::Ignored;
+        }
+
+        match msg.action {
+            b'A' => {
+                let Some(side) = msg.order_side() else {
+                    return ApplyOutcome::Ignored;
+                };
+                // A repeated add is a modify
+                if book.order(msg.order_id).is_some() {
+                    return self.modify(book, msg);
+                }
+                let Some(price) = self.price(msg.price) else {
+                    return ApplyOutcome::Ignored;
+                };
+                add(book, msg.order_id, side, msg.size, price)
+            }
+            b'M' => self.modify(book, msg),
+            b'C' => {
+                let Some((_, shares, _)) = book.order(msg.order_id) else {
+                    self.stats.unknown_orders += 1;
+                    return ApplyOutcome::Ignored;
+                };
+                let event = if msg.size >= shares {
+                    OrderEvent::Deleted {
+                        reference: msg.order_id,
+                    }
+                } else {
+                    OrderEvent::Cancelled {
+                        reference: msg.order_id,
+                        cancelled: msg.size,
+                    }
+                };
+                itch::apply_event(book, event)
+            }
+            b'R' => {
+                self.stats.clears += 1;
+                book.clear();
+                book.set_stale(msg.flags & F_MAYBE_BAD_BOOK != 0);
+                ApplyOutcome::Cleared
+            }
+            // Trades and fills are reported for the tape, the resting order
+            // is reduced by a Cancel that follows them
+            _ => ApplyOutcome::Ignored,
+        }
+    }
+
+    // A size cut at the same price keeps the order's priority. Anything else
+    // sends it to the back of the queue at its new price and side. Modifies
+    // for unknown orders are adds, and orders modified to a price the book
+    // can't show are removed
+    fn modify(&mut self, book: &mut OrderBook, msg: &MboMsg) -> ApplyOutcome {
+        let Some(side) = msg.order_side() else {
+            return ApplyOutcome::Ignored;
+        };
+        let reference = msg.order_id;
+        let Some((old_price, shares, old_side)) = book.order(reference) else {
+            self.stats.unknown_orders += 1;
+            if msg.size == 0 {
+                return ApplyOutcome::Ignored;
+            }
+            return match self.price(msg.price) {
+                Some(price) => add(book, reference, side, msg.size, price),
+                None => ApplyOutcome::Ignored,
+            };
+        };
+
+        if msg.size == 0 {
+            return itch::apply_event(book, OrderEvent::Deleted { reference });
+        }
+        let Some(price) = self.price(msg.price) else {
+            return itch::apply_event(book, OrderEvent::Deleted { reference });
+        };
+        if price == old_price && side == old_side && msg.size <= shares {
+            self.stats.modifies_in_place += 1;
+            if msg.size == shares {
+                return ApplyOutcome::Ignored;
+            }
+            return itch::apply_event(
+                book,
+                OrderEvent::Cancelled {
+                    reference,
+                    cancelled: shares - msg.size,
+                },
+            );
+        }
+
+        self.stats.modifies_requeued += 1;
+        if side != old_side {
+            itch::apply_event(book, OrderEvent::Deleted { reference });
+            add(book, reference, side, msg.size, price);
+            return ApplyOutcome::Replaced;
+        }
+        itch::apply_event(
+            book,
+            OrderEvent::Replaced {
+                old_reference: reference,
+                new_reference: reference,
+                shares: msg.size,
+                price,
+            },
+        )
+    }
+
+    // The instrument's locate, listing it on first sight. None once the
+    // locates run out
+    fn listing(&mut self, books: &mut BookSet, msg: &MboMsg) -> Option<u16> {
+        if let Some(locate) = self.locates.get(&msg.instrument_id) {
+            return Some(*locate);
+        }
+        let locate = u16::try_from(self.locates.len() + 1).ok()?;
+        self.locates.insert(msg.instrument_id, locate);
+
+        let symbol = self
+            .symbols
+            .get(&msg.instrument_id)
+            .cloned()
+            .unwrap_or_else(|| msg.instrument_id.to_string());
+        books.add_listing(StockInfo::new(locate, &symbol, msg.ts_event));
+        Some(locate)
+    }
+
+    // The book price for a DBN price, None if it has to be rejected
+    fn price(&mut self, price: i64) -> Option<u32> {
+        let book_price = match price % PRICE_SCALE {
+            0 => u32::try_from(price / PRICE_SCALE).ok(),
+            _ => None,
+        };
+        if book_price.is_none() {
+            self.stats.rejected += 1;
+        }
+        book_price
+    }
+}
+
+fn add(
+    book: &mut OrderBook,
+    reference: u64,
+    side: OrderSide,
+    shares: u32,
+    price: u32,
+) -> ApplyOutcome {
+    itch::apply_event(
+        book,
+        OrderEvent::Add {
+            reference,
+            side,
+            shares,
+            price,
+            mpid: None,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file's prelude and metadata, mapping each symbol to one instrument
+    fn header(version: u8, symbol_len: usize, mappings: &[(&str, u32)]) -> Vec<u8> {
+        let field = |text: &str, len: usize| {
+            let mut b = text.as_bytes().to_vec();
+            b.resize(len, 0);
+            b
+        };
+        let mut metadata = field("XNAS.ITCH", 16);
+        metadata.extend_from_slice(&160u16.to_le_bytes());
+        metadata.extend_from_slice(&1_000u64.to_le_bytes());
+        metadata.extend_from_slice(&2_000u64.to_le_bytes());
+        metadata.resize(45, 0);
+        if version > 1 {
+            metadata.extend_from_slice(&(symbol_len as u16).to_le_bytes());
+        }
+        metadata.resize(METADATA_FIXED_LEN, 0);
+
+        // A schema definition, then one requested symbol and no partial or
+        // not found ones
+        metadata.extend_from_slice(&3u32.to_le_bytes());
+        metadata.extend_from_slice(b"abc");
+        metadata.extend_from_slice(&1u32.to_le_bytes());
+        metadata.extend(field("ALL", symbol_len));
+        metadata.extend_from_slice(&0u32.to_le_bytes());
+        metadata.extend_from_slice(&0u32.to_le_bytes());
+
+        metadata.extend_from_slice(&(mappings.len() as u32).to_le_bytes());
+        for (symbol, instrument_id) in mappings {
+            metadata.extend(field(symbol, symbol_len));
+            metadata.extend_from_slice(&1u32.to_le_bytes());
+            metadata.extend_from_slice(&20240102u32.to_le_bytes());
+            metadata.extend_from_slice(&20240103u32.to_le_bytes());
+            metadata.extend(field(&instrument_id.to_string(), symbol_len));
+        }
+
+        let mut file = MAGIC.to_vec();
+        file.push(version);
+        file.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+        file.extend(metadata);
+        file
+    }
+
+    fn mbo(order_id: u64, action: u8, side: u8, price: i64, size: u32, flags: u8) -> MboMsg {
+        MboMsg {
+            publisher_id: 2,
+            instrument_id: 7,
+            ts_event: 1_000,
+            order_id,
+            price,
+            size,
+            flags,
+            channel_id: 0,
+            action,
+            side,
+            ts_recv: 1_500,
+            ts_in_delta: 200,
+            sequence: 1,
+        }
+    }
+
+    fn encode(msg: &MboMsg) -> Vec<u8> {
+        let mut b = vec![(MBO_LEN / 4) as u8, RTYPE_MBO];
+        b.extend_from_slice(&msg.publisher_id.to_le_bytes());
+        b.extend_from_slice(&msg.instrument_id.to_le_bytes());
+        b.extend_from_slice(&msg.ts_event.to_le_bytes());
+        b.extend_from_slice(&msg.order_id.to_le_bytes());
+        b.extend_from_slice(&msg.price.to_le_bytes());
+        b.extend_from_slice(&msg.size.to_le_bytes());
+        b.extend_from_slice(&[msg.flags, msg.channel_id, msg.action, msg.side]);
+        b.extend_from_slice(&msg.ts_recv.to_le_bytes());
+        b.extend_from_slice(&msg.ts_in_delta.to_le_bytes());
+        b.extend_from_slice(&msg.sequence.to_le_bytes());
+        b
+    }
+
+    #[test]
+    fn metadata_headers() {
+        for (version, symbol_len) in [(1, V1_SYMBOL_CSTR_LEN), (2, 71), (3, 71)] {
+            let file = header(version, symbol_len, &[("AAPL", 7), ("MSFT", 9)]);
+            let reader = DbnReader::new(&file[..]).unwrap();
+            let metadata = reader.metadata();
+            assert_eq!(metadata.version, version);
+            assert_eq!(metadata.dataset, "XNAS.ITCH");
+            assert_eq!(metadata.schema, Some(160));
+            assert_eq!((metadata.start, metadata.end), (1_000, 2_000));
+            assert_eq!(metadata.symbols.len(), 2);
+            assert_eq!(metadata.symbols[&7], "AAPL");
+            assert_eq!(metadata.symbols[&9], "MSFT");
+        }
+
+        let file = header(2, 71, &[]);
+        assert!(matches!(
+            DbnReader::new(&file[..file.len() - 1]),
+            Err(DbnError::Truncated)
+        ));
+        let mut file = header(2, 71, &[]);
+        file[3] = 4;
+        assert!(matches!(
+            DbnReader::new(&file[..]),
+            Err(DbnError::UnsupportedVersion(4))
+        ));
+        assert!(matches!(
+            DbnReader::new(&b"DBZ\x01\0\0\0\0"[..]),
+            Err(DbnError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn mbo_records() {
+        let msg = MboMsg {
+            publisher_id: 0x0102,
+            instrument_id: 0x0304_0506,
+            ts_event: 0x0708_090a_0b0c_0d0e,
+            order_id: 0x1112_1314_1516_1718,
+            price: -0x2122_2324_2526_2728,
+            size: 0x3132_3334,
+            flags: F_LAST | F_SNAPSHOT,
+            channel_id: 0x41,
+            action: b'M',
+            side: b'A',
+            ts_recv: 0x5152_5354_5556_5758,
+            ts_in_delta: -0x6162_6364,
+            sequence: 0x7172_7374,
+        };
+        assert_eq!(MboMsg::parse(&encode(&msg)), Some(msg));
+        assert_eq!(MboMsg::parse(&encode(&msg)[..MBO_LEN - 1]), None);
+
+        let mut file = header(2, 71, &[]);
+        file.extend(encode(&msg));
+        // A record of another type, then one cut short
+        let mut other = vec![0u8; 32];
+        other[0] = 8;
+        other[1] = 0x01;
+        file.extend(other);
+        file.extend(&encode(&msg)[..40]);
+
+        let mut reader = DbnReader::new(&file[..]).unwrap();
+        assert_eq!(reader.next_mbo().unwrap(), Some(msg));
+        assert!(matches!(reader.next_mbo(), Err(DbnError::Truncated)));
+    }
+
+    #[test]
+    fn symbol_mapping_records() {
+        for version in [1, 2] {
+            let mut record = vec![0u8; 176];
+            record[0] = 44;
+            record[1] = RTYPE_SYMBOL_MAPPING;
+            record[4..8].copy_from_slice(&7u32.to_le_bytes());
+            let at = if version == 1 { 16 } else { 17 };
+            record[at..at + 4].copy_from_slice(b"AAPL");
+
+            let mut file = header(version, V1_SYMBOL_CSTR_LEN, &[]);
+            file.extend(record);
+            let mut reader = DbnReader::new(&file[..]).unwrap();
+            assert_eq!(reader.next_mbo().unwrap(), None);
+            assert_eq!(reader.metadata().symbols[&7], "AAPL");
+        }
+    }
+
+    #[test]
+    fn prices_the_book_cant_show_are_rejected() {
+        let mut router = MboRouter::default();
+        let mut books = BookSet::new();
+        let add = |order_id, price| mbo(order_id, b'A', b'B', price, 100, F_LAST);
+        router.apply(&mut books, &add(1, 150_000_000_000));
+        router.apply(&mut books, &add(2, 150_000_050_000));
+        router.apply(&mut books, &add(3, -100_000));
+        router.apply(&mut books, &add(4, (u32::MAX as i64 + 1) * PRICE_SCALE));
+        router.apply(&mut books, &add(5, 149_000_000_000));
+        // Moves to a price finer than 1e-4, and to one the book can show
+        router.apply(
+            &mut books,
+            &mbo(5, b'M', b'B', 149_000_000_001, 100, F_LAST),
+        );
+        router.apply(
+            &mut books,
+            &mbo(6, b'M', b'B', 148_000_000_001, 100, F_LAST),
+        );
+
+        let book = books.get(router.locate(7)).unwrap();
+        let bids: Vec<_> = book
+            .levels(OrderSide::Buy)
+            .map(|(price, volume, _)| (price, volume))
+            .collect();
+        assert_eq!(bids, [(1_500_000, 100)]);
+        assert_eq!(book.order(5), None);
+        let stats = router.stats();
+        assert_eq!(stats.rejected, 5);
+        assert_eq!(stats.unknown_orders, 1);
+    }
+}
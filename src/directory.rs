@@ -0,0 +1,156 @@
+use itchy::{
+    Body, FinancialStatus, IssueClassification, IssueSubType, LuldRefPriceTier, MarketCategory,
+    Message,
+};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::orderbook::BookConfig;
+
+// Everything the Stock Directory (R) message says about a symbol
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StockInfo {
+    pub stock_locate: u16,
+    pub symbol: String,
+    pub timestamp: u64,
+    pub market_category: MarketCategory,
+    pub financial_status: FinancialStatus,
+    pub round_lot_size: u32,
+    pub round_lots_only: bool,
+    pub issue_classification: IssueClassification,
+    pub issue_subtype: IssueSubType,
+    pub authenticity: bool,
+    pub short_sale_threshold: Option<bool>,
+    pub ipo_flag: Option<bool>,
+    pub luld_ref_price_tier: LuldRefPriceTier,
+    pub etp_flag: Option<bool>,
+    pub etp_leverage_factor: u32,
+    pub inverse_indicator: bool,
+}
+
+impl StockInfo {
+    // For feeds whose directory carries less than ITCH 5.0's. Fields they
+    // don't have are left unavailable or not applicable.
+    pub fn new(stock_locate: u16, symbol: &str, timestamp: u64) -> Self {
+        StockInfo {
+            stock_locate,
+            symbol: symbol.trim_end().to_string(),
+            timestamp,
+            market_category: MarketCategory::Unavailable,
+            financial_status: FinancialStatus::Unavailable,
+            round_lot_size: 100,
+            round_lots_only: false,
+            // There's no unknown classification
+            issue_classification: IssueClassification::OtherSecurities,
+            issue_subtype: IssueSubType::NotApplicable,
+            authenticity: true,
+            short_sale_threshold: None,
+            ipo_flag: None,
+            luld_ref_price_tier: LuldRefPriceTier::Na,
+            etp_flag: None,
+            etp_leverage_factor: 0,
+            inverse_indicator: false,
+        }
+    }
+
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let Body::StockDirectory(dir) = &msg.body else {
+            return None;
+        };
+
+        Some(StockInfo {
+            stock_locate: msg.stock_locate,
+            symbol: dir.stock.trim_end().to_string(),
+            timestamp: msg.timestamp,
+            market_category: dir.market_category,
+            financial_status: dir.financial_status,
+            round_lot_size: dir.round_lot_size,
+            round_lots_only: dir.round_lots_only,
+            issue_classification: dir.issue_classification,
+            issue_subtype: dir.issue_subtype,
+            authenticity: dir.authenticity,
+            short_sale_threshold: dir.short_sale_threshold,
+            ipo_flag: dir.ipo_flag,
+            luld_ref_price_tier: dir.luld_ref_price_tier,
+            etp_flag: dir.etp_flag,
+            etp_leverage_factor: dir.etp_leverage_factor,
+            inverse_indicator: dir.inverse_indicator,
+        })
+    }
+}
+
+impl From<&StockInfo> for BookConfig {
+    fn from(info: &StockInfo) -> Self {
+        BookConfig {
+            stock_locate: info.stock_locate,
+            symbol: info.symbol.clone(),
+            round_lot_size: info.round_lot_size,
+            round_lots_only: info.round_lots_only,
+        }
+    }
+}
+
+pub(crate) fn normalize_symbol(symbol: &str) -> String {
+    symbol.trim_end().to_ascii_uppercase()
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StockDirectory {
+    by_locate: FxHashMap<u16, StockInfo>,
+    // Upper-cased symbol -> stock_locate
+    by_symbol: FxHashMap<String, u16>,
+}
+
+impl StockDirectory {
+    pub fn new() -> Self {
+        StockDirectory {
+            by_locate: FxHashMap::with_capacity_and_hasher(10_000, rustc_hash::FxBuildHasher),
+            by_symbol: FxHashMap::with_capacity_and_hasher(10_000, rustc_hash::FxBuildHasher),
+        }
+    }
+
+    // Records a Stock Directory message, returning the stored entry
+    pub fn update(&mut self, msg: &Message) -> Option<&StockInfo> {
+        let info = StockInfo::from_message(msg)?;
+        Some(self.insert(info))
+    }
+
+    // A locate given a new symbol is no longer found by its old one
+    pub fn insert(&mut self, info: StockInfo) -> &StockInfo {
+        let locate = info.stock_locate;
+        if let Some(old) = self.by_locate.get(&locate) {
+            let old_symbol = normalize_symbol(&old.symbol);
+            if self.by_symbol.get(&old_symbol) == Some(&locate) {
+                self.by_symbol.remove(&old_symbol);
+            }
+        }
+        self.by_symbol
+            .insert(normalize_symbol(&info.symbol), locate);
+        self.by_locate.insert(locate, info);
+        &self.by_locate[&locate]
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_locate.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_locate.is_empty()
+    }
+
+    pub fn get(&self, stock_locate: u16) -> Option<&StockInfo> {
+        self.by_locate.get(&stock_locate)
+    }
+
+    pub fn get_by_symbol(&self, symbol: &str) -> Option<&StockInfo> {
+        self.get(self.locate(symbol)?)
+    }
+
+    pub fn locate(&self, symbol: &str) -> Option<u16> {
+        self.by_symbol.get(&normalize_symbol(symbol)).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StockInfo> {
+        self.by_locate.values()
+    }
+}
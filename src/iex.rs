@@ -0,0 +1,926 @@
+// IEX DEEP, as published live and in IEX's pcap HIST files. DEEP carries
+// aggregated price levels rather than orders, so books are built through
+// OrderBook::set_level. Messages arrive in IEX-TP segments, one per UDP
+// datagram: a 40 byte header followed by messages that are each prefixed
+// with their length. Fields are little-endian.
+//
+// Timestamps are nanoseconds since the Unix epoch, not since midnight as in
+// ITCH. Prices have 4 implied decimals, as in ITCH.
+
+use std::fmt;
+
+use itchy::{ArrayString4, ArrayString8, Body, EventCode, Message, RegShoAction};
+use rustc_hash::FxHashMap;
+
+use crate::bookset::BookSet;
+use crate::directory::StockInfo;
+use crate::itch::ApplyOutcome;
+use crate::orderbook::OrderSide;
+
+pub const HEADER_LEN: usize = 40;
+// Message Protocol IDs in the IEX-TP header
+pub const DEEP_PROTOCOL: u16 = 0x8004;
+
+pub const SYSTEM_EVENT: u8 = b'S';
+pub const SECURITY_DIRECTORY: u8 = b'D';
+pub const TRADING_STATUS: u8 = b'H';
+pub const OPERATIONAL_HALT: u8 = b'O';
+pub const SHORT_SALE_PRICE_TEST: u8 = b'P';
+pub const PRICE_LEVEL_BUY: u8 = b'8';
+pub const PRICE_LEVEL_SELL: u8 = b'5';
+pub const TRADE_REPORT: u8 = b'T';
+pub const TRADE_BREAK: u8 = b'B';
+
+// Set on the last price level update of an event. Updates without it leave
+// the book in transition, e.g. half way through an order sweeping levels
+const EVENT_PROCESSING_COMPLETE: u8 = 0x01;
+
+// Length of each message type the books use. Other types are skipped
+pub fn message_len(kind: u8) -> Option<usize> {
+    Some(match kind {
+        SYSTEM_EVENT => 10,
+        SECURITY_DIRECTORY => 31,
+        TRADING_STATUS => 22,
+        OPERATIONAL_HALT => 18,
+        SHORT_SALE_PRICE_TEST => 19,
+        PRICE_LEVEL_BUY | PRICE_LEVEL_SELL => 30,
+        TRADE_REPORT | TRADE_BREAK => 38,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DeepError {
+    // Shorter than the header, or the messages don't add up to the header's
+    // payload length and count
+    Truncated,
+    // An IEX-TP segment for another protocol, e.g. TOPS
+    WrongProtocol(u16),
+    // A known message type with the wrong length
+    BadLength {
+        kind: u8,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for DeepError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeepError::Truncated => write!(f, "truncated IEX-TP segment"),
+            DeepError::WrongProtocol(id) => {
+                write!(f, "IEX-TP segment for protocol {id:#06x}, expected DEEP")
+            }
+            DeepError::BadLength {
+                kind,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "DEEP message {:?} is {actual} bytes, expected {expected}",
+                *kind as char
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DeepError {}
+
+fn le_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+fn le_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+// Space padded alphanumeric field, trimmed
+fn alpha(b: &[u8], at: usize, len: usize) -> &str {
+    std::str::from_utf8(&b[at..at + len])
+        .unwrap_or("")
+        .trim_end()
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Segment<'a> {
+    pub channel: u32,
+    pub session: u32,
+    pub count: u16,
+    // Sequence number of the first message
+    pub sequence: u64,
+    // Nanoseconds since the Unix epoch
+    pub send_time: u64,
+    messages: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Self, DeepError> {
+        if buf.len() < HEADER_LEN {
+            return Err(DeepError::Truncated);
+        }
+        let protocol = le_u16(buf, 2);
+        if protocol != DEEP_PROTOCOL {
+            return Err(DeepError::WrongProtocol(protocol));
+        }
+        let len = le_u16(buf, 12) as usize;
+        if HEADER_LEN + len > buf.len() {
+            return Err(DeepError::Truncated);
+        }
+
+        let segment = Segment {
+            channel: le_u32(buf, 4),
+            session: le_u32(buf, 8),
+            count: le_u16(buf, 14),
+            sequence: le_u64(buf, 24),
+            send_time: le_u64(buf, 32),
+            messages: &buf[HEADER_LEN..HEADER_LEN + len],
+        };
+
+        // Check the framing up front so a short segment can't be half applied
+        let mut messages = 0;
+        let mut pos = 0;
+        while pos + 2 <= segment.messages.len() {
+            pos += 2 + le_u16(segment.messages, pos) as usize;
+            messages += 1;
+        }
+        if pos != segment.messages.len() || messages != segment.count as usize {
+            return Err(DeepError::Truncated);
+        }
+        Ok(segment)
+    }
+
+    // Heartbeats carry no messages. Their sequence number is the next one
+    // to be sent
+    pub fn is_heartbeat(&self) -> bool {
+        self.count == 0
+    }
+
+    // Sequence number after the last message in the segment
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence + self.count as u64
+    }
+
+    pub fn messages(&self) -> Messages<'a> {
+        Messages {
+            buf: self.messages,
+            pos: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Messages<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Messages<'a> {
+    type Item = Result<DeepMessage, DeepError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos + 2 > self.buf.len() {
+            return None;
+        }
+        let start = self.pos + 2;
+        let end = (start + le_u16(self.buf, self.pos) as usize).min(self.buf.len());
+        self.pos = end;
+        Some(DeepMessage::parse(&self.buf[start..end]))
+    }
+}
+
+// A decoded message. Timestamps are nanoseconds since the Unix epoch
+#[derive(Debug, PartialEq, Clone)]
+pub enum DeepMessage {
+    SystemEvent {
+        timestamp: u64,
+        event: u8,
+    },
+    SecurityDirectory {
+        timestamp: u64,
+        flags: u8,
+        symbol: ArrayString8,
+        round_lot_size: u32,
+        adjusted_poc_price: i64,
+        luld_tier: u8,
+    },
+    TradingStatus {
+        timestamp: u64,
+        status: u8,
+        symbol: ArrayString8,
+        reason: ArrayString4,
+    },
+    OperationalHalt {
+        timestamp: u64,
+        halted: bool,
+        symbol: ArrayString8,
+    },
+    ShortSalePriceTest {
+        timestamp: u64,
+        in_effect: bool,
+        symbol: ArrayString8,
+        detail: u8,
+    },
+    // The new total size of a level, zero once it's gone. complete is set on
+    // the last update of an event
+    PriceLevelUpdate {
+        timestamp: u64,
+        side: OrderSide,
+        complete: bool,
+        symbol: ArrayString8,
+        size: u32,
+        price: i64,
+    },
+    TradeReport {
+        timestamp: u64,
+        sale_condition: u8,
+        symbol: ArrayString8,
+        size: u32,
+        price: i64,
+        trade_id: u64,
+    },
+    TradeBreak {
+        timestamp: u64,
+        symbol: ArrayString8,
+        size: u32,
+        price: i64,
+        trade_id: u64,
+    },
+    // Types the books don't use, e.g. auction information and official prices
+    Other {
+        kind: u8,
+    },
+}
+
+impl DeepMessage {
+    pub fn parse(b: &[u8]) -> Result<Self, DeepError> {
+        let Some(&kind) = b.first() else {
+            return Err(DeepError::Truncated);
+        };
+        let Some(expected) = message_len(kind) else {
+            return Ok(DeepMessage::Other { kind });
+        };
+        if b.len() != expected {
+            return Err(DeepError::BadLength {
+                kind,
+                expected,
+                actual: b.len(),
+            });
+        }
+
+        let timestamp = le_u64(b, 2);
+        let symbol = ArrayString8::from(alpha(b, 10, 8)).unwrap_or_default();
+        Ok(match kind {
+            SYSTEM_EVENT => DeepMessage::SystemEvent {
+                timestamp,
+                event: b[1],
+            },
+            SECURITY_DIRECTORY => DeepMessage::SecurityDirectory {
+                timestamp,
+                flags: b[1],
+                symbol,
+                round_lot_size: le_u32(b, 18),
+                adjusted_poc_price: le_u64(b, 22) as i64,
+                luld_tier: b[30],
+            },
+            TRADING_STATUS => DeepMessage::TradingStatus {
+                timestamp,
+                status: b[1],
+                symbol,
+                reason: ArrayString4::from(alpha(b, 18, 4)).unwrap_or_default(),
+            },
+            OPERATIONAL_HALT => DeepMessage::OperationalHalt {
+                timestamp,
+                halted: b[1] == b'O',
+                symbol,
+            },
+            SHORT_SALE_PRICE_TEST => DeepMessage::ShortSalePriceTest {
+                timestamp,
+                in_effect: b[1] == 1,
+                symbol,
+                detail: b[18],
+            },
+            PRICE_LEVEL_BUY | PRICE_LEVEL_SELL => DeepMessage::PriceLevelUpdate {
+                timestamp,
+                side: if kind == PRICE_LEVEL_BUY {
+                    OrderSide::Buy
+                } else {
+                    OrderSide::Sell
+                },
+                complete: b[1] & EVENT_PROCESSING_COMPLETE != 0,
+                symbol,
+                size: le_u32(b, 18),
+                price: le_u64(b, 22) as i64,
+            },
+            TRADE_REPORT => DeepMessage::TradeReport {
+                timestamp,
+                sale_condition: b[1],
+                symbol,
+                size: le_u32(b, 18),
+                price: le_u64(b, 22) as i64,
+                trade_id: le_u64(b, 30),
+            },
+            TRADE_BREAK => DeepMessage::TradeBreak {
+                timestamp,
+                symbol,
+                size: le_u32(b, 18),
+                price: le_u64(b, 22) as i64,
+                trade_id: le_u64(b, 30),
+            },
+            _ => unreachable!(),
+        })
+    }
+
+    // Nanoseconds since the Unix epoch, 0 for types the books don't use
+    pub fn timestamp(&self) -> u64 {
+        match *self {
+            DeepMessage::SystemEvent { timestamp, .. }
+            | DeepMessage::SecurityDirectory { timestamp, .. }
+            | DeepMessage::TradingStatus { timestamp, .. }
+            | DeepMessage::OperationalHalt { timestamp, .. }
+            | DeepMessage::ShortSalePriceTest { timestamp, .. }
+            | DeepMessage::PriceLevelUpdate { timestamp, .. }
+            | DeepMessage::TradeReport { timestamp, .. }
+            | DeepMessage::TradeBreak { timestamp, .. } => timestamp,
+            DeepMessage::Other { .. } => 0,
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct DeepStats {
+    pub segments: u64,
+    pub heartbeats: u64,
+    pub messages: u64,
+    pub duplicates: u64,
+    pub gaps: u64,
+    pub lost: u64,
+    // Segments with bad framing, messages with bad lengths and level updates
+    // with prices the book can't hold
+    pub malformed: u64,
+    // Segments for another protocol
+    pub rejected: u64,
+    pub level_updates: u64,
+    // Events whose level updates were applied together
+    pub events: u64,
+    pub trades: u64,
+}
+
+// A level update held until its event completes
+#[derive(Debug, Copy, Clone)]
+struct LevelUpdate {
+    stock_locate: u16,
+    side: OrderSide,
+    price: u32,
+    size: u32,
+}
+
+// Applies DEEP to a BookSet. Symbols get stock locates from the Security
+// Directory, or as they're first seen. Level updates are held until the
+// update that completes their event, then applied together, so a book is
+// never seen in transition. Held updates report Deferred, the completing
+// one LevelsUpdated.
+#[derive(Debug, Default)]
+pub struct DeepRouter {
+    session: Option<u32>,
+    next_sequence: Option<u64>,
+    locates: FxHashMap<ArrayString8, u16>,
+    pending: Vec<LevelUpdate>,
+    stats: DeepStats,
+}
+
+impl DeepRouter {
+    pub fn new() -> Self {
+        DeepRouter::default()
+    }
+
+    pub fn stats(&self) -> &DeepStats {
+        &self.stats
+    }
+
+    // Locate assigned to a symbol, 0 if it hasn't been seen
+    pub fn locate(&self, symbol: &str) -> u16 {
+        ArrayString8::from(symbol)
+            .ok()
+            .and_then(|symbol| self.locates.get(&symbol).copied())
+            .unwrap_or(0)
+    }
+
+    // Whether level updates are held waiting for their event to complete
+    pub fn in_transition(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    // Applies the messages of a segment that haven't been applied yet, in
+    // order. A gap in the sequence marks every book stale, as the levels
+    // missed can't be recovered from later updates.
+    pub fn apply_segment<F>(
+        &mut self,
+        books: &mut BookSet,
+        buf: &[u8],
+        mut on_applied: F,
+    ) -> Result<(), DeepError>
+    where
+        F: FnMut(&DeepMessage, ApplyOutcome),
+    {
+        let segment = Segment::parse(buf).inspect_err(|e| match e {
+            DeepError::WrongProtocol(_) => self.stats.rejected += 1,
+            _ => self.stats.malformed += 1,
+        })?;
+        self.stats.segments += 1;
+
+        // A new session numbers its messages from 1 again
+        if self.session != Some(segment.session) {
+            self.session = Some(segment.session);
+            self.next_sequence = None;
+        }
+
+        if segment.is_heartbeat() {
+            self.stats.heartbeats += 1;
+            return Ok(());
+        }
+
+        let next = *self.next_sequence.get_or_insert(segment.sequence);
+        if segment.next_sequence() <= next {
+            self.stats.duplicates += segment.count as u64;
+            return Ok(());
+        }
+        let mut skip = 0;
+        if segment.sequence > next {
+            self.stats.gaps += 1;
+            self.stats.lost += segment.sequence - next;
+            books.mark_stale();
+            // Half an event is worse than none
+            self.pending.clear();
+        } else {
+            skip = (next - segment.sequence) as usize;
+            self.stats.duplicates += skip as u64;
+        }
+        self.next_sequence = Some(segment.next_sequence());
+
+        for msg in segment.messages().skip(skip) {
+            match msg {
+                Ok(msg) => {
+                    let outcome = self.apply(books, &msg);
+                    on_applied(&msg, outcome);
+                }
+                Err(_) => self.stats.malformed += 1,
+            }
+        }
+        Ok(())
+    }
+
+    // Applies one message, without sequence checks
+    pub fn apply(&mut self, books: &mut BookSet, msg: &DeepMessage) -> ApplyOutcome {
+        self.stats.messages += 1;
+
+        match *msg {
+            DeepMessage::SystemEvent { timestamp, event } => {
+                let Some(event) = system_event(event) else {
+                    return ApplyOutcome::Ignored;
+                };
+                books.apply(&Message {
+                    tag: b'S',
+                    stock_locate: 0,
+                    tracking_number: 0,
+                    timestamp,
+                    body: Body::SystemEvent { event },
+                })
+            }
+            DeepMessage::SecurityDirectory {
+                timestamp,
+                symbol,
+                round_lot_size,
+                luld_tier,
+                ..
+            } => {
+                if self.locates.contains_key(&symbol) {
+                    return ApplyOutcome::Ignored;
+                }
+                let Some(locate) = self.next_locate(symbol) else {
+                    return ApplyOutcome::Ignored;
+                };
+                let mut info = StockInfo::new(locate, &symbol, timestamp);
+                info.round_lot_size = round_lot_size;
+                info.luld_ref_price_tier = match luld_tier {
+                    1 => itchy::LuldRefPriceTier::Tier1,
+                    2 => itchy::LuldRefPriceTier::Tier2,
+                    _ => itchy::LuldRefPriceTier::Na,
+                };
+                books.add_listing(info);
+                ApplyOutcome::Ignored
+            }
+            DeepMessage::TradingStatus {
+                timestamp,
+                status,
+                symbol,
+                reason,
+            } => {
+                let trading_state = match status {
+                    b'H' => itchy::TradingState::Halted,
+                    b'O' => itchy::TradingState::QuotationOnly,
+                    b'P' => itchy::TradingState::Paused,
+                    b'T' => itchy::TradingState::Trading,
+                    _ => return ApplyOutcome::Ignored,
+                };
+                self.apply_to(
+                    books,
+                    symbol,
+                    timestamp,
+                    b'H',
+                    Body::TradingAction {
+                        stock: symbol,
+                        trading_state,
+                        reason,
+                    },
+                )
+            }
+            DeepMessage::ShortSalePriceTest {
+                timestamp,
+                in_effect,
+                symbol,
+                ..
+            } => self.apply_to(
+                books,
+                symbol,
+                timestamp,
+                b'Y',
+                Body::RegShoRestriction {
+                    stock: symbol,
+                    action: if in_effect {
+                        RegShoAction::Intraday
+                    } else {
+                        RegShoAction::None
+                    },
+                },
+            ),
+            DeepMessage::PriceLevelUpdate {
+                timestamp,
+                side,
+                complete,
+                symbol,
+                size,
+                price,
+            } => {
+                self.stats.level_updates += 1;
+                let Some(stock_locate) = self.listing(books, symbol, timestamp) else {
+                    return ApplyOutcome::Ignored;
+                };
+                // Negative prices and ones past the book's 32 bits are
+                // dropped, the rest of the event is still applied
+                match u32::try_from(price) {
+                    Ok(price) => self.pending.push(LevelUpdate {
+                        stock_locate,
+                        side,
+                        price,
+                        size,
+                    }),
+                    Err(_) => self.stats.malformed += 1,
+                }
+                if !complete {
+                    return ApplyOutcome::Deferred;
+                }
+
+                self.stats.events += 1;
+                for update in self.pending.drain(..) {
+                    if let Some(book) = books.get_mut(update.stock_locate) {
+                        book.set_level(update.side, update.price, update.size);
+                    }
+                }
+                ApplyOutcome::LevelsUpdated
+            }
+            DeepMessage::TradeReport { .. } => {
+                self.stats.trades += 1;
+                ApplyOutcome::Ignored
+            }
+            DeepMessage::OperationalHalt { .. }
+            | DeepMessage::TradeBreak { .. }
+            | DeepMessage::Other { .. } => ApplyOutcome::Ignored,
+        }
+    }
+
+    fn apply_to(
+        &mut self,
+        books: &mut BookSet,
+        symbol: ArrayString8,
+        timestamp: u64,
+        tag: u8,
+        body: Body,
+    ) -> ApplyOutcome {
+        let Some(stock_locate) = self.listing(books, symbol, timestamp) else {
+            return ApplyOutcome::Ignored;
+        };
+        books.apply(&Message {
+            tag,
+            stock_locate,
+            tracking_number: 0,
+            timestamp,
+            body,
+        })
+    }
+
+    // The symbol's locate, listing it if the Security Directory message was
+    // missed. None once the locates run out
+    fn listing(
+        &mut self,
+        books: &mut BookSet,
+        symbol: ArrayString8,
+        timestamp: u64,
+    ) -> Option<u16> {
+        if let Some(locate) = self.locates.get(&symbol) {
+            return Some(*locate);
+        }
+        let locate = self.next_locate(symbol)?;
+        books.add_listing(StockInfo::new(locate, &symbol, timestamp));
+        Some(locate)
+    }
+
+    fn next_locate(&mut self, symbol: ArrayString8) -> Option<u16> {
+        let locate = u16::try_from(self.locates.len() + 1).ok()?;
+        self.locates.insert(symbol, locate);
+        Some(locate)
+    }
+}
+
+fn system_event(event: u8) -> Option<EventCode> {
+    Some(match event {
+        b'O' => EventCode::StartOfMessages,
+        b'S' => EventCode::StartOfSystemHours,
+        b'R' => EventCode::StartOfMarketHours,
+        b'M' => EventCode::EndOfMarketHours,
+        b'E' => EventCode::EndOfSystemHours,
+        b'C' => EventCode::EndOfMessages,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME: u64 = 1_700_000_000_000_000_000;
+
+    fn symbol(s: &str) -> ArrayString8 {
+        ArrayString8::from(s).unwrap()
+    }
+
+    // Type, flags, timestamp and space padded symbol
+    fn head(kind: u8, flags: u8, symbol: &str) -> Vec<u8> {
+        let mut b = vec![kind, flags];
+        b.extend_from_slice(&TIME.to_le_bytes());
+        b.extend_from_slice(format!("{symbol:<8}").as_bytes());
+        b
+    }
+
+    fn level(side: OrderSide, complete: bool, symbol: &str, size: u32, price: i64) -> Vec<u8> {
+        let kind = match side {
+            OrderSide::Buy => PRICE_LEVEL_BUY,
+            OrderSide::Sell => PRICE_LEVEL_SELL,
+        };
+        let mut b = head(kind, complete as u8, symbol);
+        b.extend_from_slice(&size.to_le_bytes());
+        b.extend_from_slice(&price.to_le_bytes());
+        b
+    }
+
+    fn trade(symbol: &str, size: u32, price: i64, trade_id: u64) -> Vec<u8> {
+        let mut b = head(TRADE_REPORT, 0x80, symbol);
+        b.extend_from_slice(&size.to_le_bytes());
+        b.extend_from_slice(&price.to_le_bytes());
+        b.extend_from_slice(&trade_id.to_le_bytes());
+        b
+    }
+
+    fn segment(sequence: u64, messages: &[Vec<u8>]) -> Vec<u8> {
+        let payload: Vec<u8> = messages
+            .iter()
+            .flat_map(|msg| {
+                let mut framed = (msg.len() as u16).to_le_bytes().to_vec();
+                framed.extend_from_slice(msg);
+                framed
+            })
+            .collect();
+        let mut b = vec![1, 0];
+        b.extend_from_slice(&DEEP_PROTOCOL.to_le_bytes());
+        b.extend_from_slice(&1u32.to_le_bytes());
+        b.extend_from_slice(&7u32.to_le_bytes());
+        b.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        b.extend_from_slice(&(messages.len() as u16).to_le_bytes());
+        b.extend_from_slice(&0u64.to_le_bytes());
+        b.extend_from_slice(&sequence.to_le_bytes());
+        b.extend_from_slice(&TIME.to_le_bytes());
+        b.extend_from_slice(&payload);
+        b
+    }
+
+    fn levels(books: &BookSet, locate: u16, side: OrderSide) -> Vec<(u32, u32, usize)> {
+        books.get(locate).unwrap().levels(side).collect()
+    }
+
+    #[test]
+    fn price_level_updates_and_trades() {
+        let update = level(OrderSide::Sell, true, "ZIEXT", 300, 1_010_500);
+        assert_eq!(
+            DeepMessage::parse(&update),
+            Ok(DeepMessage::PriceLevelUpdate {
+                timestamp: TIME,
+                side: OrderSide::Sell,
+                complete: true,
+                symbol: symbol("ZIEXT"),
+                size: 300,
+                price: 1_010_500,
+            })
+        );
+        let update = level(OrderSide::Buy, false, "ZIEXT", 0, 1_000_000);
+        assert!(matches!(
+            DeepMessage::parse(&update),
+            Ok(DeepMessage::PriceLevelUpdate {
+                side: OrderSide::Buy,
+                complete: false,
+                size: 0,
+                ..
+            })
+        ));
+
+        assert_eq!(
+            DeepMessage::parse(&trade("ZIEXT", 100, 1_005_000, 42)),
+            Ok(DeepMessage::TradeReport {
+                timestamp: TIME,
+                sale_condition: 0x80,
+                symbol: symbol("ZIEXT"),
+                size: 100,
+                price: 1_005_000,
+                trade_id: 42,
+            })
+        );
+
+        assert_eq!(
+            DeepMessage::parse(&update[..29]),
+            Err(DeepError::BadLength {
+                kind: PRICE_LEVEL_BUY,
+                expected: 30,
+                actual: 29,
+            })
+        );
+        assert_eq!(
+            DeepMessage::parse(b"A auction"),
+            Ok(DeepMessage::Other { kind: b'A' })
+        );
+        assert_eq!(DeepMessage::parse(&[]), Err(DeepError::Truncated));
+    }
+
+    #[test]
+    fn segments_frame_their_messages() {
+        let buf = segment(5, &[trade("ZIEXT", 1, 1, 1), trade("ZIEXT", 2, 2, 2)]);
+        let parsed = Segment::parse(&buf).unwrap();
+        assert_eq!(parsed.session, 7);
+        assert_eq!(parsed.sequence, 5);
+        assert_eq!(parsed.next_sequence(), 7);
+        assert_eq!(parsed.send_time, TIME);
+        assert_eq!(parsed.messages().count(), 2);
+
+        // A count that doesn't match the messages
+        let mut bad = buf.clone();
+        bad[14] = 3;
+        assert_eq!(Segment::parse(&bad).unwrap_err(), DeepError::Truncated);
+        assert_eq!(
+            Segment::parse(&buf[..buf.len() - 1]).unwrap_err(),
+            DeepError::Truncated
+        );
+        assert_eq!(
+            Segment::parse(&buf[..39]).unwrap_err(),
+            DeepError::Truncated
+        );
+        let mut tops = buf;
+        tops[2..4].copy_from_slice(&0x8003u16.to_le_bytes());
+        assert_eq!(
+            Segment::parse(&tops).unwrap_err(),
+            DeepError::WrongProtocol(0x8003)
+        );
+    }
+
+    #[test]
+    fn levels_apply_when_their_event_completes() {
+        let mut router = DeepRouter::new();
+        let mut books = BookSet::new();
+        let mut outcomes = Vec::new();
+        let mut apply = |router: &mut DeepRouter, books: &mut BookSet, buf: &[u8]| {
+            router
+                .apply_segment(books, buf, |_, outcome| outcomes.push(outcome))
+                .unwrap();
+        };
+
+        apply(
+            &mut router,
+            &mut books,
+            &segment(
+                1,
+                &[
+                    level(OrderSide::Buy, false, "ZIEXT", 100, 1_000_000),
+                    level(OrderSide::Buy, false, "ZIEXT", 200, 999_900),
+                ],
+            ),
+        );
+        let locate = router.locate("ZIEXT");
+        assert_eq!(locate, 1);
+        assert!(router.in_transition());
+        assert!(levels(&books, locate, OrderSide::Buy).is_empty());
+
+        apply(
+            &mut router,
+            &mut books,
+            &segment(
+                3,
+                &[
+                    level(OrderSide::Sell, true, "ZIEXT", 300, 1_000_100),
+                    trade("ZIEXT", 100, 1_000_000, 1),
+                ],
+            ),
+        );
+        assert!(!router.in_transition());
+        assert_eq!(
+            levels(&books, locate, OrderSide::Buy),
+            [(1_000_000, 100, 0), (999_900, 200, 0)]
+        );
+        assert_eq!(
+            levels(&books, locate, OrderSide::Sell),
+            [(1_000_100, 300, 0)]
+        );
+
+        // A size of zero removes the level
+        apply(
+            &mut router,
+            &mut books,
+            &segment(5, &[level(OrderSide::Buy, true, "ZIEXT", 0, 1_000_000)]),
+        );
+        assert_eq!(levels(&books, locate, OrderSide::Buy), [(999_900, 200, 0)]);
+
+        assert_eq!(
+            outcomes,
+            [
+                ApplyOutcome::Deferred,
+                ApplyOutcome::Deferred,
+                ApplyOutcome::LevelsUpdated,
+                ApplyOutcome::Ignored,
+                ApplyOutcome::LevelsUpdated,
+            ]
+        );
+        let stats = router.stats();
+        assert_eq!(stats.level_updates, 4);
+        assert_eq!(stats.events, 2);
+        assert_eq!(stats.trades, 1);
+    }
+
+    #[test]
+    fn gaps_drop_the_event_in_transition() {
+        let mut router = DeepRouter::new();
+        let mut books = BookSet::new();
+        let first = segment(1, &[level(OrderSide::Buy, false, "ZIEXT", 100, 1_000_000)]);
+        router.apply_segment(&mut books, &first, |_, _| {}).unwrap();
+        router.apply_segment(&mut books, &first, |_, _| {}).unwrap();
+
+        let after_gap = segment(3, &[level(OrderSide::Sell, true, "ZIEXT", 300, 1_000_100)]);
+        router
+            .apply_segment(&mut books, &after_gap, |_, _| {})
+            .unwrap();
+        let locate = router.locate("ZIEXT");
+        assert!(levels(&books, locate, OrderSide::Buy).is_empty());
+        assert_eq!(
+            levels(&books, locate, OrderSide::Sell),
+            [(1_000_100, 300, 0)]
+        );
+        assert!(books.get(locate).unwrap().is_stale());
+
+        let stats = router.stats();
+        assert_eq!((stats.duplicates, stats.gaps, stats.lost), (1, 1, 1));
+    }
+
+    #[test]
+    fn prices_the_book_cant_hold_are_dropped() {
+        let mut router = DeepRouter::new();
+        let mut books = BookSet::new();
+        let buf = segment(
+            1,
+            &[
+                level(OrderSide::Buy, false, "ZIEXT", 100, -1),
+                level(OrderSide::Buy, false, "ZIEXT", 200, 1_000_000),
+                level(OrderSide::Sell, true, "ZIEXT", 300, u32::MAX as i64 + 1),
+            ],
+        );
+        router.apply_segment(&mut books, &buf, |_, _| {}).unwrap();
+
+        // The rest of the event is still applied
+        let locate = router.locate("ZIEXT");
+        assert_eq!(
+            levels(&books, locate, OrderSide::Buy),
+            [(1_000_000, 200, 0)]
+        );
+        assert!(levels(&books, locate, OrderSide::Sell).is_empty());
+        assert!(!router.in_transition());
+        assert_eq!(router.stats().malformed, 2);
+        assert_eq!(router.stats().events, 1);
+    }
+}
@@ -0,0 +1,133 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use flate2::Compression as GzipLevel;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const BUFFER_SIZE: usize = 1 << 20;
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    // Detects the compression from the start of a file
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    // Picks the compression for an output file from its extension
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!("unknown compression {s:?}, expected none, gzip or zstd")),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+// Wraps a reader so it yields decompressed bytes, whatever the compression
+pub fn decompress<R: Read + Send + 'static>(reader: R) -> io::Result<Box<dyn BufRead + Send>> {
+    let mut reader = BufReader::with_capacity(BUFFER_SIZE, reader);
+    Ok(match Compression::detect(reader.fill_buf()?) {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(BufReader::with_capacity(
+            BUFFER_SIZE,
+            MultiGzDecoder::new(reader),
+        )),
+        Compression::Zstd => Box::new(BufReader::with_capacity(
+            BUFFER_SIZE,
+            zstd::Decoder::with_buffer(reader)?,
+        )),
+    })
+}
+
+// Opens a raw, gzip or zstd file for streaming reads
+pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn BufRead + Send>> {
+    decompress(File::open(path)?)
+}
+
+// Output that may be compressed. finish must be called to write the end of
+// the compressed stream
+pub enum Output {
+    Raw(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl Output {
+    pub fn create<P: AsRef<Path>>(path: P, compression: Compression) -> io::Result<Self> {
+        let file = BufWriter::with_capacity(BUFFER_SIZE, File::create(path)?);
+        Ok(match compression {
+            Compression::None => Output::Raw(file),
+            Compression::Gzip => Output::Gzip(GzEncoder::new(file, GzipLevel::default())),
+            Compression::Zstd => Output::Zstd(zstd::Encoder::new(file, ZSTD_LEVEL)?),
+        })
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Raw(mut w) => w.flush(),
+            Output::Gzip(w) => w.finish()?.flush(),
+            Output::Zstd(w) => w.finish()?.flush(),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Raw(w) => w.write(buf),
+            Output::Gzip(w) => w.write(buf),
+            Output::Zstd(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Raw(w) => w.flush(),
+            Output::Gzip(w) => w.flush(),
+            Output::Zstd(w) => w.flush(),
+        }
+    }
+}
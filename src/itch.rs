@@ -0,0 +1,831 @@
+use itchy::{ArrayString4, Body, Message};
+
+use crate::auction::AuctionState;
+use crate::limits::AuctionCollar;
+use crate::orderbook::{OrderBook, OrderSide};
+use crate::orphans::{self, OrphanPolicy};
+use crate::trading::{StateChange, TradingState};
+
+mod codes;
+pub mod decode;
+pub mod encode;
+pub mod nordic;
+pub mod protocol;
+pub mod v41;
+
+use decode::MessageView;
+
+pub const ORDER_ADD: u8 = b'A';
+pub const ORDER_ADD_ATTRIBUTED: u8 = b'F';
+pub const ORDER_EXECUTED: u8 = b'E';
+pub const ORDER_EXECUTED_PRICE: u8 = b'C';
+pub const ORDER_CANCEL: u8 = b'X';
+pub const ORDER_DELETE: u8 = b'D';
+pub const ORDER_REPLACE: u8 = b'U';
+pub const STOCK_DIRECTORY: u8 = b'R';
+pub const SYSTEM_EVENT: u8 = b'S';
+pub const TRADING_ACTION: u8 = b'H';
+pub const REG_SHO_RESTRICTION: u8 = b'Y';
+pub const MWCB_STATUS: u8 = b'W';
+pub const NOII: u8 = b'I';
+pub const LULD_AUCTION_COLLAR: u8 = b'J';
+pub const MWCB_DECLINE_LEVEL: u8 = b'V';
+pub const PARTICIPANT_POSITION: u8 = b'L';
+
+// Messages that apply to every book rather than a single stock_locate
+pub fn is_market_wide_tag(tag: u8) -> bool {
+    matches!(tag, SYSTEM_EVENT | MWCB_STATUS | MWCB_DECLINE_LEVEL)
+}
+
+pub fn is_order_tag(tag: u8) -> bool {
+    matches!(
+        tag,
+        ORDER_ADD
+            | ORDER_ADD_ATTRIBUTED
+            | ORDER_EXECUTED
+            | ORDER_EXECUTED_PRICE
+            | ORDER_CANCEL
+            | ORDER_DELETE
+            | ORDER_REPLACE
+    )
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ApplyOutcome {
+    Added,
+    Executed,
+    // Non-printable executions still take shares out of the book, they just
+    // shouldn't be counted towards traded volume
+    ExecutedWithPrice { printable: bool },
+    Cancelled,
+    Deleted,
+    Replaced,
+    // A price level feed set the volume of one or more levels
+    LevelsUpdated,
+    // Held until the rest of an atomic update arrives
+    Deferred,
+    // Every order was removed, e.g. when a feed resets its state
+    Cleared,
+    // The book's trading state changed
+    StateChanged(StateChange),
+    // The book's trading status was updated without changing its state
+    StatusUpdated,
+    // A NOII message updated the book's auction state
+    AuctionUpdated,
+    // A LULD Auction Collar message updated the book's collar
+    CollarUpdated,
+    // Refers to an order the book doesn't hold, e.g. one added before the
+    // start of the file. Counted in the book's OrphanStats
+    Orphaned,
+    // The handler asked for the message to be skipped
+    Skipped,
+    // Not a message that changes the book
+    Ignored,
+}
+
+impl ApplyOutcome {
+    // True if the message changed the book's orders
+    pub fn changed_book(&self) -> bool {
+        matches!(
+            self,
+            ApplyOutcome::Added
+                | ApplyOutcome::Executed
+                | ApplyOutcome::ExecutedWithPrice { .. }
+                | ApplyOutcome::Cancelled
+                | ApplyOutcome::Deleted
+                | ApplyOutcome::Replaced
+                | ApplyOutcome::LevelsUpdated
+                | ApplyOutcome::Cleared
+        )
+    }
+}
+
+// Hooks around apply_with. Both see the book as it is at that point, so
+// before_apply can still look up the resting order of an execution.
+pub trait ItchHandler {
+    // Returning false skips the message
+    fn before_apply(&mut self, _book: &OrderBook, _msg: &Message) -> bool {
+        true
+    }
+
+    fn after_apply(&mut self, _book: &OrderBook, _msg: &Message, _outcome: ApplyOutcome) {}
+}
+
+impl ItchHandler for () {}
+
+impl<H: ItchHandler> ItchHandler for &mut H {
+    fn before_apply(&mut self, book: &OrderBook, msg: &Message) -> bool {
+        (**self).before_apply(book, msg)
+    }
+
+    fn after_apply(&mut self, book: &OrderBook, msg: &Message, outcome: ApplyOutcome) {
+        (**self).after_apply(book, msg, outcome)
+    }
+}
+
+// Runs both handlers. The message is skipped if either asks for it
+impl<A: ItchHandler, B: ItchHandler> ItchHandler for (A, B) {
+    fn before_apply(&mut self, book: &OrderBook, msg: &Message) -> bool {
+        let a = self.0.before_apply(book, msg);
+        let b = self.1.before_apply(book, msg);
+        a && b
+    }
+
+    fn after_apply(&mut self, book: &OrderBook, msg: &Message, outcome: ApplyOutcome) {
+        self.0.after_apply(book, msg, outcome);
+        self.1.after_apply(book, msg, outcome);
+    }
+}
+
+// Counts of applied messages by outcome
+#[derive(Debug, Default, Clone)]
+pub struct ApplyCounts {
+    pub add: usize,
+    pub executed: usize,
+    pub executed_price: usize,
+    pub executed_price_non_printable: usize,
+    pub cancel: usize,
+    pub delete: usize,
+    pub replace: usize,
+    // Not part of the total, these didn't change the book
+    pub orphaned: usize,
+}
+
+impl ApplyCounts {
+    pub fn total(&self) -> usize {
+        self.add
+            + self.executed
+            + self.executed_price
+            + self.executed_price_non_printable
+            + self.cancel
+            + self.delete
+            + self.replace
+    }
+
+    pub fn record(&mut self, outcome: ApplyOutcome) {
+        match outcome {
+            ApplyOutcome::Added => self.add += 1,
+            ApplyOutcome::Executed => self.executed += 1,
+            ApplyOutcome::ExecutedWithPrice { printable: true } => self.executed_price += 1,
+            ApplyOutcome::ExecutedWithPrice { printable: false } => {
+                self.executed_price_non_printable += 1
+            }
+            ApplyOutcome::Cancelled => self.cancel += 1,
+            ApplyOutcome::Deleted => self.delete += 1,
+            ApplyOutcome::Replaced => self.replace += 1,
+            ApplyOutcome::Orphaned => self.orphaned += 1,
+            _ => {}
+        }
+    }
+}
+
+impl ItchHandler for ApplyCounts {
+    fn after_apply(&mut self, _book: &OrderBook, _msg: &Message, outcome: ApplyOutcome) {
+        self.record(outcome);
+    }
+}
+
+// Applies an ITCH message to the book. The caller is responsible for only
+// passing messages that belong to this book's stock_locate.
+pub fn apply(book: &mut OrderBook, msg: &Message) -> ApplyOutcome {
+    apply_with(book, msg, &mut ())
+}
+
+pub fn apply_with<H: ItchHandler>(
+    book: &mut OrderBook,
+    msg: &Message,
+    handler: &mut H,
+) -> ApplyOutcome {
+    if !handler.before_apply(book, msg) {
+        return ApplyOutcome::Skipped;
+    }
+
+    let outcome = apply_body(book, msg);
+    handler.after_apply(book, msg, outcome);
+    outcome
+}
+
+fn apply_body(book: &mut OrderBook, msg: &Message) -> ApplyOutcome {
+    if let Some(event) = OrderEvent::from_body(&msg.body) {
+        return apply_event(book, event);
+    }
+
+    if let Some(auction) = AuctionState::from_message(msg) {
+        book.update_auction(auction);
+        return ApplyOutcome::AuctionUpdated;
+    }
+
+    if let Some(collar) = AuctionCollar::from_message(msg) {
+        book.set_collar(Some(collar));
+        return ApplyOutcome::CollarUpdated;
+    }
+
+    let Some(status) = book.trading_status_mut().on_message(&msg.body) else {
+        return ApplyOutcome::Ignored;
+    };
+
+    match status {
+        Some((from, to)) => {
+            // Collars only apply to the reopening auction after a halt
+            if to == TradingState::Trading {
+                book.set_collar(None);
+            }
+            ApplyOutcome::StateChanged(StateChange {
+                stock_locate: book.config().stock_locate,
+                timestamp: msg.timestamp,
+                from,
+                to,
+            })
+        }
+        None => ApplyOutcome::StatusUpdated,
+    }
+}
+
+// Applies a decoded message to the book. Order messages are read straight
+// from the buffer, anything else goes through the itchy Message path. There
+// are no handler hooks here, use to_message and apply_with for those.
+pub fn apply_view(book: &mut OrderBook, view: &MessageView) -> ApplyOutcome {
+    if let Some(event) = view.order_event() {
+        return apply_event(book, event);
+    }
+
+    match view.to_message() {
+        Some(msg) => apply_body(book, &msg),
+        None => ApplyOutcome::Ignored,
+    }
+}
+
+// The change an order message makes to the book, independent of how the
+// message was decoded. Prices are raw ITCH prices, 4 implied decimals.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum OrderEvent {
+    Add {
+        reference: u64,
+        side: OrderSide,
+        shares: u32,
+        price: u32,
+        mpid: Option<ArrayString4>,
+    },
+    Executed {
+        reference: u64,
+        executed: u32,
+    },
+    ExecutedWithPrice {
+        reference: u64,
+        executed: u32,
+        printable: bool,
+    },
+    Cancelled {
+        reference: u64,
+        cancelled: u32,
+    },
+    Deleted {
+        reference: u64,
+    },
+    Replaced {
+        old_reference: u64,
+        new_reference: u64,
+        shares: u32,
+        price: u32,
+    },
+}
+
+impl OrderEvent {
+    pub fn from_body(body: &Body) -> Option<Self> {
+        Some(match body {
+            Body::AddOrder(order) => OrderEvent::Add {
+                reference: order.reference,
+                side: if order.side == itchy::Side::Buy {
+                    OrderSide::Buy
+                } else {
+                    OrderSide::Sell
+                },
+                shares: order.shares,
+                price: order.price.raw(),
+                mpid: order.mpid,
+            },
+            Body::OrderExecuted {
+                reference,
+                executed,
+                ..
+            } => OrderEvent::Executed {
+                reference: *reference,
+                executed: *executed,
+            },
+            Body::OrderExecutedWithPrice {
+                reference,
+                executed,
+                printable,
+                ..
+            } => OrderEvent::ExecutedWithPrice {
+                reference: *reference,
+                executed: *executed,
+                printable: *printable,
+            },
+            Body::OrderCancelled {
+                reference,
+                cancelled,
+            } => OrderEvent::Cancelled {
+                reference: *reference,
+                cancelled: *cancelled,
+            },
+            Body::DeleteOrder { reference } => OrderEvent::Deleted {
+                reference: *reference,
+            },
+            Body::ReplaceOrder(order) => OrderEvent::Replaced {
+                old_reference: order.old_reference,
+                new_reference: order.new_reference,
+                shares: order.shares,
+                price: order.price.raw(),
+            },
+            _ => return None,
+        })
+    }
+
+    // The existing order the event refers to. None for adds
+    pub fn reference(&self) -> Option<u64> {
+        match *self {
+            OrderEvent::Add { .. } => None,
+            OrderEvent::Executed { reference, .. }
+            | OrderEvent::ExecutedWithPrice { reference, .. }
+            | OrderEvent::Cancelled { reference, .. }
+            | OrderEvent::Deleted { reference } => Some(reference),
+            OrderEvent::Replaced { old_reference, .. } => Some(old_reference),
+        }
+    }
+}
+
+// Messages for orders the book doesn't hold are handled by the book's
+// OrphanPolicy. A stale book ignores them without counting, the order may
+// have been added by a message lost in a feed gap
+pub fn apply_event(book: &mut OrderBook, event: OrderEvent) -> ApplyOutcome {
+    if book.is_stale()
+        && let Some(reference) = event.reference()
+        && book.order(reference).is_none()
+    {
+        return ApplyOutcome::Ignored;
+    }
+
+    let (applied, outcome) = match event {
+        OrderEvent::Add {
+            reference,
+            side,
+            shares,
+            price,
+            mpid,
+        } => {
+            match mpid {
+                Some(mpid) => book.add_attributed_order(reference, price, shares, side, mpid),
+                None => book.add_order(reference, price, shares, side),
+            }
+            (true, ApplyOutcome::Added)
+        }
+        OrderEvent::Executed {
+            reference,
+            executed,
+        } => (
+            book.execute_order(reference, executed),
+            ApplyOutcome::Executed,
+        ),
+        OrderEvent::ExecutedWithPrice {
+            reference,
+            executed,
+            printable,
+        } => (
+            book.execute_order(reference, executed),
+            ApplyOutcome::ExecutedWithPrice { printable },
+        ),
+        OrderEvent::Cancelled {
+            reference,
+            cancelled,
+        } => (
+            book.cancel_order(reference, cancelled),
+            ApplyOutcome::Cancelled,
+        ),
+        OrderEvent::Deleted { reference } => (book.delete_order(reference), ApplyOutcome::Deleted),
+        OrderEvent::Replaced {
+            old_reference,
+            new_reference,
+            shares,
+            price,
+        } => (
+            book.replace_order(old_reference, new_reference, price, shares),
+            ApplyOutcome::Replaced,
+        ),
+    };
+
+    if !applied {
+        return apply_orphan(book, event);
+    }
+    outcome
+}
+
+fn apply_orphan(book: &mut OrderBook, event: OrderEvent) -> ApplyOutcome {
+    let policy = book.orphan_policy();
+    if policy == OrphanPolicy::Ignore {
+        return ApplyOutcome::Ignored;
+    }
+    book.orphan_stats_mut().record(&event);
+
+    if policy == OrphanPolicy::Placeholder
+        && let OrderEvent::Replaced {
+            new_reference,
+            shares,
+            price,
+            ..
+        } = event
+        && let Some(side) = orphans::infer_side(book, price)
+    {
+        book.add_order(new_reference, price, shares, side);
+        book.orphan_stats_mut().placeholders += 1;
+        return ApplyOutcome::Added;
+    }
+    ApplyOutcome::Orphaned
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::{AddOrder, ArrayString8, ReplaceOrder, Side};
+
+    use super::*;
+    use crate::orphans::OrphanStats;
+
+    fn msg(tag: u8, body: Body) -> Message {
+        Message {
+            tag,
+            stock_locate: 1,
+            tracking_number: 0,
+            timestamp: 0,
+            body,
+        }
+    }
+
+    fn add(reference: u64, side: Side, shares: u32, price: u32) -> Message {
+        msg(
+            ORDER_ADD,
+            Body::AddOrder(AddOrder {
+                reference,
+                side,
+                shares,
+                stock: ArrayString8::from("TEST").unwrap(),
+                price: price.into(),
+                mpid: None,
+            }),
+        )
+    }
+
+    fn add_attributed(reference: u64, side: Side, shares: u32, price: u32, mpid: &str) -> Message {
+        let mut m = add(reference, side, shares, price);
+        m.tag = ORDER_ADD_ATTRIBUTED;
+        if let Body::AddOrder(order) = &mut m.body {
+            order.mpid = Some(ArrayString4::from(mpid).unwrap());
+        }
+        m
+    }
+
+    fn executed(reference: u64, executed: u32) -> Message {
+        msg(
+            ORDER_EXECUTED,
+            Body::OrderExecuted {
+                reference,
+                executed,
+                match_number: 1,
+            },
+        )
+    }
+
+    fn executed_price(reference: u64, executed: u32, printable: bool) -> Message {
+        msg(
+            ORDER_EXECUTED_PRICE,
+            Body::OrderExecutedWithPrice {
+                reference,
+                executed,
+                match_number: 1,
+                printable,
+                price: 1_000_000.into(),
+            },
+        )
+    }
+
+    fn cancelled(reference: u64, cancelled: u32) -> Message {
+        msg(
+            ORDER_CANCEL,
+            Body::OrderCancelled {
+                reference,
+                cancelled,
+            },
+        )
+    }
+
+    fn deleted(reference: u64) -> Message {
+        msg(ORDER_DELETE, Body::DeleteOrder { reference })
+    }
+
+    fn replaced(old_reference: u64, new_reference: u64, shares: u32, price: u32) -> Message {
+        msg(
+            ORDER_REPLACE,
+            Body::ReplaceOrder(ReplaceOrder {
+                old_reference,
+                new_reference,
+                shares,
+                price: price.into(),
+            }),
+        )
+    }
+
+    fn book() -> OrderBook {
+        OrderBook::with_capacity(16, 16)
+    }
+
+    fn levels(book: &OrderBook, side: OrderSide) -> Vec<(u32, u32, usize)> {
+        book.levels(side).collect()
+    }
+
+    #[test]
+    fn add_orders() {
+        let mut book = book();
+        assert_eq!(
+            apply(&mut book, &add(1, Side::Buy, 100, 1_000_000)),
+            ApplyOutcome::Added
+        );
+        assert_eq!(
+            apply(
+                &mut book,
+                &add_attributed(2, Side::Sell, 200, 1_001_000, "GSCO")
+            ),
+            ApplyOutcome::Added
+        );
+        apply(&mut book, &add(3, Side::Buy, 50, 1_000_000));
+
+        assert_eq!(levels(&book, OrderSide::Buy), [(1_000_000, 150, 2)]);
+        assert_eq!(levels(&book, OrderSide::Sell), [(1_001_000, 200, 1)]);
+        assert_eq!(book.order(2), Some((1_001_000, 200, OrderSide::Sell)));
+        assert_eq!(book.attribution(2), Some("GSCO"));
+        assert_eq!(book.attribution(1), None);
+    }
+
+    #[test]
+    fn executions() {
+        let mut book = book();
+        apply(&mut book, &add(1, Side::Buy, 100, 1_000_000));
+        apply(&mut book, &add(2, Side::Buy, 100, 1_000_000));
+
+        assert_eq!(apply(&mut book, &executed(1, 30)), ApplyOutcome::Executed);
+        assert_eq!(book.order(1), Some((1_000_000, 70, OrderSide::Buy)));
+        assert_eq!(
+            apply(&mut book, &executed_price(1, 20, true)),
+            ApplyOutcome::ExecutedWithPrice { printable: true }
+        );
+        assert_eq!(
+            apply(&mut book, &executed_price(2, 100, false)),
+            ApplyOutcome::ExecutedWithPrice { printable: false }
+        );
+
+        // Non-printable executions still take shares out of the book
+        assert_eq!(book.order(2), None);
+        assert_eq!(book.best_bid_raw(), Some(1_000_000));
+        assert_eq!(levels(&book, OrderSide::Buy), [(1_000_000, 50, 1)]);
+
+        apply(&mut book, &executed(1, 50));
+        assert_eq!(book.order(1), None);
+        assert!(levels(&book, OrderSide::Buy).is_empty());
+    }
+
+    #[test]
+    fn cancels_and_deletes() {
+        let mut book = book();
+        apply(
+            &mut book,
+            &add_attributed(1, Side::Sell, 300, 1_001_000, "GSCO"),
+        );
+        apply(&mut book, &add(2, Side::Sell, 100, 1_002_000));
+
+        assert_eq!(
+            apply(&mut book, &cancelled(1, 100)),
+            ApplyOutcome::Cancelled
+        );
+        assert_eq!(book.order(1), Some((1_001_000, 200, OrderSide::Sell)));
+        assert_eq!(book.attribution(1), Some("GSCO"));
+
+        assert_eq!(apply(&mut book, &deleted(1)), ApplyOutcome::Deleted);
+        assert_eq!(book.order(1), None);
+        assert_eq!(book.attribution(1), None);
+        assert_eq!(levels(&book, OrderSide::Sell), [(1_002_000, 100, 1)]);
+    }
+
+    #[test]
+    fn replace_keeps_side_and_attribution() {
+        let mut book = book();
+        apply(
+            &mut book,
+            &add_attributed(1, Side::Sell, 300, 1_001_000, "GSCO"),
+        );
+
+        assert_eq!(
+            apply(&mut book, &replaced(1, 5, 200, 1_003_000)),
+            ApplyOutcome::Replaced
+        );
+        assert_eq!(book.order(1), None);
+        assert_eq!(book.order(5), Some((1_003_000, 200, OrderSide::Sell)));
+        assert_eq!(book.attribution(5), Some("GSCO"));
+        assert_eq!(levels(&book, OrderSide::Sell), [(1_003_000, 200, 1)]);
+    }
+
+    fn orphans(book: &mut OrderBook) -> Vec<ApplyOutcome> {
+        [
+            executed(90, 10),
+            executed_price(91, 10, true),
+            cancelled(92, 10),
+            deleted(93),
+            replaced(94, 95, 100, 999_000),
+        ]
+        .iter()
+        .map(|m| apply(book, m))
+        .collect()
+    }
+
+    #[test]
+    fn orphans_ignored() {
+        let mut book = book();
+        book.set_orphan_policy(OrphanPolicy::Ignore);
+        apply(&mut book, &add(1, Side::Buy, 100, 1_000_000));
+
+        assert!(
+            orphans(&mut book)
+                .iter()
+                .all(|outcome| *outcome == ApplyOutcome::Ignored)
+        );
+        assert_eq!(*book.orphan_stats(), OrphanStats::default());
+        assert_eq!(levels(&book, OrderSide::Buy), [(1_000_000, 100, 1)]);
+    }
+
+    #[test]
+    fn orphans_counted() {
+        let mut book = book();
+        apply(&mut book, &add(1, Side::Buy, 100, 1_000_000));
+
+        assert!(
+            orphans(&mut book)
+                .iter()
+                .all(|outcome| *outcome == ApplyOutcome::Orphaned)
+        );
+        assert_eq!(
+            *book.orphan_stats(),
+            OrphanStats {
+                executed: 1,
+                executed_price: 1,
+                cancelled: 1,
+                deleted: 1,
+                replaced: 1,
+                placeholders: 0,
+            }
+        );
+        assert_eq!(book.orphan_stats().total(), 5);
+        assert_eq!(book.order(95), None);
+        assert_eq!(levels(&book, OrderSide::Buy), [(1_000_000, 100, 1)]);
+    }
+
+    #[test]
+    fn orphan_replace_placeholder() {
+        let mut book = book();
+        book.set_orphan_policy(OrphanPolicy::Placeholder);
+        apply(&mut book, &add(1, Side::Buy, 100, 1_000_000));
+        apply(&mut book, &add(2, Side::Sell, 100, 1_010_000));
+
+        let outcomes = orphans(&mut book);
+        assert_eq!(outcomes[..4], [ApplyOutcome::Orphaned; 4]);
+        assert_eq!(outcomes[4], ApplyOutcome::Added);
+        assert_eq!(book.orphan_stats().replaced, 1);
+        assert_eq!(book.orphan_stats().placeholders, 1);
+
+        // Below the best bid, so a buy
+        assert_eq!(book.order(95), Some((999_000, 100, OrderSide::Buy)));
+
+        // Nearer the ask than the bid
+        apply(&mut book, &replaced(96, 97, 10, 1_008_000));
+        assert_eq!(book.order(97), Some((1_008_000, 10, OrderSide::Sell)));
+    }
+
+    #[test]
+    fn orphan_replace_placeholder_needs_a_book() {
+        let mut book = book();
+        book.set_orphan_policy(OrphanPolicy::Placeholder);
+
+        assert_eq!(
+            apply(&mut book, &replaced(1, 2, 100, 1_000_000)),
+            ApplyOutcome::Orphaned
+        );
+        assert_eq!(book.order(2), None);
+        assert_eq!(book.orphan_stats().placeholders, 0);
+    }
+
+    #[test]
+    fn stale_books_ignore_orphans() {
+        let mut book = book();
+        book.set_stale(true);
+
+        assert_eq!(apply(&mut book, &deleted(1)), ApplyOutcome::Ignored);
+        assert_eq!(book.orphan_stats().total(), 0);
+    }
+
+    fn remaining(book: &OrderBook) -> Option<u32> {
+        book.order(1).map(|(_, shares, _)| shares)
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        // (hook, tag, remaining shares of order 1)
+        calls: Vec<(&'static str, u8, Option<u32>)>,
+        veto: Option<u8>,
+    }
+
+    impl ItchHandler for Recorder {
+        fn before_apply(&mut self, book: &OrderBook, msg: &Message) -> bool {
+            self.calls.push(("before", msg.tag, remaining(book)));
+            self.veto != Some(msg.tag)
+        }
+
+        fn after_apply(&mut self, book: &OrderBook, msg: &Message, _outcome: ApplyOutcome) {
+            self.calls.push(("after", msg.tag, remaining(book)));
+        }
+    }
+
+    #[test]
+    fn handlers_see_the_book_before_and_after() {
+        let mut book = book();
+        let mut recorder = Recorder::default();
+        apply_with(&mut book, &add(1, Side::Buy, 100, 1_000_000), &mut recorder);
+        apply_with(&mut book, &executed(1, 40), &mut recorder);
+
+        assert_eq!(
+            recorder.calls,
+            [
+                ("before", ORDER_ADD, None),
+                ("after", ORDER_ADD, Some(100)),
+                ("before", ORDER_EXECUTED, Some(100)),
+                ("after", ORDER_EXECUTED, Some(60)),
+            ]
+        );
+    }
+
+    #[test]
+    fn handlers_can_skip_messages() {
+        let mut book = book();
+        let mut recorder = Recorder {
+            veto: Some(ORDER_DELETE),
+            ..Recorder::default()
+        };
+        apply_with(&mut book, &add(1, Side::Buy, 100, 1_000_000), &mut recorder);
+        recorder.calls.clear();
+
+        assert_eq!(
+            apply_with(&mut book, &deleted(1), &mut recorder),
+            ApplyOutcome::Skipped
+        );
+        assert_eq!(book.order(1), Some((1_000_000, 100, OrderSide::Buy)));
+        assert_eq!(recorder.calls, [("before", ORDER_DELETE, Some(100))]);
+    }
+
+    #[test]
+    fn either_handler_of_a_pair_can_skip() {
+        let mut book = book();
+        let mut first = Recorder::default();
+        let mut second = Recorder {
+            veto: Some(ORDER_ADD),
+            ..Recorder::default()
+        };
+
+        let outcome = apply_with(
+            &mut book,
+            &add(1, Side::Buy, 100, 1_000_000),
+            &mut (&mut first, &mut second),
+        );
+        assert_eq!(outcome, ApplyOutcome::Skipped);
+        assert_eq!(book.order(1), None);
+        // Both are asked, neither hears about the skipped message after
+        assert_eq!(first.calls, [("before", ORDER_ADD, None)]);
+        assert_eq!(second.calls, [("before", ORDER_ADD, None)]);
+    }
+
+    #[test]
+    fn counts_by_outcome() {
+        let mut book = book();
+        let mut counts = ApplyCounts::default();
+        for m in [
+            add(1, Side::Buy, 100, 1_000_000),
+            executed(1, 10),
+            executed_price(1, 10, true),
+            executed_price(1, 10, false),
+            cancelled(1, 10),
+            replaced(1, 2, 100, 1_000_100),
+            deleted(2),
+            deleted(3),
+        ] {
+            apply_with(&mut book, &m, &mut counts);
+        }
+
+        assert_eq!(counts.total(), 7);
+        assert_eq!(counts.executed_price_non_printable, 1);
+        assert_eq!(counts.orphaned, 1);
+    }
+}
@@ -0,0 +1,565 @@
+// Mappings between ITCH 5.0 single-character codes and itchy's enums. These
+// follow itchy's parser so both decoders produce the same messages.
+
+use itchy::{
+    CrossType, EventCode, FinancialStatus, ImbalanceDirection, InterestFlag, IpoReleaseQualifier,
+    IssueClassification, IssueSubType, LevelBreached, LuldRefPriceTier, MarketCategory,
+    MarketMakerMode, MarketParticipantState, RegShoAction, Side, TradingState,
+};
+
+pub fn bool_flag(code: u8) -> Option<bool> {
+    match code {
+        b'Y' => Some(true),
+        b'N' => Some(false),
+        _ => None,
+    }
+}
+
+// Y/N flags that may be blank. The outer None means the code was invalid
+pub fn maybe_bool_flag(code: u8) -> Option<Option<bool>> {
+    match code {
+        b' ' => Some(None),
+        _ => bool_flag(code).map(Some),
+    }
+}
+
+pub fn etp_flag(code: u8) -> Option<Option<bool>> {
+    match code {
+        b'M' => Some(Some(true)),
+        _ => maybe_bool_flag(code),
+    }
+}
+
+pub fn authenticity(code: u8) -> Option<bool> {
+    match code {
+        b'P' => Some(true),
+        b'T' => Some(false),
+        _ => None,
+    }
+}
+
+pub fn side(code: u8) -> Option<Side> {
+    match code {
+        b'B' => Some(Side::Buy),
+        b'S' => Some(Side::Sell),
+        _ => None,
+    }
+}
+
+pub fn event_code(code: u8) -> Option<EventCode> {
+    Some(match code {
+        b'O' => EventCode::StartOfMessages,
+        b'S' => EventCode::StartOfSystemHours,
+        b'Q' => EventCode::StartOfMarketHours,
+        b'M' => EventCode::EndOfMarketHours,
+        b'E' => EventCode::EndOfSystemHours,
+        b'C' => EventCode::EndOfMessages,
+        _ => return None,
+    })
+}
+
+pub fn market_category(code: u8) -> Option<MarketCategory> {
+    Some(match code {
+        b'Q' => MarketCategory::NasdaqGlobalSelect,
+        b'G' => MarketCategory::NasdaqGlobalMarket,
+        b'S' => MarketCategory::NasdaqCapitalMarket,
+        b'N' => MarketCategory::Nyse,
+        b'A' => MarketCategory::NyseMkt,
+        b'P' => MarketCategory::NyseArca,
+        b'Z' => MarketCategory::BatsZExchange,
+        b'V' => MarketCategory::InvestorsExchange,
+        b' ' => MarketCategory::Unavailable,
+        _ => return None,
+    })
+}
+
+pub fn financial_status(code: u8) -> Option<FinancialStatus> {
+    Some(match code {
+        b'N' => FinancialStatus::Normal,
+        b'D' => FinancialStatus::Deficient,
+        b'E' => FinancialStatus::Delinquent,
+        b'Q' => FinancialStatus::Bankrupt,
+        b'S' => FinancialStatus::Suspended,
+        b'G' => FinancialStatus::DeficientBankrupt,
+        b'H' => FinancialStatus::DeficientDelinquent,
+        b'J' => FinancialStatus::DelinquentBankrupt,
+        b'K' => FinancialStatus::DeficientDelinquentBankrupt,
+        b'C' => FinancialStatus::EtpSuspended,
+        b' ' => FinancialStatus::Unavailable,
+        _ => return None,
+    })
+}
+
+pub fn issue_classification(code: u8) -> Option<IssueClassification> {
+    use IssueClassification::*;
+    Some(match code {
+        b'A' => AmericanDepositaryShare,
+        b'B' => Bond,
+        b'C' => CommonStock,
+        b'F' => DepositoryReceipt,
+        b'I' => A144,
+        b'L' => LimitedPartnership,
+        b'N' => Notes,
+        b'O' => OrdinaryShare,
+        b'P' => PreferredStock,
+        b'Q' => OtherSecurities,
+        b'R' => Right,
+        b'S' => SharesOfBeneficialInterest,
+        b'T' => ConvertibleDebenture,
+        b'U' => Unit,
+        b'V' => UnitsPerBenifInt,
+        b'W' => Warrant,
+        _ => return None,
+    })
+}
+
+pub fn issue_subtype(code: &[u8]) -> Option<IssueSubType> {
+    use IssueSubType::*;
+    Some(match code {
+        b"A " => PreferredTrustSecurities,
+        b"AI" => AlphaIndexETNs,
+        b"B " => IndexBasedDerivative,
+        b"C " => CommonShares,
+        b"CB" => CommodityBasedTrustShares,
+        b"CF" => CommodityFuturesTrustShares,
+        b"CL" => CommodityLinkedSecurities,
+        b"CM" => CommodityIndexTrustShares,
+        b"CO" => CollateralizedMortgageObligation,
+        b"CT" => CurrencyTrustShares,
+        b"CU" => CommodityCurrencyLinkedSecurities,
+        b"CW" => CurrencyWarrants,
+        b"D " => GlobalDepositaryShares,
+        b"E " => ETFPortfolioDepositaryReceipt,
+        b"EG" => EquityGoldShares,
+        b"EI" => ETNEquityIndexLinkedSecurities,
+        b"EM" => ExchangeTradedManagedFunds,
+        b"EN" => ExchangeTradedNotes,
+        b"EU" => EquityUnits,
+        b"F " => Holdrs,
+        b"FI" => ETNFixedIncomeLinkedSecurities,
+        b"FL" => ETNFuturesLinkedSecurities,
+        b"G " => GlobalShares,
+        b"I " => ETFIndexFundShares,
+        b"IR" => InterestRate,
+        b"IW" => IndexWarrant,
+        b"IX" => IndexLinkedExchangeableNotes,
+        b"J " => CorporateBackedTrustSecurity,
+        b"L " => ContingentLitigationRight,
+        b"LL" => Llc,
+        b"M " => EquityBasedDerivative,
+        b"MF" => ManagedFundShares,
+        b"ML" => ETNMultiFactorIndexLinkedSecurities,
+        b"MT" => ManagedTrustSecurities,
+        b"N " => NYRegistryShares,
+        b"O " => OpenEndedMutualFund,
+        b"P " => PrivatelyHeldSecurity,
+        b"PP" => PoisonPill,
+        b"PU" => PartnershipUnits,
+        b"Q " => ClosedEndFunds,
+        b"R " => RegS,
+        b"RC" => CommodityRedeemableCommodityLinkedSecurities,
+        b"RF" => ETNRedeemableFuturesLinkedSecurities,
+        b"RT" => REIT,
+        b"RU" => CommodityRedeemableCurrencyLinkedSecurities,
+        b"S " => Seed,
+        b"SC" => SpotRateClosing,
+        b"SI" => SpotRateIntraday,
+        b"T " => TrackingStock,
+        b"TC" => TrustCertificates,
+        b"TU" => TrustUnits,
+        b"U " => Portal,
+        b"V " => ContingentValueRight,
+        b"W " => TrustIssuedReceipts,
+        b"WC" => WorldCurrencyOption,
+        b"X " => Trust,
+        b"Y " => Other,
+        b"Z " => NotApplicable,
+        _ => return None,
+    })
+}
+
+pub fn luld_ref_price_tier(code: u8) -> Option<LuldRefPriceTier> {
+    Some(match code {
+        b' ' => LuldRefPriceTier::Na,
+        b'1' => LuldRefPriceTier::Tier1,
+        b'2' => LuldRefPriceTier::Tier2,
+        _ => return None,
+    })
+}
+
+pub fn market_maker_mode(code: u8) -> Option<MarketMakerMode> {
+    Some(match code {
+        b'N' => MarketMakerMode::Normal,
+        b'P' => MarketMakerMode::Passive,
+        b'S' => MarketMakerMode::Syndicate,
+        b'R' => MarketMakerMode::Presyndicate,
+        b'L' => MarketMakerMode::Penalty,
+        _ => return None,
+    })
+}
+
+pub fn market_participant_state(code: u8) -> Option<MarketParticipantState> {
+    Some(match code {
+        b'A' => MarketParticipantState::Active,
+        b'E' => MarketParticipantState::Excused,
+        b'W' => MarketParticipantState::Withdrawn,
+        b'S' => MarketParticipantState::Suspended,
+        b'D' => MarketParticipantState::Deleted,
+        _ => return None,
+    })
+}
+
+pub fn reg_sho_action(code: u8) -> Option<RegShoAction> {
+    Some(match code {
+        b'0' => RegShoAction::None,
+        b'1' => RegShoAction::Intraday,
+        b'2' => RegShoAction::Extant,
+        _ => return None,
+    })
+}
+
+pub fn trading_state(code: u8) -> Option<TradingState> {
+    Some(match code {
+        b'H' => TradingState::Halted,
+        b'P' => TradingState::Paused,
+        b'Q' => TradingState::QuotationOnly,
+        b'T' => TradingState::Trading,
+        _ => return None,
+    })
+}
+
+pub fn imbalance_direction(code: u8) -> Option<ImbalanceDirection> {
+    Some(match code {
+        b'B' => ImbalanceDirection::Buy,
+        b'S' => ImbalanceDirection::Sell,
+        b'N' => ImbalanceDirection::NoImbalance,
+        b'O' => ImbalanceDirection::InsufficientOrders,
+        _ => return None,
+    })
+}
+
+pub fn cross_type(code: u8) -> Option<CrossType> {
+    Some(match code {
+        b'O' => CrossType::Opening,
+        b'C' => CrossType::Closing,
+        b'H' => CrossType::IpoOrHalted,
+        b'I' => CrossType::Intraday,
+        b'A' => CrossType::ExtendedTradingClose,
+        _ => return None,
+    })
+}
+
+pub fn ipo_release_qualifier(code: u8) -> Option<IpoReleaseQualifier> {
+    Some(match code {
+        b'A' => IpoReleaseQualifier::Anticipated,
+        b'C' => IpoReleaseQualifier::Cancelled,
+        _ => return None,
+    })
+}
+
+pub fn level_breached(code: u8) -> Option<LevelBreached> {
+    Some(match code {
+        b'1' => LevelBreached::L1,
+        b'2' => LevelBreached::L2,
+        b'3' => LevelBreached::L3,
+        _ => return None,
+    })
+}
+
+pub fn interest_flag(code: u8) -> Option<InterestFlag> {
+    Some(match code {
+        b'B' => InterestFlag::RPIAvailableBuySide,
+        b'S' => InterestFlag::RPIAvailableSellSide,
+        b'A' => InterestFlag::RPIAvailableBothSides,
+        b'N' => InterestFlag::RPINoneAvailable,
+        _ => return None,
+    })
+}
+
+// Reverse mappings, for encoding. Where several codes decode to the same
+// value the first listed above is used
+
+pub fn bool_flag_code(value: bool) -> u8 {
+    if value { b'Y' } else { b'N' }
+}
+
+pub fn maybe_bool_flag_code(value: Option<bool>) -> u8 {
+    value.map_or(b' ', bool_flag_code)
+}
+
+pub fn authenticity_code(value: bool) -> u8 {
+    if value { b'P' } else { b'T' }
+}
+
+pub fn side_code(value: Side) -> u8 {
+    match value {
+        Side::Buy => b'B',
+        Side::Sell => b'S',
+    }
+}
+
+pub fn system_event_code(value: EventCode) -> u8 {
+    match value {
+        EventCode::StartOfMessages => b'O',
+        EventCode::StartOfSystemHours => b'S',
+        EventCode::StartOfMarketHours => b'Q',
+        EventCode::EndOfMarketHours => b'M',
+        EventCode::EndOfSystemHours => b'E',
+        EventCode::EndOfMessages => b'C',
+    }
+}
+
+pub fn market_category_code(value: MarketCategory) -> u8 {
+    match value {
+        MarketCategory::NasdaqGlobalSelect => b'Q',
+        MarketCategory::NasdaqGlobalMarket => b'G',
+        MarketCategory::NasdaqCapitalMarket => b'S',
+        MarketCategory::Nyse => b'N',
+        MarketCategory::NyseMkt => b'A',
+        MarketCategory::NyseArca => b'P',
+        MarketCategory::BatsZExchange => b'Z',
+        MarketCategory::InvestorsExchange => b'V',
+        MarketCategory::Unavailable => b' ',
+    }
+}
+
+pub fn financial_status_code(value: FinancialStatus) -> u8 {
+    match value {
+        FinancialStatus::Normal => b'N',
+        FinancialStatus::Deficient => b'D',
+        FinancialStatus::Delinquent => b'E',
+        FinancialStatus::Bankrupt => b'Q',
+        FinancialStatus::Suspended => b'S',
+        FinancialStatus::DeficientBankrupt => b'G',
+        FinancialStatus::DeficientDelinquent => b'H',
+        FinancialStatus::DelinquentBankrupt => b'J',
+        FinancialStatus::DeficientDelinquentBankrupt => b'K',
+        FinancialStatus::EtpSuspended => b'C',
+        FinancialStatus::Unavailable => b' ',
+    }
+}
+
+pub fn issue_classification_code(value: IssueClassification) -> u8 {
+    use IssueClassification::*;
+    match value {
+        AmericanDepositaryShare => b'A',
+        Bond => b'B',
+        CommonStock => b'C',
+        DepositoryReceipt => b'F',
+        A144 => b'I',
+        LimitedPartnership => b'L',
+        Notes => b'N',
+        OrdinaryShare => b'O',
+        PreferredStock => b'P',
+        OtherSecurities => b'Q',
+        Right => b'R',
+        SharesOfBeneficialInterest => b'S',
+        ConvertibleDebenture => b'T',
+        Unit => b'U',
+        UnitsPerBenifInt => b'V',
+        Warrant => b'W',
+    }
+}
+
+pub fn luld_ref_price_tier_code(value: LuldRefPriceTier) -> u8 {
+    match value {
+        LuldRefPriceTier::Na => b' ',
+        LuldRefPriceTier::Tier1 => b'1',
+        LuldRefPriceTier::Tier2 => b'2',
+    }
+}
+
+pub fn market_maker_mode_code(value: MarketMakerMode) -> u8 {
+    match value {
+        MarketMakerMode::Normal => b'N',
+        MarketMakerMode::Passive => b'P',
+        MarketMakerMode::Syndicate => b'S',
+        MarketMakerMode::Presyndicate => b'R',
+        MarketMakerMode::Penalty => b'L',
+    }
+}
+
+pub fn market_participant_state_code(value: MarketParticipantState) -> u8 {
+    match value {
+        MarketParticipantState::Active => b'A',
+        MarketParticipantState::Excused => b'E',
+        MarketParticipantState::Withdrawn => b'W',
+        MarketParticipantState::Suspended => b'S',
+        MarketParticipantState::Deleted => b'D',
+    }
+}
+
+pub fn reg_sho_action_code(value: RegShoAction) -> u8 {
+    match value {
+        RegShoAction::None => b'0',
+        RegShoAction::Intraday => b'1',
+        RegShoAction::Extant => b'2',
+    }
+}
+
+pub fn trading_state_code(value: TradingState) -> u8 {
+    match value {
+        TradingState::Halted => b'H',
+        TradingState::Paused => b'P',
+        TradingState::QuotationOnly => b'Q',
+        TradingState::Trading => b'T',
+    }
+}
+
+pub fn imbalance_direction_code(value: ImbalanceDirection) -> u8 {
+    match value {
+        ImbalanceDirection::Buy => b'B',
+        ImbalanceDirection::Sell => b'S',
+        ImbalanceDirection::NoImbalance => b'N',
+        ImbalanceDirection::InsufficientOrders => b'O',
+    }
+}
+
+pub fn cross_type_code(value: CrossType) -> u8 {
+    match value {
+        CrossType::Opening => b'O',
+        CrossType::Closing => b'C',
+        CrossType::IpoOrHalted => b'H',
+        CrossType::Intraday => b'I',
+        CrossType::ExtendedTradingClose => b'A',
+    }
+}
+
+pub fn ipo_release_qualifier_code(value: IpoReleaseQualifier) -> u8 {
+    match value {
+        IpoReleaseQualifier::Anticipated => b'A',
+        IpoReleaseQualifier::Cancelled => b'C',
+    }
+}
+
+pub fn level_breached_code(value: LevelBreached) -> u8 {
+    match value {
+        LevelBreached::L1 => b'1',
+        LevelBreached::L2 => b'2',
+        LevelBreached::L3 => b'3',
+    }
+}
+
+pub fn interest_flag_code(value: InterestFlag) -> u8 {
+    match value {
+        InterestFlag::RPIAvailableBuySide => b'B',
+        InterestFlag::RPIAvailableSellSide => b'S',
+        InterestFlag::RPIAvailableBothSides => b'A',
+        InterestFlag::RPINoneAvailable => b'N',
+    }
+}
+
+pub fn issue_subtype_code(subtype: IssueSubType) -> &'static [u8; 2] {
+    use IssueSubType::*;
+    match subtype {
+        PreferredTrustSecurities => b"A ",
+        AlphaIndexETNs => b"AI",
+        IndexBasedDerivative => b"B ",
+        CommonShares => b"C ",
+        CommodityBasedTrustShares => b"CB",
+        CommodityFuturesTrustShares => b"CF",
+        CommodityLinkedSecurities => b"CL",
+        CommodityIndexTrustShares => b"CM",
+        CollateralizedMortgageObligation => b"CO",
+        CurrencyTrustShares => b"CT",
+        CommodityCurrencyLinkedSecurities => b"CU",
+        CurrencyWarrants => b"CW",
+        GlobalDepositaryShares => b"D ",
+        ETFPortfolioDepositaryReceipt => b"E ",
+        EquityGoldShares => b"EG",
+        ETNEquityIndexLinkedSecurities => b"EI",
+        ExchangeTradedManagedFunds => b"EM",
+        ExchangeTradedNotes => b"EN",
+        EquityUnits => b"EU",
+        Holdrs => b"F ",
+        ETNFixedIncomeLinkedSecurities => b"FI",
+        ETNFuturesLinkedSecurities => b"FL",
+        GlobalShares => b"G ",
+        ETFIndexFundShares => b"I ",
+        InterestRate => b"IR",
+        IndexWarrant => b"IW",
+        IndexLinkedExchangeableNotes => b"IX",
+        CorporateBackedTrustSecurity => b"J ",
+        ContingentLitigationRight => b"L ",
+        Llc => b"LL",
+        EquityBasedDerivative => b"M ",
+        ManagedFundShares => b"MF",
+        ETNMultiFactorIndexLinkedSecurities => b"ML",
+        ManagedTrustSecurities => b"MT",
+        NYRegistryShares => b"N ",
+        OpenEndedMutualFund => b"O ",
+        PrivatelyHeldSecurity => b"P ",
+        PoisonPill => b"PP",
+        PartnershipUnits => b"PU",
+        ClosedEndFunds => b"Q ",
+        RegS => b"R ",
+        CommodityRedeemableCommodityLinkedSecurities => b"RC",
+        ETNRedeemableFuturesLinkedSecurities => b"RF",
+        REIT => b"RT",
+        CommodityRedeemableCurrencyLinkedSecurities => b"RU",
+        Seed => b"S ",
+        SpotRateClosing => b"SC",
+        SpotRateIntraday => b"SI",
+        TrackingStock => b"T ",
+        TrustCertificates => b"TC",
+        TrustUnits => b"TU",
+        Portal => b"U ",
+        ContingentValueRight => b"V ",
+        TrustIssuedReceipts => b"W ",
+        WorldCurrencyOption => b"WC",
+        Trust => b"X ",
+        Other => b"Y ",
+        NotApplicable => b"Z ",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every value's code decodes back to it
+    fn round_trips<T: Copy + PartialEq + std::fmt::Debug>(
+        decode: fn(u8) -> Option<T>,
+        code: fn(T) -> u8,
+    ) {
+        for c in 0..=u8::MAX {
+            if let Some(value) = decode(c) {
+                assert_eq!(decode(code(value)), Some(value), "code {:?}", c as char);
+            }
+        }
+    }
+
+    #[test]
+    fn reverse_mappings() {
+        round_trips(bool_flag, bool_flag_code);
+        round_trips(maybe_bool_flag, maybe_bool_flag_code);
+        round_trips(authenticity, authenticity_code);
+        round_trips(side, side_code);
+        round_trips(event_code, system_event_code);
+        round_trips(market_category, market_category_code);
+        round_trips(financial_status, financial_status_code);
+        round_trips(issue_classification, issue_classification_code);
+        round_trips(luld_ref_price_tier, luld_ref_price_tier_code);
+        round_trips(market_maker_mode, market_maker_mode_code);
+        round_trips(market_participant_state, market_participant_state_code);
+        round_trips(reg_sho_action, reg_sho_action_code);
+        round_trips(trading_state, trading_state_code);
+        round_trips(imbalance_direction, imbalance_direction_code);
+        round_trips(cross_type, cross_type_code);
+        round_trips(ipo_release_qualifier, ipo_release_qualifier_code);
+        round_trips(level_breached, level_breached_code);
+        round_trips(interest_flag, interest_flag_code);
+    }
+
+    #[test]
+    fn issue_subtypes_round_trip() {
+        for first in b' '..=b'Z' {
+            for second in b' '..=b'Z' {
+                let code = [first, second];
+                if let Some(subtype) = issue_subtype(&code) {
+                    assert_eq!(issue_subtype_code(subtype), &code);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,927 @@
+// Zero-copy ITCH 5.0 decoding. Views borrow the payload bytes and read fields
+// at their fixed big-endian offsets on access, so nothing is allocated or
+// parsed up front. Lengths are checked once when the view is created.
+
+use std::fmt;
+use std::io::{self, ErrorKind, Read};
+use std::str;
+
+use itchy::{
+    AddOrder, ArrayString4, ArrayString8, Body, CrossType, EventCode, FinancialStatus,
+    ImbalanceDirection, ImbalanceIndicator, InterestFlag, IpoQuotingPeriod, IpoReleaseQualifier,
+    IssueClassification, IssueSubType, LevelBreached, LuldRefPriceTier, MarketCategory,
+    MarketMakerMode, MarketParticipantPosition, MarketParticipantState, Message, NonCrossTrade,
+    RegShoAction, ReplaceOrder, RetailPriceImprovementIndicator, Side, StockDirectory,
+    TradingState,
+};
+
+use super::codes;
+use super::OrderEvent;
+use crate::orderbook::OrderSide;
+
+// Message header: type, stock locate, tracking number, 48-bit timestamp
+pub const HEADER_LEN: usize = 11;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum DecodeError {
+    UnknownType(u8),
+    // The payload length doesn't match the message type
+    BadLength {
+        tag: u8,
+        expected: usize,
+        actual: usize,
+    },
+    // The buffer ends partway through a frame
+    Truncated { needed: usize, available: usize },
+    // A legacy message with a value that doesn't fit its ITCH 5.0 field
+    OutOfRange { tag: u8, field: &'static str },
+    // A field holding a code the message type doesn't define
+    BadCode { tag: u8, field: &'static str },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnknownType(tag) => {
+                write!(f, "unknown ITCH message type {:?}", *tag as char)
+            }
+            DecodeError::BadLength {
+                tag,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "ITCH message {:?} should be {} bytes, got {}",
+                *tag as char, expected, actual
+            ),
+            DecodeError::Truncated { needed, available } => write!(
+                f,
+                "truncated ITCH frame: needed {} bytes, {} available",
+                needed, available
+            ),
+            DecodeError::OutOfRange { tag, field } => write!(
+                f,
+                "message {:?} has a {} that doesn't fit ITCH 5.0",
+                *tag as char, field
+            ),
+            DecodeError::BadCode { tag, field } => {
+                write!(f, "message {:?} has an invalid {}", *tag as char, field)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// Payload length of each ITCH 5.0 message type, header included
+pub fn message_len(tag: u8) -> Option<usize> {
+    Some(match tag {
+        b'S' => 12,
+        b'R' => 39,
+        b'H' => 25,
+        b'Y' => 20,
+        b'L' => 26,
+        b'V' => 35,
+        b'W' => 12,
+        b'K' => 28,
+        b'J' => 35,
+        b'h' => 21,
+        b'A' => 36,
+        b'F' => 40,
+        b'E' => 31,
+        b'C' => 36,
+        b'X' => 23,
+        b'D' => 19,
+        b'U' => 35,
+        b'P' => 44,
+        b'Q' => 40,
+        b'B' => 19,
+        b'I' => 50,
+        b'N' => 20,
+        b'O' => 48,
+        _ => return None,
+    })
+}
+
+// Field readers. Offsets are always in bounds since views are only built
+// from payloads whose length matched the message type. The legacy decoders
+// share them.
+
+#[inline]
+pub(super) fn byte(b: &[u8], at: usize) -> u8 {
+    b[at]
+}
+
+#[inline]
+pub(super) fn be_u16(b: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([b[at], b[at + 1]])
+}
+
+#[inline]
+pub(super) fn be_u32(b: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+#[inline]
+pub(super) fn be_u48(b: &[u8], at: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf[2..].copy_from_slice(&b[at..at + 6]);
+    u64::from_be_bytes(buf)
+}
+
+#[inline]
+pub(super) fn be_u64(b: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+// Alpha fields are space padded ASCII. Anything else reads as ""
+#[inline]
+pub(super) fn alpha(b: &[u8], at: usize, len: usize) -> &str {
+    str::from_utf8(&b[at..at + len]).unwrap_or_default()
+}
+
+#[inline]
+pub(super) fn alpha4(b: &[u8], at: usize) -> &str {
+    alpha(b, at, 4)
+}
+
+#[inline]
+pub(super) fn alpha8(b: &[u8], at: usize) -> &str {
+    alpha(b, at, 8)
+}
+
+// Single character codes. None if the code isn't valid for the field
+#[inline]
+fn code<T>(b: &[u8], at: usize, decode: fn(u8) -> Option<T>) -> Option<T> {
+    decode(b[at])
+}
+
+#[inline]
+fn subtype(b: &[u8], at: usize) -> Option<IssueSubType> {
+    codes::issue_subtype(&b[at..at + 2])
+}
+
+macro_rules! view {
+    ($(#[$meta:meta])* $name:ident {
+        $($field:ident: $ty:ty = $reader:ident($($arg:expr),*)),* $(,)?
+    }) => {
+        $(#[$meta])*
+        #[derive(Debug, Copy, Clone)]
+        pub struct $name<'a>(&'a [u8]);
+
+        impl<'a> $name<'a> {
+            pub fn as_bytes(&self) -> &'a [u8] {
+                self.0
+            }
+
+            $(
+                #[inline]
+                pub fn $field(&self) -> $ty {
+                    $reader(self.0, $($arg),*)
+                }
+            )*
+        }
+    };
+}
+
+view!(
+    // S
+    SystemEventView {
+        event: Option<EventCode> = code(11, codes::event_code),
+    }
+);
+
+view!(
+    // R
+    StockDirectoryView {
+        stock: &'a str = alpha8(11),
+        market_category: Option<MarketCategory> = code(19, codes::market_category),
+        financial_status: Option<FinancialStatus> = code(20, codes::financial_status),
+        round_lot_size: u32 = be_u32(21),
+        round_lots_only: Option<bool> = code(25, codes::bool_flag),
+        issue_classification: Option<IssueClassification> = code(26, codes::issue_classification),
+        issue_subtype: Option<IssueSubType> = subtype(27),
+        authenticity: Option<bool> = code(29, codes::authenticity),
+        short_sale_threshold: Option<Option<bool>> = code(30, codes::maybe_bool_flag),
+        ipo_flag: Option<Option<bool>> = code(31, codes::maybe_bool_flag),
+        luld_ref_price_tier: Option<LuldRefPriceTier> = code(32, codes::luld_ref_price_tier),
+        etp_flag: Option<Option<bool>> = code(33, codes::etp_flag),
+        etp_leverage_factor: u32 = be_u32(34),
+        inverse_indicator: Option<bool> = code(38, codes::bool_flag),
+    }
+);
+
+view!(
+    // H
+    TradingActionView {
+        stock: &'a str = alpha8(11),
+        trading_state: Option<TradingState> = code(19, codes::trading_state),
+        reason: &'a str = alpha4(21),
+    }
+);
+
+view!(
+    // Y
+    RegShoRestrictionView {
+        stock: &'a str = alpha8(11),
+        action: Option<RegShoAction> = code(19, codes::reg_sho_action),
+    }
+);
+
+view!(
+    // L
+    ParticipantPositionView {
+        mpid: &'a str = alpha4(11),
+        stock: &'a str = alpha8(15),
+        primary_market_maker: Option<bool> = code(23, codes::bool_flag),
+        market_maker_mode: Option<MarketMakerMode> = code(24, codes::market_maker_mode),
+        market_participant_state: Option<MarketParticipantState> =
+            code(25, codes::market_participant_state),
+    }
+);
+
+view!(
+    // V, raw Price(8) values
+    MwcbDeclineLevelView {
+        level1: u64 = be_u64(11),
+        level2: u64 = be_u64(19),
+        level3: u64 = be_u64(27),
+    }
+);
+
+view!(
+    // W
+    MwcbStatusView {
+        level: Option<LevelBreached> = code(11, codes::level_breached),
+    }
+);
+
+view!(
+    // K
+    IpoQuotingPeriodView {
+        stock: &'a str = alpha8(11),
+        release_time: u32 = be_u32(19),
+        release_qualifier: Option<IpoReleaseQualifier> = code(23, codes::ipo_release_qualifier),
+        price: u32 = be_u32(24),
+    }
+);
+
+view!(
+    // J
+    LuldAuctionCollarView {
+        stock: &'a str = alpha8(11),
+        ref_price: u32 = be_u32(19),
+        upper_price: u32 = be_u32(23),
+        lower_price: u32 = be_u32(27),
+        extension: u32 = be_u32(31),
+    }
+);
+
+view!(
+    // h. Market code is Q (Nasdaq), B (BX) or X (PSX), action is H (halted)
+    // or T (resumed)
+    OperationalHaltView {
+        stock: &'a str = alpha8(11),
+        market_code: u8 = byte(19),
+        action: u8 = byte(20),
+    }
+);
+
+view!(
+    // A and F
+    AddOrderView {
+        reference: u64 = be_u64(11),
+        side: Option<Side> = code(19, codes::side),
+        shares: u32 = be_u32(20),
+        stock: &'a str = alpha8(24),
+        price: u32 = be_u32(32),
+    }
+);
+
+impl<'a> AddOrderView<'a> {
+    // Only set on F messages
+    pub fn mpid(&self) -> Option<&'a str> {
+        (self.0.len() == 40).then(|| alpha4(self.0, 36))
+    }
+}
+
+view!(
+    // E
+    OrderExecutedView {
+        reference: u64 = be_u64(11),
+        executed: u32 = be_u32(19),
+        match_number: u64 = be_u64(23),
+    }
+);
+
+view!(
+    // C
+    OrderExecutedWithPriceView {
+        reference: u64 = be_u64(11),
+        executed: u32 = be_u32(19),
+        match_number: u64 = be_u64(23),
+        printable: Option<bool> = code(31, codes::bool_flag),
+        price: u32 = be_u32(32),
+    }
+);
+
+view!(
+    // X
+    OrderCancelView {
+        reference: u64 = be_u64(11),
+        cancelled: u32 = be_u32(19),
+    }
+);
+
+view!(
+    // D
+    OrderDeleteView {
+        reference: u64 = be_u64(11),
+    }
+);
+
+view!(
+    // U
+    OrderReplaceView {
+        old_reference: u64 = be_u64(11),
+        new_reference: u64 = be_u64(19),
+        shares: u32 = be_u32(27),
+        price: u32 = be_u32(31),
+    }
+);
+
+view!(
+    // P
+    NonCrossTradeView {
+        reference: u64 = be_u64(11),
+        side: Option<Side> = code(19, codes::side),
+        shares: u32 = be_u32(20),
+        stock: &'a str = alpha8(24),
+        price: u32 = be_u32(32),
+        match_number: u64 = be_u64(36),
+    }
+);
+
+view!(
+    // Q
+    CrossTradeView {
+        shares: u64 = be_u64(11),
+        stock: &'a str = alpha8(19),
+        cross_price: u32 = be_u32(27),
+        match_number: u64 = be_u64(31),
+        cross_type: Option<CrossType> = code(39, codes::cross_type),
+    }
+);
+
+view!(
+    // B
+    BrokenTradeView {
+        match_number: u64 = be_u64(11),
+    }
+);
+
+view!(
+    // I
+    NoiiView {
+        paired_shares: u64 = be_u64(11),
+        imbalance_shares: u64 = be_u64(19),
+        imbalance_direction: Option<ImbalanceDirection> = code(27, codes::imbalance_direction),
+        stock: &'a str = alpha8(28),
+        far_price: u32 = be_u32(36),
+        near_price: u32 = be_u32(40),
+        current_ref_price: u32 = be_u32(44),
+        cross_type: Option<CrossType> = code(48, codes::cross_type),
+        price_variation_indicator: u8 = byte(49),
+    }
+);
+
+view!(
+    // N
+    RpiiView {
+        stock: &'a str = alpha8(11),
+        interest_flag: Option<InterestFlag> = code(19, codes::interest_flag),
+    }
+);
+
+view!(
+    // O
+    DirectListingView {
+        stock: &'a str = alpha8(11),
+        open_eligibility: u8 = byte(19),
+        min_allowable_price: u32 = be_u32(20),
+        max_allowable_price: u32 = be_u32(24),
+        near_execution_price: u32 = be_u32(28),
+        near_execution_time: u64 = be_u64(32),
+        lower_price_range_collar: u32 = be_u32(40),
+        upper_price_range_collar: u32 = be_u32(44),
+    }
+);
+
+#[derive(Debug, Copy, Clone)]
+pub enum BodyView<'a> {
+    SystemEvent(SystemEventView<'a>),
+    StockDirectory(StockDirectoryView<'a>),
+    TradingAction(TradingActionView<'a>),
+    RegShoRestriction(RegShoRestrictionView<'a>),
+    ParticipantPosition(ParticipantPositionView<'a>),
+    MwcbDeclineLevel(MwcbDeclineLevelView<'a>),
+    MwcbStatus(MwcbStatusView<'a>),
+    IpoQuotingPeriod(IpoQuotingPeriodView<'a>),
+    LuldAuctionCollar(LuldAuctionCollarView<'a>),
+    OperationalHalt(OperationalHaltView<'a>),
+    AddOrder(AddOrderView<'a>),
+    OrderExecuted(OrderExecutedView<'a>),
+    OrderExecutedWithPrice(OrderExecutedWithPriceView<'a>),
+    OrderCancel(OrderCancelView<'a>),
+    OrderDelete(OrderDeleteView<'a>),
+    OrderReplace(OrderReplaceView<'a>),
+    NonCrossTrade(NonCrossTradeView<'a>),
+    CrossTrade(CrossTradeView<'a>),
+    BrokenTrade(BrokenTradeView<'a>),
+    Noii(NoiiView<'a>),
+    Rpii(RpiiView<'a>),
+    DirectListing(DirectListingView<'a>),
+}
+
+// A single ITCH 5.0 message, without its length prefix
+#[derive(Debug, Copy, Clone)]
+pub struct MessageView<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> MessageView<'a> {
+    pub fn new(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        let Some(&tag) = buf.first() else {
+            return Err(DecodeError::Truncated {
+                needed: HEADER_LEN,
+                available: 0,
+            });
+        };
+        let expected = message_len(tag).ok_or(DecodeError::UnknownType(tag))?;
+        if buf.len() != expected {
+            return Err(DecodeError::BadLength {
+                tag,
+                expected,
+                actual: buf.len(),
+            });
+        }
+        // Order events are read without going through the views, so their
+        // side is checked here
+        if matches!(tag, b'A' | b'F') && !matches!(buf[19], b'B' | b'S') {
+            return Err(DecodeError::BadCode { tag, field: "side" });
+        }
+        Ok(MessageView { buf })
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    #[inline]
+    pub fn tag(&self) -> u8 {
+        self.buf[0]
+    }
+
+    #[inline]
+    pub fn stock_locate(&self) -> u16 {
+        be_u16(self.buf, 1)
+    }
+
+    #[inline]
+    pub fn tracking_number(&self) -> u16 {
+        be_u16(self.buf, 3)
+    }
+
+    // Nanoseconds since midnight
+    #[inline]
+    pub fn timestamp(&self) -> u64 {
+        be_u48(self.buf, 5)
+    }
+
+    pub fn body(&self) -> BodyView<'a> {
+        let b = self.buf;
+        match self.tag() {
+            b'S' => BodyView::SystemEvent(SystemEventView(b)),
+            b'R' => BodyView::StockDirectory(StockDirectoryView(b)),
+            b'H' => BodyView::TradingAction(TradingActionView(b)),
+            b'Y' => BodyView::RegShoRestriction(RegShoRestrictionView(b)),
+            b'L' => BodyView::ParticipantPosition(ParticipantPositionView(b)),
+            b'V' => BodyView::MwcbDeclineLevel(MwcbDeclineLevelView(b)),
+            b'W' => BodyView::MwcbStatus(MwcbStatusView(b)),
+            b'K' => BodyView::IpoQuotingPeriod(IpoQuotingPeriodView(b)),
+            b'J' => BodyView::LuldAuctionCollar(LuldAuctionCollarView(b)),
+            b'h' => BodyView::OperationalHalt(OperationalHaltView(b)),
+            b'A' | b'F' => BodyView::AddOrder(AddOrderView(b)),
+            b'E' => BodyView::OrderExecuted(OrderExecutedView(b)),
+            b'C' => BodyView::OrderExecutedWithPrice(OrderExecutedWithPriceView(b)),
+            b'X' => BodyView::OrderCancel(OrderCancelView(b)),
+            b'D' => BodyView::OrderDelete(OrderDeleteView(b)),
+            b'U' => BodyView::OrderReplace(OrderReplaceView(b)),
+            b'P' => BodyView::NonCrossTrade(NonCrossTradeView(b)),
+            b'Q' => BodyView::CrossTrade(CrossTradeView(b)),
+            b'B' => BodyView::BrokenTrade(BrokenTradeView(b)),
+            b'I' => BodyView::Noii(NoiiView(b)),
+            b'N' => BodyView::Rpii(RpiiView(b)),
+            b'O' => BodyView::DirectListing(DirectListingView(b)),
+            // new() only accepts tags message_len knows
+            _ => unreachable!(),
+        }
+    }
+
+    // The order book change carried by A, F, E, C, X, D and U messages, read
+    // straight from the buffer
+    #[inline]
+    pub fn order_event(&self) -> Option<OrderEvent> {
+        let b = self.buf;
+        Some(match self.tag() {
+            b'A' | b'F' => OrderEvent::Add {
+                reference: be_u64(b, 11),
+                side: match b[19] {
+                    b'B' => OrderSide::Buy,
+                    b'S' => OrderSide::Sell,
+                    _ => return None,
+                },
+                shares: be_u32(b, 20),
+                price: be_u32(b, 32),
+                mpid: AddOrderView(b)
+                    .mpid()
+                    .map(|mpid| ArrayString4::from(mpid).unwrap_or_default()),
+            },
+            b'E' => OrderEvent::Executed {
+                reference: be_u64(b, 11),
+                executed: be_u32(b, 19),
+            },
+            b'C' => OrderEvent::ExecutedWithPrice {
+                reference: be_u64(b, 11),
+                executed: be_u32(b, 19),
+                printable: b[31] == b'Y',
+            },
+            b'X' => OrderEvent::Cancelled {
+                reference: be_u64(b, 11),
+                cancelled: be_u32(b, 19),
+            },
+            b'D' => OrderEvent::Deleted {
+                reference: be_u64(b, 11),
+            },
+            b'U' => OrderEvent::Replaced {
+                old_reference: be_u64(b, 11),
+                new_reference: be_u64(b, 19),
+                shares: be_u32(b, 27),
+                price: be_u32(b, 31),
+            },
+            _ => return None,
+        })
+    }
+
+    // Decodes into the owned itchy message, for code that works on Message.
+    // None for Operational Halt (h) and Direct Listing (O), which itchy has
+    // no body for, and for messages with invalid codes.
+    pub fn to_message(&self) -> Option<Message> {
+        let stock = |s: &str| ArrayString8::from(s).ok();
+        let mpid = |s: &str| ArrayString4::from(s).ok();
+
+        let body = match self.body() {
+            BodyView::SystemEvent(v) => Body::SystemEvent { event: v.event()? },
+            BodyView::StockDirectory(v) => Body::StockDirectory(StockDirectory {
+                stock: stock(v.stock())?,
+                market_category: v.market_category()?,
+                financial_status: v.financial_status()?,
+                round_lot_size: v.round_lot_size(),
+                round_lots_only: v.round_lots_only()?,
+                issue_classification: v.issue_classification()?,
+                issue_subtype: v.issue_subtype()?,
+                authenticity: v.authenticity()?,
+                short_sale_threshold: v.short_sale_threshold()?,
+                ipo_flag: v.ipo_flag()?,
+                luld_ref_price_tier: v.luld_ref_price_tier()?,
+                etp_flag: v.etp_flag()?,
+                etp_leverage_factor: v.etp_leverage_factor(),
+                inverse_indicator: v.inverse_indicator()?,
+            }),
+            BodyView::TradingAction(v) => Body::TradingAction {
+                stock: stock(v.stock())?,
+                trading_state: v.trading_state()?,
+                reason: mpid(v.reason())?,
+            },
+            BodyView::RegShoRestriction(v) => Body::RegShoRestriction {
+                stock: stock(v.stock())?,
+                action: v.action()?,
+            },
+            BodyView::ParticipantPosition(v) => {
+                Body::ParticipantPosition(MarketParticipantPosition {
+                    mpid: mpid(v.mpid())?,
+                    stock: stock(v.stock())?,
+                    primary_market_maker: v.primary_market_maker()?,
+                    market_maker_mode: v.market_maker_mode()?,
+                    market_participant_state: v.market_participant_state()?,
+                })
+            }
+            BodyView::MwcbDeclineLevel(v) => Body::MwcbDeclineLevel {
+                level1: v.level1().into(),
+                level2: v.level2().into(),
+                level3: v.level3().into(),
+            },
+            BodyView::MwcbStatus(v) => Body::Breach(v.level()?),
+            BodyView::IpoQuotingPeriod(v) => Body::IpoQuotingPeriod(IpoQuotingPeriod {
+                stock: stock(v.stock())?,
+                release_time: v.release_time(),
+                release_qualifier: v.release_qualifier()?,
+                price: v.price().into(),
+            }),
+            BodyView::LuldAuctionCollar(v) => Body::LULDAuctionCollar {
+                stock: stock(v.stock())?,
+                ref_price: v.ref_price().into(),
+                upper_price: v.upper_price().into(),
+                lower_price: v.lower_price().into(),
+                extension: v.extension(),
+            },
+            BodyView::OperationalHalt(_) | BodyView::DirectListing(_) => return None,
+            BodyView::AddOrder(v) => Body::AddOrder(AddOrder {
+                reference: v.reference(),
+                side: v.side()?,
+                shares: v.shares(),
+                stock: stock(v.stock())?,
+                price: v.price().into(),
+                mpid: match v.mpid() {
+                    Some(m) => Some(mpid(m)?),
+                    None => None,
+                },
+            }),
+            BodyView::OrderExecuted(v) => Body::OrderExecuted {
+                reference: v.reference(),
+                executed: v.executed(),
+                match_number: v.match_number(),
+            },
+            BodyView::OrderExecutedWithPrice(v) => Body::OrderExecutedWithPrice {
+                reference: v.reference(),
+                executed: v.executed(),
+                match_number: v.match_number(),
+                printable: v.printable()?,
+                price: v.price().into(),
+            },
+            BodyView::OrderCancel(v) => Body::OrderCancelled {
+                reference: v.reference(),
+                cancelled: v.cancelled(),
+            },
+            BodyView::OrderDelete(v) => Body::DeleteOrder {
+                reference: v.reference(),
+            },
+            BodyView::OrderReplace(v) => Body::ReplaceOrder(ReplaceOrder {
+                old_reference: v.old_reference(),
+                new_reference: v.new_reference(),
+                shares: v.shares(),
+                price: v.price().into(),
+            }),
+            BodyView::NonCrossTrade(v) => Body::NonCrossTrade(NonCrossTrade {
+                reference: v.reference(),
+                side: v.side()?,
+                shares: v.shares(),
+                stock: stock(v.stock())?,
+                price: v.price().into(),
+                match_number: v.match_number(),
+            }),
+            BodyView::CrossTrade(v) => Body::CrossTrade(itchy::CrossTrade {
+                shares: v.shares(),
+                stock: stock(v.stock())?,
+                cross_price: v.cross_price().into(),
+                match_number: v.match_number(),
+                cross_type: v.cross_type()?,
+            }),
+            BodyView::BrokenTrade(v) => Body::BrokenTrade {
+                match_number: v.match_number(),
+            },
+            BodyView::Noii(v) => Body::Imbalance(ImbalanceIndicator {
+                paired_shares: v.paired_shares(),
+                imbalance_shares: v.imbalance_shares(),
+                imbalance_direction: v.imbalance_direction()?,
+                stock: stock(v.stock())?,
+                far_price: v.far_price().into(),
+                near_price: v.near_price().into(),
+                current_ref_price: v.current_ref_price().into(),
+                cross_type: v.cross_type()?,
+                price_variation_indicator: v.price_variation_indicator() as char,
+            }),
+            BodyView::Rpii(v) => {
+                Body::RetailPriceImprovementIndicator(RetailPriceImprovementIndicator {
+                    stock: stock(v.stock())?,
+                    interest_flag: v.interest_flag()?,
+                })
+            }
+        };
+
+        Some(Message {
+            tag: self.tag(),
+            stock_locate: self.stock_locate(),
+            tracking_number: self.tracking_number(),
+            timestamp: self.timestamp(),
+            body,
+        })
+    }
+}
+
+// Splits a buffer of length-prefixed frames into payloads without decoding
+// them. The framing is shared by ITCH 5.0, 4.1 and Nordic files. A truncated
+// frame ends iteration.
+#[derive(Debug, Clone)]
+pub struct RawFrames<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RawFrames<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        RawFrames { buf, pos: 0 }
+    }
+
+    // Starts at a byte offset, which must be the start of a frame. Positions
+    // stay relative to the whole buffer
+    pub fn starting_at(buf: &'a [u8], pos: usize) -> Self {
+        RawFrames {
+            buf,
+            pos: pos.min(buf.len()),
+        }
+    }
+
+    // Byte offset of the next frame
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+impl<'a> Iterator for RawFrames<'a> {
+    type Item = Result<&'a [u8], DecodeError>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.buf[self.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let len = match rest {
+            [hi, lo, ..] => u16::from_be_bytes([*hi, *lo]) as usize,
+            _ => 0,
+        };
+        if rest.len() < 2 || rest.len() < 2 + len {
+            self.pos = self.buf.len();
+            return Some(Err(DecodeError::Truncated {
+                needed: 2 + len,
+                available: rest.len(),
+            }));
+        }
+
+        self.pos += 2 + len;
+        Some(Ok(&rest[2..2 + len]))
+    }
+}
+
+// Reads length-prefixed frames from a stream one at a time, e.g. a
+// compressed file from input::open, so the input never has to fit in memory.
+// Payloads are read into a buffer that's reused for every frame.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(reader: R) -> Self {
+        FrameReader {
+            reader,
+            buf: Vec::with_capacity(u16::MAX as usize),
+        }
+    }
+
+    // The next frame's payload, None at the end of the input. A frame cut
+    // short by the end of the input is an UnexpectedEof error
+    pub fn next_frame(&mut self) -> io::Result<Option<&[u8]>> {
+        let mut len = [0u8; 2];
+        let read = read_full(&mut self.reader, &mut len)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        if read < len.len() {
+            return Err(truncated(2, read));
+        }
+
+        let len = u16::from_be_bytes(len) as usize;
+        self.buf.resize(len, 0);
+        let read = read_full(&mut self.reader, &mut self.buf)?;
+        if read < len {
+            return Err(truncated(2 + len, 2 + read));
+        }
+        Ok(Some(&self.buf))
+    }
+}
+
+// Fills buf unless the input ends first, returning the bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn truncated(needed: usize, available: usize) -> io::Error {
+    io::Error::new(
+        ErrorKind::UnexpectedEof,
+        DecodeError::Truncated { needed, available },
+    )
+}
+
+// Iterates over the messages in a buffer of length-prefixed frames, as found
+// in ITCH files. Malformed messages are reported and skipped. A truncated
+// frame ends iteration.
+#[derive(Debug, Clone)]
+pub struct Frames<'a> {
+    raw: RawFrames<'a>,
+}
+
+impl<'a> Frames<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Frames {
+            raw: RawFrames::new(buf),
+        }
+    }
+
+    pub fn starting_at(buf: &'a [u8], pos: usize) -> Self {
+        Frames {
+            raw: RawFrames::starting_at(buf, pos),
+        }
+    }
+
+    // Byte offset of the next frame
+    pub fn position(&self) -> usize {
+        self.raw.position()
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<MessageView<'a>, DecodeError>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.raw.next()?.and_then(MessageView::new))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_order(side: u8) -> Vec<u8> {
+        let mut buf = vec![0; 36];
+        buf[0] = b'A';
+        buf[11..19].copy_from_slice(&7u64.to_be_bytes());
+        buf[19] = side;
+        buf[20..24].copy_from_slice(&100u32.to_be_bytes());
+        buf[24..32].copy_from_slice(b"AAPL    ");
+        buf[32..36].copy_from_slice(&1_000_000u32.to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn add_order_sides() {
+        for (byte, side) in [(b'B', OrderSide::Buy), (b'S', OrderSide::Sell)] {
+            let buf = add_order(byte);
+            let event = MessageView::new(&buf).unwrap().order_event();
+            assert_eq!(
+                event,
+                Some(OrderEvent::Add {
+                    reference: 7,
+                    side,
+                    shares: 100,
+                    price: 1_000_000,
+                    mpid: None,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_sides_are_bad_frames() {
+        let buf = add_order(b'X');
+        assert_eq!(
+            MessageView::new(&buf).err(),
+            Some(DecodeError::BadCode {
+                tag: b'A',
+                field: "side"
+            })
+        );
+
+        // Counted like any other bad frame, the frames after it still decode
+        let mut data = Vec::new();
+        for side in [b'X', b'S'] {
+            data.extend_from_slice(&36u16.to_be_bytes());
+            data.extend_from_slice(&add_order(side));
+        }
+        let frames: Vec<_> = Frames::new(&data).map(|frame| frame.is_ok()).collect();
+        assert_eq!(frames, [false, true]);
+    }
+}
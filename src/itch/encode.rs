@@ -0,0 +1,634 @@
+// ITCH 5.0 encoding, the reverse of decode. Messages are written with their
+// big-endian fields at the same offsets the views read them from, and framed
+// with a u16 length prefix as in ITCH files.
+
+use std::fmt;
+use std::io::{self, Write};
+
+use itchy::{Body, Message};
+
+use super::codes;
+use super::decode::{HEADER_LEN, MessageView, message_len};
+
+// Largest timestamp that fits in 48 bits
+pub const MAX_TIMESTAMP: u64 = (1 << 48) - 1;
+
+#[derive(Debug)]
+pub enum EncodeError {
+    Io(io::Error),
+    TimestampOutOfRange(u64),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Io(e) => write!(f, "{e}"),
+            EncodeError::TimestampOutOfRange(ts) => {
+                write!(f, "timestamp {ts} doesn't fit in 48 bits")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+impl From<io::Error> for EncodeError {
+    fn from(e: io::Error) -> Self {
+        EncodeError::Io(e)
+    }
+}
+
+// Fields shared by every message
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct Header {
+    pub stock_locate: u16,
+    pub tracking_number: u16,
+    pub timestamp: u64,
+}
+
+impl From<&Message> for Header {
+    fn from(msg: &Message) -> Self {
+        Header {
+            stock_locate: msg.stock_locate,
+            tracking_number: msg.tracking_number,
+            timestamp: msg.timestamp,
+        }
+    }
+}
+
+// Direct Listing with Capital Raise price discovery (O), which itchy has no
+// body for
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DirectListing {
+    pub stock: String,
+    pub open_eligibility: u8,
+    pub min_allowable_price: u32,
+    pub max_allowable_price: u32,
+    pub near_execution_price: u32,
+    pub near_execution_time: u64,
+    pub lower_price_range_collar: u32,
+    pub upper_price_range_collar: u32,
+}
+
+// Appends fields to a payload. Alpha fields are left aligned and space
+// padded, longer values are cut to the field width.
+struct Fields<'a>(&'a mut Vec<u8>);
+
+impl Fields<'_> {
+    fn header(&mut self, tag: u8, header: &Header) -> Result<&mut Self, EncodeError> {
+        if header.timestamp > MAX_TIMESTAMP {
+            return Err(EncodeError::TimestampOutOfRange(header.timestamp));
+        }
+        self.0.push(tag);
+        self.0.extend_from_slice(&header.stock_locate.to_be_bytes());
+        self.0
+            .extend_from_slice(&header.tracking_number.to_be_bytes());
+        self.0
+            .extend_from_slice(&header.timestamp.to_be_bytes()[2..]);
+        Ok(self)
+    }
+
+    fn byte(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_be_bytes());
+        self
+    }
+
+    fn alpha(&mut self, s: &str, len: usize) -> &mut Self {
+        let bytes = &s.as_bytes()[..s.len().min(len)];
+        self.0.extend_from_slice(bytes);
+        self.0.resize(self.0.len() + len - bytes.len(), b' ');
+        self
+    }
+
+    fn bool(&mut self, v: bool) -> &mut Self {
+        self.byte(codes::bool_flag_code(v))
+    }
+
+    fn maybe_bool(&mut self, v: Option<bool>) -> &mut Self {
+        self.byte(codes::maybe_bool_flag_code(v))
+    }
+}
+
+// Appends a message payload, without its length prefix. The tag comes from
+// the body, with adds that carry an MPID written as F.
+pub fn encode(msg: &Message, out: &mut Vec<u8>) -> Result<(), EncodeError> {
+    let start = out.len();
+    let tag = match &msg.body {
+        Body::AddOrder(add) if add.mpid.is_some() => b'F',
+        Body::AddOrder(_) => b'A',
+        Body::Breach(_) => b'W',
+        Body::BrokenTrade { .. } => b'B',
+        Body::CrossTrade(_) => b'Q',
+        Body::DeleteOrder { .. } => b'D',
+        Body::Imbalance(_) => b'I',
+        Body::IpoQuotingPeriod(_) => b'K',
+        Body::LULDAuctionCollar { .. } => b'J',
+        Body::MwcbDeclineLevel { .. } => b'V',
+        Body::NonCrossTrade(_) => b'P',
+        Body::OrderCancelled { .. } => b'X',
+        Body::OrderExecuted { .. } => b'E',
+        Body::OrderExecutedWithPrice { .. } => b'C',
+        Body::ParticipantPosition(_) => b'L',
+        Body::RegShoRestriction { .. } => b'Y',
+        Body::ReplaceOrder(_) => b'U',
+        Body::StockDirectory(_) => b'R',
+        Body::SystemEvent { .. } => b'S',
+        Body::TradingAction { .. } => b'H',
+        Body::RetailPriceImprovementIndicator(_) => b'N',
+    };
+
+    let mut f = Fields(out);
+    f.header(tag, &Header::from(msg))?;
+
+    match &msg.body {
+        Body::SystemEvent { event } => {
+            f.byte(codes::system_event_code(*event));
+        }
+        Body::StockDirectory(d) => {
+            f.alpha(&d.stock, 8)
+                .byte(codes::market_category_code(d.market_category))
+                .byte(codes::financial_status_code(d.financial_status))
+                .u32(d.round_lot_size)
+                .bool(d.round_lots_only)
+                .byte(codes::issue_classification_code(d.issue_classification));
+            f.0.extend_from_slice(codes::issue_subtype_code(d.issue_subtype));
+            f.byte(codes::authenticity_code(d.authenticity))
+                .maybe_bool(d.short_sale_threshold)
+                .maybe_bool(d.ipo_flag)
+                .byte(codes::luld_ref_price_tier_code(d.luld_ref_price_tier))
+                // Y rather than M for ETPs, itchy reads both as true
+                .maybe_bool(d.etp_flag)
+                .u32(d.etp_leverage_factor)
+                .bool(d.inverse_indicator);
+        }
+        Body::TradingAction {
+            stock,
+            trading_state,
+            reason,
+        } => {
+            f.alpha(stock, 8)
+                .byte(codes::trading_state_code(*trading_state))
+                // Reserved
+                .byte(b' ')
+                .alpha(reason, 4);
+        }
+        Body::RegShoRestriction { stock, action } => {
+            f.alpha(stock, 8).byte(codes::reg_sho_action_code(*action));
+        }
+        Body::ParticipantPosition(p) => {
+            f.alpha(&p.mpid, 4)
+                .alpha(&p.stock, 8)
+                .bool(p.primary_market_maker)
+                .byte(codes::market_maker_mode_code(p.market_maker_mode))
+                .byte(codes::market_participant_state_code(
+                    p.market_participant_state,
+                ));
+        }
+        Body::MwcbDeclineLevel {
+            level1,
+            level2,
+            level3,
+        } => {
+            f.u64(level1.raw()).u64(level2.raw()).u64(level3.raw());
+        }
+        Body::Breach(level) => {
+            f.byte(codes::level_breached_code(*level));
+        }
+        Body::IpoQuotingPeriod(ipo) => {
+            f.alpha(&ipo.stock, 8)
+                .u32(ipo.release_time)
+                .byte(codes::ipo_release_qualifier_code(ipo.release_qualifier))
+                .u32(ipo.price.raw());
+        }
+        Body::LULDAuctionCollar {
+            stock,
+            ref_price,
+            upper_price,
+            lower_price,
+            extension,
+        } => {
+            f.alpha(stock, 8)
+                .u32(ref_price.raw())
+                .u32(upper_price.raw())
+                .u32(lower_price.raw())
+                .u32(*extension);
+        }
+        Body::AddOrder(add) => {
+            f.u64(add.reference)
+                .byte(codes::side_code(add.side))
+                .u32(add.shares)
+                .alpha(&add.stock, 8)
+                .u32(add.price.raw());
+            if let Some(mpid) = &add.mpid {
+                f.alpha(mpid, 4);
+            }
+        }
+        Body::OrderExecuted {
+            reference,
+            executed,
+            match_number,
+        } => {
+            f.u64(*reference).u32(*executed).u64(*match_number);
+        }
+        Body::OrderExecutedWithPrice {
+            reference,
+            executed,
+            match_number,
+            printable,
+            price,
+        } => {
+            f.u64(*reference)
+                .u32(*executed)
+                .u64(*match_number)
+                .bool(*printable)
+                .u32(price.raw());
+        }
+        Body::OrderCancelled {
+            reference,
+            cancelled,
+        } => {
+            f.u64(*reference).u32(*cancelled);
+        }
+        Body::DeleteOrder { reference } => {
+            f.u64(*reference);
+        }
+        Body::ReplaceOrder(r) => {
+            f.u64(r.old_reference)
+                .u64(r.new_reference)
+                .u32(r.shares)
+                .u32(r.price.raw());
+        }
+        Body::NonCrossTrade(t) => {
+            f.u64(t.reference)
+                .byte(codes::side_code(t.side))
+                .u32(t.shares)
+                .alpha(&t.stock, 8)
+                .u32(t.price.raw())
+                .u64(t.match_number);
+        }
+        Body::CrossTrade(t) => {
+            f.u64(t.shares)
+                .alpha(&t.stock, 8)
+                .u32(t.cross_price.raw())
+                .u64(t.match_number)
+                .byte(codes::cross_type_code(t.cross_type));
+        }
+        Body::BrokenTrade { match_number } => {
+            f.u64(*match_number);
+        }
+        Body::Imbalance(i) => {
+            f.u64(i.paired_shares)
+                .u64(i.imbalance_shares)
+                .byte(codes::imbalance_direction_code(i.imbalance_direction))
+                .alpha(&i.stock, 8)
+                .u32(i.far_price.raw())
+                .u32(i.near_price.raw())
+                .u32(i.current_ref_price.raw())
+                .byte(codes::cross_type_code(i.cross_type))
+                .byte(i.price_variation_indicator as u8);
+        }
+        Body::RetailPriceImprovementIndicator(r) => {
+            f.alpha(&r.stock, 8)
+                .byte(codes::interest_flag_code(r.interest_flag));
+        }
+    }
+
+    debug_assert_eq!(Some(out.len() - start), message_len(tag));
+    Ok(())
+}
+
+// Operational Halt (h). Market code is Q, B or X, action is H (halted) or T
+// (resumed)
+pub fn encode_operational_halt(
+    header: &Header,
+    stock: &str,
+    market_code: u8,
+    action: u8,
+    out: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    Fields(out)
+        .header(b'h', header)?
+        .alpha(stock, 8)
+        .byte(market_code)
+        .byte(action);
+    Ok(())
+}
+
+pub fn encode_direct_listing(
+    header: &Header,
+    listing: &DirectListing,
+    out: &mut Vec<u8>,
+) -> Result<(), EncodeError> {
+    Fields(out)
+        .header(b'O', header)?
+        .alpha(&listing.stock, 8)
+        .byte(listing.open_eligibility)
+        .u32(listing.min_allowable_price)
+        .u32(listing.max_allowable_price)
+        .u32(listing.near_execution_price)
+        .u64(listing.near_execution_time)
+        .u32(listing.lower_price_range_collar)
+        .u32(listing.upper_price_range_collar);
+    Ok(())
+}
+
+// Rewrites the header of an encoded payload in place, e.g. to shift
+// timestamps or remap locates while copying a feed
+pub fn patch_header(payload: &mut [u8], header: &Header) -> Result<(), EncodeError> {
+    if header.timestamp > MAX_TIMESTAMP {
+        return Err(EncodeError::TimestampOutOfRange(header.timestamp));
+    }
+    payload[1..3].copy_from_slice(&header.stock_locate.to_be_bytes());
+    payload[3..5].copy_from_slice(&header.tracking_number.to_be_bytes());
+    payload[5..HEADER_LEN].copy_from_slice(&header.timestamp.to_be_bytes()[2..]);
+    Ok(())
+}
+
+// Writes length-prefixed frames, the layout Frames and itchy read
+#[derive(Debug)]
+pub struct ItchWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    messages: u64,
+}
+
+impl<W: Write> ItchWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ItchWriter {
+            inner,
+            buf: Vec::with_capacity(64),
+            messages: 0,
+        }
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages
+    }
+
+    pub fn write(&mut self, msg: &Message) -> Result<(), EncodeError> {
+        self.buf.clear();
+        encode(msg, &mut self.buf)?;
+        self.flush_frame()
+    }
+
+    // Copies a decoded message as is
+    pub fn write_view(&mut self, view: &MessageView) -> io::Result<()> {
+        self.write_payload(view.as_bytes())
+    }
+
+    pub fn write_operational_halt(
+        &mut self,
+        header: &Header,
+        stock: &str,
+        market_code: u8,
+        action: u8,
+    ) -> Result<(), EncodeError> {
+        self.buf.clear();
+        encode_operational_halt(header, stock, market_code, action, &mut self.buf)?;
+        self.flush_frame()
+    }
+
+    pub fn write_direct_listing(
+        &mut self,
+        header: &Header,
+        listing: &DirectListing,
+    ) -> Result<(), EncodeError> {
+        self.buf.clear();
+        encode_direct_listing(header, listing, &mut self.buf)?;
+        self.flush_frame()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn flush_frame(&mut self) -> Result<(), EncodeError> {
+        let buf = std::mem::take(&mut self.buf);
+        let res = self.write_payload(&buf);
+        self.buf = buf;
+        Ok(res?)
+    }
+
+    // Writes a payload that is already encoded, e.g. one patched in place
+    pub fn write_payload(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.inner
+            .write_all(&(payload.len() as u16).to_be_bytes())?;
+        self.inner.write_all(payload)?;
+        self.messages += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::{
+        AddOrder, ArrayString4, ArrayString8, CrossTrade, CrossType, EventCode, FinancialStatus,
+        ImbalanceDirection, ImbalanceIndicator, InterestFlag, IpoQuotingPeriod,
+        IpoReleaseQualifier, IssueClassification, IssueSubType, LevelBreached, LuldRefPriceTier,
+        MarketCategory, MarketMakerMode, MarketParticipantPosition, MarketParticipantState,
+        NonCrossTrade, RegShoAction, ReplaceOrder, RetailPriceImprovementIndicator, Side,
+        StockDirectory, TradingState,
+    };
+
+    use super::*;
+
+    // Alpha fields are space padded, as itchy reads them
+    fn stock() -> ArrayString8 {
+        ArrayString8::from("AAPL    ").unwrap()
+    }
+
+    fn mpid() -> ArrayString4 {
+        ArrayString4::from("GSCO").unwrap()
+    }
+
+    // One of every body itchy has
+    fn bodies() -> Vec<Body> {
+        vec![
+            Body::SystemEvent {
+                event: EventCode::StartOfMarketHours,
+            },
+            Body::StockDirectory(StockDirectory {
+                stock: stock(),
+                market_category: MarketCategory::NasdaqGlobalSelect,
+                financial_status: FinancialStatus::DeficientDelinquent,
+                round_lot_size: 100,
+                round_lots_only: true,
+                issue_classification: IssueClassification::CommonStock,
+                issue_subtype: IssueSubType::ExchangeTradedNotes,
+                authenticity: true,
+                short_sale_threshold: Some(false),
+                ipo_flag: None,
+                luld_ref_price_tier: LuldRefPriceTier::Tier2,
+                etp_flag: Some(true),
+                etp_leverage_factor: 3,
+                inverse_indicator: false,
+            }),
+            Body::TradingAction {
+                stock: stock(),
+                trading_state: TradingState::Halted,
+                reason: ArrayString4::from("T1  ").unwrap(),
+            },
+            Body::RegShoRestriction {
+                stock: stock(),
+                action: RegShoAction::Intraday,
+            },
+            Body::ParticipantPosition(MarketParticipantPosition {
+                mpid: mpid(),
+                stock: stock(),
+                primary_market_maker: true,
+                market_maker_mode: MarketMakerMode::Syndicate,
+                market_participant_state: MarketParticipantState::Excused,
+            }),
+            Body::MwcbDeclineLevel {
+                level1: 1_000_000_000_000u64.into(),
+                level2: 900_000_000_000u64.into(),
+                level3: 800_000_000_000u64.into(),
+            },
+            Body::Breach(LevelBreached::L2),
+            Body::IpoQuotingPeriod(IpoQuotingPeriod {
+                stock: stock(),
+                release_time: 34_200,
+                release_qualifier: IpoReleaseQualifier::Anticipated,
+                price: 250_000u32.into(),
+            }),
+            Body::LULDAuctionCollar {
+                stock: stock(),
+                ref_price: 1_500_000u32.into(),
+                upper_price: 1_575_000u32.into(),
+                lower_price: 1_425_000u32.into(),
+                extension: 1,
+            },
+            Body::AddOrder(AddOrder {
+                reference: 1,
+                side: Side::Buy,
+                shares: 100,
+                stock: stock(),
+                price: 1_500_000u32.into(),
+                mpid: None,
+            }),
+            Body::AddOrder(AddOrder {
+                reference: 2,
+                side: Side::Sell,
+                shares: 200,
+                stock: stock(),
+                price: 1_500_100u32.into(),
+                mpid: Some(mpid()),
+            }),
+            Body::OrderExecuted {
+                reference: 1,
+                executed: 50,
+                match_number: 7,
+            },
+            Body::OrderExecutedWithPrice {
+                reference: 1,
+                executed: 25,
+                match_number: 8,
+                printable: false,
+                price: 1_499_900u32.into(),
+            },
+            Body::OrderCancelled {
+                reference: 2,
+                cancelled: 100,
+            },
+            Body::DeleteOrder { reference: 2 },
+            Body::ReplaceOrder(ReplaceOrder {
+                old_reference: 1,
+                new_reference: 3,
+                shares: 300,
+                price: 1_500_200u32.into(),
+            }),
+            Body::NonCrossTrade(NonCrossTrade {
+                reference: 0,
+                side: Side::Buy,
+                shares: 400,
+                stock: stock(),
+                price: 1_500_300u32.into(),
+                match_number: 9,
+            }),
+            Body::CrossTrade(CrossTrade {
+                shares: 5_000_000_000,
+                stock: stock(),
+                cross_price: 1_500_000u32.into(),
+                match_number: 10,
+                cross_type: CrossType::Closing,
+            }),
+            Body::BrokenTrade { match_number: 9 },
+            Body::Imbalance(ImbalanceIndicator {
+                paired_shares: 10_000,
+                imbalance_shares: 2_500,
+                imbalance_direction: ImbalanceDirection::Sell,
+                stock: stock(),
+                far_price: 1_490_000u32.into(),
+                near_price: 1_495_000u32.into(),
+                current_ref_price: 1_500_000u32.into(),
+                cross_type: CrossType::Opening,
+                price_variation_indicator: 'A',
+            }),
+            Body::RetailPriceImprovementIndicator(RetailPriceImprovementIndicator {
+                stock: stock(),
+                interest_flag: InterestFlag::RPIAvailableBothSides,
+            }),
+        ]
+    }
+
+    #[test]
+    fn views_decode_what_was_encoded() {
+        let mut out = Vec::new();
+        for (i, body) in bodies().into_iter().enumerate() {
+            let msg = Message {
+                tag: 0,
+                stock_locate: 7,
+                tracking_number: i as u16,
+                timestamp: MAX_TIMESTAMP - i as u64,
+                body,
+            };
+            out.clear();
+            encode(&msg, &mut out).unwrap();
+
+            let view = MessageView::new(&out).unwrap();
+            let decoded = view.to_message().unwrap();
+            assert_eq!(decoded.body, msg.body);
+            assert_eq!(decoded.tag, view.tag());
+            assert_eq!(
+                Header::from(&decoded),
+                Header::from(&msg),
+                "header of {:?}",
+                view.tag() as char
+            );
+        }
+    }
+
+    #[test]
+    fn timestamps_must_fit_48_bits() {
+        let msg = Message {
+            tag: b'D',
+            stock_locate: 1,
+            tracking_number: 0,
+            timestamp: MAX_TIMESTAMP + 1,
+            body: Body::DeleteOrder { reference: 1 },
+        };
+        let mut out = Vec::new();
+        assert!(matches!(
+            encode(&msg, &mut out),
+            Err(EncodeError::TimestampOutOfRange(_))
+        ));
+    }
+}
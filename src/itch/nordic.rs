@@ -0,0 +1,564 @@
+// Nasdaq Nordic ITCH, for the Genium INET markets (Stockholm, Helsinki,
+// Copenhagen, Iceland and the Baltics). Instruments are order book IDs
+// rather than stock locates, and the Order Book Directory (R) gives each its
+// own number of price decimals. Timestamps are split between Seconds (T)
+// messages and a 32-bit nanosecond offset, as in ITCH 4.1.
+//
+// Order book IDs are given stock locates in the order they're listed.
+// Prices are rescaled to ITCH 5.0's four decimals and quantities narrowed to
+// 32 bits. Messages with values that don't fit, negative prices or ones with
+// more than four significant decimals, are malformed.
+
+use itchy::{AddOrder, ArrayString4, ArrayString8, Body, Message, NonCrossTrade, ReplaceOrder};
+use rustc_hash::FxHashMap;
+
+use super::codes;
+use super::decode::{DecodeError, alpha, be_u16, be_u32, be_u64, byte};
+use super::protocol::{Decoded, check_len};
+use crate::directory::StockInfo;
+use crate::seek::NANOS_PER_SECOND;
+
+// Decimals of ITCH 5.0 prices
+const PRICE_DECIMALS: u32 = 4;
+
+// Payload length of each Nordic ITCH message type
+pub fn message_len(tag: u8) -> Option<usize> {
+    Some(match tag {
+        b'T' => 5,
+        b'S' => 6,
+        b'R' => 130,
+        b'H' => 10,
+        b'O' => 29,
+        b'A' => 37,
+        b'F' => 44,
+        b'E' => 52,
+        b'C' => 58,
+        b'D' => 18,
+        b'U' => 42,
+        b'P' => 50,
+        b'Z' => 53,
+        _ => return None,
+    })
+}
+
+// Add Order (A and F). The ranking is the order's position in the book's
+// priority queue, which ITCH 5.0 has no field for.
+#[derive(Debug, Copy, Clone)]
+pub struct AddOrderView<'a>(&'a [u8]);
+
+impl<'a> AddOrderView<'a> {
+    pub fn new(b: &'a [u8]) -> Option<Self> {
+        let tag = *b.first()?;
+        (matches!(tag, b'A' | b'F') && message_len(tag) == Some(b.len())).then_some(AddOrderView(b))
+    }
+
+    pub fn order_id(&self) -> u64 {
+        be_u64(self.0, 5)
+    }
+
+    pub fn order_book(&self) -> u32 {
+        be_u32(self.0, 13)
+    }
+
+    pub fn side(&self) -> u8 {
+        byte(self.0, 17)
+    }
+
+    pub fn ranking(&self) -> u32 {
+        be_u32(self.0, 18)
+    }
+
+    pub fn quantity(&self) -> u64 {
+        be_u64(self.0, 22)
+    }
+
+    // In the order book's own decimals
+    pub fn price(&self) -> i32 {
+        be_u32(self.0, 30) as i32
+    }
+
+    // Only set on F messages
+    pub fn participant(&self) -> Option<&'a str> {
+        (self.0.len() == 44).then(|| alpha(self.0, 37, 7))
+    }
+}
+
+// Order Replace (U). The original order is removed and the new one added
+// with its own ID and ranking, on the same order book and side.
+#[derive(Debug, Copy, Clone)]
+pub struct ReplaceOrderView<'a>(&'a [u8]);
+
+impl<'a> ReplaceOrderView<'a> {
+    pub fn new(b: &'a [u8]) -> Option<Self> {
+        (b.first() == Some(&b'U') && message_len(b'U') == Some(b.len()))
+            .then_some(ReplaceOrderView(b))
+    }
+
+    pub fn original_order_id(&self) -> u64 {
+        be_u64(self.0, 5)
+    }
+
+    pub fn order_book(&self) -> u32 {
+        be_u32(self.0, 13)
+    }
+
+    pub fn side(&self) -> u8 {
+        byte(self.0, 17)
+    }
+
+    pub fn new_order_id(&self) -> u64 {
+        be_u64(self.0, 18)
+    }
+
+    pub fn ranking(&self) -> u32 {
+        be_u32(self.0, 26)
+    }
+
+    pub fn quantity(&self) -> u64 {
+        be_u64(self.0, 30)
+    }
+
+    // In the order book's own decimals
+    pub fn price(&self) -> i32 {
+        be_u32(self.0, 38) as i32
+    }
+}
+
+#[derive(Debug, Clone)]
+struct OrderBookInfo {
+    stock_locate: u16,
+    // Truncated to fit ITCH 5.0 messages, the listing has the full symbol
+    stock: ArrayString8,
+    price_decimals: u16,
+}
+
+#[derive(Debug, Default)]
+pub struct Translator {
+    seconds: u64,
+    books: FxHashMap<u32, OrderBookInfo>,
+}
+
+impl Translator {
+    pub fn new() -> Self {
+        Translator::default()
+    }
+
+    // Locate assigned to an order book ID, 0 if it hasn't been listed
+    pub fn locate(&self, order_book: u32) -> u16 {
+        self.books.get(&order_book).map_or(0, |b| b.stock_locate)
+    }
+
+    // None for Seconds, Order Book State and Equilibrium messages, messages
+    // with invalid codes and ones for order books that weren't listed
+    pub fn translate(&mut self, b: &[u8]) -> Result<Option<Decoded<'static>>, DecodeError> {
+        let tag = check_len(b, message_len)?;
+        if tag == b'T' {
+            self.seconds = be_u32(b, 1) as u64;
+            return Ok(None);
+        }
+        let timestamp = self.seconds * NANOS_PER_SECOND + be_u32(b, 1) as u64;
+
+        if tag == b'R' {
+            return Ok(self.list(b, timestamp).map(Decoded::Listing));
+        }
+
+        let Some((stock_locate, body)) = self.body(tag, b)? else {
+            return Ok(None);
+        };
+        Ok(Some(Decoded::Message(Message {
+            tag,
+            stock_locate,
+            tracking_number: 0,
+            timestamp,
+            body,
+        })))
+    }
+
+    fn list(&mut self, b: &[u8], timestamp: u64) -> Option<StockInfo> {
+        let order_book = be_u32(b, 5);
+        let symbol = alpha(b, 9, 32).trim_end();
+        let stock_locate = match self.books.get(&order_book) {
+            Some(book) => book.stock_locate,
+            None => u16::try_from(self.books.len() + 1).ok()?,
+        };
+
+        let stock =
+            ArrayString8::from(&symbol[..symbol.floor_char_boundary(8)]).unwrap_or_default();
+        self.books.insert(
+            order_book,
+            OrderBookInfo {
+                stock_locate,
+                stock,
+                price_decimals: be_u16(b, 89),
+            },
+        );
+
+        let mut info = StockInfo::new(stock_locate, symbol, timestamp);
+        info.round_lot_size = be_u32(b, 97);
+        Some(info)
+    }
+
+    fn body(&self, tag: u8, b: &[u8]) -> Result<Option<(u16, Body)>, DecodeError> {
+        let book = |id: u32| self.books.get(&id);
+        Ok(Some(match tag {
+            b'S' => {
+                let Some(event) = codes::event_code(byte(b, 5)) else {
+                    return Ok(None);
+                };
+                (0, Body::SystemEvent { event })
+            }
+            b'H' => {
+                let (Some(book), Some(trading_state)) =
+                    (book(be_u32(b, 5)), codes::trading_state(byte(b, 9)))
+                else {
+                    return Ok(None);
+                };
+                (
+                    book.stock_locate,
+                    Body::TradingAction {
+                        stock: book.stock,
+                        trading_state,
+                        reason: ArrayString4::new(),
+                    },
+                )
+            }
+            b'A' | b'F' => {
+                let add = AddOrderView(b);
+                let (Some(book), Some(side)) = (book(add.order_book()), codes::side(add.side()))
+                else {
+                    return Ok(None);
+                };
+                let mpid = add.participant().map(|p| {
+                    ArrayString4::from(&p[..p.floor_char_boundary(4)]).unwrap_or_default()
+                });
+                (
+                    book.stock_locate,
+                    Body::AddOrder(AddOrder {
+                        reference: reference(add.order_id(), add.side()),
+                        side,
+                        shares: quantity(tag, add.quantity())?,
+                        stock: book.stock,
+                        price: price(tag, add.price(), book.price_decimals)?.into(),
+                        mpid,
+                    }),
+                )
+            }
+            b'E' | b'C' => {
+                let Some(book) = book(be_u32(b, 13)) else {
+                    return Ok(None);
+                };
+                let reference = reference(be_u64(b, 5), byte(b, 17));
+                let executed = quantity(tag, be_u64(b, 18))?;
+                let match_number = be_u64(b, 26);
+                let body = if tag == b'E' {
+                    Body::OrderExecuted {
+                        reference,
+                        executed,
+                        match_number,
+                    }
+                } else {
+                    let Some(printable) = codes::bool_flag(byte(b, 57)) else {
+                        return Ok(None);
+                    };
+                    Body::OrderExecutedWithPrice {
+                        reference,
+                        executed,
+                        match_number,
+                        printable,
+                        price: price(tag, be_u32(b, 52) as i32, book.price_decimals)?.into(),
+                    }
+                };
+                (book.stock_locate, body)
+            }
+            b'D' => (
+                self.locate(be_u32(b, 13)),
+                Body::DeleteOrder {
+                    reference: reference(be_u64(b, 5), byte(b, 17)),
+                },
+            ),
+            b'U' => {
+                let replace = ReplaceOrderView(b);
+                let Some(book) = book(replace.order_book()) else {
+                    return Ok(None);
+                };
+                (
+                    book.stock_locate,
+                    Body::ReplaceOrder(ReplaceOrder {
+                        old_reference: reference(replace.original_order_id(), replace.side()),
+                        new_reference: reference(replace.new_order_id(), replace.side()),
+                        shares: quantity(tag, replace.quantity())?,
+                        price: price(tag, replace.price(), book.price_decimals)?.into(),
+                    }),
+                )
+            }
+            b'P' => {
+                let Some(book) = book(be_u32(b, 26)) else {
+                    return Ok(None);
+                };
+                (
+                    book.stock_locate,
+                    Body::NonCrossTrade(NonCrossTrade {
+                        reference: 0,
+                        side: codes::side(byte(b, 17)).unwrap_or(itchy::Side::Buy),
+                        shares: quantity(tag, be_u64(b, 18))?,
+                        stock: book.stock,
+                        price: price(tag, be_u32(b, 30) as i32, book.price_decimals)?.into(),
+                        match_number: be_u64(b, 5),
+                    }),
+                )
+            }
+            _ => return Ok(None),
+        }))
+    }
+}
+
+fn quantity(tag: u8, quantity: u64) -> Result<u32, DecodeError> {
+    u32::try_from(quantity).map_err(|_| DecodeError::OutOfRange {
+        tag,
+        field: "quantity",
+    })
+}
+
+// Rescales a price to four decimals
+fn price(tag: u8, price: i32, decimals: u16) -> Result<u32, DecodeError> {
+    let out_of_range = DecodeError::OutOfRange {
+        tag,
+        field: "price",
+    };
+    let price = u64::try_from(price).map_err(|_| out_of_range)?;
+    let decimals = decimals as u32;
+    let scaled = if decimals <= PRICE_DECIMALS {
+        Some(price * 10u64.pow(PRICE_DECIMALS - decimals))
+    } else {
+        // Decimals past the fourth must be 0
+        let divisor = 10u64.checked_pow(decimals - PRICE_DECIMALS);
+        divisor.and_then(|d| (price % d == 0).then_some(price / d))
+    };
+    scaled
+        .and_then(|scaled| u32::try_from(scaled).ok())
+        .ok_or(out_of_range)
+}
+
+// Order IDs are only unique within an order book and side, so the side goes
+// in the low bit of the reference
+fn reference(order_id: u64, side: u8) -> u64 {
+    order_id << 1 | (side == b'S') as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::Side;
+
+    use super::*;
+
+    const BOOK: u32 = 4_242;
+
+    // A zeroed payload of a message type, filled in at spec offsets
+    struct Payload(Vec<u8>);
+
+    impl Payload {
+        fn new(tag: u8, nanos: u32) -> Self {
+            let mut b = vec![0; message_len(tag).unwrap()];
+            b[0] = tag;
+            b[1..5].copy_from_slice(&nanos.to_be_bytes());
+            Payload(b)
+        }
+
+        fn put(mut self, at: usize, bytes: &[u8]) -> Self {
+            self.0[at..at + bytes.len()].copy_from_slice(bytes);
+            self
+        }
+    }
+
+    // Seconds 100 and a listing of BOOK with two price decimals
+    fn translator() -> Translator {
+        let mut t = Translator::new();
+        let seconds = Payload::new(b'T', 0).put(1, &100u32.to_be_bytes());
+        assert!(t.translate(&seconds.0).unwrap().is_none());
+
+        let mut symbol = [b' '; 32];
+        symbol[..9].copy_from_slice(b"ERIC B SE");
+        let directory = Payload::new(b'R', 5)
+            .put(5, &BOOK.to_be_bytes())
+            .put(9, &symbol)
+            .put(89, &2u16.to_be_bytes())
+            .put(97, &100u32.to_be_bytes());
+        let Some(Decoded::Listing(info)) = t.translate(&directory.0).unwrap() else {
+            panic!("R should list the order book");
+        };
+        assert_eq!(info.symbol, "ERIC B SE");
+        assert_eq!(info.stock_locate, 1);
+        assert_eq!(info.round_lot_size, 100);
+        assert_eq!(info.timestamp, 100 * NANOS_PER_SECOND + 5);
+        t
+    }
+
+    fn message(t: &mut Translator, payload: Payload) -> Message {
+        match t.translate(&payload.0).unwrap() {
+            Some(Decoded::Message(msg)) => msg,
+            other => panic!("expected a message, got {other:?}"),
+        }
+    }
+
+    fn add(tag: u8, order_id: u64, side: u8, quantity: u64, price: i32) -> Payload {
+        Payload::new(tag, 7)
+            .put(5, &order_id.to_be_bytes())
+            .put(13, &BOOK.to_be_bytes())
+            .put(17, &[side])
+            .put(18, &3u32.to_be_bytes())
+            .put(22, &quantity.to_be_bytes())
+            .put(30, &price.to_be_bytes())
+    }
+
+    #[test]
+    fn add_orders() {
+        let mut t = translator();
+        let msg = message(&mut t, add(b'A', 10, b'B', 500, 12_345));
+        assert_eq!(msg.stock_locate, 1);
+        assert_eq!(msg.timestamp, 100 * NANOS_PER_SECOND + 7);
+        let Body::AddOrder(add_order) = msg.body else {
+            panic!("A should be an add");
+        };
+        assert_eq!(add_order.reference, 20);
+        assert_eq!(add_order.side, Side::Buy);
+        assert_eq!(add_order.shares, 500);
+        assert_eq!(add_order.stock.as_str(), "ERIC B S");
+        // 123.45 at four decimals
+        assert_eq!(add_order.price.raw(), 1_234_500);
+        assert_eq!(add_order.mpid, None);
+
+        let payload = add(b'A', 10, b'B', 500, 12_345);
+        assert_eq!(AddOrderView::new(&payload.0).unwrap().ranking(), 3);
+
+        let f = add(b'F', 10, b'S', 500, 12_345).put(37, b"NORDEA ");
+        let Body::AddOrder(add_order) = message(&mut t, f).body else {
+            panic!("F should be an add");
+        };
+        // Same order ID on the other side is another order
+        assert_eq!(add_order.reference, 21);
+        assert_eq!(add_order.mpid.unwrap().as_str(), "NORD");
+    }
+
+    #[test]
+    fn executions_deletes_and_trades() {
+        let mut t = translator();
+        let execution = Payload::new(b'E', 8)
+            .put(5, &10u64.to_be_bytes())
+            .put(13, &BOOK.to_be_bytes())
+            .put(17, b"S")
+            .put(18, &200u64.to_be_bytes())
+            .put(26, &99u64.to_be_bytes());
+        assert_eq!(
+            message(&mut t, execution).body,
+            Body::OrderExecuted {
+                reference: 21,
+                executed: 200,
+                match_number: 99,
+            }
+        );
+
+        let with_price = Payload::new(b'C', 8)
+            .put(5, &10u64.to_be_bytes())
+            .put(13, &BOOK.to_be_bytes())
+            .put(17, b"B")
+            .put(18, &50u64.to_be_bytes())
+            .put(26, &100u64.to_be_bytes())
+            .put(52, &12_300i32.to_be_bytes())
+            .put(57, b"N");
+        assert_eq!(
+            message(&mut t, with_price).body,
+            Body::OrderExecutedWithPrice {
+                reference: 20,
+                executed: 50,
+                match_number: 100,
+                printable: false,
+                price: 1_230_000u32.into(),
+            }
+        );
+
+        let delete = Payload::new(b'D', 9)
+            .put(5, &10u64.to_be_bytes())
+            .put(13, &BOOK.to_be_bytes())
+            .put(17, b"B");
+        let msg = message(&mut t, delete);
+        assert_eq!(msg.stock_locate, 1);
+        assert_eq!(msg.body, Body::DeleteOrder { reference: 20 });
+
+        let trade = Payload::new(b'P', 9)
+            .put(5, &101u64.to_be_bytes())
+            .put(17, b"S")
+            .put(18, &75u64.to_be_bytes())
+            .put(26, &BOOK.to_be_bytes())
+            .put(30, &12_400i32.to_be_bytes());
+        let Body::NonCrossTrade(trade) = message(&mut t, trade).body else {
+            panic!("P should be a trade");
+        };
+        assert_eq!(trade.match_number, 101);
+        assert_eq!(trade.side, Side::Sell);
+        assert_eq!(trade.shares, 75);
+        assert_eq!(trade.price.raw(), 1_240_000);
+    }
+
+    #[test]
+    fn replace_orders() {
+        let mut t = translator();
+        let replace = Payload::new(b'U', 9)
+            .put(5, &10u64.to_be_bytes())
+            .put(13, &BOOK.to_be_bytes())
+            .put(17, b"S")
+            .put(18, &11u64.to_be_bytes())
+            .put(26, &4u32.to_be_bytes())
+            .put(30, &300u64.to_be_bytes())
+            .put(38, &12_500i32.to_be_bytes());
+        assert_eq!(ReplaceOrderView::new(&replace.0).unwrap().ranking(), 4);
+        assert_eq!(
+            message(&mut t, replace).body,
+            Body::ReplaceOrder(ReplaceOrder {
+                old_reference: 21,
+                new_reference: 23,
+                shares: 300,
+                price: 1_250_000u32.into(),
+            })
+        );
+    }
+
+    #[test]
+    fn unlisted_books_are_skipped() {
+        let mut t = translator();
+        let other_book = add(b'A', 10, b'B', 500, 12_345).put(13, &7u32.to_be_bytes());
+        assert!(t.translate(&other_book.0).unwrap().is_none());
+    }
+
+    #[test]
+    fn unrepresentable_values_are_malformed() {
+        let mut t = translator();
+        let too_many = add(b'A', 10, b'B', u32::MAX as u64 + 1, 12_345);
+        assert_eq!(
+            t.translate(&too_many.0).unwrap_err(),
+            DecodeError::OutOfRange {
+                tag: b'A',
+                field: "quantity",
+            }
+        );
+        let negative = add(b'A', 10, b'B', 500, -100);
+        assert_eq!(
+            t.translate(&negative.0).unwrap_err(),
+            DecodeError::OutOfRange {
+                tag: b'A',
+                field: "price",
+            }
+        );
+        // Over u32::MAX at four decimals
+        let too_high = add(b'A', 10, b'B', 500, 50_000_000);
+        assert!(t.translate(&too_high.0).is_err());
+    }
+
+    #[test]
+    fn prices_keep_four_decimals() {
+        assert_eq!(price(b'A', 12_345, 0), Ok(123_450_000));
+        assert_eq!(price(b'A', 12_345, 4), Ok(12_345));
+        assert_eq!(price(b'A', 1_234_500, 6), Ok(12_345));
+        assert!(price(b'A', 1_234_567, 6).is_err());
+        assert!(price(b'A', 1, 30).is_err());
+    }
+}
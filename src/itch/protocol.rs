@@ -0,0 +1,240 @@
+// Feed versions that share ITCH's length-prefixed framing. ITCH 4.1 and
+// Nasdaq Nordic ITCH are translated to ITCH 5.0 messages, so books, trackers
+// and handlers see the same messages whichever feed they came from.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use itchy::Message;
+
+use super::decode::{self, DecodeError, FrameReader, MessageView, RawFrames};
+use super::{ApplyOutcome, nordic, v41};
+use crate::bookset::BookSet;
+use crate::directory::StockInfo;
+
+// Frames looked at when detecting the protocol
+const DETECT_FRAMES: usize = 256;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum Protocol {
+    #[default]
+    Itch50,
+    Itch41,
+    Nordic,
+}
+
+impl Protocol {
+    pub fn message_len(self, tag: u8) -> Option<usize> {
+        match self {
+            Protocol::Itch50 => decode::message_len(tag),
+            Protocol::Itch41 => v41::message_len(tag),
+            Protocol::Nordic => nordic::message_len(tag),
+        }
+    }
+
+    // Picks the protocol whose message lengths match most of the first
+    // frames. None if no frame matched any of them.
+    pub fn detect(buf: &[u8]) -> Option<Self> {
+        let frames: Vec<&[u8]> = RawFrames::new(buf)
+            .take(DETECT_FRAMES)
+            .map_while(Result::ok)
+            .filter(|payload| !payload.is_empty())
+            .collect();
+
+        [Protocol::Itch50, Protocol::Itch41, Protocol::Nordic]
+            .into_iter()
+            .map(|protocol| {
+                let matched = frames
+                    .iter()
+                    .filter(|payload| protocol.message_len(payload[0]) == Some(payload.len()))
+                    .count();
+                (protocol, matched)
+            })
+            .filter(|(_, matched)| *matched > 0)
+            // max_by_key keeps the last of equal counts, prefer the first
+            .rev()
+            .max_by_key(|(_, matched)| *matched)
+            .map(|(protocol, _)| protocol)
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "itch50" | "5.0" => Ok(Protocol::Itch50),
+            "itch41" | "4.1" => Ok(Protocol::Itch41),
+            "nordic" => Ok(Protocol::Nordic),
+            _ => Err(format!(
+                "unknown protocol {s:?}, expected itch50, itch41 or nordic"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Protocol::Itch50 => "itch50",
+            Protocol::Itch41 => "itch41",
+            Protocol::Nordic => "nordic",
+        })
+    }
+}
+
+// A message in ITCH 5.0 terms. ITCH 5.0 feeds are decoded in place, the
+// legacy ones are translated.
+#[derive(Debug, Clone)]
+pub enum Decoded<'a> {
+    View(MessageView<'a>),
+    Message(Message),
+    // A new instrument from a legacy directory message, which may not fit a
+    // Stock Directory message
+    Listing(StockInfo),
+}
+
+impl Decoded<'_> {
+    // Nanoseconds since midnight
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Decoded::View(view) => view.timestamp(),
+            Decoded::Message(msg) => msg.timestamp,
+            Decoded::Listing(info) => info.timestamp,
+        }
+    }
+
+    pub fn apply(&self, books: &mut BookSet) -> ApplyOutcome {
+        match self {
+            Decoded::View(view) => books.apply_view(view),
+            Decoded::Message(msg) => books.apply(msg),
+            Decoded::Listing(info) => {
+                books.add_listing(info.clone());
+                ApplyOutcome::Ignored
+            }
+        }
+    }
+}
+
+// Turns payloads of a protocol into ITCH 5.0 terms. The legacy translators
+// keep state between messages, so every payload of a feed must go through
+// the same one, in order.
+#[derive(Debug)]
+pub(crate) enum Translator {
+    Itch50,
+    Itch41(v41::Translator),
+    Nordic(nordic::Translator),
+}
+
+impl Translator {
+    pub(crate) fn new(protocol: Protocol) -> Self {
+        match protocol {
+            Protocol::Itch50 => Translator::Itch50,
+            Protocol::Itch41 => Translator::Itch41(v41::Translator::new()),
+            Protocol::Nordic => Translator::Nordic(nordic::Translator::new()),
+        }
+    }
+
+    pub(crate) fn protocol(&self) -> Protocol {
+        match self {
+            Translator::Itch50 => Protocol::Itch50,
+            Translator::Itch41(_) => Protocol::Itch41,
+            Translator::Nordic(_) => Protocol::Nordic,
+        }
+    }
+
+    // None for legacy messages with no ITCH 5.0 equivalent
+    pub(crate) fn translate<'a>(
+        &mut self,
+        payload: &'a [u8],
+    ) -> Result<Option<Decoded<'a>>, DecodeError> {
+        match self {
+            Translator::Itch50 => MessageView::new(payload).map(|view| Some(Decoded::View(view))),
+            Translator::Itch41(t) => t.translate(payload),
+            Translator::Nordic(t) => t.translate(payload),
+        }
+    }
+}
+
+// Decodes a buffer of frames in any supported protocol. Malformed messages
+// are reported and skipped, legacy messages with no ITCH 5.0 equivalent are
+// skipped silently.
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    frames: RawFrames<'a>,
+    translator: Translator,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8], protocol: Protocol) -> Self {
+        Decoder {
+            frames: RawFrames::new(buf),
+            translator: Translator::new(protocol),
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.translator.protocol()
+    }
+}
+
+impl<'a> Iterator for Decoder<'a> {
+    type Item = Result<Decoded<'a>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let payload = match self.frames.next()? {
+                Ok(payload) => payload,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.translator.translate(payload) {
+                Ok(Some(decoded)) => return Some(Ok(decoded)),
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+// Decodes frames streamed from a reader, e.g. a compressed file from
+// input::open, passing each message or decode error to on_decoded as Decoder
+// does for a buffer. A truncated frame is an UnexpectedEof error
+pub fn decode_stream<R, F>(reader: R, protocol: Protocol, mut on_decoded: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(Result<Decoded<'_>, DecodeError>),
+{
+    let mut frames = FrameReader::new(reader);
+    let mut translator = Translator::new(protocol);
+    while let Some(payload) = frames.next_frame()? {
+        match translator.translate(payload) {
+            Ok(Some(decoded)) => on_decoded(Ok(decoded)),
+            Ok(None) => {}
+            Err(e) => on_decoded(Err(e)),
+        }
+    }
+    Ok(())
+}
+
+// Length check shared by the legacy decoders
+pub(super) fn check_len(
+    payload: &[u8],
+    message_len: fn(u8) -> Option<usize>,
+) -> Result<u8, DecodeError> {
+    let Some(&tag) = payload.first() else {
+        return Err(DecodeError::Truncated {
+            needed: 1,
+            available: 0,
+        });
+    };
+    let expected = message_len(tag).ok_or(DecodeError::UnknownType(tag))?;
+    if payload.len() != expected {
+        return Err(DecodeError::BadLength {
+            tag,
+            expected,
+            actual: payload.len(),
+        });
+    }
+    Ok(tag)
+}
@@ -0,0 +1,304 @@
+// Nasdaq TotalView-ITCH 4.1, as found in older US archives. Messages have no
+// stock locate or tracking number, and timestamps are split between Seconds
+// (T) messages and a 32-bit nanosecond offset on every other message.
+// Stock locates are assigned in the order symbols are listed, and order
+// references are tracked so executions and cancels reach the right book.
+
+use itchy::{
+    AddOrder, ArrayString4, ArrayString8, Body, FinancialStatus, ImbalanceIndicator,
+    MarketCategory, MarketParticipantPosition, Message, NonCrossTrade, ReplaceOrder,
+    RetailPriceImprovementIndicator,
+};
+use rustc_hash::FxHashMap;
+
+use super::codes;
+use super::decode::{DecodeError, alpha4, alpha8, be_u32, be_u64, byte};
+use super::protocol::{Decoded, check_len};
+use crate::directory::StockInfo;
+use crate::seek::NANOS_PER_SECOND;
+
+// Payload length of each ITCH 4.1 message type
+pub fn message_len(tag: u8) -> Option<usize> {
+    Some(match tag {
+        b'T' => 5,
+        b'S' => 6,
+        b'R' => 20,
+        b'H' => 19,
+        b'Y' => 14,
+        b'L' => 20,
+        b'A' => 30,
+        b'F' => 34,
+        b'E' => 25,
+        b'C' => 30,
+        b'X' => 17,
+        b'D' => 13,
+        b'U' => 29,
+        b'P' => 38,
+        b'Q' => 34,
+        b'B' => 13,
+        b'I' => 44,
+        b'N' => 14,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct Translator {
+    seconds: u64,
+    locates: FxHashMap<ArrayString8, u16>,
+    // Live orders: reference -> (stock_locate, shares)
+    orders: FxHashMap<u64, (u16, u32)>,
+}
+
+impl Translator {
+    pub fn new() -> Self {
+        Translator::default()
+    }
+
+    // Locate assigned to a symbol, 0 if it hasn't been listed
+    pub fn locate(&self, stock: &str) -> u16 {
+        ArrayString8::from(stock)
+            .ok()
+            .and_then(|stock| self.locates.get(&stock).copied())
+            .unwrap_or(0)
+    }
+
+    // None for Seconds messages, messages with invalid codes, and symbols
+    // that don't fit ITCH 5.0's 8 characters
+    pub fn translate(&mut self, b: &[u8]) -> Result<Option<Decoded<'static>>, DecodeError> {
+        let tag = check_len(b, message_len)?;
+        if tag == b'T' {
+            self.seconds = be_u32(b, 1) as u64;
+            return Ok(None);
+        }
+        let timestamp = self.seconds * NANOS_PER_SECOND + be_u32(b, 1) as u64;
+        let stock = |at: usize| ArrayString8::from(alpha8(b, at)).ok();
+
+        if tag == b'R' {
+            let Some(symbol) = stock(5) else {
+                return Ok(None);
+            };
+            let next = self.locates.len() as u16 + 1;
+            let locate = *self.locates.entry(symbol).or_insert(next);
+
+            let mut info = StockInfo::new(locate, &symbol, timestamp);
+            info.market_category =
+                codes::market_category(byte(b, 13)).unwrap_or(MarketCategory::Unavailable);
+            info.financial_status =
+                codes::financial_status(byte(b, 14)).unwrap_or(FinancialStatus::Unavailable);
+            info.round_lot_size = be_u32(b, 15);
+            info.round_lots_only = byte(b, 19) == b'Y';
+            return Ok(Some(Decoded::Listing(info)));
+        }
+
+        let (stock_locate, body) = match self.body(tag, b) {
+            Some(translated) => translated,
+            None => return Ok(None),
+        };
+        Ok(Some(Decoded::Message(Message {
+            tag,
+            stock_locate,
+            tracking_number: 0,
+            timestamp,
+            body,
+        })))
+    }
+
+    fn body(&mut self, tag: u8, b: &[u8]) -> Option<(u16, Body)> {
+        let stock = |at: usize| ArrayString8::from(alpha8(b, at)).ok();
+        let mpid = |at: usize| ArrayString4::from(alpha4(b, at)).ok();
+
+        Some(match tag {
+            b'S' => (
+                0,
+                Body::SystemEvent {
+                    event: codes::event_code(byte(b, 5))?,
+                },
+            ),
+            b'H' => {
+                let stock = stock(5)?;
+                (
+                    self.locate(&stock),
+                    Body::TradingAction {
+                        stock,
+                        trading_state: codes::trading_state(byte(b, 13))?,
+                        reason: mpid(15)?,
+                    },
+                )
+            }
+            b'Y' => {
+                let stock = stock(5)?;
+                (
+                    self.locate(&stock),
+                    Body::RegShoRestriction {
+                        stock,
+                        action: codes::reg_sho_action(byte(b, 13))?,
+                    },
+                )
+            }
+            b'L' => {
+                let stock = stock(9)?;
+                (
+                    self.locate(&stock),
+                    Body::ParticipantPosition(MarketParticipantPosition {
+                        mpid: mpid(5)?,
+                        stock,
+                        primary_market_maker: codes::bool_flag(byte(b, 17))?,
+                        market_maker_mode: codes::market_maker_mode(byte(b, 18))?,
+                        market_participant_state: codes::market_participant_state(byte(b, 19))?,
+                    }),
+                )
+            }
+            b'A' | b'F' => {
+                let stock = stock(18)?;
+                let locate = self.locate(&stock);
+                let add = AddOrder {
+                    reference: be_u64(b, 5),
+                    side: codes::side(byte(b, 13))?,
+                    shares: be_u32(b, 14),
+                    stock,
+                    price: be_u32(b, 26).into(),
+                    mpid: if tag == b'F' { Some(mpid(30)?) } else { None },
+                };
+                if locate != 0 {
+                    self.orders.insert(add.reference, (locate, add.shares));
+                }
+                (locate, Body::AddOrder(add))
+            }
+            b'E' => {
+                let reference = be_u64(b, 5);
+                let executed = be_u32(b, 13);
+                (
+                    self.reduce(reference, executed),
+                    Body::OrderExecuted {
+                        reference,
+                        executed,
+                        match_number: be_u64(b, 17),
+                    },
+                )
+            }
+            b'C' => {
+                let reference = be_u64(b, 5);
+                let executed = be_u32(b, 13);
+                (
+                    self.reduce(reference, executed),
+                    Body::OrderExecutedWithPrice {
+                        reference,
+                        executed,
+                        match_number: be_u64(b, 17),
+                        printable: codes::bool_flag(byte(b, 25))?,
+                        price: be_u32(b, 26).into(),
+                    },
+                )
+            }
+            b'X' => {
+                let reference = be_u64(b, 5);
+                let cancelled = be_u32(b, 13);
+                (
+                    self.reduce(reference, cancelled),
+                    Body::OrderCancelled {
+                        reference,
+                        cancelled,
+                    },
+                )
+            }
+            b'D' => {
+                let reference = be_u64(b, 5);
+                let locate = self.orders.remove(&reference).map_or(0, |(l, _)| l);
+                (locate, Body::DeleteOrder { reference })
+            }
+            b'U' => {
+                let replace = ReplaceOrder {
+                    old_reference: be_u64(b, 5),
+                    new_reference: be_u64(b, 13),
+                    shares: be_u32(b, 21),
+                    price: be_u32(b, 25).into(),
+                };
+                let locate = self
+                    .orders
+                    .remove(&replace.old_reference)
+                    .map_or(0, |(l, _)| l);
+                if locate != 0 {
+                    self.orders
+                        .insert(replace.new_reference, (locate, replace.shares));
+                }
+                (locate, Body::ReplaceOrder(replace))
+            }
+            b'P' => {
+                let stock = stock(18)?;
+                (
+                    self.locate(&stock),
+                    Body::NonCrossTrade(NonCrossTrade {
+                        reference: be_u64(b, 5),
+                        side: codes::side(byte(b, 13))?,
+                        shares: be_u32(b, 14),
+                        stock,
+                        price: be_u32(b, 26).into(),
+                        match_number: be_u64(b, 30),
+                    }),
+                )
+            }
+            b'Q' => {
+                let stock = stock(13)?;
+                (
+                    self.locate(&stock),
+                    Body::CrossTrade(itchy::CrossTrade {
+                        shares: be_u64(b, 5),
+                        stock,
+                        cross_price: be_u32(b, 21).into(),
+                        match_number: be_u64(b, 25),
+                        cross_type: codes::cross_type(byte(b, 33))?,
+                    }),
+                )
+            }
+            b'B' => (
+                0,
+                Body::BrokenTrade {
+                    match_number: be_u64(b, 5),
+                },
+            ),
+            b'I' => {
+                let stock = stock(22)?;
+                (
+                    self.locate(&stock),
+                    Body::Imbalance(ImbalanceIndicator {
+                        paired_shares: be_u64(b, 5),
+                        imbalance_shares: be_u64(b, 13),
+                        imbalance_direction: codes::imbalance_direction(byte(b, 21))?,
+                        stock,
+                        far_price: be_u32(b, 30).into(),
+                        near_price: be_u32(b, 34).into(),
+                        current_ref_price: be_u32(b, 38).into(),
+                        cross_type: codes::cross_type(byte(b, 42))?,
+                        price_variation_indicator: byte(b, 43) as char,
+                    }),
+                )
+            }
+            b'N' => {
+                let stock = stock(5)?;
+                (
+                    self.locate(&stock),
+                    Body::RetailPriceImprovementIndicator(RetailPriceImprovementIndicator {
+                        stock,
+                        interest_flag: codes::interest_flag(byte(b, 13))?,
+                    }),
+                )
+            }
+            _ => return None,
+        })
+    }
+
+    // Takes shares off a tracked order, forgetting it once it's filled.
+    // Returns the order's locate, 0 if it isn't known
+    fn reduce(&mut self, reference: u64, shares: u32) -> u16 {
+        let Some((locate, remaining)) = self.orders.get_mut(&reference) else {
+            return 0;
+        };
+        let locate = *locate;
+        *remaining = remaining.saturating_sub(shares);
+        if *remaining == 0 {
+            self.orders.remove(&reference);
+        }
+        locate
+    }
+}
@@ -1,2 +1,23 @@
+pub mod auction;
+pub mod bookset;
+pub mod cboe;
+pub mod cme;
+pub mod crypto;
+pub mod dbn;
+pub mod directory;
+pub mod iex;
+pub mod input;
+pub mod itch;
+pub mod limits;
+pub mod lobster;
+pub mod mmap;
+pub mod moldudp;
 pub mod orderbook;
+pub mod orphans;
+pub mod participants;
+pub mod pcap;
+pub mod seek;
+pub mod soupbin;
+pub mod tape;
+pub mod trading;
 //pub mod orderbook_fixed;
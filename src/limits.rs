@@ -0,0 +1,204 @@
+use itchy::{Body, LevelBreached, Message};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+// Price(8) fields such as MWCB decline levels have 8 implied decimals
+pub fn price8_to_decimal(raw: u64) -> Decimal {
+    Decimal::from(raw) / Decimal::from(100_000_000)
+}
+
+// LULD Auction Collar (J) for a halted symbol's reopening auction
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct AuctionCollar {
+    pub timestamp: u64,
+    // Raw ITCH prices, 4 implied decimals
+    pub ref_price: u32,
+    pub upper_price: u32,
+    pub lower_price: u32,
+    // Number of times the collar has been extended
+    pub extension: u32,
+}
+
+impl AuctionCollar {
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        let Body::LULDAuctionCollar {
+            ref_price,
+            upper_price,
+            lower_price,
+            extension,
+            ..
+        } = &msg.body
+        else {
+            return None;
+        };
+
+        Some(AuctionCollar {
+            timestamp: msg.timestamp,
+            ref_price: ref_price.raw(),
+            upper_price: upper_price.raw(),
+            lower_price: lower_price.raw(),
+            extension: *extension,
+        })
+    }
+
+    pub fn contains(&self, price: u32) -> bool {
+        (self.lower_price..=self.upper_price).contains(&price)
+    }
+}
+
+// Market-wide circuit breaker levels (V) and the last breach (W)
+#[derive(Debug, Default, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakers {
+    // Raw Price(8) values for levels 1, 2 and 3
+    pub decline_levels: Option<[u64; 3]>,
+    pub breached: Option<LevelBreached>,
+    pub breach_timestamp: Option<u64>,
+}
+
+impl CircuitBreakers {
+    // Returns true if the message was a V or W message
+    pub fn on_message(&mut self, msg: &Message) -> bool {
+        match &msg.body {
+            Body::MwcbDeclineLevel {
+                level1,
+                level2,
+                level3,
+            } => {
+                self.decline_levels = Some([level1.raw(), level2.raw(), level3.raw()]);
+            }
+            Body::Breach(level) => {
+                self.breached = Some(*level);
+                self.breach_timestamp = Some(msg.timestamp);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn decline_level(&self, level: LevelBreached) -> Option<Decimal> {
+        let levels = self.decline_levels?;
+        let raw = match level {
+            LevelBreached::L1 => levels[0],
+            LevelBreached::L2 => levels[1],
+            LevelBreached::L3 => levels[2],
+        };
+        Some(price8_to_decimal(raw))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::{ArrayString4, ArrayString8, TradingState};
+
+    use super::*;
+    use crate::itch::{
+        self, ApplyOutcome, LULD_AUCTION_COLLAR, MWCB_DECLINE_LEVEL, MWCB_STATUS, TRADING_ACTION,
+    };
+    use crate::orderbook::OrderBook;
+
+    fn msg(tag: u8, timestamp: u64, body: Body) -> Message {
+        Message {
+            tag,
+            stock_locate: 1,
+            tracking_number: 0,
+            timestamp,
+            body,
+        }
+    }
+
+    fn collar(timestamp: u64, upper: u32, lower: u32, extension: u32) -> Message {
+        msg(
+            LULD_AUCTION_COLLAR,
+            timestamp,
+            Body::LULDAuctionCollar {
+                stock: ArrayString8::from("TEST    ").unwrap(),
+                ref_price: 1_000_000.into(),
+                upper_price: upper.into(),
+                lower_price: lower.into(),
+                extension,
+            },
+        )
+    }
+
+    fn trading_action(state: TradingState) -> Message {
+        msg(
+            TRADING_ACTION,
+            0,
+            Body::TradingAction {
+                stock: ArrayString8::from("TEST    ").unwrap(),
+                trading_state: state,
+                reason: ArrayString4::from("LUDP").unwrap(),
+            },
+        )
+    }
+
+    #[test]
+    fn collars_extend_until_trading_resumes() {
+        let mut book = OrderBook::with_capacity(4, 4);
+        itch::apply(&mut book, &trading_action(TradingState::Halted));
+        assert_eq!(
+            itch::apply(&mut book, &collar(10, 1_050_000, 950_000, 0)),
+            ApplyOutcome::CollarUpdated
+        );
+        let first = *book.collar().unwrap();
+        assert!(first.contains(1_050_000));
+        assert!(!first.contains(1_060_000));
+
+        // An extension widens the collar and replaces the one before it
+        itch::apply(&mut book, &collar(20, 1_100_000, 900_000, 1));
+        let extended = book.collar().unwrap();
+        assert_eq!(
+            *extended,
+            AuctionCollar {
+                timestamp: 20,
+                ref_price: 1_000_000,
+                upper_price: 1_100_000,
+                lower_price: 900_000,
+                extension: 1,
+            }
+        );
+        assert!(extended.contains(1_060_000));
+
+        // Quoting before the reopening keeps it, the reopening clears it
+        itch::apply(&mut book, &trading_action(TradingState::QuotationOnly));
+        assert!(book.collar().is_some());
+        itch::apply(
+            &mut book,
+            &msg(
+                itch::SYSTEM_EVENT,
+                30,
+                Body::SystemEvent {
+                    event: itchy::EventCode::StartOfMarketHours,
+                },
+            ),
+        );
+        itch::apply(&mut book, &trading_action(TradingState::Trading));
+        assert_eq!(book.collar(), None);
+    }
+
+    #[test]
+    fn circuit_breaker_levels_and_breaches() {
+        let mut breakers = CircuitBreakers::default();
+        assert_eq!(breakers.decline_level(LevelBreached::L1), None);
+
+        let levels = msg(
+            MWCB_DECLINE_LEVEL,
+            10,
+            Body::MwcbDeclineLevel {
+                level1: 390_000_000_000u64.into(),
+                level2: 370_000_000_000u64.into(),
+                level3: 340_000_000_000u64.into(),
+            },
+        );
+        assert!(breakers.on_message(&levels));
+        assert_eq!(
+            breakers.decline_level(LevelBreached::L2),
+            Some(Decimal::from(3_700))
+        );
+
+        assert!(breakers.on_message(&msg(MWCB_STATUS, 20, Body::Breach(LevelBreached::L1))));
+        assert_eq!(breakers.breached, Some(LevelBreached::L1));
+        assert_eq!(breakers.breach_timestamp, Some(20));
+        assert!(!breakers.on_message(&collar(30, 0, 0, 0)));
+    }
+}
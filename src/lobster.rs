@@ -0,0 +1,698 @@
+// LOBSTER message and orderbook files, as used in academic market
+// microstructure work. Each message row has a matching orderbook row with
+// the top levels of the book after the message:
+//
+//   message:   time,type,order id,size,price,direction
+//   orderbook: ask price 1,ask size 1,bid price 1,bid size 1,ask price 2,...
+//
+// Times are seconds after midnight with up to nine decimals. Prices are
+// dollars times 10,000, the same as raw ITCH prices. Direction is 1 for buy
+// orders and -1 for sell orders, executions carry the direction of the
+// resting order.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use itchy::{Body, Message};
+
+use crate::itch::{self, ApplyOutcome, ItchHandler, OrderEvent};
+use crate::orderbook::{OrderBook, OrderSide};
+use crate::seek::NANOS_PER_SECOND;
+use crate::trading::StateChange;
+
+// Prices LOBSTER writes for missing levels
+pub const EMPTY_ASK_PRICE: i64 = 9_999_999_999;
+pub const EMPTY_BID_PRICE: i64 = -9_999_999_999;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum EventType {
+    Submission = 1,
+    // Partial cancellation
+    Cancellation = 2,
+    Deletion = 3,
+    ExecutionVisible = 4,
+    ExecutionHidden = 5,
+    Cross = 6,
+    // The price is -1 for a halt, 0 when quoting resumes and 1 when trading
+    // resumes
+    Halt = 7,
+}
+
+impl EventType {
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            1 => EventType::Submission,
+            2 => EventType::Cancellation,
+            3 => EventType::Deletion,
+            4 => EventType::ExecutionVisible,
+            5 => EventType::ExecutionHidden,
+            6 => EventType::Cross,
+            7 => EventType::Halt,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum LobsterError {
+    Io(io::Error),
+    // A row that doesn't parse, with its 1-based line number
+    Parse { line: usize, reason: String },
+}
+
+impl fmt::Display for LobsterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LobsterError::Io(e) => write!(f, "{e}"),
+            LobsterError::Parse { line, reason } => write!(f, "line {line}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for LobsterError {}
+
+impl From<io::Error> for LobsterError {
+    fn from(e: io::Error) -> Self {
+        LobsterError::Io(e)
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct LobsterEvent {
+    // Nanoseconds since midnight
+    pub time: u64,
+    pub event_type: EventType,
+    pub order_id: u64,
+    pub size: u32,
+    pub price: i64,
+    pub direction: OrderSide,
+}
+
+impl LobsterEvent {
+    pub fn parse(row: &str) -> Result<Self, String> {
+        let mut fields = row.trim().split(',').map(str::trim);
+        let mut field = |name: &str| fields.next().ok_or_else(|| format!("missing {name}"));
+
+        let time = parse_seconds(field("time")?)?;
+        let code = field("event type")?;
+        let event_type = code
+            .parse::<u8>()
+            .ok()
+            .and_then(EventType::from_code)
+            .ok_or_else(|| format!("unknown event type {code:?}"))?;
+        let order_id = parse_number(field("order id")?)?;
+        let size = parse_number(field("size")?)?;
+        let price = parse_number(field("price")?)?;
+        let direction = match field("direction")? {
+            "1" => OrderSide::Buy,
+            "-1" => OrderSide::Sell,
+            d => return Err(format!("invalid direction {d:?}")),
+        };
+
+        Ok(LobsterEvent {
+            time,
+            event_type,
+            order_id,
+            size,
+            price,
+            direction,
+        })
+    }
+
+    // The change to the book's orders. None for hidden executions, crosses
+    // and halts, which don't touch displayed orders
+    pub fn order_event(&self) -> Option<OrderEvent> {
+        let reference = self.order_id;
+        Some(match self.event_type {
+            EventType::Submission => OrderEvent::Add {
+                reference,
+                side: self.direction,
+                shares: self.size,
+                price: u32::try_from(self.price).ok()?,
+                mpid: None,
+            },
+            EventType::Cancellation => OrderEvent::Cancelled {
+                reference,
+                cancelled: self.size,
+            },
+            EventType::Deletion => OrderEvent::Deleted { reference },
+            EventType::ExecutionVisible => OrderEvent::Executed {
+                reference,
+                executed: self.size,
+            },
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for LobsterEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:09},{},{},{},{},{}",
+            self.time / NANOS_PER_SECOND,
+            self.time % NANOS_PER_SECOND,
+            self.event_type as u8,
+            self.order_id,
+            self.size,
+            self.price,
+            direction(self.direction),
+        )
+    }
+}
+
+fn direction(side: OrderSide) -> i8 {
+    if side == OrderSide::Buy { 1 } else { -1 }
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid number {s:?}"))
+}
+
+// Seconds after midnight, e.g. 34200.004241176
+fn parse_seconds(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid time {s:?}");
+    let (secs, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let secs: u64 = secs.parse().map_err(|_| invalid())?;
+    let frac = if fraction.is_empty() {
+        0
+    } else {
+        fraction.parse::<u64>().map_err(|_| invalid())? * 10u64.pow(9 - fraction.len() as u32)
+    };
+    secs.checked_mul(NANOS_PER_SECOND)
+        .and_then(|ns| ns.checked_add(frac))
+        .ok_or_else(invalid)
+}
+
+// The orderbook row for a book, as its columns. Missing levels are filled
+// with LOBSTER's dummy prices and zero size
+pub fn book_row(book: &OrderBook, levels: usize) -> Vec<i64> {
+    book_row_without(book, levels, None)
+}
+
+// Same, as if `order` (side, price, volume) had already left the book
+fn book_row_without(
+    book: &OrderBook,
+    levels: usize,
+    order: Option<(OrderSide, u32, u32)>,
+) -> Vec<i64> {
+    let side_levels = |side: OrderSide| -> Vec<(i64, i64)> {
+        book.levels(side)
+            .filter_map(|(price, volume, _)| {
+                let volume = match order {
+                    Some((s, p, v)) if s == side && p == price => volume - v,
+                    _ => volume,
+                };
+                (volume > 0).then_some((price as i64, volume as i64))
+            })
+            .take(levels)
+            .collect()
+    };
+    let asks = side_levels(OrderSide::Sell);
+    let bids = side_levels(OrderSide::Buy);
+
+    let mut row = Vec::with_capacity(levels * 4);
+    for i in 0..levels {
+        let (ask_price, ask_size) = asks.get(i).copied().unwrap_or((EMPTY_ASK_PRICE, 0));
+        let (bid_price, bid_size) = bids.get(i).copied().unwrap_or((EMPTY_BID_PRICE, 0));
+        row.extend([ask_price, ask_size, bid_price, bid_size]);
+    }
+    row
+}
+
+pub fn parse_book_row(row: &str) -> Result<Vec<i64>, String> {
+    row.trim()
+        .split(',')
+        .map(|v| parse_number(v.trim()))
+        .collect()
+}
+
+fn write_row<W: Write>(w: &mut W, row: &[i64]) -> io::Result<()> {
+    for (i, value) in row.iter().enumerate() {
+        if i > 0 {
+            w.write_all(b",")?;
+        }
+        write!(w, "{value}")?;
+    }
+    w.write_all(b"\n")
+}
+
+// Writes a symbol's message and orderbook files from an ITCH replay. Use it
+// as the handler for apply_with, so executions and deletes are looked up
+// before the resting order leaves the book. Replaces are written as a
+// deletion followed by a submission, as LOBSTER does.
+#[derive(Debug)]
+pub struct LobsterWriter<W: Write> {
+    symbol: String,
+    levels: usize,
+    messages: W,
+    orderbook: W,
+    // Submission half of a replace, written once the book has applied it
+    pending: Option<LobsterEvent>,
+    rows: u64,
+    // Handlers can't fail, the first error is kept for finish
+    error: Option<io::Error>,
+}
+
+impl<W: Write> LobsterWriter<W> {
+    pub fn new(symbol: &str, levels: usize, messages: W, orderbook: W) -> Self {
+        LobsterWriter {
+            symbol: symbol.trim_end().to_string(),
+            levels,
+            messages,
+            orderbook,
+            pending: None,
+            rows: 0,
+            error: None,
+        }
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    // Flushes both files, returning them or the first write error
+    pub fn finish(mut self) -> io::Result<(W, W)> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.messages.flush()?;
+        self.orderbook.flush()?;
+        Ok((self.messages, self.orderbook))
+    }
+
+    fn write(&mut self, event: &LobsterEvent, row: &[i64]) {
+        if self.error.is_some() {
+            return;
+        }
+        let result =
+            writeln!(self.messages, "{event}").and_then(|_| write_row(&mut self.orderbook, row));
+        match result {
+            Ok(()) => self.rows += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    // The event for a message, looking up the resting order where the
+    // message doesn't carry its price or side
+    fn event(&self, book: &OrderBook, msg: &Message) -> Option<LobsterEvent> {
+        let resting = |reference: u64| book.order(reference);
+        let event = |event_type, order_id, size, price: i64, direction| LobsterEvent {
+            time: msg.timestamp,
+            event_type,
+            order_id,
+            size,
+            price,
+            direction,
+        };
+        let side = |side: itchy::Side| {
+            if side == itchy::Side::Buy {
+                OrderSide::Buy
+            } else {
+                OrderSide::Sell
+            }
+        };
+
+        Some(match &msg.body {
+            Body::AddOrder(add) => event(
+                EventType::Submission,
+                add.reference,
+                add.shares,
+                add.price.raw() as i64,
+                side(add.side),
+            ),
+            Body::OrderExecuted {
+                reference,
+                executed,
+                ..
+            } => {
+                let (price, _, side) = resting(*reference)?;
+                event(
+                    EventType::ExecutionVisible,
+                    *reference,
+                    *executed,
+                    price as i64,
+                    side,
+                )
+            }
+            // Written with the execution price rather than the order's
+            Body::OrderExecutedWithPrice {
+                reference,
+                executed,
+                price,
+                ..
+            } => {
+                let (_, _, side) = resting(*reference)?;
+                event(
+                    EventType::ExecutionVisible,
+                    *reference,
+                    *executed,
+                    price.raw() as i64,
+                    side,
+                )
+            }
+            Body::OrderCancelled {
+                reference,
+                cancelled,
+            } => {
+                let (price, _, side) = resting(*reference)?;
+                event(
+                    EventType::Cancellation,
+                    *reference,
+                    *cancelled,
+                    price as i64,
+                    side,
+                )
+            }
+            Body::DeleteOrder { reference } => {
+                let (price, shares, side) = resting(*reference)?;
+                event(EventType::Deletion, *reference, shares, price as i64, side)
+            }
+            Body::ReplaceOrder(replace) => {
+                let (price, shares, side) = resting(replace.old_reference)?;
+                event(
+                    EventType::Deletion,
+                    replace.old_reference,
+                    shares,
+                    price as i64,
+                    side,
+                )
+            }
+            Body::NonCrossTrade(trade) => event(
+                EventType::ExecutionHidden,
+                trade.reference,
+                trade.shares,
+                trade.price.raw() as i64,
+                side(trade.side),
+            ),
+            // Sent with zero shares when the cross didn't happen
+            Body::CrossTrade(cross) if cross.shares > 0 => event(
+                EventType::Cross,
+                0,
+                u32::try_from(cross.shares).unwrap_or(u32::MAX),
+                cross.cross_price.raw() as i64,
+                OrderSide::Sell,
+            ),
+            Body::TradingAction { trading_state, .. } => event(
+                EventType::Halt,
+                0,
+                0,
+                match trading_state {
+                    itchy::TradingState::Halted | itchy::TradingState::Paused => -1,
+                    itchy::TradingState::QuotationOnly => 0,
+                    itchy::TradingState::Trading => 1,
+                },
+                OrderSide::Sell,
+            ),
+            _ => return None,
+        })
+    }
+}
+
+impl<W: Write> ItchHandler for LobsterWriter<W> {
+    fn before_apply(&mut self, book: &OrderBook, msg: &Message) -> bool {
+        if book.symbol().trim_end() != self.symbol {
+            return true;
+        }
+        let Some(event) = self.event(book, msg) else {
+            return true;
+        };
+
+        if let Body::ReplaceOrder(replace) = &msg.body {
+            // The deletion's row is the book without the old order
+            let row = book_row_without(
+                book,
+                self.levels,
+                Some((event.direction, event.price as u32, event.size)),
+            );
+            self.write(&event, &row);
+            self.pending = Some(LobsterEvent {
+                event_type: EventType::Submission,
+                order_id: replace.new_reference,
+                size: replace.shares,
+                price: replace.price.raw() as i64,
+                ..event
+            });
+        } else {
+            self.pending = Some(event);
+        }
+        true
+    }
+
+    fn after_apply(&mut self, book: &OrderBook, _msg: &Message, outcome: ApplyOutcome) {
+        let Some(event) = self.pending.take() else {
+            return;
+        };
+        if outcome == ApplyOutcome::Skipped {
+            return;
+        }
+        let row = book_row(book, self.levels);
+        self.write(&event, &row);
+    }
+}
+
+// Applies a LOBSTER event to a book. Halt events update the trading status,
+// hidden executions and crosses leave the book as it is.
+pub fn apply(book: &mut OrderBook, event: &LobsterEvent) -> ApplyOutcome {
+    if let Some(order_event) = event.order_event() {
+        return itch::apply_event(book, order_event);
+    }
+    if event.event_type != EventType::Halt {
+        return ApplyOutcome::Ignored;
+    }
+
+    let action = match event.price {
+        -1 => itchy::TradingState::Halted,
+        0 => itchy::TradingState::QuotationOnly,
+        _ => itchy::TradingState::Trading,
+    };
+    let from = book.trading_state();
+    book.trading_status_mut()
+        .on_trading_action(action, itchy::ArrayString4::new());
+    let to = book.trading_state();
+    if from == to {
+        return ApplyOutcome::StatusUpdated;
+    }
+    ApplyOutcome::StateChanged(StateChange {
+        stock_locate: book.config().stock_locate,
+        timestamp: event.time,
+        from,
+        to,
+    })
+}
+
+// Events from a message file. Blank lines are skipped
+pub struct LobsterReader<R> {
+    reader: R,
+    line: usize,
+    buf: String,
+}
+
+impl<R: BufRead> LobsterReader<R> {
+    pub fn new(reader: R) -> Self {
+        LobsterReader {
+            reader,
+            line: 0,
+            buf: String::new(),
+        }
+    }
+
+    // 1-based line number of the last event read
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl<R: BufRead> Iterator for LobsterReader<R> {
+    type Item = Result<LobsterEvent, LobsterError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.line += 1;
+            if self.buf.trim().is_empty() {
+                continue;
+            }
+            return Some(
+                LobsterEvent::parse(&self.buf).map_err(|reason| LobsterError::Parse {
+                    line: self.line,
+                    reason,
+                }),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::{
+        AddOrder, ArrayString4, ArrayString8, NonCrossTrade, ReplaceOrder, Side, TradingState,
+    };
+
+    use super::*;
+    use crate::bookset::BookSet;
+    use crate::directory::StockInfo;
+
+    const LEVELS: usize = 3;
+
+    fn stock() -> ArrayString8 {
+        ArrayString8::from("TEST").unwrap()
+    }
+
+    fn add(reference: u64, side: Side, shares: u32, price: u32) -> Body {
+        Body::AddOrder(AddOrder {
+            reference,
+            side,
+            shares,
+            stock: stock(),
+            price: price.into(),
+            mpid: None,
+        })
+    }
+
+    fn add_attributed(reference: u64, side: Side, shares: u32, price: u32, mpid: &str) -> Body {
+        let mut body = add(reference, side, shares, price);
+        if let Body::AddOrder(order) = &mut body {
+            order.mpid = Some(ArrayString4::from(mpid).unwrap());
+        }
+        body
+    }
+
+    // A session touching every event type the writer exports
+    fn session() -> Vec<Message> {
+        let bodies = vec![
+            (b'A', add(1, Side::Buy, 100, 1_000_000)),
+            (b'A', add(2, Side::Buy, 200, 999_900)),
+            (b'A', add(3, Side::Sell, 150, 1_000_100)),
+            (b'A', add(4, Side::Sell, 50, 1_000_100)),
+            (b'A', add(5, Side::Buy, 300, 999_800)),
+            (b'A', add(6, Side::Buy, 10, 999_700)),
+            (b'F', add_attributed(7, Side::Sell, 75, 1_000_300, "GSCO")),
+            (
+                b'E',
+                Body::OrderExecuted {
+                    reference: 1,
+                    executed: 40,
+                    match_number: 1,
+                },
+            ),
+            (
+                b'C',
+                Body::OrderExecutedWithPrice {
+                    reference: 3,
+                    executed: 150,
+                    match_number: 2,
+                    printable: true,
+                    price: 1_000_000u32.into(),
+                },
+            ),
+            (
+                b'X',
+                Body::OrderCancelled {
+                    reference: 2,
+                    cancelled: 50,
+                },
+            ),
+            (
+                b'P',
+                Body::NonCrossTrade(NonCrossTrade {
+                    reference: 0,
+                    side: Side::Buy,
+                    shares: 500,
+                    stock: stock(),
+                    price: 1_000_050u32.into(),
+                    match_number: 3,
+                }),
+            ),
+            (
+                b'U',
+                Body::ReplaceOrder(ReplaceOrder {
+                    old_reference: 5,
+                    new_reference: 8,
+                    shares: 250,
+                    price: 1_000_000u32.into(),
+                }),
+            ),
+            (
+                b'H',
+                Body::TradingAction {
+                    stock: stock(),
+                    trading_state: TradingState::Halted,
+                    reason: ArrayString4::new(),
+                },
+            ),
+            (b'D', Body::DeleteOrder { reference: 4 }),
+            (
+                b'E',
+                Body::OrderExecuted {
+                    reference: 1,
+                    executed: 60,
+                    match_number: 4,
+                },
+            ),
+        ];
+        bodies
+            .into_iter()
+            .enumerate()
+            .map(|(i, (tag, body))| Message {
+                tag,
+                stock_locate: 1,
+                tracking_number: 0,
+                timestamp: 34_200 * NANOS_PER_SECOND + i as u64 * 1_000,
+                body,
+            })
+            .collect()
+    }
+
+    fn export(messages: &[Message]) -> (String, String) {
+        let mut books = BookSet::new();
+        books.add_listing(StockInfo::new(1, "TEST", 0));
+        let mut writer = LobsterWriter::new("TEST", LEVELS, Vec::new(), Vec::new());
+        for msg in messages {
+            books.apply_with(msg, &mut writer);
+        }
+        let (messages, orderbook) = writer.finish().unwrap();
+        (
+            String::from_utf8(messages).unwrap(),
+            String::from_utf8(orderbook).unwrap(),
+        )
+    }
+
+    #[test]
+    fn exported_files_import_to_the_same_books() {
+        let (messages, orderbook) = export(&session());
+        // The replace is a deletion and a submission
+        assert_eq!(messages.lines().count(), session().len() + 1);
+        assert_eq!(orderbook.lines().count(), messages.lines().count());
+
+        let mut book = OrderBook::new();
+        let events = LobsterReader::new(messages.as_bytes());
+        for (event, row) in events.zip(orderbook.lines()) {
+            let event = event.unwrap();
+            apply(&mut book, &event);
+            assert_eq!(
+                book_row(&book, LEVELS),
+                parse_book_row(row).unwrap(),
+                "after {event}"
+            );
+        }
+        assert!(book.trading_state().is_halted());
+    }
+
+    #[test]
+    fn events_round_trip_through_text() {
+        let (messages, _) = export(&session());
+        for row in messages.lines() {
+            let event = LobsterEvent::parse(row).unwrap();
+            assert_eq!(event.to_string(), row);
+        }
+    }
+}
@@ -1,164 +1,138 @@
-use clap::Parser;
-use rustc_hash::FxHashMap;
+use std::io::{self, BufRead, ErrorKind};
 
-use orderbook_rust::orderbook::{OrderBook, OrderSide};
+use clap::Parser;
+use rust_decimal::Decimal;
 
-const ORDER_ADD: u8 = b'A';
-const ORDER_ADD_ATTRIBUTED: u8 = b'F';
-const ORDER_EXECUTED: u8 = b'E';
-const ORDER_EXECUTED_PRICE: u8 = b'C';
-const ORDER_CANCEL: u8 = b'X';
-const ORDER_DELETE: u8 = b'D';
-const ORDER_REPLACE: u8 = b'U';
-const STOCK_DIRECTORY: u8 = b'R';
+use orderbook_rust::bookset::BookSet;
+use orderbook_rust::itch::protocol::Protocol;
+use orderbook_rust::input;
+use orderbook_rust::mmap::MappedFile;
+use orderbook_rust::orphans::{self, OrphanPolicy};
+use orderbook_rust::seek::{self, BookSnapshot, Replay, format_time, parse_duration, parse_time};
 
 #[derive(Parser)]
 struct Args {
     file: String,
     symbol: String,
+
+    // itch50, itch41 or nordic. Detected from the file by default
+    #[arg(long)]
+    protocol: Option<Protocol>,
+
+    // Replay up to this time, HH:MM:SS.nnnnnnnnn or nanoseconds since
+    // midnight. Defaults to the end of the file
+    #[arg(long, value_parser = parse_time, conflicts_with = "from")]
+    at: Option<u64>,
+
+    // Print snapshots of the book from this time to --to, every --every
+    #[arg(long, value_parser = parse_time, requires = "to")]
+    from: Option<u64>,
+    #[arg(long, value_parser = parse_time, requires = "from")]
+    to: Option<u64>,
+    // e.g. 500ms, 1s or 5m
+    #[arg(long, value_parser = parse_duration, default_value = "1m")]
+    every: u64,
+
+    // Levels per side shown in snapshots
+    #[arg(long, default_value_t = 5)]
+    depth: usize,
+
+    // What to do with messages for orders added before the start of the
+    // file: ignore, count or placeholder
+    #[arg(long, default_value_t = OrphanPolicy::Count)]
+    orphans: OrphanPolicy,
+    // Warn when more messages than this are orphaned
+    #[arg(long, default_value_t = orphans::DEFAULT_WARN_THRESHOLD)]
+    orphan_warn: u64,
 }
 
 fn main() {
     let args = Args::parse();
-
-    let stream = itchy::MessageStream::from_file(args.file).unwrap();
-
-    let mut stock_directory: FxHashMap<String, u16> =
-        FxHashMap::with_capacity_and_hasher(5000, rustc_hash::FxBuildHasher);
-
-    let mut book = OrderBook::new();
-
-    let mut processed = 0;
-
-    for msg in stream {
-        if processed > 10_000 {
-            dbg!(&book.spread());
-            dbg!(&book.best_bid());
-            dbg!(&book.best_ask());
-            dbg!(&book.meta());
-            return;
+    // Raw files are mapped, compressed ones streamed so they never have to
+    // fit in memory
+    let result = match MappedFile::open(&args.file) {
+        Ok(file) => replay(file.as_bytes(), &args),
+        Err(e) if e.kind() == ErrorKind::InvalidData => {
+            input::open(&args.file).and_then(|reader| replay(reader, &args))
         }
-        let m = msg.unwrap();
-
-        match m.tag {
-            STOCK_DIRECTORY => {
-                let itchy::Body::StockDirectory(dir) = &m.body else {
-                    continue;
-                };
-
-                let stock = dir.stock.trim_end().to_lowercase();
-
-                stock_directory.insert(stock, m.stock_locate);
-            }
-            ORDER_ADD | ORDER_ADD_ATTRIBUTED => {
-                let aapl = stock_directory[&args.symbol];
-                if m.stock_locate != aapl {
-                    continue;
-                }
-
-                processed += 1;
-
-                let itchy::Body::AddOrder(order) = &m.body else {
-                    continue;
-                };
-
-                let side = if order.side == itchy::Side::Buy {
-                    OrderSide::Buy
-                } else {
-                    OrderSide::Sell
-                };
-
-                book.add_order(order.reference, order.price.raw(), order.shares, side);
-            }
-            ORDER_EXECUTED => {
-                let aapl = stock_directory[&args.symbol];
-                if m.stock_locate != aapl {
-                    continue;
-                }
-                processed += 1;
-
-                let itchy::Body::OrderExecuted {
-                    reference,
-                    executed,
-                    ..
-                } = m.body
-                else {
-                    continue;
-                };
-
-                book.execute_order(reference, executed);
-            }
-            ORDER_EXECUTED_PRICE => {
-                let aapl = stock_directory[&args.symbol];
-                if m.stock_locate != aapl {
-                    continue;
-                }
-                processed += 1;
-
-                let itchy::Body::OrderExecutedWithPrice {
-                    reference,
-                    executed,
-                    printable,
-                    ..
-                } = m.body
-                else {
-                    continue;
-                };
-
-                if !printable {
-                    continue;
-                }
-
-                book.execute_order(reference, executed);
-            }
-            ORDER_CANCEL => {
-                let aapl = stock_directory[&args.symbol];
-                if m.stock_locate != aapl {
-                    continue;
-                }
-                processed += 1;
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("can't read {}: {e}", args.file);
+    }
+}
 
-                let itchy::Body::OrderCancelled {
-                    reference,
-                    cancelled,
-                } = m.body
-                else {
-                    continue;
-                };
-                book.cancel_order(reference, cancelled);
-            }
-            ORDER_DELETE => {
-                let aapl = stock_directory[&args.symbol];
-                if m.stock_locate != aapl {
-                    continue;
-                }
-                processed += 1;
+fn replay<R: BufRead>(mut reader: R, args: &Args) -> io::Result<()> {
+    let protocol = match args.protocol {
+        Some(protocol) => protocol,
+        None => Protocol::detect(reader.fill_buf()?).unwrap_or_default(),
+    };
+
+    if let (Some(from), Some(to)) = (args.from, args.to) {
+        let snapshots = seek::snapshots(
+            reader,
+            protocol,
+            &args.symbol,
+            from,
+            to,
+            args.every,
+            args.depth,
+        )?;
+        if snapshots.is_empty() {
+            eprintln!("symbol {} not listed by {}", args.symbol, format_time(to));
+        }
+        for snapshot in &snapshots {
+            print_snapshot(snapshot);
+        }
+        return Ok(());
+    }
 
-                let itchy::Body::DeleteOrder { reference } = m.body else {
-                    continue;
-                };
+    let mut books = BookSet::new();
+    books.set_orphan_policy(args.orphans);
+    let mut replay = Replay::with_protocol(reader, protocol);
+    // The books are still shown as of the last message before a truncated
+    // frame
+    match replay.advance_to(&mut books, args.at.unwrap_or(u64::MAX)) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => eprintln!("stopped at a bad frame: {e}"),
+        result => {
+            result?;
+        }
+    }
 
-                book.delete_order(reference);
-            }
-            ORDER_REPLACE => {
-                let aapl = stock_directory[&args.symbol];
-                if m.stock_locate != aapl {
-                    continue;
-                }
-                processed += 1;
+    eprintln!(
+        "replayed {} {protocol} messages into {} books, last at {}",
+        replay.applied(),
+        books.len(),
+        format_time(replay.time())
+    );
+    if let Some(warning) = books.orphan_stats().warning(args.orphan_warn) {
+        eprintln!("{warning}");
+    }
 
-                let itchy::Body::ReplaceOrder(order) = &m.body else {
-                    continue;
-                };
+    match books.get_by_symbol(&args.symbol) {
+        Some(book) => println!("{}", book.summary()),
+        None => eprintln!("symbol {} not found in stock directory", args.symbol),
+    }
+    Ok(())
+}
 
-                book.replace_order(
-                    order.old_reference,
-                    order.new_reference,
-                    order.price.raw(),
-                    order.shares,
-                );
-            }
-            _ => continue,
-        }
+fn print_snapshot(snapshot: &BookSnapshot) {
+    println!(
+        "{} {:?}{}",
+        format_time(snapshot.timestamp),
+        snapshot.state,
+        if snapshot.stale { " (stale)" } else { "" }
+    );
+    for (price, volume, orders) in snapshot.asks.iter().rev() {
+        println!(
+            "  ask {:>12} {volume:>8} ({orders})",
+            Decimal::new(*price as i64, 4)
+        );
+    }
+    for (price, volume, orders) in &snapshot.bids {
+        println!(
+            "  bid {:>12} {volume:>8} ({orders})",
+            Decimal::new(*price as i64, 4)
+        );
     }
 }
@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::{self, ErrorKind};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use crate::input::Compression;
+use crate::itch::decode::Frames;
+
+// A raw ITCH file mapped into memory. Frames are decoded in place, and
+// clones share the mapping so several threads can read one file at once.
+#[derive(Debug, Clone)]
+pub struct MappedFile {
+    mmap: Arc<Mmap>,
+}
+
+impl MappedFile {
+    // Compressed files can't be read in place, use input::open for those
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the file must not be truncated or modified while mapped,
+        // which holds for the read-only captures this is meant for
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        let compression = Compression::detect(&mmap);
+        if compression != Compression::None {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{compression} compressed files can't be memory mapped"),
+            ));
+        }
+        Ok(MappedFile {
+            mmap: Arc::new(mmap),
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+
+    pub fn len(&self) -> usize {
+        self.mmap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mmap.is_empty()
+    }
+
+    pub fn frames(&self) -> Frames<'_> {
+        Frames::new(&self.mmap)
+    }
+
+    // Frames from a byte offset, e.g. one saved from Frames::position. The
+    // offset must be the start of a frame
+    pub fn frames_at(&self, offset: usize) -> Frames<'_> {
+        Frames::starting_at(&self.mmap, offset)
+    }
+
+    // Frames within a byte range returned by chunks
+    pub fn frames_in(&self, range: Range<usize>) -> Frames<'_> {
+        Frames::starting_at(&self.mmap[..range.end.min(self.len())], range.start)
+    }
+
+    // Splits the file into up to n byte ranges of about the same size, each
+    // starting and ending on a frame boundary, for scanning in parallel. Only
+    // the length prefixes are read.
+    pub fn chunks(&self, n: usize) -> Vec<Range<usize>> {
+        let data = self.as_bytes();
+        let target = data.len().div_ceil(n.max(1)).max(1);
+
+        let mut chunks = Vec::with_capacity(n);
+        let mut start = 0;
+        let mut pos = 0;
+        while pos + 2 <= data.len() {
+            pos += 2 + u16::from_be_bytes([data[pos], data[pos + 1]]) as usize;
+            if pos - start >= target {
+                chunks.push(start..pos.min(data.len()));
+                start = pos.min(data.len());
+            }
+        }
+        if start < data.len() {
+            chunks.push(start..data.len());
+        }
+        chunks
+    }
+}
@@ -0,0 +1,763 @@
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::fmt;
+
+use crate::bookset::BookSet;
+use crate::itch::ApplyOutcome;
+use crate::itch::decode::{DecodeError, Frames, MessageView};
+
+// Session (10 alpha), sequence number of the first message (u64) and message
+// count (u16). Message blocks use the same length prefix as ITCH files.
+pub const HEADER_LEN: usize = 20;
+// Message count of the packet sent when the session ends
+pub const END_OF_SESSION: u16 = 0xFFFF;
+// Leaves room for IP and UDP headers in a 1500 byte Ethernet frame
+pub const DEFAULT_MAX_PACKET: usize = 1400;
+// Packets buffered behind a gap before the missing messages are given up on
+const DEFAULT_MAX_PENDING: usize = 10_000;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Hash)]
+pub struct Session([u8; 10]);
+
+impl Session {
+    // Space padded or truncated to 10 characters
+    pub fn new(name: &str) -> Self {
+        let mut session = [b' '; 10];
+        for (dst, src) in session.iter_mut().zip(name.bytes()) {
+            *dst = src;
+        }
+        Session(session)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 10] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(String::from_utf8_lossy(&self.0).trim_end())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MoldError {
+    // Shorter than the header, or the message blocks don't add up to the
+    // message count
+    Truncated,
+    // The packet's sequence numbers run past the largest u64
+    BadSequence,
+    // The packet belongs to another session than the one being received
+    WrongSession { expected: Session, actual: Session },
+}
+
+impl fmt::Display for MoldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoldError::Truncated => write!(f, "truncated MoldUDP64 packet"),
+            MoldError::BadSequence => write!(f, "MoldUDP64 packet sequence overflows"),
+            MoldError::WrongSession { expected, actual } => write!(
+                f,
+                "MoldUDP64 packet for session {actual}, expected {expected}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MoldError {}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Packet<'a> {
+    pub session: Session,
+    pub sequence: u64,
+    pub count: u16,
+    blocks: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn parse(buf: &'a [u8]) -> Result<Self, MoldError> {
+        if buf.len() < HEADER_LEN {
+            return Err(MoldError::Truncated);
+        }
+
+        let packet = Packet {
+            session: Session(buf[0..10].try_into().unwrap()),
+            sequence: u64::from_be_bytes(buf[10..18].try_into().unwrap()),
+            count: u16::from_be_bytes([buf[18], buf[19]]),
+            blocks: &buf[HEADER_LEN..],
+        };
+
+        // Check the framing up front so a short packet can't be half applied
+        let mut frames = 0;
+        let mut pos = 0;
+        while pos + 2 <= packet.blocks.len() {
+            pos += 2 + u16::from_be_bytes([packet.blocks[pos], packet.blocks[pos + 1]]) as usize;
+            frames += 1;
+        }
+        if pos != packet.blocks.len() || frames != packet.message_count() {
+            return Err(MoldError::Truncated);
+        }
+        if packet
+            .sequence
+            .checked_add(packet.message_count())
+            .is_none()
+        {
+            return Err(MoldError::BadSequence);
+        }
+        Ok(packet)
+    }
+
+    // Heartbeats carry no messages. Their sequence number is the next one
+    // the server will send
+    pub fn is_heartbeat(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn is_end_of_session(&self) -> bool {
+        self.count == END_OF_SESSION
+    }
+
+    pub fn message_count(&self) -> u64 {
+        if self.is_end_of_session() {
+            0
+        } else {
+            self.count as u64
+        }
+    }
+
+    // Sequence number after the last message in the packet
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence + self.message_count()
+    }
+
+    pub fn messages(&self) -> Frames<'a> {
+        Frames::new(self.blocks)
+    }
+}
+
+// Builds MoldUDP64 packets from ITCH messages, numbering them from the
+// session's first sequence number
+#[derive(Debug)]
+pub struct PacketWriter {
+    session: Session,
+    next_sequence: u64,
+    max_len: usize,
+    buf: Vec<u8>,
+    count: u16,
+}
+
+impl PacketWriter {
+    pub fn new(session: Session, first_sequence: u64, max_len: usize) -> Self {
+        let mut writer = PacketWriter {
+            session,
+            next_sequence: first_sequence,
+            max_len: max_len.max(HEADER_LEN),
+            buf: Vec::with_capacity(max_len),
+            count: 0,
+        };
+        writer.start();
+        writer
+    }
+
+    fn start(&mut self) {
+        self.buf.clear();
+        self.buf.extend_from_slice(&self.session.0);
+        self.buf
+            .extend_from_slice(&self.next_sequence.to_be_bytes());
+        self.buf.extend_from_slice(&0u16.to_be_bytes());
+        self.count = 0;
+    }
+
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    // Whether a message of len bytes fits in the current packet
+    pub fn fits(&self, len: usize) -> bool {
+        self.buf.len() + 2 + len <= self.max_len && self.count < END_OF_SESSION - 1
+    }
+
+    // Adds a message payload, without its length prefix. Returns false if the
+    // packet is full and needs to be taken first
+    pub fn push(&mut self, msg: &[u8]) -> bool {
+        // A message that doesn't fit in an empty packet still goes out alone
+        if !self.fits(msg.len()) && !self.is_empty() {
+            return false;
+        }
+        self.buf
+            .extend_from_slice(&(msg.len() as u16).to_be_bytes());
+        self.buf.extend_from_slice(msg);
+        self.count += 1;
+        true
+    }
+
+    // Returns the current packet and starts the next one
+    pub fn take(&mut self) -> Vec<u8> {
+        self.buf[18..20].copy_from_slice(&self.count.to_be_bytes());
+        self.next_sequence += self.count as u64;
+        let packet = self.buf.clone();
+        self.start();
+        packet
+    }
+
+    pub fn heartbeat(&self) -> Vec<u8> {
+        self.control_packet(0)
+    }
+
+    pub fn end_of_session(&self) -> Vec<u8> {
+        self.control_packet(END_OF_SESSION)
+    }
+
+    fn control_packet(&self, count: u16) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(HEADER_LEN);
+        packet.extend_from_slice(&self.session.0);
+        packet.extend_from_slice(&self.next_sequence.to_be_bytes());
+        packet.extend_from_slice(&count.to_be_bytes());
+        packet
+    }
+}
+
+// Messages with sequence numbers in start..end
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Gap {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Gap {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct PacketResult {
+    // New messages passed on, including any released from behind a gap
+    pub delivered: u64,
+    // Messages that had already been delivered
+    pub duplicates: u64,
+    // Set when the packet showed messages were missing
+    pub gap: Option<Gap>,
+    // Messages given up on because too many packets were waiting behind them
+    pub lost: Option<Gap>,
+    // Every missing message has since arrived
+    pub recovered: bool,
+    pub end_of_session: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct MoldStats {
+    pub packets: u64,
+    pub heartbeats: u64,
+    pub messages: u64,
+    pub duplicates: u64,
+    pub gaps: u64,
+    pub lost: u64,
+    // Messages that didn't decode as ITCH. They still use up a sequence number
+    pub malformed: u64,
+    // Packets that were truncated, had sequence numbers that overflow or were
+    // from another session
+    pub rejected: u64,
+}
+
+// Puts MoldUDP64 packets back in sequence. Packets that arrive ahead of a
+// gap are held until the missing messages arrive, e.g. from a retransmission
+// request, so messages are always delivered in order.
+#[derive(Debug)]
+pub struct MoldReceiver {
+    session: Option<Session>,
+    // Sequence number of the next message to deliver
+    next_sequence: Option<u64>,
+    // Highest sequence number the server is known to have reached
+    highest_sequence: u64,
+    // Packets held behind a gap, keyed by their first sequence number
+    pending: BTreeMap<u64, Vec<u8>>,
+    max_pending: usize,
+    ended: bool,
+    stats: MoldStats,
+}
+
+impl Default for MoldReceiver {
+    fn default() -> Self {
+        MoldReceiver {
+            session: None,
+            next_sequence: None,
+            highest_sequence: 0,
+            pending: BTreeMap::new(),
+            max_pending: DEFAULT_MAX_PENDING,
+            ended: false,
+            stats: MoldStats::default(),
+        }
+    }
+}
+
+impl MoldReceiver {
+    // Joins whichever session the first packet belongs to, at whatever
+    // sequence number it carries
+    pub fn new() -> Self {
+        MoldReceiver::default()
+    }
+
+    // Expects messages from first_sequence onwards. Anything before the first
+    // packet is reported as a gap
+    pub fn starting_at(session: Session, first_sequence: u64) -> Self {
+        MoldReceiver {
+            session: Some(session),
+            next_sequence: Some(first_sequence),
+            highest_sequence: first_sequence,
+            ..MoldReceiver::default()
+        }
+    }
+
+    pub fn set_max_pending(&mut self, packets: usize) {
+        self.max_pending = packets;
+    }
+
+    pub fn session(&self) -> Option<Session> {
+        self.session
+    }
+
+    pub fn next_sequence(&self) -> Option<u64> {
+        self.next_sequence
+    }
+
+    pub fn stats(&self) -> &MoldStats {
+        &self.stats
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    // The messages still missing, if any. They can be requested from the
+    // session's retransmission server
+    pub fn missing(&self) -> Option<Gap> {
+        let next = self.next_sequence?;
+        let end = self
+            .pending
+            .keys()
+            .next()
+            .copied()
+            .unwrap_or(self.highest_sequence);
+        (end > next).then_some(Gap { start: next, end })
+    }
+
+    pub fn in_gap(&self) -> bool {
+        self.missing().is_some()
+    }
+
+    // Handles one packet, passing each new message to on_message with its
+    // sequence number, in order
+    pub fn on_packet<F>(&mut self, buf: &[u8], mut on_message: F) -> Result<PacketResult, MoldError>
+    where
+        F: FnMut(u64, MessageView<'_>),
+    {
+        let packet = Packet::parse(buf).inspect_err(|_| self.stats.rejected += 1)?;
+        match self.session {
+            Some(session) if session != packet.session => {
+                self.stats.rejected += 1;
+                return Err(MoldError::WrongSession {
+                    expected: session,
+                    actual: packet.session,
+                });
+            }
+            Some(_) => {}
+            None => self.session = Some(packet.session),
+        }
+
+        let mut result = PacketResult::default();
+        self.stats.packets += 1;
+        let was_in_gap = self.in_gap();
+        let next = *self.next_sequence.get_or_insert(packet.sequence);
+
+        if packet.is_heartbeat() || packet.is_end_of_session() {
+            self.stats.heartbeats += packet.is_heartbeat() as u64;
+            self.ended |= packet.is_end_of_session();
+            result.end_of_session = packet.is_end_of_session();
+            self.highest_sequence = self.highest_sequence.max(packet.sequence);
+        } else if packet.sequence > next {
+            self.highest_sequence = self.highest_sequence.max(packet.next_sequence());
+            match self.pending.entry(packet.sequence) {
+                Entry::Occupied(_) => {
+                    self.stats.duplicates += packet.message_count();
+                    result.duplicates += packet.message_count();
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(buf.to_vec());
+                }
+            }
+        } else {
+            self.deliver(&packet, &mut result, &mut on_message);
+            self.release_pending(&mut result, &mut on_message);
+        }
+
+        if self.pending.len() > self.max_pending {
+            result.lost = self.skip_gap(&mut result, &mut on_message);
+        }
+
+        let in_gap = self.in_gap();
+        if in_gap && !was_in_gap {
+            self.stats.gaps += 1;
+            result.gap = self.missing();
+        }
+        result.recovered = was_in_gap && !in_gap && result.lost.is_none();
+        Ok(result)
+    }
+
+    // Gives up on the missing messages and carries on with the packets held
+    // behind them
+    pub fn skip<F>(&mut self, mut on_message: F) -> PacketResult
+    where
+        F: FnMut(u64, MessageView<'_>),
+    {
+        let mut result = PacketResult::default();
+        result.lost = self.skip_gap(&mut result, &mut on_message);
+        result
+    }
+
+    fn skip_gap<F>(&mut self, result: &mut PacketResult, on_message: &mut F) -> Option<Gap>
+    where
+        F: FnMut(u64, MessageView<'_>),
+    {
+        let lost = self.missing()?;
+        self.stats.lost += lost.len();
+        self.next_sequence = Some(lost.end);
+        self.release_pending(result, on_message);
+        Some(lost)
+    }
+
+    fn release_pending<F>(&mut self, result: &mut PacketResult, on_message: &mut F)
+    where
+        F: FnMut(u64, MessageView<'_>),
+    {
+        while let Some(entry) = self.pending.first_entry() {
+            if Some(*entry.key()) > self.next_sequence {
+                break;
+            }
+            let buf = entry.remove();
+            // Already parsed once when it was buffered
+            let packet = Packet::parse(&buf).unwrap();
+            self.deliver(&packet, result, on_message);
+        }
+    }
+
+    fn deliver<F>(&mut self, packet: &Packet, result: &mut PacketResult, on_message: &mut F)
+    where
+        F: FnMut(u64, MessageView<'_>),
+    {
+        let next = self.next_sequence.unwrap_or(packet.sequence);
+        let end = packet.next_sequence();
+        self.highest_sequence = self.highest_sequence.max(end);
+
+        if end <= next {
+            self.stats.duplicates += packet.message_count();
+            result.duplicates += packet.message_count();
+            return;
+        }
+
+        let skip = next - packet.sequence;
+        self.stats.duplicates += skip;
+        result.duplicates += skip;
+
+        for (sequence, frame) in (packet.sequence..)
+            .zip(packet.messages())
+            .skip(skip as usize)
+        {
+            match frame {
+                Ok(view) => on_message(sequence, view),
+                Err(
+                    DecodeError::UnknownType(_)
+                    | DecodeError::BadLength { .. }
+                    | DecodeError::OutOfRange { .. }
+                    | DecodeError::BadCode { .. },
+                ) => self.stats.malformed += 1,
+                // Framing was checked when the packet was parsed
+                Err(DecodeError::Truncated { .. }) => unreachable!(),
+            }
+        }
+
+        let delivered = end - next;
+        self.stats.messages += delivered;
+        result.delivered += delivered;
+        self.next_sequence = Some(end);
+    }
+}
+
+// Applies a packet's new messages to the books. Books are marked stale
+// while messages are missing, and cleared once they've all arrived unless
+// some were given up on.
+pub fn apply_packet(
+    receiver: &mut MoldReceiver,
+    books: &mut BookSet,
+    buf: &[u8],
+) -> Result<PacketResult, MoldError> {
+    apply_packet_with(receiver, books, buf, |_, _, _| {})
+}
+
+// As apply_packet, calling on_applied with each message's sequence number
+// and outcome once it has been applied
+pub fn apply_packet_with<F>(
+    receiver: &mut MoldReceiver,
+    books: &mut BookSet,
+    buf: &[u8],
+    mut on_applied: F,
+) -> Result<PacketResult, MoldError>
+where
+    F: FnMut(u64, &MessageView<'_>, ApplyOutcome),
+{
+    let result = receiver.on_packet(buf, |sequence, view| {
+        let outcome = books.apply_view(&view);
+        on_applied(sequence, &view, outcome);
+    })?;
+
+    if result.gap.is_some() || result.lost.is_some() {
+        books.mark_stale();
+    }
+    if result.recovered && receiver.stats().lost == 0 {
+        books.clear_stale();
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::directory::StockInfo;
+
+    // Order Delete for reference 1, which applies to nothing
+    fn message() -> Vec<u8> {
+        let mut msg = vec![b'D', 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        msg.extend_from_slice(&1u64.to_be_bytes());
+        msg
+    }
+
+    // Consecutive packets holding counts[i] messages each, numbered from 1
+    fn packets(counts: &[usize]) -> Vec<Vec<u8>> {
+        let mut writer = PacketWriter::new(Session::new("TEST"), 1, DEFAULT_MAX_PACKET);
+        counts
+            .iter()
+            .map(|&count| {
+                for _ in 0..count {
+                    assert!(writer.push(&message()));
+                }
+                writer.take()
+            })
+            .collect()
+    }
+
+    fn receive(
+        receiver: &mut MoldReceiver,
+        packet: &[u8],
+        delivered: &mut Vec<u64>,
+    ) -> PacketResult {
+        receiver
+            .on_packet(packet, |sequence, _| delivered.push(sequence))
+            .unwrap()
+    }
+
+    #[test]
+    fn in_order() {
+        let mut receiver = MoldReceiver::new();
+        let mut delivered = Vec::new();
+        for packet in packets(&[2, 3, 1]) {
+            let result = receive(&mut receiver, &packet, &mut delivered);
+            assert_eq!(result.gap, None);
+        }
+
+        assert_eq!(delivered, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(receiver.next_sequence(), Some(7));
+        assert_eq!(receiver.session(), Some(Session::new("TEST")));
+        assert_eq!(receiver.stats().messages, 6);
+        assert!(!receiver.in_gap());
+    }
+
+    #[test]
+    fn duplicates_are_dropped() {
+        let packets = packets(&[2, 3]);
+        let mut receiver = MoldReceiver::new();
+        let mut delivered = Vec::new();
+        receive(&mut receiver, &packets[0], &mut delivered);
+        receive(&mut receiver, &packets[1], &mut delivered);
+
+        let result = receive(&mut receiver, &packets[1], &mut delivered);
+        assert_eq!(result.delivered, 0);
+        assert_eq!(result.duplicates, 3);
+        assert_eq!(delivered, [1, 2, 3, 4, 5]);
+        assert_eq!(receiver.stats().duplicates, 3);
+    }
+
+    #[test]
+    fn overlapping_packets_deliver_only_new_messages() {
+        let mut receiver = MoldReceiver::new();
+        let mut delivered = Vec::new();
+        receive(&mut receiver, &packets(&[2])[0], &mut delivered);
+
+        // Messages 1 to 4 in one packet, as a retransmission might send them
+        let result = receive(&mut receiver, &packets(&[4])[0], &mut delivered);
+        assert_eq!(result.delivered, 2);
+        assert_eq!(result.duplicates, 2);
+        assert_eq!(delivered, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn gaps_are_filled_in_order() {
+        let packets = packets(&[1, 2, 1, 3]);
+        let mut receiver = MoldReceiver::new();
+        let mut delivered = Vec::new();
+        receive(&mut receiver, &packets[0], &mut delivered);
+
+        let result = receive(&mut receiver, &packets[2], &mut delivered);
+        assert_eq!(result.gap, Some(Gap { start: 2, end: 4 }));
+        let result = receive(&mut receiver, &packets[3], &mut delivered);
+        assert_eq!(result.gap, None);
+        assert_eq!(receiver.missing(), Some(Gap { start: 2, end: 4 }));
+        assert_eq!(delivered, [1]);
+
+        let result = receive(&mut receiver, &packets[1], &mut delivered);
+        assert!(result.recovered);
+        assert_eq!(result.delivered, 6);
+        assert_eq!(delivered, [1, 2, 3, 4, 5, 6, 7]);
+        assert!(!receiver.in_gap());
+        assert_eq!(receiver.stats().gaps, 1);
+        assert_eq!(receiver.stats().lost, 0);
+    }
+
+    #[test]
+    fn out_of_order_from_the_start() {
+        let packets = packets(&[2, 2]);
+        let mut receiver = MoldReceiver::starting_at(Session::new("TEST"), 1);
+        let mut delivered = Vec::new();
+
+        let result = receive(&mut receiver, &packets[1], &mut delivered);
+        assert_eq!(result.gap, Some(Gap { start: 1, end: 3 }));
+        assert!(delivered.is_empty());
+
+        let result = receive(&mut receiver, &packets[0], &mut delivered);
+        assert!(result.recovered);
+        assert_eq!(delivered, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn held_packets_are_duplicates_once_buffered() {
+        let packets = packets(&[1, 1, 1]);
+        let mut receiver = MoldReceiver::new();
+        let mut delivered = Vec::new();
+        receive(&mut receiver, &packets[0], &mut delivered);
+        receive(&mut receiver, &packets[2], &mut delivered);
+
+        let result = receive(&mut receiver, &packets[2], &mut delivered);
+        assert_eq!(result.duplicates, 1);
+        receive(&mut receiver, &packets[1], &mut delivered);
+        assert_eq!(delivered, [1, 2, 3]);
+    }
+
+    #[test]
+    fn heartbeats_reveal_gaps() {
+        let packets = packets(&[3]);
+        let mut receiver = MoldReceiver::new();
+        let mut delivered = Vec::new();
+        receive(&mut receiver, &packets[0], &mut delivered);
+
+        let mut writer = PacketWriter::new(Session::new("TEST"), 4, DEFAULT_MAX_PACKET);
+        let result = receive(&mut receiver, &writer.heartbeat(), &mut delivered);
+        assert_eq!(result.gap, None);
+        assert_eq!(receiver.stats().heartbeats, 1);
+
+        // The server has sent messages 4 to 9 since
+        writer = PacketWriter::new(Session::new("TEST"), 10, DEFAULT_MAX_PACKET);
+        let result = receive(&mut receiver, &writer.heartbeat(), &mut delivered);
+        assert_eq!(result.gap, Some(Gap { start: 4, end: 10 }));
+        assert_eq!(delivered, [1, 2, 3]);
+    }
+
+    #[test]
+    fn end_of_session() {
+        let packets = packets(&[2]);
+        let mut receiver = MoldReceiver::new();
+        let mut delivered = Vec::new();
+        receive(&mut receiver, &packets[0], &mut delivered);
+        assert!(!receiver.is_ended());
+
+        let writer = PacketWriter::new(Session::new("TEST"), 3, DEFAULT_MAX_PACKET);
+        let result = receive(&mut receiver, &writer.end_of_session(), &mut delivered);
+        assert!(result.end_of_session);
+        assert_eq!(result.gap, None);
+        assert!(receiver.is_ended());
+    }
+
+    #[test]
+    fn too_many_pending_packets_give_up_on_the_gap() {
+        let packets = packets(&[1, 1, 1, 1]);
+        let mut receiver = MoldReceiver::new();
+        receiver.set_max_pending(1);
+        let mut delivered = Vec::new();
+        receive(&mut receiver, &packets[0], &mut delivered);
+        receive(&mut receiver, &packets[2], &mut delivered);
+
+        let result = receive(&mut receiver, &packets[3], &mut delivered);
+        assert_eq!(result.lost, Some(Gap { start: 2, end: 3 }));
+        assert!(!result.recovered);
+        assert_eq!(delivered, [1, 3, 4]);
+        assert_eq!(receiver.stats().lost, 1);
+
+        // The lost message arriving late is a duplicate
+        let result = receive(&mut receiver, &packets[1], &mut delivered);
+        assert_eq!(result.duplicates, 1);
+        assert_eq!(delivered, [1, 3, 4]);
+    }
+
+    #[test]
+    fn bad_packets_are_rejected() {
+        let mut receiver = MoldReceiver::new();
+        let mut delivered = Vec::new();
+        receive(&mut receiver, &packets(&[1])[0], &mut delivered);
+
+        let mut other = PacketWriter::new(Session::new("OTHER"), 2, DEFAULT_MAX_PACKET);
+        other.push(&message());
+        assert_eq!(
+            receiver.on_packet(&other.take(), |_, _| {}),
+            Err(MoldError::WrongSession {
+                expected: Session::new("TEST"),
+                actual: Session::new("OTHER"),
+            })
+        );
+
+        let packet = packets(&[2]).remove(0);
+        assert_eq!(
+            receiver.on_packet(&packet[..packet.len() - 1], |_, _| {}),
+            Err(MoldError::Truncated)
+        );
+
+        let mut overflowing = packet.clone();
+        overflowing[10..18].copy_from_slice(&(u64::MAX - 1).to_be_bytes());
+        assert_eq!(
+            receiver.on_packet(&overflowing, |_, _| {}),
+            Err(MoldError::BadSequence)
+        );
+        assert_eq!(receiver.stats().rejected, 3);
+        assert_eq!(delivered, [1]);
+    }
+
+    #[test]
+    fn books_are_stale_while_messages_are_missing() {
+        let packets = packets(&[1, 1, 1]);
+        let mut receiver = MoldReceiver::new();
+        let mut books = BookSet::new();
+        books.add_listing(StockInfo::new(1, "TEST", 0));
+
+        apply_packet(&mut receiver, &mut books, &packets[0]).unwrap();
+        apply_packet(&mut receiver, &mut books, &packets[2]).unwrap();
+        assert!(books.get(1).unwrap().is_stale());
+
+        apply_packet(&mut receiver, &mut books, &packets[1]).unwrap();
+        assert!(!books.get(1).unwrap().is_stale());
+    }
+}
@@ -1,18 +1,60 @@
+mod buckets;
 mod ordermap;
 
+use std::fmt;
+
+pub use buckets::{BucketView, Bucketing};
+use itchy::ArrayString4;
 use ordermap::OrderMap;
 use rust_decimal::Decimal;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
 use slotmap::{DefaultKey, SlotMap};
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+use crate::auction::AuctionState;
+use crate::limits::AuctionCollar;
+use crate::orphans::{OrphanPolicy, OrphanStats};
+use crate::trading::{TradingState, TradingStatus};
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum OrderSide {
     Buy,
     Sell,
 }
 
+impl OrderSide {
+    pub fn opposite(self) -> Self {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+}
+
+// Per-symbol settings, normally taken from the Stock Directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookConfig {
+    pub stock_locate: u16,
+    pub symbol: String,
+    pub round_lot_size: u32,
+    pub round_lots_only: bool,
+}
+
+impl Default for BookConfig {
+    fn default() -> Self {
+        BookConfig {
+            stock_locate: 0,
+            symbol: String::new(),
+            round_lot_size: 100,
+            round_lots_only: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct PriceLevel {
+    price: u32,
     depth: usize,
     volume: u32,
     side: OrderSide,
@@ -28,6 +70,26 @@ pub struct OrderBook {
 
     price_levels: SlotMap<DefaultKey, PriceLevel>,
     order_map: OrderMap,
+
+    bucket_views: Vec<BucketView>,
+
+    config: BookConfig,
+    status: TradingStatus,
+
+    // One per cross type, most recently updated last
+    auctions: Vec<AuctionState>,
+    collar: Option<AuctionCollar>,
+
+    // MPID of live attributed (F) orders
+    attributions: FxHashMap<u64, ArrayString4>,
+
+    // Set while messages for the book may have been missed, e.g. during a
+    // feed gap
+    stale: bool,
+
+    // What to do with messages for orders the book hasn't seen
+    orphan_policy: OrphanPolicy,
+    orphans: OrphanStats,
 }
 
 impl OrderBook {
@@ -37,13 +99,140 @@ impl OrderBook {
             asks: Vec::with_capacity(10_000),
             price_levels: SlotMap::with_capacity(10_000),
             order_map: OrderMap::new(2_000_000),
+            bucket_views: Vec::new(),
+            config: BookConfig::default(),
+            status: TradingStatus::default(),
+            auctions: Vec::new(),
+            collar: None,
+            attributions: FxHashMap::default(),
+            stale: false,
+            orphan_policy: OrphanPolicy::default(),
+            orphans: OrphanStats::default(),
+        }
+    }
+
+    // For books that only see part of a feed, e.g. one of many symbols in a BookSet.
+    // Orders are kept in a hash map rather than a vec indexed by order id.
+    pub fn with_capacity(price_levels: usize, orders: usize) -> Self {
+        OrderBook {
+            bids: Vec::with_capacity(price_levels),
+            asks: Vec::with_capacity(price_levels),
+            price_levels: SlotMap::with_capacity(price_levels),
+            order_map: OrderMap::sparse(orders),
+            bucket_views: Vec::new(),
+            config: BookConfig::default(),
+            status: TradingStatus::default(),
+            auctions: Vec::new(),
+            collar: None,
+            attributions: FxHashMap::default(),
+            stale: false,
+            orphan_policy: OrphanPolicy::default(),
+            orphans: OrphanStats::default(),
         }
     }
 
+    pub fn config(&self) -> &BookConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: BookConfig) {
+        self.config = config;
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.config.symbol
+    }
+
+    pub fn trading_state(&self) -> TradingState {
+        self.status.state()
+    }
+
+    pub fn trading_status(&self) -> &TradingStatus {
+        &self.status
+    }
+
+    pub fn trading_status_mut(&mut self) -> &mut TradingStatus {
+        &mut self.status
+    }
+
+    pub fn auction(&self, cross_type: itchy::CrossType) -> Option<&AuctionState> {
+        self.auctions.iter().find(|a| a.cross_type == cross_type)
+    }
+
+    pub fn latest_auction(&self) -> Option<&AuctionState> {
+        self.auctions.last()
+    }
+
+    pub fn update_auction(&mut self, state: AuctionState) {
+        self.auctions.retain(|a| a.cross_type != state.cross_type);
+        self.auctions.push(state);
+    }
+
+    pub fn collar(&self) -> Option<&AuctionCollar> {
+        self.collar.as_ref()
+    }
+
+    pub fn set_collar(&mut self, collar: Option<AuctionCollar>) {
+        self.collar = collar;
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub fn set_stale(&mut self, stale: bool) {
+        self.stale = stale;
+    }
+
+    pub fn orphan_policy(&self) -> OrphanPolicy {
+        self.orphan_policy
+    }
+
+    pub fn set_orphan_policy(&mut self, policy: OrphanPolicy) {
+        self.orphan_policy = policy;
+    }
+
+    pub fn orphan_stats(&self) -> &OrphanStats {
+        &self.orphans
+    }
+
+    pub fn orphan_stats_mut(&mut self) -> &mut OrphanStats {
+        &mut self.orphans
+    }
+
+    // Number of (bid, ask) price levels resting outside the current LULD auction collar
+    pub fn levels_outside_collar(&self) -> (usize, usize) {
+        let Some(collar) = &self.collar else {
+            return (0, 0);
+        };
+
+        let outside = |levels: &Vec<(u32, DefaultKey)>| {
+            levels
+                .iter()
+                .filter(|(price, _)| !collar.contains(*price))
+                .count()
+        };
+        (outside(&self.bids), outside(&self.asks))
+    }
+
+    pub fn is_outside_collar(&self) -> bool {
+        self.levels_outside_collar() != (0, 0)
+    }
+
+    pub fn is_round_lot(&self, volume: u32) -> bool {
+        self.config.round_lot_size != 0 && volume.is_multiple_of(self.config.round_lot_size)
+    }
+
     pub fn meta(&self) -> (usize, usize, usize) {
         (self.bids.len(), self.asks.len(), self.price_levels.len())
     }
 
+    // Symbol, state, top of book and level counts, as the replay tools print
+    // them
+    pub fn summary(&self) -> Summary<'_> {
+        Summary(self)
+    }
+
     pub fn best_bid(&self) -> Option<Decimal> {
         let highest_bid = self.bids.last()?.0;
         Some(Decimal::from(highest_bid) / Decimal::from(10_000))
@@ -60,6 +249,128 @@ impl OrderBook {
         Some((lowest_ask - highest_bid) / Decimal::from(10_000))
     }
 
+    pub fn best_bid_raw(&self) -> Option<u32> {
+        Some(self.bids.last()?.0)
+    }
+
+    pub fn best_ask_raw(&self) -> Option<u32> {
+        Some(self.asks.last()?.0)
+    }
+
+    // Raw mid price, rounded down
+    pub fn mid_raw(&self) -> Option<u32> {
+        let highest_bid = self.bids.last()?.0;
+        let lowest_ask = self.asks.last()?.0;
+        Some(((highest_bid as u64 + lowest_ask as u64) / 2) as u32)
+    }
+
+    // Price, volume and order count of each level on a side, best first
+    pub fn levels(&self, side: OrderSide) -> impl Iterator<Item = (u32, u32, usize)> + '_ {
+        let list = if side == OrderSide::Sell {
+            &self.asks
+        } else {
+            &self.bids
+        };
+        list.iter().rev().map(|(_, idx)| {
+            let plevel = &self.price_levels[*idx];
+            (plevel.price, plevel.volume, plevel.depth)
+        })
+    }
+
+    // Removes every resting order, e.g. when a feed clears its state. Trading
+    // status, auctions and config are kept, bucket views are rebuilt empty.
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.price_levels.clear();
+        self.order_map.clear();
+        self.attributions.clear();
+        for idx in 0..self.bucket_views.len() {
+            self.recenter_bucket_view(idx);
+        }
+    }
+
+    // Adds an aggregated view of the book that is kept up to date on every level change.
+    // Basis point views are anchored on the current mid, or on the first mid the book
+    // has, see recenter_bucket_view. None if the bucket width overflows a raw price.
+    pub fn add_bucket_view(&mut self, bucketing: Bucketing) -> Option<usize> {
+        let view = self.build_bucket_view(bucketing)?;
+        self.bucket_views.push(view);
+        Some(self.bucket_views.len() - 1)
+    }
+
+    pub fn bucket_view(&self, idx: usize) -> Option<&BucketView> {
+        self.bucket_views.get(idx)
+    }
+
+    // Rebuilds a view from the current levels, moving a basis point view's anchor to the mid
+    pub fn recenter_bucket_view(&mut self, idx: usize) {
+        let Some(bucketing) = self.bucket_views.get(idx).map(|v| v.bucketing()) else {
+            return;
+        };
+        if let Some(view) = self.build_bucket_view(bucketing) {
+            self.bucket_views[idx] = view;
+        }
+    }
+
+    fn build_bucket_view(&self, bucketing: Bucketing) -> Option<BucketView> {
+        let mid = self.mid_raw();
+        let anchor = mid
+            .or_else(|| {
+                self.bids
+                    .last()
+                    .or(self.asks.last())
+                    .map(|(price, _)| *price)
+            })
+            .unwrap_or(0);
+
+        let mut view = BucketView::new(bucketing, anchor, mid.is_some())?;
+        for (_, plevel) in self.price_levels.iter() {
+            view.add(plevel.side, plevel.price, plevel.volume);
+        }
+        Some(view)
+    }
+
+    #[inline]
+    fn update_buckets(&mut self, side: OrderSide, price: u32, volume: u32, added: bool) {
+        for view in self.bucket_views.iter_mut() {
+            if added {
+                view.add(side, price, volume);
+            } else {
+                view.remove(side, price, volume);
+            }
+        }
+
+        if self.bucket_views.iter().any(|view| !view.is_anchored()) && self.mid_raw().is_some() {
+            for idx in 0..self.bucket_views.len() {
+                if !self.bucket_views[idx].is_anchored() {
+                    self.recenter_bucket_view(idx);
+                }
+            }
+        }
+    }
+
+    // Price, remaining volume and side of a resting order
+    pub fn order(&self, order_id: u64) -> Option<(u32, u32, OrderSide)> {
+        let (plevel_idx, volume) = self.order_map.get(order_id)?;
+        if *volume == 0 {
+            return None;
+        }
+
+        let plevel = self.price_levels.get(*plevel_idx)?;
+        Some((plevel.price, *volume, plevel.side))
+    }
+
+    // Price level and remaining volume of an order still on the book. Fully
+    // executed orders keep a zero volume entry, and their level may be gone
+    fn live_order(&self, order_id: u64) -> Option<(DefaultKey, u32)> {
+        let &(plevel_idx, volume) = self.order_map.get(order_id)?;
+        if volume == 0 || !self.price_levels.contains_key(plevel_idx) {
+            return None;
+        }
+        Some((plevel_idx, volume))
+    }
+
     pub fn add_order(&mut self, id: u64, price: u32, volume: u32, side: OrderSide) {
         let list = if side == OrderSide::Sell {
             &mut self.asks
@@ -100,6 +411,7 @@ impl OrderBook {
             plevel.volume += volume;
         } else {
             plevel_idx = self.price_levels.insert(PriceLevel {
+                price,
                 depth: 1,
                 volume,
                 side,
@@ -108,57 +420,163 @@ impl OrderBook {
         }
 
         self.order_map.put(id, (plevel_idx, volume));
+        self.update_buckets(side, price, volume, true);
     }
 
-    pub fn execute_order(&mut self, order_id: u64, volume: u32) {
-        let (plevel_idx, _) = self.order_map.get(order_id).unwrap();
+    pub fn add_attributed_order(
+        &mut self,
+        id: u64,
+        price: u32,
+        volume: u32,
+        side: OrderSide,
+        mpid: ArrayString4,
+    ) {
+        self.add_order(id, price, volume, side);
+        self.attributions.insert(id, mpid);
+    }
 
-        let plevel = self.price_levels.get_mut(*plevel_idx).unwrap();
-        let side = plevel.side;
-        plevel.volume -= volume;
+    pub fn attribution(&self, order_id: u64) -> Option<&str> {
+        self.attributions.get(&order_id).map(|mpid| mpid.trim_end())
+    }
 
-        if plevel.volume == 0 {
-            self.remove_price_level(*plevel_idx, side);
-        }
+    // (order id, MPID) of every live attributed order
+    pub fn attributed_orders(&self) -> impl Iterator<Item = (u64, &str)> {
+        self.attributions
+            .iter()
+            .map(|(id, mpid)| (*id, mpid.trim_end()))
+    }
 
-        self.order_map.reduce_volume(order_id, volume);
+    pub fn execute_order(&mut self, order_id: u64, volume: u32) -> bool {
+        self.reduce_order(order_id, volume)
     }
 
-    pub fn cancel_order(&mut self, order_id: u64, volume: u32) {
-        let (plevel_idx, _) = self.order_map.get(order_id).unwrap();
+    pub fn cancel_order(&mut self, order_id: u64, volume: u32) -> bool {
+        self.reduce_order(order_id, volume)
+    }
 
-        let plevel = self.price_levels.get_mut(*plevel_idx).unwrap();
+    // Takes volume out of an order, at most what it has left. Orders left
+    // with nothing are off the book.
+    fn reduce_order(&mut self, order_id: u64, volume: u32) -> bool {
+        let Some((plevel_idx, order_volume)) = self.live_order(order_id) else {
+            return false;
+        };
+        let volume = volume.min(order_volume);
+
+        let plevel = self.price_levels.get_mut(plevel_idx).unwrap();
         let side = plevel.side;
+        let price = plevel.price;
         plevel.volume -= volume;
+        if volume == order_volume {
+            plevel.depth -= 1;
+        }
 
         if plevel.volume == 0 {
-            self.remove_price_level(*plevel_idx, side);
+            self.remove_price_level(plevel_idx, side);
         }
 
         self.order_map.reduce_volume(order_id, volume);
+        self.update_buckets(side, price, volume, false);
+
+        if volume == order_volume && !self.attributions.is_empty() {
+            self.attributions.remove(&order_id);
+        }
+        true
     }
 
-    pub fn delete_order(&mut self, order_id: u64) {
-        let (plevel_idx, order_volume) = self.order_map.get(order_id).unwrap();
+    pub fn delete_order(&mut self, order_id: u64) -> bool {
+        let Some((plevel_idx, order_volume)) = self.live_order(order_id) else {
+            return false;
+        };
 
-        let plevel = self.price_levels.get_mut(*plevel_idx).unwrap();
+        let plevel = self.price_levels.get_mut(plevel_idx).unwrap();
         let side = plevel.side;
+        let price = plevel.price;
         plevel.volume -= order_volume;
         plevel.depth -= 1;
 
         if plevel.volume == 0 {
-            self.remove_price_level(*plevel_idx, side);
+            self.remove_price_level(plevel_idx, side);
         }
+
+        self.order_map.remove(order_id);
+        self.update_buckets(side, price, order_volume, false);
+
+        if !self.attributions.is_empty() {
+            self.attributions.remove(&order_id);
+        }
+        true
     }
 
-    pub fn replace_order(&mut self, old_order_id: u64, new_order_id: u64, price: u32, volume: u32) {
-        let (plevel_idx, _) = self.order_map.get(old_order_id).unwrap();
+    pub fn replace_order(
+        &mut self,
+        old_order_id: u64,
+        new_order_id: u64,
+        price: u32,
+        volume: u32,
+    ) -> bool {
+        let Some((_, _, side)) = self.order(old_order_id) else {
+            return false;
+        };
 
-        let plevel = self.price_levels.get_mut(*plevel_idx).unwrap();
-        let side = plevel.side;
+        // The replacement keeps the original order's attribution
+        let mpid = self.attributions.get(&old_order_id).copied();
 
         self.delete_order(old_order_id);
         self.add_order(new_order_id, price, volume, side);
+
+        if let Some(mpid) = mpid {
+            self.attributions.insert(new_order_id, mpid);
+        }
+        true
+    }
+
+    // Sets a level's volume directly, for price level feeds that don't carry
+    // orders. Zero volume removes the level. Levels set this way have no
+    // orders, so a book should be built with either this or the order
+    // methods, not both.
+    pub fn set_level(&mut self, side: OrderSide, price: u32, volume: u32) {
+        let list = if side == OrderSide::Sell {
+            &mut self.asks
+        } else {
+            &mut self.bids
+        };
+
+        // Bids are kept smallest first, asks largest first
+        let idx = list.partition_point(|(plevel_price, _)| match side {
+            OrderSide::Sell => *plevel_price > price,
+            OrderSide::Buy => *plevel_price < price,
+        });
+
+        let old_volume = match list.get(idx) {
+            Some((plevel_price, plevel_idx)) if *plevel_price == price => {
+                let plevel_idx = *plevel_idx;
+                let plevel = self.price_levels.get_mut(plevel_idx).unwrap();
+                let old_volume = plevel.volume;
+                plevel.volume = volume;
+                if volume == 0 {
+                    list.remove(idx);
+                    self.price_levels.remove(plevel_idx);
+                }
+                old_volume
+            }
+            _ if volume == 0 => return,
+            _ => {
+                let plevel_idx = self.price_levels.insert(PriceLevel {
+                    price,
+                    depth: 0,
+                    volume,
+                    side,
+                });
+                list.insert(idx, (price, plevel_idx));
+                0
+            }
+        };
+
+        if volume > old_volume {
+            self.update_buckets(side, price, volume - old_volume, true);
+        } else if volume < old_volume {
+            self.update_buckets(side, price, old_volume - volume, false);
+        }
     }
 
     fn remove_price_level(&mut self, plevel_slab_idx: DefaultKey, side: OrderSide) {
@@ -179,3 +597,136 @@ impl OrderBook {
         self.price_levels.remove(plevel_slab_idx);
     }
 }
+
+pub struct Summary<'a>(&'a OrderBook);
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // For a side of the book that may be empty
+        let or_dash = |price: Option<Decimal>| price.map_or("-".to_string(), |p| p.to_string());
+
+        let book = self.0;
+        let (bids, asks, levels) = book.meta();
+        write!(
+            f,
+            "{} (locate {}) {:?}",
+            book.symbol(),
+            book.config.stock_locate,
+            book.trading_state()
+        )?;
+        if book.is_stale() {
+            write!(f, ", stale")?;
+        }
+        writeln!(f)?;
+        writeln!(f, "  bid    {}", or_dash(book.best_bid()))?;
+        writeln!(f, "  ask    {}", or_dash(book.best_ask()))?;
+        write!(
+            f,
+            "  {bids} bid levels, {asks} ask levels, {levels} price levels"
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(book: &OrderBook, side: OrderSide) -> Vec<(u32, u32, usize)> {
+        book.levels(side).collect()
+    }
+
+    #[test]
+    fn filled_orders_leave_their_level() {
+        for mut book in [OrderBook::new(), OrderBook::with_capacity(4, 4)] {
+            book.add_order(1, 1_000_000, 100, OrderSide::Buy);
+            book.add_order(2, 1_000_000, 50, OrderSide::Buy);
+
+            assert!(book.execute_order(1, 40));
+            assert_eq!(levels(&book, OrderSide::Buy), [(1_000_000, 110, 2)]);
+            assert!(book.cancel_order(1, 60));
+            assert_eq!(levels(&book, OrderSide::Buy), [(1_000_000, 50, 1)]);
+            assert_eq!(book.order(1), None);
+            assert!(!book.execute_order(1, 1));
+
+            assert!(book.execute_order(2, 50));
+            assert!(levels(&book, OrderSide::Buy).is_empty());
+        }
+    }
+
+    #[test]
+    fn reductions_stop_at_the_order_volume() {
+        for mut book in [OrderBook::new(), OrderBook::with_capacity(4, 4)] {
+            book.add_order(1, 1_001_000, 100, OrderSide::Sell);
+            book.add_order(2, 1_001_000, 30, OrderSide::Sell);
+
+            assert!(book.execute_order(2, 500));
+            assert_eq!(book.order(2), None);
+            assert_eq!(levels(&book, OrderSide::Sell), [(1_001_000, 100, 1)]);
+
+            assert!(book.cancel_order(1, u32::MAX));
+            assert!(levels(&book, OrderSide::Sell).is_empty());
+        }
+    }
+
+    #[test]
+    fn sparse_books_forget_filled_orders() {
+        let mut book = OrderBook::with_capacity(4, 4);
+        book.add_order(1, 1_000_000, 100, OrderSide::Buy);
+        book.execute_order(1, 100);
+        assert_eq!(book.order_map.get(1), None);
+    }
+
+    #[test]
+    fn set_levels() {
+        let mut book = OrderBook::new();
+        let view = book.add_bucket_view(Bucketing::Price(1_000)).unwrap();
+        book.set_level(OrderSide::Buy, 999_900, 200);
+        book.set_level(OrderSide::Buy, 1_000_000, 100);
+        book.set_level(OrderSide::Sell, 1_000_200, 300);
+        book.set_level(OrderSide::Sell, 1_000_100, 400);
+        assert_eq!(
+            levels(&book, OrderSide::Buy),
+            [(1_000_000, 100, 0), (999_900, 200, 0)]
+        );
+        assert_eq!(
+            levels(&book, OrderSide::Sell),
+            [(1_000_100, 400, 0), (1_000_200, 300, 0)]
+        );
+        assert_eq!(book.best_bid_raw(), Some(1_000_000));
+        assert_eq!(book.best_ask_raw(), Some(1_000_100));
+
+        // A level's volume is replaced, not added to
+        book.set_level(OrderSide::Buy, 1_000_000, 150);
+        book.set_level(OrderSide::Sell, 1_000_100, 0);
+        book.set_level(OrderSide::Sell, 1_000_300, 0);
+        assert_eq!(
+            levels(&book, OrderSide::Buy),
+            [(1_000_000, 150, 0), (999_900, 200, 0)]
+        );
+        assert_eq!(levels(&book, OrderSide::Sell), [(1_000_200, 300, 0)]);
+        assert_eq!(book.meta(), (2, 1, 3));
+
+        let view = book.bucket_view(view).unwrap();
+        assert_eq!(
+            view.bids().collect::<Vec<_>>(),
+            [(1_000_000, 150), (999_000, 200)]
+        );
+        assert_eq!(view.asks().collect::<Vec<_>>(), [(1_000_000, 300)]);
+    }
+
+    #[test]
+    fn summaries() {
+        let mut book = OrderBook::with_capacity(4, 4);
+        book.set_config(BookConfig {
+            stock_locate: 3,
+            symbol: "AAPL".to_string(),
+            ..BookConfig::default()
+        });
+        book.add_order(1, 1_000_000, 100, OrderSide::Buy);
+        book.set_stale(true);
+        assert_eq!(
+            book.summary().to_string(),
+            "AAPL (locate 3) PreOpen, stale\n  bid    100\n  ask    -\n  1 bid levels, 0 ask levels, 1 price levels"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+
+use super::OrderSide;
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Bucketing {
+    // Fixed price increment in raw ITCH price units, e.g. 500 for $0.05
+    Price(u32),
+    // Basis points away from the anchor price, which is the mid when the view is (re)centered
+    BasisPoints(u32),
+    // A fixed number of ticks of tick_size raw price units each
+    Ticks { count: u32, tick_size: u32 },
+}
+
+#[derive(Debug)]
+pub struct BucketView {
+    bucketing: Bucketing,
+    anchor: u32,
+    width: u32,
+    // False for a basis point view built without a mid, which is re-anchored
+    // once the book has one
+    anchored: bool,
+
+    // Bucket index -> volume. Index 0 starts at the anchor price
+    bids: BTreeMap<i64, u64>,
+    asks: BTreeMap<i64, u64>,
+}
+
+impl BucketView {
+    // None if the bucket width doesn't fit in a raw price
+    pub(crate) fn new(bucketing: Bucketing, anchor: u32, mid: bool) -> Option<Self> {
+        let (anchor, width) = match bucketing {
+            Bucketing::Price(increment) => (0, increment),
+            Bucketing::Ticks { count, tick_size } => (0, count.checked_mul(tick_size)?),
+            Bucketing::BasisPoints(bps) => (
+                anchor,
+                u32::try_from(anchor as u64 * bps as u64 / 10_000).ok()?,
+            ),
+        };
+
+        Some(BucketView {
+            bucketing,
+            anchor,
+            width: width.max(1),
+            anchored: mid || !matches!(bucketing, Bucketing::BasisPoints(_)),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+        })
+    }
+
+    pub fn bucketing(&self) -> Bucketing {
+        self.bucketing
+    }
+
+    pub fn anchor(&self) -> u32 {
+        self.anchor
+    }
+
+    pub fn is_anchored(&self) -> bool {
+        self.anchored
+    }
+
+    // Width of each bucket in raw price units
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn bucket_of(&self, price: u32) -> i64 {
+        (price as i64 - self.anchor as i64).div_euclid(self.width as i64)
+    }
+
+    // Lowest raw price that falls in the bucket
+    pub fn bucket_price(&self, bucket: i64) -> i64 {
+        self.anchor as i64 + bucket * self.width as i64
+    }
+
+    // Bid buckets as (bucket price, volume), best first
+    pub fn bids(&self) -> impl Iterator<Item = (i64, u64)> + '_ {
+        self.bids
+            .iter()
+            .rev()
+            .map(|(bucket, volume)| (self.bucket_price(*bucket), *volume))
+    }
+
+    // Ask buckets as (bucket price, volume), best first
+    pub fn asks(&self) -> impl Iterator<Item = (i64, u64)> + '_ {
+        self.asks
+            .iter()
+            .map(|(bucket, volume)| (self.bucket_price(*bucket), *volume))
+    }
+
+    pub fn volume(&self, side: OrderSide, bucket: i64) -> u64 {
+        let buckets = match side {
+            OrderSide::Buy => &self.bids,
+            OrderSide::Sell => &self.asks,
+        };
+        buckets.get(&bucket).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn add(&mut self, side: OrderSide, price: u32, volume: u32) {
+        let bucket = self.bucket_of(price);
+        let buckets = match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+        *buckets.entry(bucket).or_insert(0) += volume as u64;
+    }
+
+    pub(crate) fn remove(&mut self, side: OrderSide, price: u32, volume: u32) {
+        let bucket = self.bucket_of(price);
+        let buckets = match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        };
+
+        let Some(total) = buckets.get_mut(&bucket) else {
+            return;
+        };
+        *total -= volume as u64;
+        if *total == 0 {
+            buckets.remove(&bucket);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderBook;
+
+    // Every bucket's volume summed again from the book's levels
+    fn recomputed(book: &OrderBook, view: &BucketView, side: OrderSide) -> Vec<(i64, u64)> {
+        let mut buckets = BTreeMap::new();
+        for (price, volume, _) in book.levels(side) {
+            *buckets.entry(view.bucket_of(price)).or_insert(0) += volume as u64;
+        }
+        let buckets = buckets
+            .into_iter()
+            .map(|(bucket, volume)| (view.bucket_price(bucket), volume));
+        match side {
+            OrderSide::Buy => buckets.rev().collect(),
+            OrderSide::Sell => buckets.collect(),
+        }
+    }
+
+    fn assert_consistent(book: &OrderBook, idx: usize) {
+        let view = book.bucket_view(idx).unwrap();
+        assert_eq!(
+            view.bids().collect::<Vec<_>>(),
+            recomputed(book, view, OrderSide::Buy)
+        );
+        assert_eq!(
+            view.asks().collect::<Vec<_>>(),
+            recomputed(book, view, OrderSide::Sell)
+        );
+    }
+
+    // Adds, partial and full executions and deletes across several buckets,
+    // checking every view after each step
+    fn churn(book: &mut OrderBook, views: &[usize]) {
+        let check = |book: &OrderBook| {
+            for &idx in views {
+                assert_consistent(book, idx);
+            }
+        };
+        let orders = [
+            (1, 999_500, 100, OrderSide::Buy),
+            (2, 999_000, 200, OrderSide::Buy),
+            (3, 997_400, 300, OrderSide::Buy),
+            (4, 999_500, 50, OrderSide::Buy),
+            (5, 1_000_500, 100, OrderSide::Sell),
+            (6, 1_001_000, 400, OrderSide::Sell),
+            (7, 1_004_900, 250, OrderSide::Sell),
+        ];
+        for (id, price, volume, side) in orders {
+            book.add_order(id, price, volume, side);
+            check(book);
+        }
+
+        book.execute_order(1, 40);
+        check(book);
+        book.execute_order(5, 100);
+        check(book);
+        book.cancel_order(6, 150);
+        check(book);
+        book.delete_order(3);
+        check(book);
+        book.replace_order(7, 8, 1_002_000, 80);
+        check(book);
+        book.delete_order(4);
+        book.delete_order(1);
+        check(book);
+    }
+
+    #[test]
+    fn price_buckets_follow_the_book() {
+        let mut book = OrderBook::with_capacity(16, 16);
+        let idx = book.add_bucket_view(Bucketing::Price(1_000)).unwrap();
+        churn(&mut book, &[idx]);
+
+        let view = book.bucket_view(idx).unwrap();
+        assert_eq!(view.bids().collect::<Vec<_>>(), [(999_000, 200)]);
+        assert_eq!(
+            view.asks().collect::<Vec<_>>(),
+            [(1_001_000, 250), (1_002_000, 80)]
+        );
+    }
+
+    #[test]
+    fn tick_buckets_follow_the_book() {
+        let mut book = OrderBook::with_capacity(16, 16);
+        let idx = book
+            .add_bucket_view(Bucketing::Ticks {
+                count: 5,
+                tick_size: 100,
+            })
+            .unwrap();
+        assert_eq!(book.bucket_view(idx).unwrap().width(), 500);
+        churn(&mut book, &[idx]);
+    }
+
+    #[test]
+    fn basis_point_buckets_follow_the_book() {
+        let mut book = OrderBook::with_capacity(16, 16);
+        book.add_order(100, 999_000, 10, OrderSide::Buy);
+        book.add_order(101, 1_001_000, 10, OrderSide::Sell);
+        let idx = book.add_bucket_view(Bucketing::BasisPoints(10)).unwrap();
+
+        let view = book.bucket_view(idx).unwrap();
+        assert!(view.is_anchored());
+        assert_eq!((view.anchor(), view.width()), (1_000_000, 1_000));
+        churn(&mut book, &[idx]);
+    }
+
+    #[test]
+    fn views_built_on_an_existing_book_match() {
+        let mut book = OrderBook::with_capacity(16, 16);
+        churn(&mut book, &[]);
+        let views = [
+            book.add_bucket_view(Bucketing::Price(700)).unwrap(),
+            book.add_bucket_view(Bucketing::BasisPoints(25)).unwrap(),
+        ];
+        for idx in views {
+            assert_consistent(&book, idx);
+        }
+    }
+
+    #[test]
+    fn basis_point_views_anchor_on_the_first_mid() {
+        let mut book = OrderBook::with_capacity(16, 16);
+        let idx = book.add_bucket_view(Bucketing::BasisPoints(10)).unwrap();
+        book.add_order(1, 999_000, 100, OrderSide::Buy);
+
+        // A one sided book has no mid, the view waits for one
+        let view = book.bucket_view(idx).unwrap();
+        assert!(!view.is_anchored());
+        assert_consistent(&book, idx);
+
+        book.add_order(2, 1_003_000, 100, OrderSide::Sell);
+        let view = book.bucket_view(idx).unwrap();
+        assert!(view.is_anchored());
+        assert_eq!((view.anchor(), view.width()), (1_001_000, 1_001));
+        assert_consistent(&book, idx);
+
+        // Once anchored the view stays put as the mid moves
+        book.add_order(3, 1_002_000, 100, OrderSide::Buy);
+        assert_eq!(book.bucket_view(idx).unwrap().anchor(), 1_001_000);
+        assert_consistent(&book, idx);
+    }
+
+    #[test]
+    fn overflowing_widths_are_rejected() {
+        let mut book = OrderBook::with_capacity(16, 16);
+        let ticks = Bucketing::Ticks {
+            count: u32::MAX,
+            tick_size: 2,
+        };
+        assert_eq!(book.add_bucket_view(ticks), None);
+        assert!(BucketView::new(Bucketing::BasisPoints(u32::MAX), u32::MAX, true).is_none());
+    }
+}
@@ -1,37 +1,104 @@
+use rustc_hash::FxHashMap;
 use slotmap::DefaultKey;
 
+#[derive(Debug)]
+enum Orders {
+    // Indexed by order id. Fast, but sized by the largest id seen
+    Dense(Vec<(DefaultKey, u32)>),
+    // For books that only see a small share of the feed's order ids
+    Sparse(FxHashMap<u64, (DefaultKey, u32)>),
+}
+
+impl Default for Orders {
+    fn default() -> Self {
+        Orders::Dense(Vec::new())
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct OrderMap {
     // Vec/Map of (slotmap indexes, order volume)
-    orders: Vec<(DefaultKey, u32)>,
+    orders: Orders,
 }
 
 impl OrderMap {
     pub fn new(size: usize) -> Self {
         OrderMap {
-            orders: vec![(DefaultKey::default(), 0); size],
+            orders: Orders::Dense(vec![(DefaultKey::default(), 0); size]),
+        }
+    }
+
+    pub fn sparse(capacity: usize) -> Self {
+        OrderMap {
+            orders: Orders::Sparse(FxHashMap::with_capacity_and_hasher(
+                capacity,
+                rustc_hash::FxBuildHasher,
+            )),
         }
     }
 
     pub fn reserve(&mut self, id: u64) {
-        if (id as usize) < self.orders.len() {
+        let Orders::Dense(orders) = &mut self.orders else {
+            return;
+        };
+
+        if (id as usize) < orders.len() {
             return;
         }
 
-        self.orders
-            .resize(id as usize + 1, (DefaultKey::default(), 0));
+        orders.resize(id as usize + 1, (DefaultKey::default(), 0));
     }
 
     pub fn get(&self, id: u64) -> Option<&(DefaultKey, u32)> {
-        self.orders.get(id as usize)
+        match &self.orders {
+            Orders::Dense(orders) => orders.get(id as usize),
+            Orders::Sparse(orders) => orders.get(&id),
+        }
     }
 
     pub fn put(&mut self, order_id: u64, data: (DefaultKey, u32)) {
         self.reserve(order_id);
-        self.orders[order_id as usize] = data;
+        match &mut self.orders {
+            Orders::Dense(orders) => orders[order_id as usize] = data,
+            Orders::Sparse(orders) => {
+                orders.insert(order_id, data);
+            }
+        }
     }
 
+    // Orders reduced to nothing are removed from sparse maps
     pub fn reduce_volume(&mut self, order_id: u64, volume: u32) {
-        self.orders[order_id as usize].1 -= volume;
+        match &mut self.orders {
+            Orders::Dense(orders) => orders[order_id as usize].1 -= volume,
+            Orders::Sparse(orders) => {
+                if let Some(order) = orders.get_mut(&order_id) {
+                    order.1 -= volume;
+                    if order.1 == 0 {
+                        orders.remove(&order_id);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, order_id: u64) {
+        match &mut self.orders {
+            Orders::Dense(orders) => {
+                if let Some(order) = orders.get_mut(order_id as usize) {
+                    order.1 = 0;
+                }
+            }
+            Orders::Sparse(orders) => {
+                orders.remove(&order_id);
+            }
+        }
+    }
+
+    // Forgets every order. Dense maps keep their size
+    pub fn clear(&mut self) {
+        match &mut self.orders {
+            Orders::Dense(orders) => orders.fill((DefaultKey::default(), 0)),
+            Orders::Sparse(orders) => orders.clear(),
+        }
     }
 }
@@ -0,0 +1,135 @@
+// Order messages for references the book has never seen. Files cut mid-day,
+// e.g. by extractor's --max_messages, and captures started after the open
+// refer to orders added before their first message.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::itch::OrderEvent;
+use crate::orderbook::{OrderBook, OrderSide};
+
+// Total orphans past which the bins warn that the input probably doesn't
+// start at the beginning of the day
+pub const DEFAULT_WARN_THRESHOLD: u64 = 1_000;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub enum OrphanPolicy {
+    // Drop the message without counting it
+    Ignore,
+    // Drop the message, counting it by message type
+    #[default]
+    Count,
+    // Count it, and add the new order of a replace to the book with its side
+    // guessed from where its price sits against the book
+    Placeholder,
+}
+
+impl FromStr for OrphanPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ignore" => Ok(OrphanPolicy::Ignore),
+            "count" => Ok(OrphanPolicy::Count),
+            "placeholder" => Ok(OrphanPolicy::Placeholder),
+            _ => Err(format!(
+                "unknown orphan policy {s:?}, expected ignore, count or placeholder"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OrphanPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OrphanPolicy::Ignore => "ignore",
+            OrphanPolicy::Count => "count",
+            OrphanPolicy::Placeholder => "placeholder",
+        })
+    }
+}
+
+// Orphaned messages by type
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct OrphanStats {
+    pub executed: u64,
+    pub executed_price: u64,
+    pub cancelled: u64,
+    pub deleted: u64,
+    pub replaced: u64,
+    // Replacement orders added by the Placeholder policy
+    pub placeholders: u64,
+}
+
+impl OrphanStats {
+    pub fn total(&self) -> u64 {
+        self.executed + self.executed_price + self.cancelled + self.deleted + self.replaced
+    }
+
+    pub fn record(&mut self, event: &OrderEvent) {
+        match event {
+            OrderEvent::Add { .. } => {}
+            OrderEvent::Executed { .. } => self.executed += 1,
+            OrderEvent::ExecutedWithPrice { .. } => self.executed_price += 1,
+            OrderEvent::Cancelled { .. } => self.cancelled += 1,
+            OrderEvent::Deleted { .. } => self.deleted += 1,
+            OrderEvent::Replaced { .. } => self.replaced += 1,
+        }
+    }
+
+    // Warning for the bins to print once more than threshold messages were
+    // orphaned
+    pub fn warning(&self, threshold: u64) -> Option<String> {
+        let total = self.total();
+        if total <= threshold {
+            return None;
+        }
+        Some(format!(
+            "warning: {total} messages for unknown orders (E {}, C {}, X {}, D {}, U {}, \
+             {} placeholders added), the input probably starts after some orders were added",
+            self.executed,
+            self.executed_price,
+            self.cancelled,
+            self.deleted,
+            self.replaced,
+            self.placeholders,
+        ))
+    }
+
+    pub fn merge(&mut self, other: &OrphanStats) {
+        self.executed += other.executed;
+        self.executed_price += other.executed_price;
+        self.cancelled += other.cancelled;
+        self.deleted += other.deleted;
+        self.replaced += other.replaced;
+        self.placeholders += other.placeholders;
+    }
+}
+
+// Side of an order at this price: at or below the best bid is a buy, at or
+// above the best ask a sell, and inside the spread whichever is nearer. None
+// for an empty book
+pub fn infer_side(book: &OrderBook, price: u32) -> Option<OrderSide> {
+    match (book.best_bid_raw(), book.best_ask_raw()) {
+        (Some(bid), Some(ask)) => Some(if price <= bid {
+            OrderSide::Buy
+        } else if price >= ask {
+            OrderSide::Sell
+        } else if price - bid <= ask - price {
+            OrderSide::Buy
+        } else {
+            OrderSide::Sell
+        }),
+        (Some(bid), None) => Some(if price <= bid {
+            OrderSide::Buy
+        } else {
+            OrderSide::Sell
+        }),
+        (None, Some(ask)) => Some(if price >= ask {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        }),
+        (None, None) => None,
+    }
+}
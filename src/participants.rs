@@ -0,0 +1,247 @@
+use itchy::{ArrayString4, Body, MarketMakerMode, MarketParticipantState, Message};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::itch::ItchHandler;
+use crate::orderbook::{OrderBook, OrderSide};
+
+// Latest Market Participant Position (L) for an MPID in one symbol
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub struct ParticipantPosition {
+    pub timestamp: u64,
+    pub primary_market_maker: bool,
+    pub mode: MarketMakerMode,
+    pub state: MarketParticipantState,
+}
+
+impl ParticipantPosition {
+    pub fn is_active(&self) -> bool {
+        self.state == MarketParticipantState::Active
+    }
+}
+
+// What an MPID is showing in a book through its attributed orders
+#[derive(Debug, PartialEq, Clone)]
+pub struct MarketMakerQuote {
+    pub mpid: ArrayString4,
+    // None if the MPID quotes without being registered for the symbol
+    pub position: Option<ParticipantPosition>,
+    // Best (price, total volume at that price) of the MPID's orders
+    pub bid: Option<(u32, u64)>,
+    pub ask: Option<(u32, u64)>,
+    pub at_best_bid: bool,
+    pub at_best_ask: bool,
+}
+
+impl MarketMakerQuote {
+    pub fn is_quoting(&self) -> bool {
+        self.bid.is_some() || self.ask.is_some()
+    }
+
+    pub fn at_inside(&self) -> bool {
+        self.at_best_bid || self.at_best_ask
+    }
+}
+
+// Market participant positions keyed by (stock_locate, MPID), updated as L
+// messages arrive
+#[derive(Debug, Default)]
+pub struct ParticipantRegistry {
+    positions: FxHashMap<(u16, ArrayString4), ParticipantPosition>,
+}
+
+// Upper-cased and cut to the 4 bytes of an MPID
+fn mpid_key(mpid: &str) -> ArrayString4 {
+    let mut key = ArrayString4::new();
+    for c in mpid.trim_end().chars() {
+        if key.try_push(c.to_ascii_uppercase()).is_err() {
+            break;
+        }
+    }
+    key
+}
+
+impl ParticipantRegistry {
+    pub fn new() -> Self {
+        ParticipantRegistry::default()
+    }
+
+    // Returns true if the message was a Market Participant Position message
+    pub fn on_message(&mut self, msg: &Message) -> bool {
+        let Body::ParticipantPosition(position) = &msg.body else {
+            return false;
+        };
+
+        self.positions.insert(
+            (msg.stock_locate, mpid_key(&position.mpid)),
+            ParticipantPosition {
+                timestamp: msg.timestamp,
+                primary_market_maker: position.primary_market_maker,
+                mode: position.market_maker_mode,
+                state: position.market_participant_state,
+            },
+        );
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn get(&self, stock_locate: u16, mpid: &str) -> Option<&ParticipantPosition> {
+        self.positions.get(&(stock_locate, mpid_key(mpid)))
+    }
+
+    pub fn for_symbol(
+        &self,
+        stock_locate: u16,
+    ) -> impl Iterator<Item = (&str, &ParticipantPosition)> {
+        self.positions
+            .iter()
+            .filter(move |((locate, _), _)| *locate == stock_locate)
+            .map(|((_, mpid), position)| (mpid.as_str(), position))
+    }
+
+    // Joins the registry with the book's attributed orders. Every active
+    // registered MPID is included, quoting or not, along with any MPID that
+    // quotes without being registered.
+    pub fn quotes(&self, book: &OrderBook) -> Vec<MarketMakerQuote> {
+        let stock_locate = book.config().stock_locate;
+        let mut quotes: FxHashMap<ArrayString4, MarketMakerQuote> = FxHashMap::default();
+
+        for (mpid, position) in self.for_symbol(stock_locate) {
+            if !position.is_active() {
+                continue;
+            }
+            let mpid = mpid_key(mpid);
+            quotes.insert(
+                mpid,
+                MarketMakerQuote {
+                    mpid,
+                    position: Some(*position),
+                    bid: None,
+                    ask: None,
+                    at_best_bid: false,
+                    at_best_ask: false,
+                },
+            );
+        }
+
+        for (order_id, mpid) in book.attributed_orders() {
+            let Some((price, volume, side)) = book.order(order_id) else {
+                continue;
+            };
+
+            let mpid = mpid_key(mpid);
+            let quote = quotes.entry(mpid).or_insert_with(|| MarketMakerQuote {
+                mpid,
+                position: self.get(stock_locate, &mpid).copied(),
+                bid: None,
+                ask: None,
+                at_best_bid: false,
+                at_best_ask: false,
+            });
+
+            let best = match side {
+                OrderSide::Buy => &mut quote.bid,
+                OrderSide::Sell => &mut quote.ask,
+            };
+            *best = match *best {
+                Some((best_price, best_volume)) if best_price == price => {
+                    Some((price, best_volume + volume as u64))
+                }
+                Some((best_price, _))
+                    if (side == OrderSide::Buy && best_price > price)
+                        || (side == OrderSide::Sell && best_price < price) =>
+                {
+                    *best
+                }
+                _ => Some((price, volume as u64)),
+            };
+        }
+
+        let best_bid = book.best_bid_raw();
+        let best_ask = book.best_ask_raw();
+
+        let mut quotes: Vec<_> = quotes.into_values().collect();
+        for quote in quotes.iter_mut() {
+            quote.at_best_bid = quote.bid.is_some() && quote.bid.map(|b| b.0) == best_bid;
+            quote.at_best_ask = quote.ask.is_some() && quote.ask.map(|a| a.0) == best_ask;
+        }
+        quotes.sort_by_key(|q| q.mpid);
+        quotes
+    }
+
+    // Active registered MPIDs with no order at the best bid or ask
+    pub fn absent_from_inside(&self, book: &OrderBook) -> Vec<ArrayString4> {
+        self.quotes(book)
+            .into_iter()
+            .filter(|q| q.position.is_some_and(|p| p.is_active()) && !q.at_inside())
+            .map(|q| q.mpid)
+            .collect()
+    }
+}
+
+impl ItchHandler for ParticipantRegistry {
+    fn before_apply(&mut self, _book: &OrderBook, msg: &Message) -> bool {
+        self.on_message(msg);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::{ArrayString8, MarketParticipantPosition};
+
+    use super::*;
+    use crate::orderbook::BookConfig;
+
+    fn position(mpid: &str, state: MarketParticipantState) -> Message {
+        Message {
+            tag: b'L',
+            stock_locate: 1,
+            tracking_number: 0,
+            timestamp: 0,
+            body: Body::ParticipantPosition(MarketParticipantPosition {
+                mpid: ArrayString4::from(mpid).unwrap(),
+                stock: ArrayString8::from("TEST").unwrap(),
+                primary_market_maker: false,
+                market_maker_mode: MarketMakerMode::Normal,
+                market_participant_state: state,
+            }),
+        }
+    }
+
+    #[test]
+    fn mpid_keys_fit_four_bytes() {
+        assert_eq!(mpid_key("gsco ").as_str(), "GSCO");
+        assert_eq!(mpid_key("ABCDE").as_str(), "ABCD");
+        // 'é' is two bytes and doesn't fit after three
+        assert_eq!(mpid_key("ABCé").as_str(), "ABC");
+        assert_eq!(mpid_key("Aéé").as_str(), "Aé");
+    }
+
+    #[test]
+    fn inactive_registrations_are_not_absent() {
+        let mut registry = ParticipantRegistry::new();
+        registry.on_message(&position("GSCO", MarketParticipantState::Active));
+        registry.on_message(&position("MSCO", MarketParticipantState::Withdrawn));
+        registry.on_message(&position("NITE", MarketParticipantState::Active));
+
+        let mut book = OrderBook::with_capacity(8, 8);
+        book.set_config(BookConfig {
+            stock_locate: 1,
+            ..BookConfig::default()
+        });
+        let mpid = |s| ArrayString4::from(s).unwrap();
+        book.add_attributed_order(1, 1_000_000, 100, OrderSide::Buy, mpid("NITE"));
+        book.add_attributed_order(2, 999_000, 100, OrderSide::Buy, mpid("MSCO"));
+
+        // MSCO quotes away from the inside but isn't active
+        assert_eq!(registry.absent_from_inside(&book), [mpid("GSCO")]);
+    }
+}
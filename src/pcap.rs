@@ -0,0 +1,938 @@
+use std::fmt;
+use std::io::{self, BufRead, ErrorKind, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::Range;
+
+use crate::bookset::BookSet;
+use crate::input;
+use crate::itch::ApplyOutcome;
+use crate::itch::decode::MessageView;
+use crate::moldudp::{self, MoldReceiver};
+
+// Link types we can strip down to IP
+pub const LINKTYPE_NULL: u32 = 0;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+pub const LINKTYPE_RAW: u32 = 101;
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+const IP_PROTO_UDP: u8 = 17;
+
+// Largest packet record accepted, tcpdump's maximum snaplen. Anything longer
+// is a corrupt length rather than a packet
+const MAX_RECORD_LEN: usize = 256 * 1024;
+// pcapng blocks also carry their own fields and options
+const MAX_BLOCK_LEN: usize = 2 * MAX_RECORD_LEN;
+
+#[derive(Debug)]
+pub enum PcapError {
+    Io(io::Error),
+    BadMagic(u32),
+    // The file ends partway through a block or record
+    Truncated,
+    // A pcapng packet refers to an interface that wasn't described
+    UnknownInterface(u32),
+    // A record or block length too large to be real, or a timestamp that
+    // doesn't fit in nanoseconds
+    Malformed(String),
+}
+
+impl fmt::Display for PcapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcapError::Io(e) => write!(f, "pcap I/O error: {e}"),
+            PcapError::BadMagic(magic) => write!(f, "not a pcap or pcapng file ({magic:#010x})"),
+            PcapError::Truncated => write!(f, "truncated pcap file"),
+            PcapError::UnknownInterface(id) => {
+                write!(f, "pcapng packet for unknown interface {id}")
+            }
+            PcapError::Malformed(what) => write!(f, "malformed pcap file: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for PcapError {}
+
+impl From<io::Error> for PcapError {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            PcapError::Truncated
+        } else {
+            PcapError::Io(e)
+        }
+    }
+}
+
+// A UDP payload and when it was captured
+#[derive(Debug, Copy, Clone)]
+pub struct Datagram<'a> {
+    // Capture time, nanoseconds since the Unix epoch
+    pub timestamp: u64,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: &'a [u8],
+}
+
+// Matches datagrams by destination, e.g. a feed's multicast group and port.
+// Unset fields match anything
+#[derive(Debug, Default, Copy, Clone)]
+pub struct UdpFilter {
+    pub group: Option<IpAddr>,
+    pub port: Option<u16>,
+}
+
+impl UdpFilter {
+    pub fn matches(&self, dst: &SocketAddr) -> bool {
+        self.group.is_none_or(|group| group == dst.ip())
+            && self.port.is_none_or(|port| port == dst.port())
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct PcapStats {
+    pub packets: u64,
+    pub datagrams: u64,
+    // UDP datagrams the filter didn't match
+    pub filtered: u64,
+    // Packets that weren't IP/UDP, or whose link type isn't supported
+    pub not_udp: u64,
+    // IP fragments, which aren't reassembled
+    pub fragments: u64,
+    // Captured with a snaplen shorter than the datagram
+    pub truncated: u64,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Interface {
+    linktype: u32,
+    // Timestamp units: 10^-n seconds, or 2^-n if the high bit is set
+    tsresol: u8,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        linktype: u32,
+    },
+    PcapNg {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+    },
+}
+
+fn u16_at(b: &[u8], at: usize, big_endian: bool) -> u16 {
+    let bytes = [b[at], b[at + 1]];
+    if big_endian {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    }
+}
+
+fn u32_at(b: &[u8], at: usize, big_endian: bool) -> u32 {
+    let bytes = b[at..at + 4].try_into().unwrap();
+    if big_endian {
+        u32::from_be_bytes(bytes)
+    } else {
+        u32::from_le_bytes(bytes)
+    }
+}
+
+// None if the resolution or the time is out of range for u64 nanoseconds
+fn tsresol_to_nanos(ts: u64, tsresol: u8) -> Option<u64> {
+    let exp = (tsresol & 0x7f) as u32;
+    if tsresol & 0x80 != 0 {
+        u64::try_from((ts as u128 * 1_000_000_000) >> exp).ok()
+    } else if exp <= 9 {
+        ts.checked_mul(10u64.pow(9 - exp))
+    } else {
+        Some(ts / 10u64.checked_pow(exp - 9)?)
+    }
+}
+
+fn too_long(len: usize) -> PcapError {
+    PcapError::Malformed(format!("record of {len} bytes"))
+}
+
+// Reads UDP datagrams out of a pcap or pcapng capture, one record at a time
+#[derive(Debug)]
+pub struct PcapReader<R> {
+    reader: R,
+    format: Format,
+    buf: Vec<u8>,
+    stats: PcapStats,
+}
+
+impl PcapReader<Box<dyn BufRead + Send>> {
+    // Compressed captures are decompressed as they're read
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self, PcapError> {
+        PcapReader::new(input::open(path)?)
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, PcapError> {
+        let mut header = [0u8; 4];
+        reader.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes(header);
+
+        let mut pcap = PcapReader {
+            reader,
+            format: Format::PcapNg {
+                big_endian: false,
+                interfaces: Vec::new(),
+            },
+            buf: Vec::with_capacity(1 << 16),
+            stats: PcapStats::default(),
+        };
+
+        if magic == PCAPNG_SECTION_HEADER {
+            pcap.read_section_header()?;
+            return Ok(pcap);
+        }
+
+        let (big_endian, nanos) = match magic {
+            PCAP_MAGIC_MICROS => (false, false),
+            PCAP_MAGIC_NANOS => (false, true),
+            m if m.swap_bytes() == PCAP_MAGIC_MICROS => (true, false),
+            m if m.swap_bytes() == PCAP_MAGIC_NANOS => (true, true),
+            m => return Err(PcapError::BadMagic(m)),
+        };
+        // Version, timezone, sigfigs, snaplen, link type
+        let mut rest = [0u8; 20];
+        pcap.reader.read_exact(&mut rest)?;
+        pcap.format = Format::Pcap {
+            big_endian,
+            nanos,
+            linktype: u32_at(&rest, 16, big_endian),
+        };
+        Ok(pcap)
+    }
+
+    pub fn stats(&self) -> &PcapStats {
+        &self.stats
+    }
+
+    // The next UDP datagram matching the filter, or None at the end of the
+    // capture
+    pub fn next_datagram(&mut self, filter: &UdpFilter) -> Result<Option<Datagram<'_>>, PcapError> {
+        loop {
+            let Some((timestamp, linktype, start, end)) = self.next_packet()? else {
+                return Ok(None);
+            };
+            self.stats.packets += 1;
+
+            let frame = &self.buf[start..end];
+            let Some((src, dst, payload)) = strip_headers(linktype, frame, &mut self.stats) else {
+                continue;
+            };
+            if !filter.matches(&dst) {
+                self.stats.filtered += 1;
+                continue;
+            }
+            self.stats.datagrams += 1;
+
+            return Ok(Some(Datagram {
+                timestamp,
+                src,
+                dst,
+                payload: &self.buf[start + payload.start..start + payload.end],
+            }));
+        }
+    }
+
+    // Reads the next packet record into buf. Returns the capture time in
+    // nanoseconds, the link type and the frame's range in buf
+    fn next_packet(&mut self) -> Result<Option<(u64, u32, usize, usize)>, PcapError> {
+        loop {
+            let mut head = [0u8; 8];
+            match self.reader.read_exact(&mut head[..4]) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+
+            match &mut self.format {
+                Format::Pcap {
+                    big_endian,
+                    nanos,
+                    linktype,
+                } => {
+                    let (big_endian, nanos, linktype) = (*big_endian, *nanos, *linktype);
+                    let mut rest = [0u8; 12];
+                    self.reader.read_exact(&mut rest)?;
+                    let secs = u32_at(&head, 0, big_endian) as u64;
+                    let frac = u32_at(&rest, 0, big_endian) as u64;
+                    let captured = u32_at(&rest, 4, big_endian) as usize;
+                    if captured > MAX_RECORD_LEN {
+                        return Err(too_long(captured));
+                    }
+
+                    self.buf.resize(captured, 0);
+                    self.reader.read_exact(&mut self.buf)?;
+                    let timestamp = secs * 1_000_000_000 + if nanos { frac } else { frac * 1_000 };
+                    return Ok(Some((timestamp, linktype, 0, captured)));
+                }
+                Format::PcapNg { big_endian, .. } => {
+                    let big_endian = *big_endian;
+                    let block_type = u32_at(&head, 0, big_endian);
+                    if block_type == PCAPNG_SECTION_HEADER {
+                        self.read_section_header()?;
+                        continue;
+                    }
+
+                    self.reader.read_exact(&mut head[4..])?;
+                    let total_len = u32_at(&head, 4, big_endian) as usize;
+                    if total_len < 12 {
+                        return Err(PcapError::Truncated);
+                    }
+                    if total_len > MAX_BLOCK_LEN {
+                        return Err(too_long(total_len));
+                    }
+                    // Block body and the trailing copy of the length
+                    self.buf.resize(total_len - 8, 0);
+                    self.reader.read_exact(&mut self.buf)?;
+
+                    if let Some(packet) = self.parse_block(block_type, big_endian)? {
+                        return Ok(Some(packet));
+                    }
+                }
+            }
+        }
+    }
+
+    // Called with the block type already read. Resets the interfaces, which
+    // are numbered per section
+    fn read_section_header(&mut self) -> Result<(), PcapError> {
+        let mut head = [0u8; 8];
+        self.reader.read_exact(&mut head)?;
+        let big_endian = match u32::from_le_bytes(head[4..8].try_into().unwrap()) {
+            PCAPNG_BYTE_ORDER_MAGIC => false,
+            m if m.swap_bytes() == PCAPNG_BYTE_ORDER_MAGIC => true,
+            m => return Err(PcapError::BadMagic(m)),
+        };
+        let total_len = u32_at(&head, 0, big_endian) as usize;
+        if total_len < 12 {
+            return Err(PcapError::Truncated);
+        }
+        if total_len > MAX_BLOCK_LEN {
+            return Err(too_long(total_len));
+        }
+        // Skip the rest: version, section length and options
+        self.buf.resize(total_len - 12, 0);
+        self.reader.read_exact(&mut self.buf)?;
+        self.format = Format::PcapNg {
+            big_endian,
+            interfaces: Vec::new(),
+        };
+        Ok(())
+    }
+
+    fn parse_block(
+        &mut self,
+        block_type: u32,
+        big_endian: bool,
+    ) -> Result<Option<(u64, u32, usize, usize)>, PcapError> {
+        let Format::PcapNg { interfaces, .. } = &mut self.format else {
+            unreachable!()
+        };
+        let body = &self.buf[..self.buf.len() - 4];
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                if body.len() < 8 {
+                    return Err(PcapError::Truncated);
+                }
+                let mut interface = Interface {
+                    linktype: u16_at(body, 0, big_endian) as u32,
+                    tsresol: 6,
+                };
+                let mut at = 8;
+                while at + 4 <= body.len() {
+                    let code = u16_at(body, at, big_endian);
+                    let len = u16_at(body, at + 2, big_endian) as usize;
+                    if code == 0 {
+                        break;
+                    }
+                    if code == PCAPNG_OPTION_TSRESOL && len >= 1 && at + 4 < body.len() {
+                        interface.tsresol = body[at + 4];
+                    }
+                    at += 4 + len.div_ceil(4) * 4;
+                }
+                interfaces.push(interface);
+                Ok(None)
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                if body.len() < 20 {
+                    return Err(PcapError::Truncated);
+                }
+                let id = u32_at(body, 0, big_endian);
+                let interface = *interfaces
+                    .get(id as usize)
+                    .ok_or(PcapError::UnknownInterface(id))?;
+                let ts = ((u32_at(body, 4, big_endian) as u64) << 32)
+                    | u32_at(body, 8, big_endian) as u64;
+                let captured = u32_at(body, 12, big_endian) as usize;
+                if 20 + captured > body.len() {
+                    return Err(PcapError::Truncated);
+                }
+                let timestamp = tsresol_to_nanos(ts, interface.tsresol).ok_or_else(|| {
+                    PcapError::Malformed(format!(
+                        "timestamp {ts} at resolution {:#04x}",
+                        interface.tsresol
+                    ))
+                })?;
+                Ok(Some((timestamp, interface.linktype, 20, 20 + captured)))
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                // No timestamp, and always from the first interface
+                let interface = *interfaces.first().ok_or(PcapError::UnknownInterface(0))?;
+                if body.len() < 4 {
+                    return Err(PcapError::Truncated);
+                }
+                let original = u32_at(body, 0, big_endian) as usize;
+                let captured = original.min(body.len() - 4);
+                Ok(Some((0, interface.linktype, 4, 4 + captured)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+// Strips link, IP and UDP headers, returning the UDP source, destination and
+// the payload's range in the frame
+fn strip_headers(
+    linktype: u32,
+    frame: &[u8],
+    stats: &mut PcapStats,
+) -> Option<(SocketAddr, SocketAddr, Range<usize>)> {
+    let ip_start = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut at = 12;
+            let mut ethertype = u16::from_be_bytes([*frame.get(at)?, *frame.get(at + 1)?]);
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                at += 4;
+                ethertype = u16::from_be_bytes([*frame.get(at)?, *frame.get(at + 1)?]);
+            }
+            if ethertype != ETHERTYPE_IPV4 && ethertype != ETHERTYPE_IPV6 {
+                stats.not_udp += 1;
+                return None;
+            }
+            at + 2
+        }
+        LINKTYPE_LINUX_SLL => 16,
+        LINKTYPE_LINUX_SLL2 => 20,
+        LINKTYPE_NULL => 4,
+        LINKTYPE_RAW => 0,
+        _ => {
+            stats.not_udp += 1;
+            return None;
+        }
+    };
+    let ip = frame.get(ip_start..)?;
+
+    let (src, dst, header_len) = match ip.first()? >> 4 {
+        4 => {
+            if ip.len() < 20 || ip[9] != IP_PROTO_UDP {
+                stats.not_udp += 1;
+                return None;
+            }
+            // More fragments flag or a fragment offset
+            if u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff != 0 {
+                stats.fragments += 1;
+                return None;
+            }
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&ip[12..16]).ok()?);
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&ip[16..20]).ok()?);
+            (
+                IpAddr::V4(src),
+                IpAddr::V4(dst),
+                ((ip[0] & 0x0f) as usize) * 4,
+            )
+        }
+        6 => {
+            // Extension headers aren't followed
+            if ip.len() < 40 || ip[6] != IP_PROTO_UDP {
+                stats.not_udp += 1;
+                return None;
+            }
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&ip[8..24]).ok()?);
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&ip[24..40]).ok()?);
+            (IpAddr::V6(src), IpAddr::V6(dst), 40)
+        }
+        _ => {
+            stats.not_udp += 1;
+            return None;
+        }
+    };
+
+    let udp_start = ip_start + header_len;
+    let Some(udp) = frame.get(udp_start..udp_start + 8) else {
+        stats.truncated += 1;
+        return None;
+    };
+    let src_port = u16::from_be_bytes([udp[0], udp[1]]);
+    let dst_port = u16::from_be_bytes([udp[2], udp[3]]);
+    let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+
+    // Ethernet pads short frames, so go by the UDP length rather than the
+    // frame's
+    let payload = udp_start + 8..udp_start + udp_len.max(8);
+    if payload.end > frame.len() {
+        stats.truncated += 1;
+        return None;
+    }
+
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        payload,
+    ))
+}
+
+// Decodes the MoldUDP64 datagrams in a capture and applies their messages to
+// the books. Capture errors end the replay, bad MoldUDP64 packets don't.
+// on_applied sees each message with the capture time of the packet that
+// delivered it, to set against the message's exchange timestamp.
+pub fn replay<R, F>(
+    reader: &mut PcapReader<R>,
+    filter: &UdpFilter,
+    receiver: &mut MoldReceiver,
+    books: &mut BookSet,
+    mut on_applied: F,
+) -> Result<(), PcapError>
+where
+    R: Read,
+    F: FnMut(u64, &MessageView<'_>, ApplyOutcome),
+{
+    while let Some(datagram) = reader.next_datagram(filter)? {
+        let receive_time = datagram.timestamp;
+        // Bad packets are counted in the receiver's stats. One shouldn't end
+        // the replay
+        let _ =
+            moldudp::apply_packet_with(receiver, books, datagram.payload, |_, view, outcome| {
+                on_applied(receive_time, view, outcome)
+            });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moldudp::{DEFAULT_MAX_PACKET, PacketWriter, Session};
+
+    const GROUP: Ipv4Addr = Ipv4Addr::new(233, 54, 12, 111);
+    const OTHER: Ipv4Addr = Ipv4Addr::new(233, 54, 12, 112);
+
+    fn put16(out: &mut Vec<u8>, v: u16, big_endian: bool) {
+        out.extend_from_slice(&if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        });
+    }
+
+    fn put32(out: &mut Vec<u8>, v: u32, big_endian: bool) {
+        out.extend_from_slice(&if big_endian {
+            v.to_be_bytes()
+        } else {
+            v.to_le_bytes()
+        });
+    }
+
+    // An IPv4 packet carrying a UDP payload from 10.0.0.1:5000
+    fn ipv4(dst: Ipv4Addr, port: u16, payload: &[u8], fragment: u16) -> Vec<u8> {
+        let mut ip = vec![0x45, 0];
+        ip.extend_from_slice(&((28 + payload.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(&fragment.to_be_bytes());
+        ip.extend_from_slice(&[64, IP_PROTO_UDP, 0, 0, 10, 0, 0, 1]);
+        ip.extend_from_slice(&dst.octets());
+        ip.extend_from_slice(&5000u16.to_be_bytes());
+        ip.extend_from_slice(&port.to_be_bytes());
+        ip.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0]);
+        ip.extend_from_slice(payload);
+        ip
+    }
+
+    // An Ethernet frame with an IP packet behind the given VLAN tags
+    fn ethernet(tags: &[u16], ethertype: u16, ip: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; 12];
+        for &tag in tags {
+            frame.extend_from_slice(&tag.to_be_bytes());
+            frame.extend_from_slice(&[0, 1]);
+        }
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(ip);
+        frame
+    }
+
+    fn udp_frame(dst: Ipv4Addr, port: u16, payload: &[u8]) -> Vec<u8> {
+        ethernet(&[], ETHERTYPE_IPV4, &ipv4(dst, port, payload, 0))
+    }
+
+    // A classic pcap file of (seconds, fraction, frame) records
+    fn pcap(
+        big_endian: bool,
+        nanos: bool,
+        linktype: u32,
+        records: &[(u32, u32, Vec<u8>)],
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        let magic = if nanos {
+            PCAP_MAGIC_NANOS
+        } else {
+            PCAP_MAGIC_MICROS
+        };
+        put32(&mut out, magic, big_endian);
+        put16(&mut out, 2, big_endian);
+        put16(&mut out, 4, big_endian);
+        put32(&mut out, 0, big_endian);
+        put32(&mut out, 0, big_endian);
+        put32(&mut out, 65535, big_endian);
+        put32(&mut out, linktype, big_endian);
+        for (secs, frac, frame) in records {
+            put32(&mut out, *secs, big_endian);
+            put32(&mut out, *frac, big_endian);
+            put32(&mut out, frame.len() as u32, big_endian);
+            put32(&mut out, frame.len() as u32, big_endian);
+            out.extend_from_slice(frame);
+        }
+        out
+    }
+
+    fn block(out: &mut Vec<u8>, block_type: u32, body: &[u8], big_endian: bool) {
+        let padded = body.len().div_ceil(4) * 4;
+        put32(out, block_type, big_endian);
+        put32(out, (12 + padded) as u32, big_endian);
+        out.extend_from_slice(body);
+        out.resize(out.len() + padded - body.len(), 0);
+        put32(out, (12 + padded) as u32, big_endian);
+    }
+
+    fn section_header(out: &mut Vec<u8>, big_endian: bool) {
+        let mut body = Vec::new();
+        put32(&mut body, PCAPNG_BYTE_ORDER_MAGIC, big_endian);
+        put16(&mut body, 1, big_endian);
+        put16(&mut body, 0, big_endian);
+        body.extend_from_slice(&[0xff; 8]);
+        block(out, PCAPNG_SECTION_HEADER, &body, big_endian);
+    }
+
+    fn interface(out: &mut Vec<u8>, tsresol: Option<u8>, big_endian: bool) {
+        let mut body = Vec::new();
+        put16(&mut body, LINKTYPE_ETHERNET as u16, big_endian);
+        put16(&mut body, 0, big_endian);
+        put32(&mut body, 65535, big_endian);
+        if let Some(tsresol) = tsresol {
+            put16(&mut body, PCAPNG_OPTION_TSRESOL, big_endian);
+            put16(&mut body, 1, big_endian);
+            body.extend_from_slice(&[tsresol, 0, 0, 0]);
+        }
+        put32(&mut body, 0, big_endian);
+        block(out, PCAPNG_INTERFACE_DESCRIPTION, &body, big_endian);
+    }
+
+    fn packet(out: &mut Vec<u8>, id: u32, ts: u64, frame: &[u8], big_endian: bool) {
+        let mut body = Vec::new();
+        put32(&mut body, id, big_endian);
+        put32(&mut body, (ts >> 32) as u32, big_endian);
+        put32(&mut body, ts as u32, big_endian);
+        put32(&mut body, frame.len() as u32, big_endian);
+        put32(&mut body, frame.len() as u32, big_endian);
+        body.extend_from_slice(frame);
+        block(out, PCAPNG_ENHANCED_PACKET, &body, big_endian);
+    }
+
+    fn simple_packet(out: &mut Vec<u8>, frame: &[u8], big_endian: bool) {
+        let mut body = Vec::new();
+        put32(&mut body, frame.len() as u32, big_endian);
+        body.extend_from_slice(frame);
+        block(out, PCAPNG_SIMPLE_PACKET, &body, big_endian);
+    }
+
+    type Received = Vec<(u64, SocketAddr, Vec<u8>)>;
+
+    fn read_all(file: &[u8], filter: &UdpFilter) -> Result<(Received, PcapStats), PcapError> {
+        let mut reader = PcapReader::new(file)?;
+        let mut received = Vec::new();
+        while let Some(datagram) = reader.next_datagram(filter)? {
+            received.push((datagram.timestamp, datagram.dst, datagram.payload.to_vec()));
+        }
+        Ok((received, *reader.stats()))
+    }
+
+    fn to(ip: Ipv4Addr, port: u16) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(ip), port)
+    }
+
+    #[test]
+    fn pcap_headers_in_both_byte_orders() {
+        for big_endian in [false, true] {
+            for nanos in [false, true] {
+                let records = [
+                    (1, 500, udp_frame(GROUP, 1000, b"first")),
+                    (2, 0, udp_frame(GROUP, 1000, b"second")),
+                ];
+                let file = pcap(big_endian, nanos, LINKTYPE_ETHERNET, &records);
+                let (received, stats) = read_all(&file, &UdpFilter::default()).unwrap();
+                let frac = if nanos { 500 } else { 500_000 };
+                assert_eq!(
+                    received,
+                    [
+                        (1_000_000_000 + frac, to(GROUP, 1000), b"first".to_vec()),
+                        (2_000_000_000, to(GROUP, 1000), b"second".to_vec()),
+                    ]
+                );
+                assert_eq!(stats.packets, 2);
+                assert_eq!(stats.datagrams, 2);
+            }
+        }
+    }
+
+    #[test]
+    fn pcapng_blocks_in_both_byte_orders() {
+        for big_endian in [false, true] {
+            let mut file = Vec::new();
+            section_header(&mut file, big_endian);
+            interface(&mut file, None, big_endian);
+            interface(&mut file, Some(9), big_endian);
+            packet(
+                &mut file,
+                0,
+                1_500_000,
+                &udp_frame(GROUP, 1000, b"micros"),
+                big_endian,
+            );
+            packet(
+                &mut file,
+                1,
+                7,
+                &udp_frame(GROUP, 1000, b"nanos"),
+                big_endian,
+            );
+            simple_packet(&mut file, &udp_frame(GROUP, 1000, b"simple"), big_endian);
+            // Interfaces are numbered per section
+            section_header(&mut file, big_endian);
+            interface(&mut file, None, big_endian);
+            packet(
+                &mut file,
+                0,
+                2,
+                &udp_frame(GROUP, 1000, b"again"),
+                big_endian,
+            );
+
+            let (received, stats) = read_all(&file, &UdpFilter::default()).unwrap();
+            assert_eq!(
+                received,
+                [
+                    (1_500_000_000, to(GROUP, 1000), b"micros".to_vec()),
+                    (7, to(GROUP, 1000), b"nanos".to_vec()),
+                    (0, to(GROUP, 1000), b"simple".to_vec()),
+                    (2_000, to(GROUP, 1000), b"again".to_vec()),
+                ]
+            );
+            assert_eq!(stats.packets, 4);
+
+            packet(
+                &mut file,
+                1,
+                0,
+                &udp_frame(GROUP, 1000, b"lost"),
+                big_endian,
+            );
+            let error = read_all(&file, &UdpFilter::default()).unwrap_err();
+            assert!(matches!(error, PcapError::UnknownInterface(1)), "{error}");
+        }
+    }
+
+    #[test]
+    fn bad_files() {
+        let error = PcapReader::new(&b"not a capture"[..]).unwrap_err();
+        assert!(matches!(error, PcapError::BadMagic(_)), "{error}");
+
+        let file = pcap(
+            false,
+            false,
+            LINKTYPE_ETHERNET,
+            &[(1, 0, udp_frame(GROUP, 1000, b"cut"))],
+        );
+        let error = read_all(&file[..file.len() - 1], &UdpFilter::default()).unwrap_err();
+        assert!(matches!(error, PcapError::Truncated), "{error}");
+
+        let mut file = pcap(false, false, LINKTYPE_ETHERNET, &[]);
+        put32(&mut file, 1, false);
+        put32(&mut file, 0, false);
+        put32(&mut file, (MAX_RECORD_LEN + 1) as u32, false);
+        put32(&mut file, (MAX_RECORD_LEN + 1) as u32, false);
+        let error = read_all(&file, &UdpFilter::default()).unwrap_err();
+        assert!(matches!(error, PcapError::Malformed(_)), "{error}");
+    }
+
+    #[test]
+    fn headers_are_stripped() {
+        let ip = ipv4(GROUP, 1000, b"payload", 0);
+        let mut padded = ethernet(&[], ETHERTYPE_IPV4, &ipv4(GROUP, 1000, b"pad", 0));
+        padded.resize(60, 0);
+        let records = [
+            (1, 0, ethernet(&[], ETHERTYPE_IPV4, &ip)),
+            (2, 0, ethernet(&[ETHERTYPE_VLAN], ETHERTYPE_IPV4, &ip)),
+            (
+                3,
+                0,
+                ethernet(&[ETHERTYPE_QINQ, ETHERTYPE_VLAN], ETHERTYPE_IPV4, &ip),
+            ),
+            (4, 0, padded),
+            // ARP
+            (5, 0, ethernet(&[], 0x0806, &[0; 28])),
+            (
+                6,
+                0,
+                ethernet(&[], ETHERTYPE_IPV4, &ipv4(GROUP, 1000, b"frag", 0x2000)),
+            ),
+            (7, 0, ethernet(&[], ETHERTYPE_IPV4, &ip[..ip.len() - 1])),
+        ];
+        let file = pcap(false, false, LINKTYPE_ETHERNET, &records);
+        let (received, stats) = read_all(&file, &UdpFilter::default()).unwrap();
+        let payloads: Vec<_> = received
+            .iter()
+            .map(|(_, _, payload)| payload.as_slice())
+            .collect();
+        assert_eq!(payloads, [&b"payload"[..], b"payload", b"payload", b"pad"]);
+        assert_eq!(
+            stats,
+            PcapStats {
+                packets: 7,
+                datagrams: 4,
+                not_udp: 1,
+                fragments: 1,
+                truncated: 1,
+                ..PcapStats::default()
+            }
+        );
+
+        let mut tcp = ip.clone();
+        tcp[9] = 6;
+        let file = pcap(true, true, LINKTYPE_RAW, &[(1, 0, ip), (2, 0, tcp)]);
+        let (received, stats) = read_all(&file, &UdpFilter::default()).unwrap();
+        assert_eq!(
+            received,
+            [(1_000_000_000, to(GROUP, 1000), b"payload".to_vec())]
+        );
+        assert_eq!(stats.not_udp, 1);
+    }
+
+    #[test]
+    fn filter_by_group_and_port() {
+        let records = [
+            (1, 0, udp_frame(GROUP, 1000, b"a")),
+            (2, 0, udp_frame(GROUP, 1001, b"b")),
+            (3, 0, udp_frame(OTHER, 1000, b"c")),
+        ];
+        let file = pcap(false, false, LINKTYPE_ETHERNET, &records);
+        let filters = [
+            (None, None, &[&b"a"[..], b"b", b"c"][..]),
+            (Some(GROUP), None, &[&b"a"[..], b"b"][..]),
+            (None, Some(1000), &[&b"a"[..], b"c"][..]),
+            (Some(GROUP), Some(1000), &[&b"a"[..]][..]),
+            (Some(OTHER), Some(1001), &[][..]),
+        ];
+        for (group, port, expected) in filters {
+            let filter = UdpFilter {
+                group: group.map(IpAddr::V4),
+                port,
+            };
+            let (received, stats) = read_all(&file, &filter).unwrap();
+            let payloads: Vec<_> = received
+                .iter()
+                .map(|(_, _, payload)| payload.as_slice())
+                .collect();
+            assert_eq!(payloads, expected);
+            assert_eq!(stats.datagrams, expected.len() as u64);
+            assert_eq!(stats.filtered, 3 - expected.len() as u64);
+        }
+    }
+
+    #[test]
+    fn timestamp_resolutions() {
+        assert_eq!(tsresol_to_nanos(5, 9), Some(5));
+        assert_eq!(tsresol_to_nanos(5, 6), Some(5_000));
+        assert_eq!(tsresol_to_nanos(5, 0), Some(5_000_000_000));
+        assert_eq!(tsresol_to_nanos(5_999, 12), Some(5));
+        assert_eq!(tsresol_to_nanos(1, 0x80 | 1), Some(500_000_000));
+        assert_eq!(tsresol_to_nanos(3 << 30, 0x80 | 30), Some(3_000_000_000));
+        assert_eq!(tsresol_to_nanos(u64::MAX, 0x80 | 127), Some(0));
+
+        assert_eq!(tsresol_to_nanos(u64::MAX, 0), None);
+        assert_eq!(tsresol_to_nanos(u64::MAX, 0x80), None);
+        assert_eq!(tsresol_to_nanos(1, 29), None);
+        assert_eq!(tsresol_to_nanos(1, 127), None);
+    }
+
+    #[test]
+    fn out_of_range_timestamps_are_malformed() {
+        for tsresol in [29, 0x7f] {
+            let mut file = Vec::new();
+            section_header(&mut file, false);
+            interface(&mut file, Some(tsresol), false);
+            packet(&mut file, 0, 1, &udp_frame(GROUP, 1000, b"late"), false);
+            let error = read_all(&file, &UdpFilter::default()).unwrap_err();
+            assert!(matches!(error, PcapError::Malformed(_)), "{error}");
+        }
+    }
+
+    #[test]
+    fn replays_carry_the_capture_time() {
+        // Order Delete for reference 1, which applies to nothing
+        let mut delete = vec![b'D', 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        delete.extend_from_slice(&1u64.to_be_bytes());
+        let mut writer = PacketWriter::new(Session::new("TEST"), 1, DEFAULT_MAX_PACKET);
+        assert!(writer.push(&delete));
+        assert!(writer.push(&delete));
+        let records = [
+            (1, 0, udp_frame(GROUP, 1000, &writer.take())),
+            (2, 0, udp_frame(OTHER, 1000, &writer.heartbeat())),
+        ];
+        let file = pcap(false, true, LINKTYPE_ETHERNET, &records);
+
+        let mut reader = PcapReader::new(&file[..]).unwrap();
+        let filter = UdpFilter {
+            group: Some(IpAddr::V4(GROUP)),
+            port: Some(1000),
+        };
+        let mut receiver = MoldReceiver::new();
+        let mut books = BookSet::new();
+        let mut applied = Vec::new();
+        replay(
+            &mut reader,
+            &filter,
+            &mut receiver,
+            &mut books,
+            |receive_time, view, _| {
+                applied.push((receive_time, view.tag()));
+            },
+        )
+        .unwrap();
+        assert_eq!(applied, [(1_000_000_000, b'D'), (1_000_000_000, b'D')]);
+        assert_eq!(receiver.stats().messages, 2);
+        assert_eq!(reader.stats().filtered, 1);
+    }
+}
@@ -0,0 +1,565 @@
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::bookset::BookSet;
+use crate::itch::decode::{FrameReader, MessageView};
+use crate::itch::protocol::{Decoded, Protocol, Translator};
+use crate::orderbook::{OrderBook, OrderSide};
+use crate::trading::TradingState;
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const NANOS_PER_DAY: u64 = 86_400 * NANOS_PER_SECOND;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum TimeError {
+    Invalid(String),
+    // Past the end of the day
+    OutOfRange(u64),
+}
+
+impl fmt::Display for TimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeError::Invalid(s) => write!(
+                f,
+                "invalid time {s:?}, expected HH:MM:SS.nnnnnnnnn or nanoseconds since midnight"
+            ),
+            TimeError::OutOfRange(ns) => write!(f, "time {ns}ns is past the end of the day"),
+        }
+    }
+}
+
+impl std::error::Error for TimeError {}
+
+// Nanoseconds since midnight, like ITCH timestamps, from either
+// HH:MM[:SS[.fraction]] with up to nine fractional digits or a plain
+// nanosecond count
+pub fn parse_time(s: &str) -> Result<u64, TimeError> {
+    let invalid = || TimeError::Invalid(s.to_string());
+    let s = s.trim();
+
+    let ns = if s.contains(':') {
+        let (hms, fraction) = s.split_once('.').unwrap_or((s, ""));
+        let mut parts = hms.split(':');
+        let mut field = |max: u64| -> Result<u64, TimeError> {
+            let part = parts.next().unwrap_or("0");
+            match part.parse::<u64>() {
+                Ok(v) if v < max && !part.is_empty() && part.len() <= 2 => Ok(v),
+                _ => Err(invalid()),
+            }
+        };
+        let (h, m, sec) = (field(24)?, field(60)?, field(60)?);
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let frac = if fraction.is_empty() {
+            0
+        } else {
+            fraction.parse::<u64>().map_err(|_| invalid())? * 10u64.pow(9 - fraction.len() as u32)
+        };
+
+        (h * 3600 + m * 60 + sec) * NANOS_PER_SECOND + frac
+    } else {
+        s.parse::<u64>().map_err(|_| invalid())?
+    };
+
+    if ns >= NANOS_PER_DAY {
+        return Err(TimeError::OutOfRange(ns));
+    }
+    Ok(ns)
+}
+
+// A length of time in nanoseconds, as a number with an optional unit of
+// ns, us, ms, s, m or h. Plain numbers are nanoseconds.
+pub fn parse_duration(s: &str) -> Result<u64, TimeError> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+
+    let scale = match unit {
+        "" | "ns" => 1,
+        "us" => 1_000,
+        "ms" => 1_000_000,
+        "s" => NANOS_PER_SECOND,
+        "m" => 60 * NANOS_PER_SECOND,
+        "h" => 3600 * NANOS_PER_SECOND,
+        _ => return Err(TimeError::Invalid(s.to_string())),
+    };
+    value
+        .parse::<u64>()
+        .ok()
+        .and_then(|v| v.checked_mul(scale))
+        .ok_or_else(|| TimeError::Invalid(s.to_string()))
+}
+
+// HH:MM:SS.nnnnnnnnn
+pub fn format_time(ns: u64) -> String {
+    let secs = ns / NANOS_PER_SECOND;
+    format!(
+        "{:02}:{:02}:{:02}.{:09}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        ns % NANOS_PER_SECOND
+    )
+}
+
+// Applies a feed to a BookSet up to a point in time. Replay stops before the
+// first message stamped after that time, so it can be resumed to a later one.
+// Frames are read from any reader: a mapped raw file as a byte slice, or a
+// compressed one streamed from input::open.
+pub struct Replay<R> {
+    frames: FrameReader<R>,
+    translator: Translator,
+    // Read but not yet applied, because it is past the last stop time
+    pending: Option<Pending>,
+    time: u64,
+    applied: usize,
+    skipped: usize,
+}
+
+// ITCH 5.0 views borrow the frame reader's buffer, so a pending view keeps a
+// copy of its frame
+enum Pending {
+    Frame(Vec<u8>),
+    Decoded(Decoded<'static>),
+}
+
+impl Pending {
+    fn new(decoded: Decoded<'_>) -> Self {
+        match decoded {
+            Decoded::View(view) => Pending::Frame(view.as_bytes().to_vec()),
+            Decoded::Message(msg) => Pending::Decoded(Decoded::Message(msg)),
+            Decoded::Listing(info) => Pending::Decoded(Decoded::Listing(info)),
+        }
+    }
+
+    fn decoded(&self) -> Decoded<'_> {
+        match self {
+            // Already decoded once when it was read
+            Pending::Frame(frame) => Decoded::View(MessageView::new(frame).unwrap()),
+            Pending::Decoded(decoded) => decoded.clone(),
+        }
+    }
+}
+
+impl<R: Read> Replay<R> {
+    pub fn new(reader: R) -> Self {
+        Replay::with_protocol(reader, Protocol::Itch50)
+    }
+
+    pub fn with_protocol(reader: R, protocol: Protocol) -> Self {
+        Replay {
+            frames: FrameReader::new(reader),
+            translator: Translator::new(protocol),
+            pending: None,
+            time: 0,
+            applied: 0,
+            skipped: 0,
+        }
+    }
+
+    // Timestamp of the last applied message
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    // Messages of unknown types or with bad lengths
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    // Applies every remaining message stamped at or before `until`,
+    // returning how many were applied. Malformed messages are skipped, a
+    // truncated frame is an UnexpectedEof error
+    pub fn advance_to(&mut self, books: &mut BookSet, until: u64) -> io::Result<usize> {
+        let start = self.applied;
+        if let Some(pending) = self.pending.take() {
+            let decoded = pending.decoded();
+            if decoded.timestamp() > until {
+                self.pending = Some(pending);
+                return Ok(0);
+            }
+            decoded.apply(books);
+            self.time = decoded.timestamp();
+            self.applied += 1;
+        }
+
+        while let Some(payload) = self.frames.next_frame()? {
+            let decoded = match self.translator.translate(payload) {
+                Ok(Some(decoded)) => decoded,
+                Ok(None) => continue,
+                Err(_) => {
+                    self.skipped += 1;
+                    continue;
+                }
+            };
+            if decoded.timestamp() > until {
+                self.pending = Some(Pending::new(decoded));
+                break;
+            }
+
+            decoded.apply(books);
+            self.time = decoded.timestamp();
+            self.applied += 1;
+        }
+        Ok(self.applied - start)
+    }
+
+    pub fn run(&mut self, books: &mut BookSet) -> io::Result<usize> {
+        self.advance_to(books, u64::MAX)
+    }
+}
+
+// Top of a book at a point in time. Levels are (price, volume, orders), best
+// first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookSnapshot {
+    pub timestamp: u64,
+    pub stock_locate: u16,
+    pub bids: Vec<(u32, u32, usize)>,
+    pub asks: Vec<(u32, u32, usize)>,
+    pub state: TradingState,
+    pub stale: bool,
+}
+
+impl BookSnapshot {
+    pub fn new(book: &OrderBook, timestamp: u64, depth: usize) -> Self {
+        BookSnapshot {
+            timestamp,
+            stock_locate: book.config().stock_locate,
+            bids: book.levels(OrderSide::Buy).take(depth).collect(),
+            asks: book.levels(OrderSide::Sell).take(depth).collect(),
+            state: book.trading_state(),
+            stale: book.is_stale(),
+        }
+    }
+}
+
+// The book for a symbol as of a time, replaying the feed from the start.
+// None if the symbol hadn't been listed in the Stock Directory by then.
+pub fn book_at<R: Read>(
+    reader: R,
+    protocol: Protocol,
+    symbol: &str,
+    time: u64,
+) -> io::Result<Option<OrderBook>> {
+    let mut books = BookSet::new();
+    Replay::with_protocol(reader, protocol).advance_to(&mut books, time)?;
+
+    Ok(books.locate(symbol).and_then(|locate| books.remove(locate)))
+}
+
+// Snapshots of a symbol's book every `step` from `start` to `end` inclusive.
+// Times before the symbol is listed are skipped.
+pub fn snapshots<R: Read>(
+    reader: R,
+    protocol: Protocol,
+    symbol: &str,
+    start: u64,
+    end: u64,
+    step: u64,
+    depth: usize,
+) -> io::Result<Vec<BookSnapshot>> {
+    let mut books = BookSet::new();
+    let mut replay = Replay::with_protocol(reader, protocol);
+    let mut snapshots = Vec::new();
+
+    let mut time = start;
+    while time <= end {
+        replay.advance_to(&mut books, time)?;
+        if let Some(book) = books.get_by_symbol(symbol) {
+            snapshots.push(BookSnapshot::new(book, time, depth));
+        }
+
+        let Some(next) = time.checked_add(step.max(1)) else {
+            break;
+        };
+        time = next;
+    }
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::{
+        AddOrder, ArrayString8, Body, FinancialStatus, IssueClassification, IssueSubType,
+        LuldRefPriceTier, MarketCategory, Message, Side, StockDirectory,
+    };
+
+    use super::*;
+    use crate::itch::encode::ItchWriter;
+
+    const OPEN: u64 = 34_200 * NANOS_PER_SECOND;
+
+    fn stock() -> ArrayString8 {
+        ArrayString8::from("AAPL    ").unwrap()
+    }
+
+    fn msg(tag: u8, timestamp: u64, body: Body) -> Message {
+        Message {
+            tag,
+            stock_locate: 1,
+            tracking_number: 0,
+            timestamp,
+            body,
+        }
+    }
+
+    fn add(timestamp: u64, reference: u64, side: Side, shares: u32, price: u32) -> Message {
+        msg(
+            b'A',
+            timestamp,
+            Body::AddOrder(AddOrder {
+                reference,
+                side,
+                shares,
+                stock: stock(),
+                price: price.into(),
+                mpid: None,
+            }),
+        )
+    }
+
+    // AAPL listed at the open, then a message a second for four seconds:
+    // a bid, an ask, half the bid executed and the ask deleted
+    fn feed() -> Vec<u8> {
+        let listing = msg(
+            b'R',
+            OPEN,
+            Body::StockDirectory(StockDirectory {
+                stock: stock(),
+                market_category: MarketCategory::NasdaqGlobalSelect,
+                financial_status: FinancialStatus::Normal,
+                round_lot_size: 100,
+                round_lots_only: false,
+                issue_classification: IssueClassification::CommonStock,
+                issue_subtype: IssueSubType::NotApplicable,
+                authenticity: true,
+                short_sale_threshold: Some(false),
+                ipo_flag: None,
+                luld_ref_price_tier: LuldRefPriceTier::Tier1,
+                etp_flag: Some(false),
+                etp_leverage_factor: 0,
+                inverse_indicator: false,
+            }),
+        );
+        let second = |n: u64| OPEN + n * NANOS_PER_SECOND;
+        let messages = [
+            listing,
+            add(second(1), 1, Side::Buy, 100, 1_500_000),
+            add(second(2), 2, Side::Sell, 200, 1_500_100),
+            msg(
+                b'E',
+                second(3),
+                Body::OrderExecuted {
+                    reference: 1,
+                    executed: 50,
+                    match_number: 1,
+                },
+            ),
+            msg(b'D', second(4), Body::DeleteOrder { reference: 2 }),
+        ];
+
+        let mut w = ItchWriter::new(Vec::new());
+        for msg in &messages {
+            w.write(msg).unwrap();
+        }
+        w.into_inner()
+    }
+
+    type Levels = Vec<(u32, u32, usize)>;
+
+    fn top(book: &OrderBook) -> (Levels, Levels) {
+        (
+            book.levels(OrderSide::Buy).collect(),
+            book.levels(OrderSide::Sell).collect(),
+        )
+    }
+
+    #[test]
+    fn times_of_day() {
+        assert_eq!(parse_time("09:30:00"), Ok(OPEN));
+        assert_eq!(parse_time("09:30"), Ok(OPEN));
+        assert_eq!(parse_time("9:30"), Ok(OPEN));
+        assert_eq!(parse_time(" 09:30:00.5 "), Ok(OPEN + 500_000_000));
+        assert_eq!(parse_time("09:30:00.000000001"), Ok(OPEN + 1));
+        assert_eq!(parse_time("23:59:59.999999999"), Ok(NANOS_PER_DAY - 1));
+        assert_eq!(parse_time("34200000000000"), Ok(OPEN));
+        assert_eq!(parse_time("0"), Ok(0));
+
+        for bad in [
+            "",
+            "noon",
+            "24:00",
+            "09:60",
+            "09:30:60",
+            "09::00",
+            "009:30",
+            "09:30:00:00",
+            "09:30:00.1234567890",
+            "09:30:00.5s",
+            "09:30:00.-5",
+            "-1",
+            "1e9",
+        ] {
+            assert_eq!(
+                parse_time(bad),
+                Err(TimeError::Invalid(bad.to_string())),
+                "{bad:?}"
+            );
+        }
+        assert_eq!(
+            parse_time("86400000000000"),
+            Err(TimeError::OutOfRange(NANOS_PER_DAY))
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("42"), Ok(42));
+        assert_eq!(parse_duration("42ns"), Ok(42));
+        assert_eq!(parse_duration("10us"), Ok(10_000));
+        assert_eq!(parse_duration("500ms"), Ok(500_000_000));
+        assert_eq!(parse_duration("1s"), Ok(NANOS_PER_SECOND));
+        assert_eq!(parse_duration("5m"), Ok(300 * NANOS_PER_SECOND));
+        assert_eq!(parse_duration("2h"), Ok(7_200 * NANOS_PER_SECOND));
+
+        for bad in ["", "s", "1.5s", "1d", "-1s", "1 s", "9999999999h"] {
+            assert!(parse_duration(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn formatted_times_parse_back() {
+        assert_eq!(format_time(0), "00:00:00.000000000");
+        assert_eq!(format_time(OPEN + 1), "09:30:00.000000001");
+        assert_eq!(format_time(NANOS_PER_DAY - 1), "23:59:59.999999999");
+        for ns in [0, OPEN + 1, OPEN + 123_456_789, NANOS_PER_DAY - 1] {
+            assert_eq!(parse_time(&format_time(ns)), Ok(ns));
+        }
+    }
+
+    #[test]
+    fn replays_stop_at_a_time() {
+        let feed = feed();
+        let mut books = BookSet::new();
+        let mut replay = Replay::new(&feed[..]);
+
+        assert_eq!(replay.advance_to(&mut books, OPEN - 1).unwrap(), 0);
+        assert!(books.get_by_symbol("AAPL").is_none());
+
+        // Messages stamped at the stop time are applied
+        assert_eq!(
+            replay
+                .advance_to(&mut books, OPEN + NANOS_PER_SECOND)
+                .unwrap(),
+            2
+        );
+        assert_eq!(replay.time(), OPEN + NANOS_PER_SECOND);
+        let book = books.get_by_symbol("AAPL").unwrap();
+        assert_eq!(top(book), (vec![(1_500_000, 100, 1)], vec![]));
+
+        // Going back applies nothing, the next message is still pending
+        assert_eq!(replay.advance_to(&mut books, OPEN).unwrap(), 0);
+        assert_eq!(
+            replay
+                .advance_to(&mut books, OPEN + 2 * NANOS_PER_SECOND)
+                .unwrap(),
+            1
+        );
+        assert_eq!(replay.run(&mut books).unwrap(), 2);
+        assert_eq!(replay.applied(), 5);
+        assert_eq!(replay.time(), OPEN + 4 * NANOS_PER_SECOND);
+        let book = books.get_by_symbol("AAPL").unwrap();
+        assert_eq!(top(book), (vec![(1_500_000, 50, 1)], vec![]));
+    }
+
+    #[test]
+    fn replays_skip_bad_messages_and_stop_at_truncated_frames() {
+        let mut feed = feed();
+        // An unknown message type
+        feed.extend_from_slice(&[0, 3, b'?', 0, 0]);
+        let mut books = BookSet::new();
+        let mut replay = Replay::new(&feed[..]);
+        assert_eq!(replay.run(&mut books).unwrap(), 5);
+        assert_eq!(replay.skipped(), 1);
+
+        feed.extend_from_slice(&[0, 12, b'D']);
+        let mut replay = Replay::new(&feed[..]);
+        let error = replay.run(&mut BookSet::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(replay.applied(), 5);
+    }
+
+    #[test]
+    fn books_at_a_time() {
+        let feed = feed();
+        let aapl_at = |time| book_at(&feed[..], Protocol::Itch50, "AAPL", time).unwrap();
+
+        assert!(aapl_at(OPEN - 1).is_none());
+        assert_eq!(top(&aapl_at(OPEN).unwrap()), (vec![], vec![]));
+        assert_eq!(
+            top(&aapl_at(OPEN + 2 * NANOS_PER_SECOND).unwrap()),
+            (vec![(1_500_000, 100, 1)], vec![(1_500_100, 200, 1)])
+        );
+        assert_eq!(
+            top(&aapl_at(OPEN + 4 * NANOS_PER_SECOND - 1).unwrap()),
+            (vec![(1_500_000, 50, 1)], vec![(1_500_100, 200, 1)])
+        );
+        assert!(
+            book_at(&feed[..], Protocol::Itch50, "MSFT", u64::MAX)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn snapshot_ranges() {
+        let feed = feed();
+        let snapshots = |start, end, step, depth| {
+            snapshots(&feed[..], Protocol::Itch50, "AAPL", start, end, step, depth).unwrap()
+        };
+
+        // Times before the listing are skipped, both ends are included
+        let taken = snapshots(
+            OPEN - NANOS_PER_SECOND,
+            OPEN + 4 * NANOS_PER_SECOND,
+            NANOS_PER_SECOND,
+            5,
+        );
+        let times: Vec<_> = taken.iter().map(|s| s.timestamp).collect();
+        assert_eq!(
+            times,
+            (0..=4)
+                .map(|n| OPEN + n * NANOS_PER_SECOND)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(taken[0].stock_locate, 1);
+        assert!(taken[0].bids.is_empty() && taken[0].asks.is_empty());
+        assert_eq!(taken[2].bids, [(1_500_000, 100, 1)]);
+        assert_eq!(taken[2].asks, [(1_500_100, 200, 1)]);
+        assert_eq!(taken[4].bids, [(1_500_000, 50, 1)]);
+        assert!(taken[4].asks.is_empty());
+
+        // A step that doesn't land on the end stops short of it
+        let taken = snapshots(OPEN, OPEN + 4 * NANOS_PER_SECOND, 3 * NANOS_PER_SECOND, 0);
+        let times: Vec<_> = taken.iter().map(|s| s.timestamp).collect();
+        assert_eq!(times, [OPEN, OPEN + 3 * NANOS_PER_SECOND]);
+        assert!(taken[1].bids.is_empty());
+
+        assert_eq!(snapshots(OPEN + 1, OPEN + 1, NANOS_PER_SECOND, 5).len(), 1);
+        assert!(snapshots(OPEN + 1, OPEN, NANOS_PER_SECOND, 5).is_empty());
+        assert!(snapshots(0, OPEN - 1, NANOS_PER_SECOND, 5).is_empty());
+        // The last step can't overflow
+        assert_eq!(snapshots(u64::MAX - 1, u64::MAX, 10, 5).len(), 1);
+    }
+}
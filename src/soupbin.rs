@@ -0,0 +1,962 @@
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::itch::decode::{FrameReader, MessageView};
+use crate::moldudp::Session;
+
+// Packet types. SoupBinTCP 3.0 packets are a big-endian u16 length, counting
+// the type byte, followed by the type and payload
+pub const DEBUG: u8 = b'+';
+pub const LOGIN_ACCEPTED: u8 = b'A';
+pub const LOGIN_REJECTED: u8 = b'J';
+pub const SEQUENCED_DATA: u8 = b'S';
+pub const UNSEQUENCED_DATA: u8 = b'U';
+pub const SERVER_HEARTBEAT: u8 = b'H';
+pub const END_OF_SESSION: u8 = b'Z';
+pub const LOGIN_REQUEST: u8 = b'L';
+pub const CLIENT_HEARTBEAT: u8 = b'R';
+pub const LOGOUT_REQUEST: u8 = b'O';
+
+// Login Rejected reason codes
+pub const REJECT_NOT_AUTHORIZED: u8 = b'A';
+pub const REJECT_SESSION_UNAVAILABLE: u8 = b'S';
+
+const LOGIN_REQUEST_LEN: usize = 46;
+const LOGIN_ACCEPTED_LEN: usize = 30;
+
+#[derive(Debug)]
+pub enum SoupError {
+    Io(io::Error),
+    LoginRejected(u8),
+    UnknownPacket(u8),
+    // A packet whose payload doesn't fit its type
+    BadPacket { kind: u8, len: usize },
+    // Nothing heard from the other side within the timeout
+    Timeout,
+}
+
+impl fmt::Display for SoupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SoupError::Io(e) => write!(f, "SoupBinTCP I/O error: {e}"),
+            SoupError::LoginRejected(reason) => {
+                write!(f, "SoupBinTCP login rejected ({})", *reason as char)
+            }
+            SoupError::UnknownPacket(kind) => {
+                write!(f, "unknown SoupBinTCP packet type {:?}", *kind as char)
+            }
+            SoupError::BadPacket { kind, len } => write!(
+                f,
+                "SoupBinTCP packet {:?} with bad length {len}",
+                *kind as char
+            ),
+            SoupError::Timeout => write!(f, "SoupBinTCP peer timed out"),
+        }
+    }
+}
+
+impl std::error::Error for SoupError {}
+
+impl From<io::Error> for SoupError {
+    fn from(e: io::Error) -> Self {
+        SoupError::Io(e)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum SoupPacket<'a> {
+    Debug(&'a [u8]),
+    LoginAccepted {
+        session: Session,
+        sequence: u64,
+    },
+    LoginRejected(u8),
+    SequencedData(&'a [u8]),
+    UnsequencedData(&'a [u8]),
+    ServerHeartbeat,
+    EndOfSession,
+    LoginRequest {
+        username: &'a str,
+        password: &'a str,
+        // Blank for the current session
+        session: Session,
+        // 0 for the most recent message
+        sequence: u64,
+    },
+    ClientHeartbeat,
+    LogoutRequest,
+}
+
+fn alpha(b: &[u8]) -> &str {
+    std::str::from_utf8(b).unwrap_or_default().trim()
+}
+
+// Numeric fields are ASCII, padded with spaces
+fn numeric(b: &[u8]) -> Option<u64> {
+    let s = alpha(b);
+    if s.is_empty() {
+        Some(0)
+    } else {
+        s.parse().ok()
+    }
+}
+
+impl<'a> SoupPacket<'a> {
+    // Parses the first packet in buf. Returns the packet and the number of
+    // bytes it used, or None if buf doesn't hold a whole packet yet
+    pub fn parse(buf: &'a [u8]) -> Result<Option<(Self, usize)>, SoupError> {
+        let [hi, lo, ..] = *buf else {
+            return Ok(None);
+        };
+        let len = u16::from_be_bytes([hi, lo]) as usize;
+        if buf.len() < 2 + len {
+            return Ok(None);
+        }
+        let Some((&kind, payload)) = buf[2..2 + len].split_first() else {
+            return Err(SoupError::BadPacket { kind: 0, len });
+        };
+
+        let expect = |expected: usize| {
+            if payload.len() == expected {
+                Ok(())
+            } else {
+                Err(SoupError::BadPacket { kind, len })
+            }
+        };
+
+        let packet = match kind {
+            DEBUG => SoupPacket::Debug(payload),
+            LOGIN_ACCEPTED => {
+                expect(LOGIN_ACCEPTED_LEN)?;
+                SoupPacket::LoginAccepted {
+                    session: Session::new(alpha(&payload[0..10])),
+                    sequence: numeric(&payload[10..30])
+                        .ok_or(SoupError::BadPacket { kind, len })?,
+                }
+            }
+            LOGIN_REJECTED => {
+                expect(1)?;
+                SoupPacket::LoginRejected(payload[0])
+            }
+            SEQUENCED_DATA => SoupPacket::SequencedData(payload),
+            UNSEQUENCED_DATA => SoupPacket::UnsequencedData(payload),
+            SERVER_HEARTBEAT => SoupPacket::ServerHeartbeat,
+            END_OF_SESSION => SoupPacket::EndOfSession,
+            LOGIN_REQUEST => {
+                expect(LOGIN_REQUEST_LEN)?;
+                SoupPacket::LoginRequest {
+                    username: alpha(&payload[0..6]),
+                    password: alpha(&payload[6..16]),
+                    session: Session::new(alpha(&payload[16..26])),
+                    sequence: numeric(&payload[26..46])
+                        .ok_or(SoupError::BadPacket { kind, len })?,
+                }
+            }
+            CLIENT_HEARTBEAT => SoupPacket::ClientHeartbeat,
+            LOGOUT_REQUEST => SoupPacket::LogoutRequest,
+            _ => return Err(SoupError::UnknownPacket(kind)),
+        };
+        Ok(Some((packet, 2 + len)))
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        fn pad_right(out: &mut Vec<u8>, s: &[u8], width: usize) {
+            let s = &s[..s.len().min(width)];
+            out.extend_from_slice(s);
+            out.resize(out.len() + width - s.len(), b' ');
+        }
+        fn pad_left(out: &mut Vec<u8>, n: u64, width: usize) {
+            out.extend_from_slice(format!("{n:>width$}").as_bytes());
+        }
+
+        let start = out.len();
+        out.extend_from_slice(&[0, 0]);
+        match *self {
+            SoupPacket::Debug(text) => {
+                out.push(DEBUG);
+                out.extend_from_slice(text);
+            }
+            SoupPacket::LoginAccepted { session, sequence } => {
+                out.push(LOGIN_ACCEPTED);
+                out.extend_from_slice(session.as_bytes());
+                pad_left(out, sequence, 20);
+            }
+            SoupPacket::LoginRejected(reason) => out.extend_from_slice(&[LOGIN_REJECTED, reason]),
+            SoupPacket::SequencedData(data) => {
+                out.push(SEQUENCED_DATA);
+                out.extend_from_slice(data);
+            }
+            SoupPacket::UnsequencedData(data) => {
+                out.push(UNSEQUENCED_DATA);
+                out.extend_from_slice(data);
+            }
+            SoupPacket::ServerHeartbeat => out.push(SERVER_HEARTBEAT),
+            SoupPacket::EndOfSession => out.push(END_OF_SESSION),
+            SoupPacket::LoginRequest {
+                username,
+                password,
+                session,
+                sequence,
+            } => {
+                out.push(LOGIN_REQUEST);
+                pad_right(out, username.as_bytes(), 6);
+                pad_right(out, password.as_bytes(), 10);
+                out.extend_from_slice(session.as_bytes());
+                pad_left(out, sequence, 20);
+            }
+            SoupPacket::ClientHeartbeat => out.push(CLIENT_HEARTBEAT),
+            SoupPacket::LogoutRequest => out.push(LOGOUT_REQUEST),
+        }
+        let len = (out.len() - start - 2) as u16;
+        out[start..start + 2].copy_from_slice(&len.to_be_bytes());
+    }
+}
+
+fn send(stream: &mut TcpStream, packet: SoupPacket) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(64);
+    packet.encode(&mut buf);
+    stream.write_all(&buf)
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub addr: String,
+    pub username: String,
+    pub password: String,
+    // None to join the current session
+    pub session: Option<Session>,
+    pub first_sequence: u64,
+    pub heartbeat_interval: Duration,
+    // The server is considered gone after this long without a packet
+    pub timeout: Duration,
+    pub max_reconnects: usize,
+    pub reconnect_delay: Duration,
+}
+
+impl ClientConfig {
+    pub fn new(addr: &str) -> Self {
+        ClientConfig {
+            addr: addr.to_string(),
+            username: String::new(),
+            password: String::new(),
+            session: None,
+            first_sequence: 1,
+            heartbeat_interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(15),
+            max_reconnects: 5,
+            reconnect_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Copy, Clone)]
+pub struct ClientStats {
+    pub messages: u64,
+    pub heartbeats: u64,
+    pub reconnects: u64,
+    // Sequenced messages that didn't decode as ITCH
+    pub malformed: u64,
+}
+
+// Blocking SoupBinTCP client. After a disconnect it logs back in to the same
+// session, asking for the message after the last one it received.
+#[derive(Debug)]
+pub struct SoupClient {
+    config: ClientConfig,
+    stream: Option<TcpStream>,
+    session: Option<Session>,
+    next_sequence: u64,
+    buf: Vec<u8>,
+    last_sent: Instant,
+    last_received: Instant,
+    ended: bool,
+    stats: ClientStats,
+}
+
+impl SoupClient {
+    pub fn new(config: ClientConfig) -> Self {
+        SoupClient {
+            session: config.session,
+            next_sequence: config.first_sequence,
+            config,
+            stream: None,
+            buf: Vec::with_capacity(1 << 16),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            ended: false,
+            stats: ClientStats::default(),
+        }
+    }
+
+    pub fn session(&self) -> Option<Session> {
+        self.session
+    }
+
+    // Sequence number of the next message expected from the server
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    pub fn stats(&self) -> &ClientStats {
+        &self.stats
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    pub fn login(&mut self) -> Result<(), SoupError> {
+        let addr = self
+            .config
+            .addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no address"))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.config.timeout)?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(self.config.heartbeat_interval))?;
+
+        send(
+            &mut stream,
+            SoupPacket::LoginRequest {
+                username: &self.config.username,
+                password: &self.config.password,
+                session: self.session.unwrap_or(Session::new("")),
+                sequence: self.next_sequence,
+            },
+        )?;
+        self.buf.clear();
+        self.last_sent = Instant::now();
+        self.last_received = Instant::now();
+        self.stream = Some(stream);
+
+        loop {
+            self.fill()?;
+            while let Some((packet, used)) = SoupPacket::parse(&self.buf)? {
+                let packet = match packet {
+                    SoupPacket::LoginAccepted { session, sequence } => Ok((session, sequence)),
+                    SoupPacket::LoginRejected(reason) => Err(reason),
+                    _ => {
+                        self.buf.drain(..used);
+                        continue;
+                    }
+                };
+                self.buf.drain(..used);
+
+                return match packet {
+                    Ok((session, sequence)) => {
+                        self.session = Some(session);
+                        self.next_sequence = sequence;
+                        Ok(())
+                    }
+                    Err(reason) => {
+                        self.stream = None;
+                        Err(SoupError::LoginRejected(reason))
+                    }
+                };
+            }
+        }
+    }
+
+    pub fn logout(&mut self) -> Result<(), SoupError> {
+        if let Some(mut stream) = self.stream.take() {
+            send(&mut stream, SoupPacket::LogoutRequest)?;
+        }
+        Ok(())
+    }
+
+    // Receives messages until the server ends the session, passing each to
+    // on_message with its sequence number. Connection failures are retried up
+    // to max_reconnects times in a row, where a connection that delivered new
+    // messages breaks the run.
+    pub fn run<F>(&mut self, mut on_message: F) -> Result<(), SoupError>
+    where
+        F: FnMut(u64, MessageView<'_>),
+    {
+        let mut failures = 0;
+        let mut progress = self.next_sequence;
+        while !self.ended {
+            let result = match self.stream {
+                Some(_) => self.poll(&mut on_message),
+                None => self.login(),
+            };
+            match result {
+                // Logging in alone isn't progress, or a server that accepts
+                // logins and drops the connection would be retried forever
+                Ok(()) if self.next_sequence != progress => {
+                    failures = 0;
+                    progress = self.next_sequence;
+                }
+                Ok(()) => {}
+                Err(SoupError::Io(_) | SoupError::Timeout)
+                    if failures < self.config.max_reconnects =>
+                {
+                    failures += 1;
+                    self.stream = None;
+                    self.stats.reconnects += 1;
+                    thread::sleep(self.config.reconnect_delay);
+                }
+                Err(e) => {
+                    self.stream = None;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Reads whatever has arrived and handles the complete packets in it
+    fn poll<F>(&mut self, on_message: &mut F) -> Result<(), SoupError>
+    where
+        F: FnMut(u64, MessageView<'_>),
+    {
+        // Packets that arrived before a disconnect are handled before it's
+        // reported, or they'd be lost with the connection
+        let filled = self.fill();
+
+        let mut pos = 0;
+        while let Some((packet, used)) = SoupPacket::parse(&self.buf[pos..])? {
+            pos += used;
+            match packet {
+                SoupPacket::SequencedData(data) => {
+                    match MessageView::new(data) {
+                        Ok(view) => on_message(self.next_sequence, view),
+                        Err(_) => self.stats.malformed += 1,
+                    }
+                    self.next_sequence += 1;
+                    self.stats.messages += 1;
+                }
+                SoupPacket::ServerHeartbeat => self.stats.heartbeats += 1,
+                SoupPacket::EndOfSession => {
+                    self.ended = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        self.buf.drain(..pos);
+
+        if self.ended {
+            self.stream = None;
+            return Ok(());
+        }
+        filled
+    }
+
+    // Reads into buf, sending a heartbeat if one is due
+    fn fill(&mut self) -> Result<(), SoupError> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Err(io::Error::from(ErrorKind::NotConnected).into());
+        };
+
+        if self.last_sent.elapsed() >= self.config.heartbeat_interval {
+            send(stream, SoupPacket::ClientHeartbeat)?;
+            self.last_sent = Instant::now();
+        }
+
+        let start = self.buf.len();
+        self.buf.resize(start + (1 << 16), 0);
+        let read = stream.read(&mut self.buf[start..]);
+        self.buf.truncate(start + *read.as_ref().unwrap_or(&0));
+
+        match read {
+            Ok(0) => Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(_) => {
+                self.last_received = Instant::now();
+                Ok(())
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                if self.last_received.elapsed() >= self.config.timeout {
+                    Err(SoupError::Timeout)
+                } else {
+                    Ok(())
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub session: Session,
+    // Empty to accept any login
+    pub username: String,
+    pub password: String,
+    pub heartbeat_interval: Duration,
+    // Close the connection after sending this many messages, to exercise
+    // client reconnects
+    pub disconnect_after: Option<u64>,
+}
+
+// Minimal SoupBinTCP server for one client, replaying the length-prefixed
+// ITCH frames read from itch as sequenced data followed by End of Session.
+// There is no live position in a replay, so a request for sequence 0 starts
+// at 1.
+pub fn serve<R: Read>(
+    mut stream: TcpStream,
+    itch: R,
+    config: &ServerConfig,
+) -> Result<(), SoupError> {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+
+    let mut buf = Vec::with_capacity(256);
+    let (session, sequence) = loop {
+        let mut chunk = [0u8; 256];
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if let Some((packet, _)) = SoupPacket::parse(&buf)? {
+            let SoupPacket::LoginRequest {
+                username,
+                password,
+                session,
+                sequence,
+            } = packet
+            else {
+                return Err(SoupError::UnknownPacket(buf[2]));
+            };
+            if !config.username.is_empty()
+                && (username != config.username || password != config.password)
+            {
+                send(
+                    &mut stream,
+                    SoupPacket::LoginRejected(REJECT_NOT_AUTHORIZED),
+                )?;
+                return Ok(());
+            }
+            break (session, sequence.max(1));
+        }
+    };
+
+    if session != Session::new("") && session != config.session {
+        send(
+            &mut stream,
+            SoupPacket::LoginRejected(REJECT_SESSION_UNAVAILABLE),
+        )?;
+        return Ok(());
+    }
+    send(
+        &mut stream,
+        SoupPacket::LoginAccepted {
+            session: config.session,
+            sequence,
+        },
+    )?;
+
+    let mut out = Vec::with_capacity(1 << 16);
+    let mut frames = FrameReader::new(itch);
+    let mut next = 1;
+    let mut sent = 0;
+    loop {
+        // Messages are sent as they are, malformed ones included, so every
+        // frame keeps its sequence number. A truncated frame ends the replay
+        // without an End of Session
+        let data = match frames.next_frame() {
+            Ok(Some(data)) => data,
+            Ok(None) => break,
+            Err(e) => {
+                stream.write_all(&out)?;
+                let _ = close(&mut stream);
+                return Err(e.into());
+            }
+        };
+        next += 1;
+        if next <= sequence {
+            continue;
+        }
+        SoupPacket::SequencedData(data).encode(&mut out);
+        sent += 1;
+
+        if config.disconnect_after == Some(sent) {
+            stream.write_all(&out)?;
+            return close(&mut stream);
+        }
+        if out.len() >= 1 << 15 {
+            stream.write_all(&out)?;
+            out.clear();
+        }
+    }
+    SoupPacket::EndOfSession.encode(&mut out);
+    stream.write_all(&out)?;
+
+    // Keep the connection up until the client logs out or disconnects
+    stream.set_read_timeout(Some(config.heartbeat_interval))?;
+    buf.clear();
+    loop {
+        let mut chunk = [0u8; 64];
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                send(&mut stream, SoupPacket::ServerHeartbeat)?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        while let Some((packet, used)) = SoupPacket::parse(&buf)? {
+            if packet == SoupPacket::LogoutRequest {
+                return Ok(());
+            }
+            buf.drain(..used);
+        }
+    }
+}
+
+// Closes the connection without losing what was sent. Closing a socket with
+// unread client heartbeats resets it, which can drop data still in flight
+fn close(stream: &mut TcpStream) -> Result<(), SoupError> {
+    stream.shutdown(Shutdown::Write)?;
+    let mut chunk = [0u8; 64];
+    loop {
+        match stream.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Err(SoupError::Timeout);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn encoded(packet: SoupPacket) -> Vec<u8> {
+        let mut buf = Vec::new();
+        packet.encode(&mut buf);
+        buf
+    }
+
+    // Order Delete for the reference, which applies to nothing
+    fn message(reference: u64) -> Vec<u8> {
+        let mut msg = vec![b'D', 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        msg.extend_from_slice(&reference.to_be_bytes());
+        msg
+    }
+
+    fn itch(messages: &[Vec<u8>]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for msg in messages {
+            buf.extend_from_slice(&(msg.len() as u16).to_be_bytes());
+            buf.extend_from_slice(msg);
+        }
+        buf
+    }
+
+    fn server_config() -> ServerConfig {
+        ServerConfig {
+            session: Session::new("SESSION1"),
+            username: String::new(),
+            password: String::new(),
+            heartbeat_interval: Duration::from_millis(20),
+            disconnect_after: None,
+        }
+    }
+
+    // Serves every connection until the client gets its End of Session
+    fn server(itch: Vec<u8>, config: ServerConfig) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let _ = serve(stream.unwrap(), &itch[..], &config);
+            }
+        });
+        addr
+    }
+
+    fn client_config(addr: &str) -> ClientConfig {
+        let mut config = ClientConfig::new(addr);
+        config.heartbeat_interval = Duration::from_millis(20);
+        config.timeout = Duration::from_secs(5);
+        config.reconnect_delay = Duration::from_millis(10);
+        config
+    }
+
+    // Reference of each message received, by sequence number
+    fn receive(client: &mut SoupClient) -> Result<Vec<(u64, u64)>, SoupError> {
+        let mut received = Vec::new();
+        client.run(|sequence, view| {
+            let reference = u64::from_be_bytes(view.as_bytes()[11..19].try_into().unwrap());
+            received.push((sequence, reference));
+        })?;
+        Ok(received)
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let data = message(7);
+        let packets = [
+            SoupPacket::Debug(b"hello"),
+            SoupPacket::LoginAccepted {
+                session: Session::new("SESSION1"),
+                sequence: 1_234,
+            },
+            SoupPacket::LoginRejected(REJECT_NOT_AUTHORIZED),
+            SoupPacket::SequencedData(&data),
+            SoupPacket::UnsequencedData(b"x"),
+            SoupPacket::ServerHeartbeat,
+            SoupPacket::EndOfSession,
+            SoupPacket::LoginRequest {
+                username: "user",
+                password: "secret",
+                session: Session::new(""),
+                sequence: 0,
+            },
+            SoupPacket::ClientHeartbeat,
+            SoupPacket::LogoutRequest,
+        ];
+        for packet in packets {
+            let buf = encoded(packet);
+            assert_eq!(SoupPacket::parse(&buf).unwrap(), Some((packet, buf.len())));
+            // Nothing comes out until the whole packet is there
+            assert_eq!(SoupPacket::parse(&buf[..buf.len() - 1]).unwrap(), None);
+        }
+
+        let login = encoded(SoupPacket::LoginAccepted {
+            session: Session::new("SESSION1"),
+            sequence: 42,
+        });
+        assert_eq!(&login[..3], [0, 31, LOGIN_ACCEPTED]);
+        assert_eq!(&login[3..13], b"SESSION1  ");
+        assert_eq!(&login[13..], format!("{:>20}", 42).as_bytes());
+    }
+
+    #[test]
+    fn bad_packets() {
+        assert!(matches!(
+            SoupPacket::parse(&[0, 3, b'J', b'A', b'B']),
+            Err(SoupError::BadPacket {
+                kind: LOGIN_REJECTED,
+                len: 3
+            })
+        ));
+        assert!(matches!(
+            SoupPacket::parse(&[0, 1, b'?']),
+            Err(SoupError::UnknownPacket(b'?'))
+        ));
+        assert!(matches!(
+            SoupPacket::parse(&[0, 0]),
+            Err(SoupError::BadPacket { kind: 0, len: 0 })
+        ));
+
+        let mut login = encoded(SoupPacket::LoginAccepted {
+            session: Session::new("S"),
+            sequence: 1,
+        });
+        login[20] = b'x';
+        assert!(matches!(
+            SoupPacket::parse(&login),
+            Err(SoupError::BadPacket {
+                kind: LOGIN_ACCEPTED,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn replays_a_session() {
+        let messages: Vec<_> = (1..=5).map(message).collect();
+        let addr = server(itch(&messages), server_config());
+
+        let mut client = SoupClient::new(client_config(&addr));
+        let received = receive(&mut client).unwrap();
+        assert_eq!(received, (1..=5).map(|s| (s, s)).collect::<Vec<_>>());
+        assert!(client.is_ended());
+        assert_eq!(client.session(), Some(Session::new("SESSION1")));
+        assert_eq!(client.next_sequence(), 6);
+        assert_eq!(client.stats().messages, 5);
+    }
+
+    #[test]
+    fn logins_start_at_the_requested_sequence() {
+        let messages: Vec<_> = (1..=5).map(message).collect();
+        let addr = server(itch(&messages), server_config());
+
+        let mut config = client_config(&addr);
+        config.first_sequence = 4;
+        config.session = Some(Session::new("SESSION1"));
+        let mut client = SoupClient::new(config);
+        assert_eq!(receive(&mut client).unwrap(), [(4, 4), (5, 5)]);
+
+        let mut config = client_config(&addr);
+        config.session = Some(Session::new("OTHER"));
+        let mut client = SoupClient::new(config);
+        assert!(matches!(
+            client.login(),
+            Err(SoupError::LoginRejected(REJECT_SESSION_UNAVAILABLE))
+        ));
+    }
+
+    #[test]
+    fn logins_need_the_credentials() {
+        let mut config = server_config();
+        config.username = "user".to_string();
+        config.password = "secret".to_string();
+        let addr = server(itch(&[message(1)]), config);
+
+        let mut client = SoupClient::new(client_config(&addr));
+        assert!(matches!(
+            client.login(),
+            Err(SoupError::LoginRejected(REJECT_NOT_AUTHORIZED))
+        ));
+
+        let mut config = client_config(&addr);
+        config.username = "user".to_string();
+        config.password = "secret".to_string();
+        let mut client = SoupClient::new(config);
+        assert_eq!(receive(&mut client).unwrap(), [(1, 1)]);
+    }
+
+    #[test]
+    fn reconnects_resume_after_the_last_message() {
+        let messages: Vec<_> = (1..=5).map(message).collect();
+        let mut config = server_config();
+        config.disconnect_after = Some(2);
+        let addr = server(itch(&messages), config);
+
+        let mut client = SoupClient::new(client_config(&addr));
+        let received = receive(&mut client).unwrap();
+        assert_eq!(received, (1..=5).map(|s| (s, s)).collect::<Vec<_>>());
+        assert_eq!(client.stats().reconnects, 2);
+        assert_eq!(client.next_sequence(), 6);
+    }
+
+    #[test]
+    fn reconnects_without_new_messages_run_out() {
+        let mut data = itch(&[message(1), message(2)]);
+        data.truncate(data.len() - 3);
+        let addr = server(data, server_config());
+
+        let mut config = client_config(&addr);
+        config.max_reconnects = 2;
+        let mut client = SoupClient::new(config);
+        let mut received = Vec::new();
+        let result = client.run(|sequence, _| received.push(sequence));
+        assert!(matches!(result, Err(SoupError::Io(_))));
+        assert_eq!(received, [1]);
+        // The first connection delivered message 1, the two retries nothing
+        assert_eq!(client.stats().reconnects, 3);
+    }
+
+    #[test]
+    fn malformed_messages_keep_their_sequence_number() {
+        let mut bad = message(2);
+        bad[0] = b'?';
+        let addr = server(itch(&[message(1), bad, message(3)]), server_config());
+
+        let mut client = SoupClient::new(client_config(&addr));
+        assert_eq!(receive(&mut client).unwrap(), [(1, 1), (3, 3)]);
+        assert_eq!(client.stats().malformed, 1);
+        assert_eq!(client.next_sequence(), 4);
+    }
+
+    #[test]
+    fn truncated_input_ends_without_end_of_session() {
+        let mut data = itch(&[message(1), message(2)]);
+        data.truncate(data.len() - 3);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &data[..], &server_config())
+        });
+
+        let mut config = client_config(&addr);
+        config.max_reconnects = 0;
+        let mut client = SoupClient::new(config);
+        let mut received = Vec::new();
+        let result = client.run(|sequence, _| received.push(sequence));
+        assert!(matches!(result, Err(SoupError::Io(_))));
+        assert_eq!(received, [1]);
+        assert!(!client.is_ended());
+        assert!(
+            matches!(server.join().unwrap(), Err(SoupError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn heartbeats_both_ways() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream, &itch(&[message(1)])[..], &server_config())
+        });
+
+        // Log in by hand to see what the server sends after End of Session
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        send(
+            &mut stream,
+            SoupPacket::LoginRequest {
+                username: "",
+                password: "",
+                session: Session::new(""),
+                sequence: 1,
+            },
+        )
+        .unwrap();
+
+        let mut buf = Vec::new();
+        let mut kinds = Vec::new();
+        while !kinds.ends_with(&[SERVER_HEARTBEAT]) {
+            let mut chunk = [0u8; 256];
+            let n = stream.read(&mut chunk).unwrap();
+            assert_ne!(n, 0);
+            buf.extend_from_slice(&chunk[..n]);
+            while let Some((packet, used)) = SoupPacket::parse(&buf).unwrap() {
+                kinds.push(encoded(packet)[2]);
+                buf.drain(..used);
+            }
+        }
+        assert_eq!(kinds[..3], [LOGIN_ACCEPTED, SEQUENCED_DATA, END_OF_SESSION]);
+
+        send(&mut stream, SoupPacket::LogoutRequest).unwrap();
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn clients_count_server_heartbeats() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut login = [0u8; 2 + 1 + LOGIN_REQUEST_LEN];
+            stream.read_exact(&mut login).unwrap();
+            let data = message(1);
+            let mut out = Vec::new();
+            for packet in [
+                SoupPacket::LoginAccepted {
+                    session: Session::new("S"),
+                    sequence: 1,
+                },
+                SoupPacket::ServerHeartbeat,
+                SoupPacket::SequencedData(&data),
+                SoupPacket::ServerHeartbeat,
+                SoupPacket::EndOfSession,
+            ] {
+                packet.encode(&mut out);
+            }
+            stream.write_all(&out).unwrap();
+            close(&mut stream).unwrap();
+        });
+
+        let mut client = SoupClient::new(client_config(&addr));
+        assert_eq!(receive(&mut client).unwrap(), [(1, 1)]);
+        assert_eq!(client.stats().heartbeats, 2);
+        server.join().unwrap();
+    }
+}
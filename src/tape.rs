@@ -0,0 +1,374 @@
+use itchy::{ArrayString8, Body, CrossType, Message};
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::itch::ItchHandler;
+use crate::orderbook::{OrderBook, OrderSide};
+
+#[derive(Debug, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum TradeType {
+    // Order Executed (E), at the resting order's price
+    Execution,
+    // Order Executed With Price (C)
+    ExecutionWithPrice,
+    // Trade against a non-displayed order (P)
+    NonCross,
+    // Opening, closing, IPO/halt or intraday cross (Q)
+    Cross(CrossType),
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Trade {
+    pub timestamp: u64,
+    pub stock_locate: u16,
+    pub symbol: ArrayString8,
+    // Raw ITCH price, 4 implied decimals
+    pub price: u32,
+    pub size: u64,
+    // Side of the incoming order. Unknown for crosses and non-cross trades
+    pub aggressor: Option<OrderSide>,
+    pub match_number: u64,
+    pub trade_type: TradeType,
+    // Set when a Broken Trade (B) message voids the trade
+    pub broken: bool,
+}
+
+// Normalized trade prints built from E, C, P and Q messages, with B messages
+// marking earlier trades as broken. Use it as the handler for apply_with so
+// executions are looked up before the resting order leaves the book.
+#[derive(Debug, Default)]
+pub struct TradeTape {
+    trades: Vec<Trade>,
+    // Match number -> index into trades
+    matches: FxHashMap<u64, usize>,
+}
+
+impl TradeTape {
+    pub fn new() -> Self {
+        TradeTape::default()
+    }
+
+    pub fn trades(&self) -> &[Trade] {
+        &self.trades
+    }
+
+    // Trades that haven't been broken
+    pub fn valid_trades(&self) -> impl Iterator<Item = &Trade> {
+        self.trades.iter().filter(|t| !t.broken)
+    }
+
+    pub fn len(&self) -> usize {
+        self.trades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+
+    pub fn get(&self, match_number: u64) -> Option<&Trade> {
+        self.trades.get(*self.matches.get(&match_number)?)
+    }
+
+    pub fn volume(&self, stock_locate: u16) -> u64 {
+        self.valid_trades()
+            .filter(|t| t.stock_locate == stock_locate)
+            .map(|t| t.size)
+            .sum()
+    }
+
+    // Marks the trade as broken. Returns false if the match number is unknown
+    pub fn break_trade(&mut self, match_number: u64) -> bool {
+        let Some(&idx) = self.matches.get(&match_number) else {
+            return false;
+        };
+        self.trades[idx].broken = true;
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.trades.clear();
+        self.matches.clear();
+    }
+
+    // Records the trade for a message, if it is one. Executions are priced
+    // from the resting order, so the book must not have applied msg yet.
+    pub fn record(&mut self, book: &OrderBook, msg: &Message) {
+        let symbol = || trimmed(book.symbol());
+
+        let trade = match &msg.body {
+            Body::OrderExecuted {
+                reference,
+                executed,
+                match_number,
+            } => {
+                let Some((price, _, side)) = book.order(*reference) else {
+                    return;
+                };
+                Trade {
+                    timestamp: msg.timestamp,
+                    stock_locate: msg.stock_locate,
+                    symbol: symbol(),
+                    price,
+                    size: *executed as u64,
+                    aggressor: Some(side.opposite()),
+                    match_number: *match_number,
+                    trade_type: TradeType::Execution,
+                    broken: false,
+                }
+            }
+            Body::OrderExecutedWithPrice {
+                reference,
+                executed,
+                match_number,
+                printable,
+                price,
+            } => {
+                // Non-printable executions are reported in a later cross or trade
+                if !printable {
+                    return;
+                }
+                let aggressor = book.order(*reference).map(|(_, _, side)| side.opposite());
+                Trade {
+                    timestamp: msg.timestamp,
+                    stock_locate: msg.stock_locate,
+                    symbol: symbol(),
+                    price: price.raw(),
+                    size: *executed as u64,
+                    aggressor,
+                    match_number: *match_number,
+                    trade_type: TradeType::ExecutionWithPrice,
+                    broken: false,
+                }
+            }
+            Body::NonCrossTrade(trade) => {
+                // The Buy/Sell indicator has been "B" on every P message since
+                // 2014, so it says nothing about either side
+                Trade {
+                    timestamp: msg.timestamp,
+                    stock_locate: msg.stock_locate,
+                    symbol: trimmed(&trade.stock),
+                    price: trade.price.raw(),
+                    size: trade.shares as u64,
+                    aggressor: None,
+                    match_number: trade.match_number,
+                    trade_type: TradeType::NonCross,
+                    broken: false,
+                }
+            }
+            Body::CrossTrade(cross) => {
+                // Sent with zero shares when the cross didn't happen
+                if cross.shares == 0 {
+                    return;
+                }
+                Trade {
+                    timestamp: msg.timestamp,
+                    stock_locate: msg.stock_locate,
+                    symbol: trimmed(&cross.stock),
+                    price: cross.cross_price.raw(),
+                    size: cross.shares,
+                    aggressor: None,
+                    match_number: cross.match_number,
+                    trade_type: TradeType::Cross(cross.cross_type),
+                    broken: false,
+                }
+            }
+            Body::BrokenTrade { match_number } => {
+                self.break_trade(*match_number);
+                return;
+            }
+            _ => return,
+        };
+
+        self.matches.insert(trade.match_number, self.trades.len());
+        self.trades.push(trade);
+    }
+}
+
+fn trimmed(symbol: &str) -> ArrayString8 {
+    ArrayString8::from(symbol.trim_end()).unwrap_or_default()
+}
+
+impl ItchHandler for TradeTape {
+    fn before_apply(&mut self, book: &OrderBook, msg: &Message) -> bool {
+        self.record(book, msg);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::{AddOrder, CrossTrade, NonCrossTrade, Side};
+
+    use super::*;
+    use crate::itch;
+
+    fn msg(tag: u8, timestamp: u64, body: Body) -> Message {
+        Message {
+            tag,
+            stock_locate: 1,
+            tracking_number: 0,
+            timestamp,
+            body,
+        }
+    }
+
+    fn add(reference: u64, side: Side, shares: u32, price: u32) -> Message {
+        msg(
+            b'A',
+            0,
+            Body::AddOrder(AddOrder {
+                reference,
+                side,
+                shares,
+                stock: ArrayString8::from("TEST    ").unwrap(),
+                price: price.into(),
+                mpid: None,
+            }),
+        )
+    }
+
+    fn session() -> (OrderBook, TradeTape) {
+        let mut book = OrderBook::with_capacity(4, 4);
+        book.set_config(crate::orderbook::BookConfig {
+            stock_locate: 1,
+            symbol: "TEST".to_string(),
+            ..Default::default()
+        });
+        let mut tape = TradeTape::new();
+        itch::apply_with(&mut book, &add(1, Side::Buy, 100, 1_000_000), &mut tape);
+        itch::apply_with(&mut book, &add(2, Side::Sell, 100, 1_001_000), &mut tape);
+        (book, tape)
+    }
+
+    #[test]
+    fn executions_take_the_resting_order_price() {
+        let (mut book, mut tape) = session();
+        let executed = msg(
+            b'E',
+            10,
+            Body::OrderExecuted {
+                reference: 1,
+                executed: 100,
+                match_number: 7,
+            },
+        );
+        itch::apply_with(&mut book, &executed, &mut tape);
+
+        // Priced and sided from the order even though it has left the book
+        assert_eq!(book.order(1), None);
+        let trade = tape.get(7).unwrap();
+        assert_eq!(trade.price, 1_000_000);
+        assert_eq!(trade.size, 100);
+        assert_eq!(trade.aggressor, Some(OrderSide::Sell));
+        assert_eq!(trade.symbol.as_str(), "TEST");
+        assert_eq!(trade.trade_type, TradeType::Execution);
+    }
+
+    #[test]
+    fn executions_with_price_print_only_when_printable() {
+        let (mut book, mut tape) = session();
+        let executed = |match_number, printable| {
+            msg(
+                b'C',
+                10,
+                Body::OrderExecutedWithPrice {
+                    reference: 2,
+                    executed: 30,
+                    match_number,
+                    printable,
+                    price: 1_000_500.into(),
+                },
+            )
+        };
+        itch::apply_with(&mut book, &executed(8, true), &mut tape);
+        itch::apply_with(&mut book, &executed(9, false), &mut tape);
+
+        assert_eq!(tape.len(), 1);
+        let trade = tape.get(8).unwrap();
+        assert_eq!(trade.price, 1_000_500);
+        assert_eq!(trade.aggressor, Some(OrderSide::Buy));
+        assert_eq!(trade.trade_type, TradeType::ExecutionWithPrice);
+        assert_eq!(book.order(2), Some((1_001_000, 40, OrderSide::Sell)));
+    }
+
+    #[test]
+    fn cross_and_non_cross_trades_have_no_aggressor() {
+        let (mut book, mut tape) = session();
+        let non_cross = msg(
+            b'P',
+            10,
+            Body::NonCrossTrade(NonCrossTrade {
+                reference: 0,
+                side: Side::Buy,
+                shares: 500,
+                stock: ArrayString8::from("TEST    ").unwrap(),
+                price: 1_000_200.into(),
+                match_number: 11,
+            }),
+        );
+        let cross = |shares, match_number| {
+            msg(
+                b'Q',
+                20,
+                Body::CrossTrade(CrossTrade {
+                    shares,
+                    stock: ArrayString8::from("TEST    ").unwrap(),
+                    cross_price: 1_000_300.into(),
+                    match_number,
+                    cross_type: CrossType::Closing,
+                }),
+            )
+        };
+        itch::apply_with(&mut book, &non_cross, &mut tape);
+        itch::apply_with(&mut book, &cross(2_000, 12), &mut tape);
+        // A cross that didn't happen prints nothing
+        itch::apply_with(&mut book, &cross(0, 13), &mut tape);
+
+        let trades: Vec<_> = tape
+            .trades()
+            .iter()
+            .map(|t| (t.match_number, t.price, t.size, t.aggressor, t.trade_type))
+            .collect();
+        assert_eq!(
+            trades,
+            [
+                (11, 1_000_200, 500, None, TradeType::NonCross),
+                (
+                    12,
+                    1_000_300,
+                    2_000,
+                    None,
+                    TradeType::Cross(CrossType::Closing)
+                ),
+            ]
+        );
+        assert_eq!(tape.get(11).unwrap().symbol.as_str(), "TEST");
+        // Neither touches the displayed book
+        assert_eq!(book.order(1), Some((1_000_000, 100, OrderSide::Buy)));
+    }
+
+    #[test]
+    fn broken_trades_leave_the_volume() {
+        let (mut book, mut tape) = session();
+        for (reference, match_number) in [(1, 21), (2, 22)] {
+            let executed = msg(
+                b'E',
+                10,
+                Body::OrderExecuted {
+                    reference,
+                    executed: 40,
+                    match_number,
+                },
+            );
+            itch::apply_with(&mut book, &executed, &mut tape);
+        }
+        assert_eq!(tape.volume(1), 80);
+
+        let broken = msg(b'B', 30, Body::BrokenTrade { match_number: 21 });
+        itch::apply_with(&mut book, &broken, &mut tape);
+        assert!(tape.get(21).unwrap().broken);
+        assert_eq!(tape.volume(1), 40);
+        assert_eq!(tape.valid_trades().count(), 1);
+        assert!(!tape.break_trade(99));
+    }
+}
@@ -0,0 +1,391 @@
+use itchy::{ArrayString4, Body, EventCode, LevelBreached, Message, RegShoAction};
+use serde::{Deserialize, Serialize};
+
+use crate::itch::{ApplyOutcome, ItchHandler};
+use crate::orderbook::OrderBook;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Serialize, Deserialize)]
+pub enum TradingState {
+    PreOpen,
+    QuotationOnly,
+    Trading,
+    Halted,
+    Paused,
+    Closed,
+}
+
+impl TradingState {
+    // Halted or paused, executions shouldn't be expected
+    pub fn is_halted(self) -> bool {
+        matches!(self, TradingState::Halted | TradingState::Paused)
+    }
+
+    pub fn is_trading(self) -> bool {
+        self == TradingState::Trading
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct StateChange {
+    pub stock_locate: u16,
+    pub timestamp: u64,
+    pub from: TradingState,
+    pub to: TradingState,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum Session {
+    PreOpen,
+    Open,
+    Closed,
+}
+
+// Combines the market-wide session (S), market-wide circuit breakers (W),
+// the symbol's trading action (H) and its Reg SHO restriction (Y) into a
+// single TradingState
+#[derive(Debug, Clone)]
+pub struct TradingStatus {
+    session: Session,
+    action: Option<itchy::TradingState>,
+    reason: Option<ArrayString4>,
+    mwcb_halt: bool,
+    reg_sho: RegShoAction,
+    state: TradingState,
+}
+
+impl Default for TradingStatus {
+    fn default() -> Self {
+        TradingStatus {
+            session: Session::PreOpen,
+            action: None,
+            reason: None,
+            mwcb_halt: false,
+            reg_sho: RegShoAction::None,
+            state: TradingState::PreOpen,
+        }
+    }
+}
+
+impl TradingStatus {
+    pub fn state(&self) -> TradingState {
+        self.state
+    }
+
+    // Reason code from the last Stock Trading Action message
+    pub fn reason(&self) -> Option<&str> {
+        self.reason
+            .as_ref()
+            .map(|r| r.trim_end())
+            .filter(|r| !r.is_empty())
+    }
+
+    pub fn reg_sho(&self) -> RegShoAction {
+        self.reg_sho
+    }
+
+    pub fn short_sale_restricted(&self) -> bool {
+        self.reg_sho != RegShoAction::None
+    }
+
+    // Updates the status from a S, H, Y or W message. Returns None for any other
+    // message, otherwise the (from, to) states if the state changed.
+    pub fn on_message(&mut self, body: &Body) -> Option<Option<(TradingState, TradingState)>> {
+        let change = match body {
+            Body::SystemEvent { event } => self.on_system_event(*event),
+            Body::TradingAction {
+                trading_state,
+                reason,
+                ..
+            } => self.on_trading_action(*trading_state, *reason),
+            Body::Breach(level) => self.on_breach(*level),
+            Body::RegShoRestriction { action, .. } => {
+                self.on_reg_sho(*action);
+                None
+            }
+            _ => return None,
+        };
+        Some(change)
+    }
+
+    pub fn on_system_event(&mut self, event: EventCode) -> Option<(TradingState, TradingState)> {
+        self.session = match event {
+            EventCode::StartOfMessages | EventCode::StartOfSystemHours => Session::PreOpen,
+            EventCode::StartOfMarketHours => Session::Open,
+            EventCode::EndOfMarketHours
+            | EventCode::EndOfSystemHours
+            | EventCode::EndOfMessages => Session::Closed,
+        };
+        self.update()
+    }
+
+    pub fn on_trading_action(
+        &mut self,
+        action: itchy::TradingState,
+        reason: ArrayString4,
+    ) -> Option<(TradingState, TradingState)> {
+        // Trading resumes symbol by symbol after a level 1 or 2 circuit breaker
+        if matches!(
+            action,
+            itchy::TradingState::Trading | itchy::TradingState::QuotationOnly
+        ) {
+            self.mwcb_halt = false;
+        }
+
+        self.action = Some(action);
+        self.reason = Some(reason);
+        self.update()
+    }
+
+    pub fn on_breach(&mut self, level: LevelBreached) -> Option<(TradingState, TradingState)> {
+        match level {
+            LevelBreached::L1 | LevelBreached::L2 => self.mwcb_halt = true,
+            // Level 3 halts trading for the rest of the day
+            LevelBreached::L3 => self.session = Session::Closed,
+        }
+        self.update()
+    }
+
+    pub fn on_reg_sho(&mut self, action: RegShoAction) {
+        self.reg_sho = action;
+    }
+
+    fn update(&mut self) -> Option<(TradingState, TradingState)> {
+        let state = if self.session == Session::Closed {
+            TradingState::Closed
+        } else if self.mwcb_halt {
+            TradingState::Halted
+        } else {
+            match self.action {
+                Some(itchy::TradingState::Halted) => TradingState::Halted,
+                Some(itchy::TradingState::Paused) => TradingState::Paused,
+                Some(itchy::TradingState::QuotationOnly) => TradingState::QuotationOnly,
+                Some(itchy::TradingState::Trading) | None if self.session == Session::Open => {
+                    TradingState::Trading
+                }
+                Some(itchy::TradingState::Trading) | None => TradingState::PreOpen,
+            }
+        };
+
+        if state == self.state {
+            return None;
+        }
+
+        let from = self.state;
+        self.state = state;
+        Some((from, state))
+    }
+}
+
+// Collects every state change seen by apply_with
+#[derive(Debug, Default, Clone)]
+pub struct StateChangeLog {
+    pub changes: Vec<StateChange>,
+}
+
+impl StateChangeLog {
+    // (start, end) timestamps of the periods the symbol was halted or paused.
+    // A halt still in force has no end.
+    pub fn halted_periods(&self, stock_locate: u16) -> Vec<(u64, Option<u64>)> {
+        let mut periods = Vec::new();
+        let mut start = None;
+
+        for change in self
+            .changes
+            .iter()
+            .filter(|c| c.stock_locate == stock_locate)
+        {
+            match (start, change.to.is_halted()) {
+                (None, true) => start = Some(change.timestamp),
+                (Some(s), false) => {
+                    periods.push((s, Some(change.timestamp)));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(s) = start {
+            periods.push((s, None));
+        }
+        periods
+    }
+}
+
+impl ItchHandler for StateChangeLog {
+    fn after_apply(&mut self, _book: &OrderBook, _msg: &Message, outcome: ApplyOutcome) {
+        if let ApplyOutcome::StateChanged(change) = outcome {
+            self.changes.push(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itchy::ArrayString8;
+
+    use super::*;
+    use crate::itch::{self, SYSTEM_EVENT, TRADING_ACTION};
+
+    fn trading_action(state: itchy::TradingState, reason: &str) -> Body {
+        Body::TradingAction {
+            stock: ArrayString8::from("TEST    ").unwrap(),
+            trading_state: state,
+            reason: ArrayString4::from(reason).unwrap(),
+        }
+    }
+
+    fn reg_sho(action: RegShoAction) -> Body {
+        Body::RegShoRestriction {
+            stock: ArrayString8::from("TEST    ").unwrap(),
+            action,
+        }
+    }
+
+    fn open() -> TradingStatus {
+        let mut status = TradingStatus::default();
+        status.on_system_event(EventCode::StartOfMarketHours);
+        status
+    }
+
+    #[test]
+    fn halts_and_resumptions() {
+        let mut status = TradingStatus::default();
+        assert_eq!(status.state(), TradingState::PreOpen);
+        // Symbols are released for trading before the open
+        assert_eq!(
+            status.on_message(&trading_action(itchy::TradingState::Trading, "    ")),
+            Some(None)
+        );
+        assert_eq!(
+            status.on_message(&Body::SystemEvent {
+                event: EventCode::StartOfMarketHours
+            }),
+            Some(Some((TradingState::PreOpen, TradingState::Trading)))
+        );
+
+        assert_eq!(
+            status.on_message(&trading_action(itchy::TradingState::Halted, "LUDP")),
+            Some(Some((TradingState::Trading, TradingState::Halted)))
+        );
+        assert_eq!(status.reason(), Some("LUDP"));
+        assert!(status.state().is_halted());
+        assert_eq!(
+            status.on_message(&trading_action(itchy::TradingState::QuotationOnly, "LUDP")),
+            Some(Some((TradingState::Halted, TradingState::QuotationOnly)))
+        );
+        assert_eq!(
+            status.on_message(&trading_action(itchy::TradingState::Trading, "    ")),
+            Some(Some((TradingState::QuotationOnly, TradingState::Trading)))
+        );
+        assert_eq!(status.reason(), None);
+
+        assert_eq!(
+            status.on_message(&Body::SystemEvent {
+                event: EventCode::EndOfMarketHours
+            }),
+            Some(Some((TradingState::Trading, TradingState::Closed)))
+        );
+    }
+
+    #[test]
+    fn reg_sho_restrictions_leave_the_state_alone() {
+        let mut status = open();
+        assert!(!status.short_sale_restricted());
+
+        assert_eq!(
+            status.on_message(&reg_sho(RegShoAction::Intraday)),
+            Some(None)
+        );
+        assert!(status.short_sale_restricted());
+        assert_eq!(status.reg_sho(), RegShoAction::Intraday);
+        assert_eq!(status.state(), TradingState::Trading);
+
+        status.on_message(&reg_sho(RegShoAction::None));
+        assert!(!status.short_sale_restricted());
+    }
+
+    #[test]
+    fn circuit_breakers_halt_until_symbols_resume() {
+        let mut status = open();
+        assert_eq!(
+            status.on_breach(LevelBreached::L1),
+            Some((TradingState::Trading, TradingState::Halted))
+        );
+        // Symbols reopen one by one with their own trading action
+        assert_eq!(
+            status.on_trading_action(
+                itchy::TradingState::Trading,
+                ArrayString4::from("    ").unwrap()
+            ),
+            Some((TradingState::Halted, TradingState::Trading))
+        );
+
+        assert_eq!(
+            status.on_breach(LevelBreached::L3),
+            Some((TradingState::Trading, TradingState::Closed))
+        );
+        assert_eq!(
+            status.on_trading_action(
+                itchy::TradingState::Trading,
+                ArrayString4::from("    ").unwrap()
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn other_messages_are_not_status() {
+        let mut status = open();
+        assert_eq!(status.on_message(&Body::DeleteOrder { reference: 1 }), None);
+    }
+
+    #[test]
+    fn halted_periods_from_state_changes() {
+        let mut book = OrderBook::with_capacity(4, 4);
+        let mut log = StateChangeLog::default();
+        let messages = [
+            (
+                SYSTEM_EVENT,
+                10,
+                Body::SystemEvent {
+                    event: EventCode::StartOfMarketHours,
+                },
+            ),
+            (
+                TRADING_ACTION,
+                20,
+                trading_action(itchy::TradingState::Halted, "T1"),
+            ),
+            (
+                TRADING_ACTION,
+                30,
+                trading_action(itchy::TradingState::Paused, "LUDP"),
+            ),
+            (
+                TRADING_ACTION,
+                40,
+                trading_action(itchy::TradingState::Trading, "    "),
+            ),
+            (
+                TRADING_ACTION,
+                50,
+                trading_action(itchy::TradingState::Halted, "T1"),
+            ),
+        ];
+        for (tag, timestamp, body) in messages {
+            let msg = Message {
+                tag,
+                stock_locate: 0,
+                tracking_number: 0,
+                timestamp,
+                body,
+            };
+            itch::apply_with(&mut book, &msg, &mut log);
+        }
+
+        assert_eq!(log.changes.len(), 5);
+        assert_eq!(log.halted_periods(0), [(20, Some(40)), (50, None)]);
+        assert!(log.halted_periods(1).is_empty());
+        assert_eq!(book.trading_state(), TradingState::Halted);
+    }
+}